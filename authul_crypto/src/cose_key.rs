@@ -0,0 +1,196 @@
+use ciborium::value::Value;
+use ed25519_dalek::{Signature as Ed25519Signature, VerifyingKey as Ed25519VerifyingKey};
+use p256::ecdsa::{
+	signature::Verifier as _, Signature as P256Signature, VerifyingKey as P256VerifyingKey,
+};
+
+use super::Error;
+
+// Labels and values from the IANA COSE registries
+const LABEL_KTY: i64 = 1;
+const LABEL_ALG: i64 = 3;
+const LABEL_CRV: i64 = -1;
+const LABEL_X: i64 = -2;
+const LABEL_Y: i64 = -3;
+
+const KTY_OKP: i64 = 1;
+const KTY_EC2: i64 = 2;
+
+const CRV_P256: i64 = 1;
+const CRV_ED25519: i64 = 6;
+
+/// A public key in `COSE_Key` format (RFC 9052), which is how WebAuthn authenticators hand us
+/// the keys for the credentials they create.
+///
+/// Only the algorithms that every authenticator worth having supports are accepted; anything
+/// else is an error, rather than a key we can't verify anything with.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum CoseKey {
+	Es256(P256VerifyingKey),
+	EdDsa(Ed25519VerifyingKey),
+}
+
+impl CoseKey {
+	/// The COSE algorithm identifier for ECDSA with P-256 and SHA-256
+	pub const ES256: i64 = -7;
+	/// The COSE algorithm identifier for EdDSA (which, for us, means Ed25519)
+	pub const EDDSA: i64 = -8;
+
+	/// Parse a CBOR-encoded key which takes up the entirety of `bytes`.
+	pub fn from_cbor(bytes: &[u8]) -> Result<Self, Error> {
+		let mut rest = bytes;
+		let key = Self::read(&mut rest)?;
+
+		if rest.is_empty() {
+			Ok(key)
+		} else {
+			Err(Error::cose_key("trailing data after key"))
+		}
+	}
+
+	/// Parse a CBOR-encoded key from the start of `bytes`, leaving `bytes` pointing at whatever
+	/// comes after the key.  Authenticators pack the key in amongst other data, with nothing to
+	/// say how long it is, so this is the only way to find out where it ends.
+	pub fn read(bytes: &mut &[u8]) -> Result<Self, Error> {
+		let value: Value =
+			ciborium::from_reader(&mut *bytes).map_err(|e| Error::cose_key(e.to_string()))?;
+
+		let Value::Map(fields) = value else {
+			return Err(Error::cose_key("key is not a map"));
+		};
+
+		let kty = int_field(&fields, LABEL_KTY)?;
+		let alg = int_field(&fields, LABEL_ALG)?;
+		let crv = int_field(&fields, LABEL_CRV)?;
+
+		match (kty, alg, crv) {
+			(KTY_EC2, Self::ES256, CRV_P256) => {
+				let x = bytes_field(&fields, LABEL_X, 32)?;
+				let y = bytes_field(&fields, LABEL_Y, 32)?;
+
+				let mut sec1 = Vec::with_capacity(65);
+				sec1.push(0x04);
+				sec1.extend_from_slice(x);
+				sec1.extend_from_slice(y);
+
+				Ok(Self::Es256(
+					P256VerifyingKey::from_sec1_bytes(&sec1)
+						.map_err(|e| Error::cose_key(e.to_string()))?,
+				))
+			}
+			(KTY_OKP, Self::EDDSA, CRV_ED25519) => {
+				let x: [u8; 32] = bytes_field(&fields, LABEL_X, 32)?
+					.try_into()
+					.map_err(|_| Error::cose_key("x coordinate is the wrong length"))?;
+
+				Ok(Self::EdDsa(
+					Ed25519VerifyingKey::from_bytes(&x)
+						.map_err(|e| Error::cose_key(e.to_string()))?,
+				))
+			}
+			(kty, alg, crv) => Err(Error::cose_key(format!(
+				"unsupported key type {kty}, algorithm {alg}, curve {crv}"
+			))),
+		}
+	}
+
+	/// Check that `signature` is a valid signature over `message` by this key.
+	///
+	/// ES256 signatures are expected to be DER-encoded, as WebAuthn specifies.
+	pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), Error> {
+		match self {
+			Self::Es256(k) => {
+				let sig = P256Signature::from_der(signature)
+					.map_err(|e| Error::signature_verification(e.to_string()))?;
+				k.verify(message, &sig)
+					.map_err(|e| Error::signature_verification(e.to_string()))
+			}
+			Self::EdDsa(k) => {
+				let sig = Ed25519Signature::from_slice(signature)
+					.map_err(|e| Error::signature_verification(e.to_string()))?;
+				k.verify_strict(message, &sig)
+					.map_err(|e| Error::signature_verification(e.to_string()))
+			}
+		}
+	}
+}
+
+fn field(fields: &[(Value, Value)], label: i64) -> Option<&Value> {
+	fields
+		.iter()
+		.find(|(k, _)| k.as_integer() == Some(label.into()))
+		.map(|(_, v)| v)
+}
+
+fn int_field(fields: &[(Value, Value)], label: i64) -> Result<i64, Error> {
+	field(fields, label)
+		.and_then(Value::as_integer)
+		.and_then(|i| i64::try_from(i).ok())
+		.ok_or_else(|| Error::cose_key(format!("missing or invalid field {label}")))
+}
+
+fn bytes_field(fields: &[(Value, Value)], label: i64, len: usize) -> Result<&[u8], Error> {
+	field(fields, label)
+		.and_then(Value::as_bytes)
+		.filter(|b| b.len() == len)
+		.map(Vec::as_slice)
+		.ok_or_else(|| Error::cose_key(format!("missing or invalid field {label}")))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use p256::ecdsa::{signature::Signer as _, SigningKey};
+
+	fn es256_cose(k: &SigningKey) -> Vec<u8> {
+		let point = k.verifying_key().to_encoded_point(false);
+		let key = Value::Map(vec![
+			(LABEL_KTY.into(), KTY_EC2.into()),
+			(LABEL_ALG.into(), CoseKey::ES256.into()),
+			(LABEL_CRV.into(), CRV_P256.into()),
+			(LABEL_X.into(), Value::Bytes(point.x().unwrap().to_vec())),
+			(LABEL_Y.into(), Value::Bytes(point.y().unwrap().to_vec())),
+		]);
+
+		let mut out = vec![];
+		ciborium::into_writer(&key, &mut out).unwrap();
+		out
+	}
+
+	#[test]
+	fn es256_signature_verifies() {
+		let k = SigningKey::random(&mut rand::rngs::OsRng);
+		let cose = CoseKey::from_cbor(&es256_cose(&k)).unwrap();
+
+		let sig: P256Signature = k.sign(b"hello");
+
+		assert!(cose.verify(b"hello", sig.to_der().as_bytes()).is_ok());
+		assert!(cose.verify(b"goodbye", sig.to_der().as_bytes()).is_err());
+	}
+
+	#[test]
+	fn read_leaves_trailing_data() {
+		let k = SigningKey::random(&mut rand::rngs::OsRng);
+		let mut bytes = es256_cose(&k);
+		bytes.extend_from_slice(b"extensions");
+
+		let mut rest = &bytes[..];
+		CoseKey::read(&mut rest).unwrap();
+
+		assert_eq!(b"extensions", rest);
+		assert!(CoseKey::from_cbor(&bytes).is_err());
+	}
+
+	#[test]
+	fn rs256_is_rejected() {
+		let key = Value::Map(vec![
+			(LABEL_KTY.into(), Value::from(3_i64)),
+			(LABEL_ALG.into(), Value::from(-257_i64)),
+		]);
+		let mut bytes = vec![];
+		ciborium::into_writer(&key, &mut bytes).unwrap();
+
+		assert!(CoseKey::from_cbor(&bytes).is_err());
+	}
+}
@@ -0,0 +1,211 @@
+/// DPoP proofs, as per RFC9449, which a client makes with a key of its own to show that it's the
+/// one an access token was bound to.
+///
+/// These are *almost* JWTs, but the header carries the key the proof was signed with, and the
+/// claims are nothing like the ones in a [`Jwt`](crate::Jwt), so they get their own type.
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use rand::RngCore as _;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+use crate::{Error, Jwk, PublicJwk};
+
+const DPOP_TYP: &str = "dpop+jwt";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DpopProof {
+	jti: String,
+	htm: String,
+	htu: String,
+	iat: u64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	ath: Option<String>,
+
+	// These are the verification parts
+	#[serde(skip)]
+	jwk: Option<PublicJwk>,
+	#[serde(skip)]
+	signed_text: Option<String>,
+	#[serde(skip)]
+	sig: Option<String>,
+}
+
+impl DpopProof {
+	/// How far a proof's `iat` can be from now before we won't accept it.  RFC9449 s11.1 leaves
+	/// this up to us, but a proof is meant to be made just before the request it's for, so it
+	/// doesn't need to be long.
+	pub const MAX_AGE: Duration = Duration::from_secs(60);
+
+	/// A proof for a request with the given HTTP method and URL.
+	pub fn new(htm: impl Into<String>, htu: impl Into<String>) -> Self {
+		let mut jti = [0u8; 16];
+		rand::thread_rng().fill_bytes(&mut jti);
+
+		Self {
+			jti: BASE64_URL_SAFE_NO_PAD.encode(jti),
+			htm: htm.into(),
+			htu: htu.into(),
+			iat: now(),
+			..Self::default()
+		}
+	}
+
+	/// For a proof that goes along with an access token, when calling a resource server.
+	pub fn with_access_token(mut self, access_token: &str) -> Self {
+		self.ath = Some(access_token_hash(access_token));
+		self
+	}
+
+	#[cfg(any(test, feature = "test-helpers"))]
+	pub fn with_broken_iat(mut self) -> Self {
+		self.iat = self.iat - 2 * Self::MAX_AGE.as_secs();
+		self
+	}
+
+	pub fn peek_jti(&self) -> &str {
+		&self.jti
+	}
+
+	pub fn peek_iat(&self) -> u64 {
+		self.iat
+	}
+
+	pub fn sign(&self, key: &Jwk) -> Result<String, Error> {
+		let hdr = encode(json!({ "typ": DPOP_TYP, "alg": key.alg(), "jwk": key.to_public_jwk() }));
+		let payload = encode(self);
+
+		let sig = key.sign(format!("{hdr}.{payload}").as_bytes());
+
+		Ok(format!(
+			"{hdr}.{payload}.{}",
+			BASE64_URL_SAFE_NO_PAD.encode(&sig)
+		))
+	}
+
+	/// Check that the proof was made for a request with the given HTTP method and URL (and, if
+	/// given, access token), and returns the thumbprint of the key it was made with.
+	///
+	/// Whether the proof has been seen before is up to the caller to work out, using the `jti`.
+	pub fn verify(
+		&self,
+		htm: &str,
+		htu: &str,
+		access_token: Option<&str>,
+	) -> Result<String, Error> {
+		let (Some(jwk), Some(signed_text), Some(sig)) = (
+			self.jwk.as_ref(),
+			self.signed_text.as_ref(),
+			self.sig.as_ref(),
+		) else {
+			return Err(Error::dpop("proof has not been parsed"));
+		};
+
+		// This has to come before the signature check, because it's what rejects the kinds of
+		// keys that PublicJwk doesn't know how to verify with
+		let thumbprint = jwk.thumbprint()?;
+
+		let Ok(sig) = BASE64_URL_SAFE_NO_PAD.decode(sig) else {
+			return Err(Error::dpop("undecodable signature"));
+		};
+		if !jwk.verify(signed_text.as_bytes(), &sig) {
+			return Err(Error::dpop("invalid signature"));
+		}
+
+		if self.iat.abs_diff(now()) > Self::MAX_AGE.as_secs() {
+			return Err(Error::dpop("iat too far from now"));
+		}
+
+		if self.htm != htm {
+			return Err(Error::dpop(format!("htm {} is not {htm}", self.htm)));
+		}
+
+		// RFC9449 s4.3 says the query and fragment don't count
+		match (without_query(&self.htu), without_query(htu)) {
+			(Some(proof_htu), Some(htu)) if proof_htu == htu => (),
+			_ => return Err(Error::dpop(format!("htu {} is not {htu}", self.htu))),
+		}
+
+		if let Some(access_token) = access_token {
+			if self.ath.as_deref() != Some(access_token_hash(access_token).as_str()) {
+				return Err(Error::dpop("ath does not match access token"));
+			}
+		}
+
+		Ok(thumbprint)
+	}
+}
+
+impl std::str::FromStr for DpopProof {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut parts = s.split('.');
+		let (Some(hdr), Some(payload), Some(sig), None) =
+			(parts.next(), parts.next(), parts.next(), parts.next())
+		else {
+			return Err(Error::dpop("missing or trailing part"));
+		};
+
+		#[derive(Deserialize)]
+		struct Hdr {
+			typ: String,
+			alg: String,
+			jwk: PublicJwk,
+		}
+		let decoded_hdr: Hdr = serde_json::from_slice(
+			&BASE64_URL_SAFE_NO_PAD
+				.decode(hdr)
+				.map_err(|e| Error::dpop(e.to_string()))?,
+		)
+		.map_err(|e| Error::dpop(e.to_string()))?;
+
+		if decoded_hdr.typ != DPOP_TYP {
+			return Err(Error::dpop(format!("typ != {DPOP_TYP}")));
+		}
+		// Which also takes care of "none"
+		if decoded_hdr.alg != "EdDSA" {
+			return Err(Error::dpop(format!("unsupported alg {}", decoded_hdr.alg)));
+		}
+		if decoded_hdr.jwk.has_private_key() {
+			return Err(Error::dpop("jwk contains a private key"));
+		}
+
+		let mut proof: DpopProof = serde_json::from_slice(
+			&BASE64_URL_SAFE_NO_PAD
+				.decode(payload)
+				.map_err(|e| Error::dpop(e.to_string()))?,
+		)
+		.map_err(|e| Error::dpop(e.to_string()))?;
+
+		proof.jwk = Some(decoded_hdr.jwk);
+		proof.signed_text = Some(format!("{hdr}.{payload}"));
+		proof.sig = Some(sig.to_string());
+
+		Ok(proof)
+	}
+}
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.expect("time to exist")
+		.as_secs()
+}
+
+fn encode(obj: impl Serialize) -> String {
+	BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&obj).expect("serialize failed"))
+}
+
+fn access_token_hash(access_token: &str) -> String {
+	BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(access_token))
+}
+
+fn without_query(url: &str) -> Option<Url> {
+	let mut url = Url::parse(url).ok()?;
+	url.set_query(None);
+	url.set_fragment(None);
+	Some(url)
+}
@@ -17,6 +17,18 @@ pub enum Error {
 		location: &'static std::panic::Location<'static>,
 	},
 
+	#[error("invalid COSE key: {0}")]
+	CoseKey(String, &'static std::panic::Location<'static>),
+
+	#[error("unsupported JWK: {0}")]
+	UnsupportedJwk(String, &'static std::panic::Location<'static>),
+
+	#[error("invalid DPoP proof: {0}")]
+	Dpop(String, &'static std::panic::Location<'static>),
+
+	#[error("signature verification failed: {0}")]
+	SignatureVerification(String, &'static std::panic::Location<'static>),
+
 	#[error("failed to make HTTP request")]
 	Reqwest(
 		#[from] reqwest_middleware::reqwest::Error,
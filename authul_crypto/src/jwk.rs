@@ -9,9 +9,12 @@ use jose_jwk::{
 use postgres_types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::error::Error as StdError;
 use strong_box::SharedStrongBox;
 
+use crate::Error;
+
 /// Thin(ish) wrapper around jose_jwk::Jwk, with added functions to do strange things like
 /// *actually create a key*.
 ///
@@ -136,6 +139,31 @@ impl PublicJwk {
 		}
 	}
 
+	/// The JWK Thumbprint of this key, as per RFC7638, which is what a DPoP-bound token uses to
+	/// say which key it's bound to.
+	pub fn thumbprint(&self) -> Result<String, Error> {
+		// The members have to be in lexicographic order, with no whitespace, so that everyone
+		// comes up with the same hash for the same key
+		let canonical = match &self.0.key {
+			JoseKey::Okp(JoseOkp {
+				crv: OkpCurves::Ed25519,
+				x,
+				..
+			}) => format!(
+				r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#,
+				BASE64_URL_SAFE_NO_PAD.encode(x.as_ref())
+			),
+			k => return Err(Error::unsupported_jwk(format!("{k:?}"))),
+		};
+
+		Ok(BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(canonical)))
+	}
+
+	/// Whether someone has (foolishly) included the private part of the key
+	pub(crate) fn has_private_key(&self) -> bool {
+		matches!(&self.0.key, JoseKey::Okp(JoseOkp { d: Some(_), .. }))
+	}
+
 	pub fn to_shared_strong_box(&self) -> SharedStrongBox {
 		// Yeah, this is kinda cheating...
 		let mut key = vec![1u8];
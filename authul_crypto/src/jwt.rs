@@ -10,8 +10,8 @@
 /// So, we get to do our own, tightly-scoped-to-what-we-need thing.  Yippee!
 use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value as JsonValue};
-use std::time::{SystemTime, UNIX_EPOCH};
+use serde_json::{json, Map as JsonMap, Value as JsonValue};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::{Error, Jwk, PublicJwk};
 
@@ -21,6 +21,9 @@ const TIME_FUDGE: u64 = 3;
 /// This does not need to be long, because they're just a container for transporting claims, not a
 /// long-term credential.
 const JWT_VALIDITY_PERIOD: u64 = 60;
+/// The explicit `typ` that RFC9101 s10.8 recommends request objects carry, so they can't be
+/// mistaken for any other kind of JWT
+pub const REQUEST_OBJECT_TYP: &str = "oauth-authz-req+jwt";
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Jwt {
@@ -32,9 +35,23 @@ pub struct Jwt {
 	#[serde(skip_serializing_if = "Option::is_none")]
 	nonce: Option<String>,
 	#[serde(skip_serializing_if = "Option::is_none")]
+	auth_time: Option<i64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
 	// Making this an actual IdentityAttributes would require depending on authul_db, which we
 	// can't do because it depends on authul_crypto
 	attrs: Option<JsonValue>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	events: Option<JsonValue>,
+	// Space-separated, as per RFC9068 s2.2.3
+	#[serde(skip_serializing_if = "Option::is_none")]
+	scope: Option<String>,
+	// Which key a DPoP-bound access token is bound to (RFC9449 s6.1)
+	#[serde(skip_serializing_if = "Option::is_none")]
+	cnf: Option<Confirmation>,
+	// Anything else that's in there, such as the authorization request params in a request object
+	// (RFC9101)
+	#[serde(flatten)]
+	claims: JsonMap<String, JsonValue>,
 
 	exp: u64,
 	iat: u64,
@@ -48,6 +65,11 @@ pub struct Jwt {
 	sig: Option<String>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Confirmation {
+	jkt: String,
+}
+
 impl Jwt {
 	pub fn new() -> Self {
 		let now = SystemTime::now()
@@ -70,6 +92,51 @@ impl Jwt {
 		self.jti.as_ref().map(|s| s.as_str())
 	}
 
+	pub fn peek_attrs(&self) -> Option<&JsonValue> {
+		self.attrs.as_ref()
+	}
+
+	pub fn peek_iss(&self) -> Option<&str> {
+		self.iss.as_ref().map(|s| s.as_str())
+	}
+
+	pub fn peek_aud(&self) -> Option<&str> {
+		self.aud.as_ref().map(|s| s.as_str())
+	}
+
+	pub fn peek_events(&self) -> Option<&JsonValue> {
+		self.events.as_ref()
+	}
+
+	pub fn peek_scope(&self) -> Option<&str> {
+		self.scope.as_ref().map(|s| s.as_str())
+	}
+
+	pub fn peek_nonce(&self) -> Option<&str> {
+		self.nonce.as_ref().map(|s| s.as_str())
+	}
+
+	pub fn peek_auth_time(&self) -> Option<i64> {
+		self.auth_time
+	}
+
+	pub fn peek_cnf_jkt(&self) -> Option<&str> {
+		self.cnf.as_ref().map(|c| c.jkt.as_str())
+	}
+
+	/// Claims that don't have a field of their own
+	pub fn peek_claims(&self) -> &JsonMap<String, JsonValue> {
+		&self.claims
+	}
+
+	pub fn peek_iat(&self) -> u64 {
+		self.iat
+	}
+
+	pub fn peek_exp(&self) -> u64 {
+		self.exp
+	}
+
 	pub fn with_iss(mut self, iss: impl Into<String>) -> Self {
 		self.iss = Some(iss.into());
 		self
@@ -90,16 +157,50 @@ impl Jwt {
 		self
 	}
 
+	/// For JWTs that need to last longer than the default (very short) validity period.
+	pub fn with_validity_period(mut self, period: Duration) -> Self {
+		self.exp = self.iat + TIME_FUDGE + period.as_secs() + TIME_FUDGE;
+		self
+	}
+
 	pub fn with_attrs(mut self, attrs: JsonValue) -> Self {
 		self.attrs = Some(attrs);
 		self
 	}
 
+	/// For Security Event Tokens, such as back-channel logout tokens.
+	pub fn with_events(mut self, events: JsonValue) -> Self {
+		self.events = Some(events);
+		self
+	}
+
+	pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+		self.scope = Some(scope.into());
+		self
+	}
+
+	/// Bind the token to the key with the given JWK thumbprint, so that it's no use to anyone who
+	/// can't make DPoP proofs with that key.
+	pub fn with_cnf_jkt(mut self, jkt: impl Into<String>) -> Self {
+		self.cnf = Some(Confirmation { jkt: jkt.into() });
+		self
+	}
+
+	pub fn with_claim(mut self, name: impl Into<String>, value: impl Into<JsonValue>) -> Self {
+		self.claims.insert(name.into(), value.into());
+		self
+	}
+
 	pub fn set_nonce(&mut self, nonce: impl Into<String>) -> &Self {
 		self.nonce = Some(nonce.into());
 		self
 	}
 
+	pub fn set_auth_time(&mut self, auth_time: i64) -> &Self {
+		self.auth_time = Some(auth_time);
+		self
+	}
+
 	pub fn with_broken_iat(mut self) -> Self {
 		self.iat = self.iat + 2 * JWT_VALIDITY_PERIOD;
 		self
@@ -111,7 +212,13 @@ impl Jwt {
 	}
 
 	pub fn sign(&self, key: &Jwk) -> Result<String, Error> {
-		let hdr = Self::encode(json!({ "typ": "JWT", "alg": key.alg(), "kid": key.id() }));
+		self.sign_with_typ(key, "JWT")
+	}
+
+	/// Sign the JWT with something other than plain `JWT` as its `typ`, such as
+	/// [`REQUEST_OBJECT_TYP`].
+	pub fn sign_with_typ(&self, key: &Jwk, typ: &str) -> Result<String, Error> {
+		let hdr = Self::encode(json!({ "typ": typ, "alg": key.alg(), "kid": key.id() }));
 		let payload = Self::encode(self);
 
 		let sig = key.sign(&format!("{hdr}.{payload}").as_bytes());
@@ -123,18 +230,7 @@ impl Jwt {
 	}
 
 	pub fn verify(&self, key: &PublicJwk) -> bool {
-		let (Some(hdr), Some(payload)) = (self.hdr.as_ref(), self.payload.as_ref()) else {
-			return false;
-		};
-
-		let signed_text = format!("{hdr}.{payload}");
-		let Ok(sig) = BASE64_URL_SAFE_NO_PAD
-			.decode(self.sig.as_ref().map(|s| s.as_str()).unwrap_or_else(|| ""))
-		else {
-			return false;
-		};
-
-		if !key.verify(signed_text.as_bytes(), &sig) {
+		if !self.verify_signature(key) {
 			return false;
 		}
 
@@ -154,6 +250,25 @@ impl Jwt {
 		true
 	}
 
+	/// Check that the JWT was signed by the given key, without caring whether it's current.
+	///
+	/// Only for the rare occasions when a JWT is being used as a reference to something that
+	/// happened in the past (like an `id_token_hint`), rather than as a credential.
+	pub fn verify_signature(&self, key: &PublicJwk) -> bool {
+		let (Some(hdr), Some(payload)) = (self.hdr.as_ref(), self.payload.as_ref()) else {
+			return false;
+		};
+
+		let signed_text = format!("{hdr}.{payload}");
+		let Ok(sig) = BASE64_URL_SAFE_NO_PAD
+			.decode(self.sig.as_ref().map(|s| s.as_str()).unwrap_or_else(|| ""))
+		else {
+			return false;
+		};
+
+		key.verify(signed_text.as_bytes(), &sig)
+	}
+
 	fn encode(obj: impl Serialize) -> String {
 		let mut buf: Vec<u8> = Vec::new();
 
@@ -161,12 +276,12 @@ impl Jwt {
 
 		BASE64_URL_SAFE_NO_PAD.encode(buf)
 	}
-}
 
-impl std::str::FromStr for Jwt {
-	type Err = Error;
-
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
+	/// Parse a JWT whose `typ` can be any of the given ones, rather than just plain `JWT`.
+	///
+	/// Only for the places where an explicitly-typed JWT is expected, like request objects; anywhere
+	/// else, stick to `str::parse`, so a JWT made for one purpose can't be passed off as another.
+	pub fn parse_with_typ(s: &str, typs: &[&str]) -> Result<Self, Error> {
 		let mut parts = s.split('.');
 		let (Some(hdr), Some(payload), Some(sig), None) =
 			(parts.next(), parts.next(), parts.next(), parts.next())
@@ -185,8 +300,11 @@ impl std::str::FromStr for Jwt {
 		)
 		.map_err(|e| Error::jwt_format(e.to_string()))?;
 
-		if decoded_hdr.typ != "JWT" {
-			return Err(Error::jwt_format("typ != JWT"));
+		if !typs.contains(&decoded_hdr.typ.as_str()) {
+			return Err(Error::jwt_format(format!(
+				"unexpected typ {}",
+				decoded_hdr.typ
+			)));
 		}
 
 		let mut jwt: Jwt = serde_json::from_slice(
@@ -203,3 +321,11 @@ impl std::str::FromStr for Jwt {
 		Ok(jwt)
 	}
 }
+
+impl std::str::FromStr for Jwt {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Self::parse_with_typ(s, &["JWT"])
+	}
+}
@@ -1,9 +1,13 @@
+mod cose_key;
+mod dpop;
 mod error;
 mod jwk;
 mod jwk_set;
 mod jwt;
 
+pub use cose_key::CoseKey;
+pub use dpop::DpopProof;
 pub use error::Error;
 pub use jwk::{Jwk, PublicJwk};
 pub use jwk_set::JwkSet;
-pub use jwt::Jwt;
+pub use jwt::{Jwt, REQUEST_OBJECT_TYP};
@@ -0,0 +1,8 @@
+CREATE TABLE users (
+	id UUID PRIMARY KEY,
+	principal_id UUID NOT NULL REFERENCES principals ON DELETE CASCADE,
+	email TEXT NOT NULL,
+	pwhash TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX user_email_uniqueness ON users (email);
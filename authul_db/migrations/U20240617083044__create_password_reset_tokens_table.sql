@@ -0,0 +1,5 @@
+CREATE TABLE password_reset_tokens (
+	id UUID PRIMARY KEY,
+	user_id UUID NOT NULL REFERENCES users ON DELETE CASCADE,
+	valid_before TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,8 @@
+CREATE TABLE totp_credentials (
+	id UUID PRIMARY KEY,
+	principal_id UUID NOT NULL REFERENCES principals ON DELETE CASCADE,
+	secret BYTEA NOT NULL,
+	last_used_step BIGINT NOT NULL
+);
+
+CREATE UNIQUE INDEX totp_credential_principal_uniqueness ON totp_credentials (principal_id);
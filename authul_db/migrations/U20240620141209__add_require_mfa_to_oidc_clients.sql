@@ -0,0 +1 @@
+ALTER TABLE oidc_clients ADD COLUMN require_mfa BOOLEAN NOT NULL DEFAULT false;
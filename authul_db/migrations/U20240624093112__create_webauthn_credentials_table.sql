@@ -0,0 +1,10 @@
+CREATE TABLE webauthn_credentials (
+	id UUID PRIMARY KEY,
+	principal_id UUID NOT NULL REFERENCES principals ON DELETE CASCADE,
+	credential_id BYTEA NOT NULL,
+	public_key BYTEA NOT NULL,
+	sign_count BIGINT NOT NULL
+);
+
+CREATE UNIQUE INDEX webauthn_credential_id_uniqueness ON webauthn_credentials (credential_id);
+CREATE INDEX webauthn_credentials_principal_id ON webauthn_credentials (principal_id);
@@ -0,0 +1,7 @@
+CREATE TABLE webauthn_challenges (
+	id UUID PRIMARY KEY,
+	challenge BYTEA NOT NULL,
+	valid_before TIMESTAMPTZ NOT NULL
+);
+
+CREATE UNIQUE INDEX webauthn_challenge_uniqueness ON webauthn_challenges (challenge);
@@ -0,0 +1,7 @@
+CREATE TABLE recovery_codes (
+	id UUID PRIMARY KEY,
+	principal_id UUID NOT NULL REFERENCES principals ON DELETE CASCADE,
+	code_hash BYTEA NOT NULL
+);
+
+CREATE UNIQUE INDEX recovery_code_uniqueness ON recovery_codes (principal_id, code_hash);
@@ -0,0 +1,10 @@
+CREATE TABLE sessions (
+	id UUID PRIMARY KEY,
+	principal_id UUID NOT NULL REFERENCES principals ON DELETE CASCADE,
+	attrs BYTEA NOT NULL,
+	auth_time TIMESTAMPTZ NOT NULL,
+	mfa_time TIMESTAMPTZ,
+	valid_before TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX sessions_principal_id ON sessions (principal_id);
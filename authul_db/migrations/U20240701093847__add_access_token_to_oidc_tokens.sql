@@ -0,0 +1,3 @@
+-- The default is only there to take care of any codes that are in flight when this is applied
+ALTER TABLE oidc_tokens ADD COLUMN access_token TEXT NOT NULL DEFAULT '';
+ALTER TABLE oidc_tokens ALTER COLUMN access_token DROP DEFAULT;
@@ -0,0 +1,16 @@
+CREATE TABLE refresh_tokens (
+	id UUID PRIMARY KEY,
+	family_id UUID NOT NULL,
+	oidc_client_id UUID NOT NULL REFERENCES oidc_clients ON DELETE CASCADE,
+	principal_id UUID NOT NULL REFERENCES principals ON DELETE CASCADE,
+	attrs BYTEA NOT NULL,
+	auth_time TIMESTAMPTZ NOT NULL,
+	used BOOLEAN NOT NULL,
+	valid_before TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX refresh_tokens_family_id ON refresh_tokens (family_id);
+CREATE INDEX refresh_tokens_oidc_client_id ON refresh_tokens (oidc_client_id);
+CREATE INDEX refresh_tokens_principal_id ON refresh_tokens (principal_id);
+
+ALTER TABLE oidc_tokens ADD COLUMN refresh_token_id UUID REFERENCES refresh_tokens ON DELETE SET NULL;
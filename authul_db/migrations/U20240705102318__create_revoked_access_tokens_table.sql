@@ -0,0 +1,4 @@
+CREATE TABLE revoked_access_tokens (
+	id UUID PRIMARY KEY,
+	valid_before TIMESTAMPTZ NOT NULL
+);
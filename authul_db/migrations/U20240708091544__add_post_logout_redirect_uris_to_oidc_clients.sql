@@ -0,0 +1 @@
+ALTER TABLE oidc_clients ADD COLUMN post_logout_redirect_uris TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,18 @@
+ALTER TABLE oidc_clients ADD COLUMN backchannel_logout_uri TEXT;
+
+CREATE TABLE session_clients (
+	session_id UUID NOT NULL REFERENCES sessions ON DELETE CASCADE,
+	oidc_client_id UUID NOT NULL REFERENCES oidc_clients ON DELETE CASCADE,
+	PRIMARY KEY (session_id, oidc_client_id)
+);
+
+CREATE TABLE backchannel_logout_notifications (
+	id UUID PRIMARY KEY,
+	oidc_client_id UUID NOT NULL REFERENCES oidc_clients ON DELETE CASCADE,
+	principal_id UUID NOT NULL REFERENCES principals ON DELETE CASCADE,
+	attempts INTEGER NOT NULL,
+	next_attempt_at TIMESTAMPTZ NOT NULL,
+	valid_before TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX backchannel_logout_notifications_next_attempt_at ON backchannel_logout_notifications (next_attempt_at);
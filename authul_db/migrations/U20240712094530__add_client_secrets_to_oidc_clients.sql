@@ -0,0 +1,11 @@
+CREATE TYPE client_auth_method AS ENUM ('private_key_jwt', 'client_secret_basic', 'client_secret_post');
+
+ALTER TABLE oidc_clients ALTER COLUMN jwks_uri DROP NOT NULL;
+ALTER TABLE oidc_clients ADD COLUMN token_endpoint_auth_method client_auth_method NOT NULL DEFAULT 'private_key_jwt';
+ALTER TABLE oidc_clients ADD COLUMN client_secret_hash TEXT;
+ALTER TABLE oidc_clients ADD CONSTRAINT oidc_clients_auth_credentials CHECK (
+	CASE token_endpoint_auth_method
+		WHEN 'private_key_jwt' THEN jwks_uri IS NOT NULL
+		ELSE client_secret_hash IS NOT NULL
+	END
+);
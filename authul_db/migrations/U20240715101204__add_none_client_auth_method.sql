@@ -0,0 +1 @@
+ALTER TYPE client_auth_method ADD VALUE 'none';
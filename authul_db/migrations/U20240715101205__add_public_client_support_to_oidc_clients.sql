@@ -0,0 +1,10 @@
+ALTER TABLE oidc_clients ADD COLUMN allowed_origins TEXT[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE oidc_clients DROP CONSTRAINT oidc_clients_auth_credentials;
+ALTER TABLE oidc_clients ADD CONSTRAINT oidc_clients_auth_credentials CHECK (
+	CASE token_endpoint_auth_method
+		WHEN 'private_key_jwt' THEN jwks_uri IS NOT NULL
+		WHEN 'none' THEN client_secret_hash IS NULL
+		ELSE client_secret_hash IS NOT NULL
+	END
+);
@@ -0,0 +1 @@
+ALTER TABLE oidc_clients ADD COLUMN allowed_scopes TEXT[] NOT NULL DEFAULT '{}';
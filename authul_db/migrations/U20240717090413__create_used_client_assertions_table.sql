@@ -0,0 +1,9 @@
+CREATE TABLE used_client_assertions (
+	id UUID PRIMARY KEY,
+	oidc_client_id UUID NOT NULL REFERENCES oidc_clients ON DELETE CASCADE,
+	jti TEXT NOT NULL,
+	valid_before TIMESTAMPTZ NOT NULL,
+	UNIQUE (oidc_client_id, jti)
+);
+
+CREATE INDEX used_client_assertions_valid_before ON used_client_assertions (valid_before);
@@ -0,0 +1,12 @@
+CREATE TABLE device_codes (
+	id UUID PRIMARY KEY,
+	oidc_client_id UUID NOT NULL REFERENCES oidc_clients ON DELETE CASCADE,
+	user_code TEXT NOT NULL UNIQUE,
+	token TEXT,
+	access_token TEXT,
+	refresh_token_id UUID REFERENCES refresh_tokens ON DELETE SET NULL,
+	last_polled_at TIMESTAMPTZ NOT NULL,
+	valid_before TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX device_codes_oidc_client_id ON device_codes (oidc_client_id);
@@ -0,0 +1 @@
+ALTER TABLE oidc_clients ADD COLUMN require_pushed_authorization_requests BOOLEAN NOT NULL DEFAULT false;
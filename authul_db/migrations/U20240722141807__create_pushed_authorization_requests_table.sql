@@ -0,0 +1,8 @@
+CREATE TABLE pushed_authorization_requests (
+	id UUID PRIMARY KEY,
+	oidc_client_id UUID NOT NULL REFERENCES oidc_clients ON DELETE CASCADE,
+	params TEXT NOT NULL,
+	valid_before TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX pushed_authorization_requests_valid_before ON pushed_authorization_requests (valid_before);
@@ -0,0 +1,9 @@
+CREATE TABLE used_dpop_proofs (
+	id UUID PRIMARY KEY,
+	jkt TEXT NOT NULL,
+	jti TEXT NOT NULL,
+	valid_before TIMESTAMPTZ NOT NULL,
+	UNIQUE (jkt, jti)
+);
+
+CREATE INDEX used_dpop_proofs_valid_before ON used_dpop_proofs (valid_before);
@@ -0,0 +1,4 @@
+CREATE TABLE initial_access_tokens (
+	id UUID PRIMARY KEY,
+	description TEXT NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE oidc_clients ADD COLUMN registration_access_token_hash TEXT;
@@ -0,0 +1 @@
+ALTER TABLE oidc_clients ADD COLUMN include_attrs_claim BOOLEAN NOT NULL DEFAULT false;
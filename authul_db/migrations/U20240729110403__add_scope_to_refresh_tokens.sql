@@ -0,0 +1,2 @@
+-- Until now, openid was the only scope there was to be granted
+ALTER TABLE refresh_tokens ADD COLUMN scope TEXT NOT NULL DEFAULT 'openid';
@@ -0,0 +1 @@
+ALTER TABLE device_codes ADD COLUMN scope TEXT NOT NULL DEFAULT 'openid';
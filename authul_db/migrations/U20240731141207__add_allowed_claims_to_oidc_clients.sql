@@ -0,0 +1,2 @@
+-- NULL means the client may be told anything it asks for, which is how it has always been
+ALTER TABLE oidc_clients ADD COLUMN allowed_claims TEXT[];
@@ -0,0 +1,2 @@
+ALTER TABLE refresh_tokens ADD COLUMN id_token_claims TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE refresh_tokens ADD COLUMN userinfo_claims TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,2 @@
+-- NULL means the client gets the principal's ID as its sub, as every client always has
+ALTER TABLE oidc_clients ADD COLUMN sector_identifier TEXT;
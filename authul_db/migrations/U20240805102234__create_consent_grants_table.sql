@@ -0,0 +1,8 @@
+CREATE TABLE consent_grants (
+	id UUID PRIMARY KEY,
+	principal_id UUID NOT NULL REFERENCES principals ON DELETE CASCADE,
+	oidc_client_id UUID NOT NULL REFERENCES oidc_clients ON DELETE CASCADE,
+	scopes TEXT[] NOT NULL,
+	claims TEXT[] NOT NULL,
+	UNIQUE (principal_id, oidc_client_id)
+);
@@ -0,0 +1,5 @@
+CREATE TABLE server_secrets (
+	id UUID PRIMARY KEY,
+	name TEXT NOT NULL UNIQUE,
+	secret BYTEA NOT NULL
+);
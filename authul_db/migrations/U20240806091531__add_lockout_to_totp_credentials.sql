@@ -0,0 +1,2 @@
+ALTER TABLE totp_credentials ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE totp_credentials ADD COLUMN locked_until TIMESTAMPTZ;
@@ -0,0 +1,4 @@
+-- Initial access tokens used to be kept as they were, as the primary key.  There's no hashing the
+-- ones already issued after the fact, so they have to go, and be issued again.
+DELETE FROM initial_access_tokens;
+ALTER TABLE initial_access_tokens ADD COLUMN secret_hash TEXT NOT NULL;
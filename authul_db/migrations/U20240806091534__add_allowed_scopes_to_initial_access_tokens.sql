@@ -0,0 +1 @@
+ALTER TABLE initial_access_tokens ADD COLUMN allowed_scopes TEXT[] NOT NULL DEFAULT '{}';
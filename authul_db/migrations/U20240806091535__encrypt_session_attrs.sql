@@ -0,0 +1,3 @@
+-- Session attributes used to be stored as they were.  They're encrypted now, and there's no
+-- encrypting the existing ones from in here, so those sessions end, and their users log in again.
+DELETE FROM sessions;
@@ -0,0 +1,4 @@
+-- Refresh token attributes used to be stored as they were.  They're encrypted now, and there's no
+-- encrypting the existing ones from in here, so those tokens go, and their clients have to send
+-- the user through authentication again.
+DELETE FROM refresh_tokens;
@@ -0,0 +1,9 @@
+CREATE TABLE used_request_objects (
+	id UUID PRIMARY KEY,
+	oidc_client_id UUID NOT NULL REFERENCES oidc_clients ON DELETE CASCADE,
+	jti TEXT NOT NULL,
+	valid_before TIMESTAMPTZ NOT NULL,
+	UNIQUE (oidc_client_id, jti)
+);
+
+CREATE INDEX used_request_objects_valid_before ON used_request_objects (valid_before);
@@ -0,0 +1,5 @@
+CREATE TABLE recovery_code_attempts (
+	principal_id UUID PRIMARY KEY REFERENCES principals ON DELETE CASCADE,
+	failed_attempts INTEGER NOT NULL DEFAULT 0,
+	locked_until TIMESTAMPTZ
+);
@@ -85,4 +85,13 @@ impl Error {
 			_ => false,
 		}
 	}
+
+	pub fn is_unique_violation(&self) -> bool {
+		match self {
+			Self::Postgres(e, _) => {
+				e.code() == Some(&tokio_postgres::error::SqlState::UNIQUE_VIOLATION)
+			}
+			_ => false,
+		}
+	}
 }
@@ -0,0 +1,73 @@
+use std::time::Duration;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{Error, OidcClient, Principal};
+use authul_macros::authul_table;
+
+const ONE_DAY: Duration = Duration::from_secs(86400);
+
+// A client that needs to be told that a principal has logged out, and hasn't been told yet
+#[authul_table]
+#[derive(Debug)]
+pub struct BackchannelLogoutNotification {
+	id: Uuid,
+	#[relation(belongs_to)]
+	oidc_client: OidcClient,
+	#[relation(belongs_to)]
+	principal: Principal,
+	#[column(default(0))]
+	attempts: i32,
+	#[column(default(OffsetDateTime::now_utc()))]
+	next_attempt_at: OffsetDateTime,
+	// If the client hasn't been listening for this long, it's not going to start now
+	#[column(default(OffsetDateTime::now_utc() + ONE_DAY))]
+	valid_before: OffsetDateTime,
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn delete_expired(&self) -> Result<(), Error> {
+		let sql = "DELETE FROM backchannel_logout_notifications WHERE valid_before <= NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[]).await?;
+		self.execute(&stmt, &[]).await?;
+		Ok(())
+	}
+
+	/// Take every notification that is due to be sent.
+	///
+	/// Each one is rescheduled (with exponential backoff, up to an hour) in the same statement, so
+	/// if sending it fails, it'll be tried again later without anyone having to do anything, and if
+	/// it succeeds, the caller should delete it.  Notifications that are already being worked on
+	/// by someone else are left alone.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn claim_due(&self) -> Result<Vec<BackchannelLogoutNotification>, Error> {
+		let sql = "UPDATE backchannel_logout_notifications SET attempts=attempts+1, next_attempt_at=NOW() + make_interval(mins => LEAST(POWER(2, attempts), 60)::INTEGER) WHERE id IN (SELECT id FROM backchannel_logout_notifications WHERE next_attempt_at <= NOW() AND valid_before > NOW() FOR UPDATE SKIP LOCKED) RETURNING *";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[]).await?;
+
+		let mut notifications = Vec::new();
+		for row in self.query(&stmt, &[]).await? {
+			let oidc_client = self
+				.conn
+				.oidc_client()
+				.find(&row.get::<_, Uuid>("oidc_client_id"))
+				.await?;
+			let principal = self
+				.conn
+				.principal()
+				.find(&row.get::<_, Uuid>("principal_id"))
+				.await?;
+			notifications.push(BackchannelLogoutNotification::from_row(
+				&row,
+				oidc_client,
+				principal,
+			)?);
+		}
+
+		Ok(notifications)
+	}
+}
@@ -0,0 +1,85 @@
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::{Error, OidcClient, Principal};
+use authul_macros::authul_table;
+
+// What a principal has agreed to let a client know about them, so that they aren't asked again
+// every time they sign in to it
+#[authul_table]
+#[derive(Debug)]
+pub struct ConsentGrant {
+	id: Uuid,
+	#[relation(belongs_to)]
+	principal: Principal,
+	#[relation(belongs_to)]
+	oidc_client: OidcClient,
+	scopes: Vec<String>,
+	claims: Vec<String>,
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	/// Whether the principal has already agreed to the client having all of the given scopes and
+	/// claims
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn covers(
+		&self,
+		principal_id: &Uuid,
+		oidc_client_id: &Uuid,
+		scopes: &[String],
+		claims: &[String],
+	) -> Result<bool, Error> {
+		let sql = "SELECT EXISTS (SELECT 1 FROM consent_grants WHERE principal_id=$1 AND oidc_client_id=$2 AND scopes @> $3 AND claims @> $4)";
+		tracing::debug!(sql);
+		let stmt = self
+			.prepare_typed_cached(
+				sql,
+				&[Type::UUID, Type::UUID, Type::TEXT_ARRAY, Type::TEXT_ARRAY],
+			)
+			.await?;
+
+		Ok(self
+			.query_one(&stmt, &[principal_id, oidc_client_id, &scopes, &claims])
+			.await?
+			.get(0))
+	}
+
+	/// Record that the principal has agreed to the client having the given scopes and claims, on
+	/// top of whatever they'd already agreed to.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn grant(
+		&self,
+		principal_id: &Uuid,
+		oidc_client_id: &Uuid,
+		scopes: &[String],
+		claims: &[String],
+	) -> Result<(), Error> {
+		let sql = "INSERT INTO consent_grants (id, principal_id, oidc_client_id, scopes, claims) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (principal_id, oidc_client_id) DO UPDATE SET scopes=ARRAY(SELECT DISTINCT unnest(consent_grants.scopes || EXCLUDED.scopes)), claims=ARRAY(SELECT DISTINCT unnest(consent_grants.claims || EXCLUDED.claims))";
+		tracing::debug!(sql);
+		let stmt = self
+			.prepare_typed_cached(
+				sql,
+				&[
+					Type::UUID,
+					Type::UUID,
+					Type::UUID,
+					Type::TEXT_ARRAY,
+					Type::TEXT_ARRAY,
+				],
+			)
+			.await?;
+
+		self.execute(
+			&stmt,
+			&[
+				&Uuid::now_v7(),
+				principal_id,
+				oidc_client_id,
+				&scopes,
+				&claims,
+			],
+		)
+		.await?;
+		Ok(())
+	}
+}
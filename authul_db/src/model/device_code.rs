@@ -0,0 +1,109 @@
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::{Error, OidcClient};
+use authul_macros::authul_table;
+
+const TEN_MINUTES: Duration = Duration::from_secs(600);
+
+// A device authorization grant (RFC8628), which sits here while the user goes off to another
+// device to sign in, and the device that asked for it keeps checking whether they've done so yet
+#[authul_table]
+#[derive(Debug)]
+pub struct DeviceCode {
+	// This is what the device gets as the device_code, so it had better not be guessable
+	#[column(v4_uuid)]
+	id: Uuid,
+	#[relation(belongs_to)]
+	oidc_client: OidcClient,
+	// What the user types in to say which device they're signing in
+	user_code: String,
+	// Space-separated, as granted when the device asked for the code
+	#[column(default("openid".to_string()))]
+	scope: String,
+	// The tokens that were issued once the user signed in, waiting to be collected
+	token: Option<String>,
+	access_token: Option<String>,
+	refresh_token_id: Option<Uuid>,
+	// So we can tell a device that's polling too often to back off
+	#[column(default(OffsetDateTime::now_utc()))]
+	last_polled_at: OffsetDateTime,
+	#[column(default(OffsetDateTime::now_utc() + TEN_MINUTES))]
+	valid_before: OffsetDateTime,
+}
+
+impl DeviceCode {
+	pub fn is_expired(&self) -> bool {
+		self.valid_before < OffsetDateTime::now_utc()
+	}
+
+	pub fn is_approved(&self) -> bool {
+		self.token.is_some()
+	}
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn delete_expired(&self) -> Result<(), Error> {
+		let sql = "DELETE FROM device_codes WHERE valid_before <= NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[]).await?;
+		self.execute(&stmt, &[]).await?;
+		Ok(())
+	}
+
+	/// The device code that the user has been asked to enter the given user code for, as long as
+	/// it's still waiting for them to do so
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn find_pending_by_user_code(
+		&self,
+		user_code: &str,
+	) -> Result<Option<DeviceCode>, Error> {
+		let sql = "SELECT id FROM device_codes WHERE user_code=$1 AND token IS NULL AND valid_before > NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[Type::TEXT]).await?;
+		match self.query_opt(&stmt, &[&user_code]).await? {
+			Some(row) => Ok(Some(self.find(&row.get::<_, Uuid>(0)).await?)),
+			None => Ok(None),
+		}
+	}
+
+	/// Hand over the tokens issued to the user, for the device to collect.  Returns `false` if the
+	/// device code has expired, or has already been approved, in which case nobody is going to
+	/// collect them.
+	#[tracing::instrument(level = "debug", skip(self, token, access_token))]
+	pub async fn approve(
+		&self,
+		id: &Uuid,
+		token: &str,
+		access_token: &str,
+		refresh_token_id: &Uuid,
+	) -> Result<bool, Error> {
+		let sql = "UPDATE device_codes SET token=$2, access_token=$3, refresh_token_id=$4 WHERE id=$1 AND token IS NULL AND valid_before > NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self
+			.prepare_typed_cached(sql, &[Type::UUID, Type::TEXT, Type::TEXT, Type::UUID])
+			.await?;
+		Ok(self
+			.execute(&stmt, &[id, &token, &access_token, refresh_token_id])
+			.await? == 1)
+	}
+
+	/// Note that the device has come asking about its device code.  Returns `false` if it last
+	/// asked less than `interval` ago, in which case it needs to be told to slow down.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn mark_polled(&self, id: &Uuid, interval: Duration) -> Result<bool, Error> {
+		let sql = "UPDATE device_codes SET last_polled_at=NOW() WHERE id=$1 AND last_polled_at <= NOW() - make_interval(secs => $2)";
+		tracing::debug!(sql);
+
+		let stmt = self
+			.prepare_typed_cached(sql, &[Type::UUID, Type::FLOAT8])
+			.await?;
+		Ok(self.execute(&stmt, &[id, &interval.as_secs_f64()]).await? == 1)
+	}
+}
@@ -0,0 +1,22 @@
+use uuid::Uuid;
+
+use authul_macros::authul_table;
+
+// A token that lets whoever holds it register new clients at the dynamic client registration
+// endpoint (RFC7591)
+#[authul_table]
+#[derive(Debug)]
+pub struct InitialAccessToken {
+	// The token is this ID and a secret, separated by a `.`; the ID is how the token gets found, but
+	// the secret is what makes it any good, so only a (bcrypt) hash of that is kept
+	#[column(v4_uuid)]
+	id: Uuid,
+	secret_hash: String,
+	// Whatever the token was issued for, so that whoever comes across it later knows what will
+	// break if it is revoked
+	description: String,
+	// The scopes that clients registered with the token can be allowed to ask for on their own
+	// behalf; a client can't register itself for anything beyond these
+	#[column(default(Vec::new()))]
+	allowed_scopes: Vec<String>,
+}
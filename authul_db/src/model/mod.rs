@@ -1,17 +1,51 @@
+pub mod backchannel_logout_notification;
+pub mod consent_grant;
+pub mod device_code;
+pub mod initial_access_token;
 pub mod oauth_callback_state;
 pub mod oauth_identity;
 pub mod oidc_client;
 pub mod oidc_token;
+pub mod password_reset_token;
 pub mod principal;
+pub mod pushed_authorization_request;
+pub mod recovery_code;
+pub mod refresh_token;
+pub mod revoked_access_token;
+pub mod server_secret;
+pub mod session;
 pub mod signing_key;
+pub mod totp_credential;
+pub mod used_client_assertion;
+pub mod used_dpop_proof;
+pub mod used_request_object;
 pub mod user;
+pub mod webauthn_challenge;
+pub mod webauthn_credential;
 
+pub use backchannel_logout_notification::BackchannelLogoutNotification;
+pub use consent_grant::ConsentGrant;
+pub use device_code::DeviceCode;
+pub use initial_access_token::InitialAccessToken;
 pub use oauth_callback_state::OAuthCallbackState;
 pub use oauth_identity::OAuthIdentity;
 pub use oidc_client::OidcClient;
 pub use oidc_token::OidcToken;
+pub use password_reset_token::PasswordResetToken;
 pub use principal::Principal;
+pub use pushed_authorization_request::PushedAuthorizationRequest;
+pub use recovery_code::RecoveryCode;
+pub use refresh_token::RefreshToken;
+pub use revoked_access_token::RevokedAccessToken;
+pub use server_secret::ServerSecret;
+pub use session::Session;
 pub use signing_key::SigningKey;
+pub use totp_credential::TotpCredential;
+pub use used_client_assertion::UsedClientAssertion;
+pub use used_dpop_proof::UsedDpopProof;
+pub use used_request_object::UsedRequestObject;
 pub use user::User;
+pub use webauthn_challenge::WebauthnChallenge;
+pub use webauthn_credential::WebauthnCredential;
 
 use super::{types, Error};
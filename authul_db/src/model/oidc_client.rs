@@ -1,5 +1,11 @@
+use tokio_postgres::types::Type;
+use url::{Host, Url};
 use uuid::Uuid;
 
+use super::{
+	types::{ClientAuthMethod, InvalidRegistration},
+	Error,
+};
 use authul_macros::authul_table;
 
 #[authul_table]
@@ -11,12 +17,197 @@ pub struct OidcClient {
 	id: Uuid,
 	name: String,
 	redirect_uris: Vec<String>,
-	jwks_uri: String,
+	// Only clients that authenticate with private_key_jwt need one of these
+	jwks_uri: Option<String>,
 	token_forward_jwk_uri: Option<String>,
+	#[column(default(false))]
+	require_mfa: bool,
+	// Where the client is allowed to have the user sent once they've logged out
+	#[column(default(Vec::new()))]
+	post_logout_redirect_uris: Vec<String>,
+	// Where to send logout tokens when a principal who has been issued tokens for this client logs
+	// out
+	backchannel_logout_uri: Option<String>,
+	#[column(default(ClientAuthMethod::PrivateKeyJwt))]
+	token_endpoint_auth_method: ClientAuthMethod,
+	// bcrypt hash of the client's secret, if it authenticates with one
+	client_secret_hash: Option<String>,
+	// Web origins (scheme://host[:port]) that browser-based clients are allowed to call us from
+	#[column(default(Vec::new()))]
+	allowed_origins: Vec<String>,
+	// What the client can ask for access to on its own behalf, with the client credentials grant
+	#[column(default(Vec::new()))]
+	allowed_scopes: Vec<String>,
+	// Whether the client has to push its authorization requests to us (RFC9126) rather than
+	// sending them via the user's browser
+	#[column(default(false))]
+	require_pushed_authorization_requests: bool,
+	// bcrypt hash of the token the client can use to manage its own registration, if it was
+	// registered through the dynamic client registration endpoint
+	registration_access_token_hash: Option<String>,
+	// Whether the client still gets all of the principal's identity attributes in our own `attrs`
+	// claim, from before we handed out the standard claims
+	#[column(default(false))]
+	include_attrs_claim: bool,
+	// The only standard claims and identity attribute kinds the client may ever be told about a
+	// principal, whatever it asks for; NULL means there's no limit
+	allowed_claims: Option<Vec<String>>,
+	// The host that pairwise subject identifiers (OIDC Core 1.0 s8.1) are worked out for, so that
+	// the client can't link its users to those of clients in other sectors; NULL means the client
+	// gets the principal's ID as its sub
+	sector_identifier: Option<String>,
+	// First-party clients get whatever they ask for about a principal, without having to ask the
+	// principal first
+	#[column(default(false))]
+	skip_consent: bool,
 }
 
 impl OidcClient {
 	pub fn has_redirect_uri(&self, uri: impl AsRef<str>) -> bool {
-		self.redirect_uris.iter().any(|u| u == uri.as_ref())
+		let uri = uri.as_ref();
+
+		self.redirect_uris.iter().any(|u| {
+			u == uri
+				|| (self.is_public()
+					&& matches!(
+						(without_loopback_port(u), without_loopback_port(uri)),
+						(Some(registered), Some(requested)) if registered == requested
+					))
+		})
+	}
+
+	/// Whether the client is a public client (a browser or native app), which can't keep a
+	/// secret, and so can't authenticate itself to us
+	pub fn is_public(&self) -> bool {
+		self.token_endpoint_auth_method == ClientAuthMethod::None
+	}
+
+	pub fn has_allowed_origin(&self, origin: impl AsRef<str>) -> bool {
+		self.allowed_origins.iter().any(|o| o == origin.as_ref())
+	}
+
+	/// Whether a public client should be allowed to register the given redirect URI.
+	///
+	/// Since anyone can pretend to be a public client, we have to be careful about where we send
+	/// codes for them.  As per RFC8252, native apps get loopback IP addresses (on any port) or a
+	/// private-use URI scheme named after a domain, and browser-based apps can use HTTPS URLs on
+	/// one of the origins they're registered to call us from.
+	pub fn is_acceptable_public_redirect_uri(
+		uri: impl AsRef<str>,
+		allowed_origins: &[impl AsRef<str>],
+	) -> bool {
+		let Ok(url) = Url::parse(uri.as_ref()) else {
+			return false;
+		};
+
+		match url.scheme() {
+			"http" => is_loopback(&url),
+			"https" => allowed_origins
+				.iter()
+				.any(|o| o.as_ref() == url.origin().ascii_serialization()),
+			// RFC8252 s7.1
+			scheme => scheme.contains('.'),
+		}
+	}
+
+	/// Check that a client's registration hangs together, whether it's being done from the
+	/// command line or by the client itself.
+	pub fn check_registration(
+		token_endpoint_auth_method: ClientAuthMethod,
+		redirect_uris: &[impl AsRef<str>],
+		allowed_origins: &[impl AsRef<str>],
+		jwks_uri: Option<&str>,
+	) -> Result<(), InvalidRegistration> {
+		if redirect_uris.is_empty() {
+			return Err(InvalidRegistration::NoRedirectUris);
+		}
+
+		if token_endpoint_auth_method == ClientAuthMethod::PrivateKeyJwt && jwks_uri.is_none() {
+			return Err(InvalidRegistration::NoJwksUri);
+		}
+
+		if token_endpoint_auth_method == ClientAuthMethod::None {
+			if let Some(uri) = redirect_uris
+				.iter()
+				.find(|u| !Self::is_acceptable_public_redirect_uri(u, allowed_origins))
+			{
+				return Err(InvalidRegistration::PublicRedirectUri(
+					uri.as_ref().to_string(),
+				));
+			}
+		}
+
+		Ok(())
+	}
+
+	/// The sector a client with the given redirect URIs belongs to, for pairwise subject
+	/// identifiers, which is the host they all share (OIDC Core 1.0 s8.1).
+	pub fn sector_identifier_for(
+		redirect_uris: &[impl AsRef<str>],
+	) -> Result<String, InvalidRegistration> {
+		let mut hosts = redirect_uris.iter().map(|u| {
+			Url::parse(u.as_ref())
+				.ok()
+				.and_then(|u| u.host_str().map(str::to_string))
+		});
+
+		let Some(Some(host)) = hosts.next() else {
+			return Err(InvalidRegistration::NoSectorIdentifier);
+		};
+		if hosts.any(|h| h.as_ref() != Some(&host)) {
+			return Err(InvalidRegistration::NoSectorIdentifier);
+		}
+
+		Ok(host)
+	}
+
+	pub fn has_allowed_scope(&self, scope: impl AsRef<str>) -> bool {
+		self.allowed_scopes.iter().any(|s| s == scope.as_ref())
+	}
+
+	/// Whether the client may be given the named claim, or identity attribute kind, about a
+	/// principal
+	pub fn may_release(&self, claim: impl AsRef<str>) -> bool {
+		match &self.allowed_claims {
+			None => true,
+			Some(allowed) => allowed.iter().any(|c| c == claim.as_ref()),
+		}
+	}
+
+	pub fn has_post_logout_redirect_uri(&self, uri: impl AsRef<str>) -> bool {
+		self.post_logout_redirect_uris
+			.iter()
+			.any(|u| u == uri.as_ref())
+	}
+}
+
+fn is_loopback(url: &Url) -> bool {
+	match url.host() {
+		Some(Host::Ipv4(ip)) => ip.is_loopback(),
+		Some(Host::Ipv6(ip)) => ip.is_loopback(),
+		_ => false,
+	}
+}
+
+// RFC8252 s7.3 says that a native app has to be able to use whatever port it can get on the
+// loopback interface, so loopback redirect URIs are compared without their port
+fn without_loopback_port(uri: &str) -> Option<Url> {
+	let mut url = Url::parse(uri).ok()?;
+	if url.scheme() != "http" || !is_loopback(&url) {
+		return None;
+	}
+	url.set_port(None).ok()?;
+	Some(url)
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	/// Whether any client is allowed to make cross-origin requests from the given origin
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn origin_is_registered(&self, origin: &str) -> Result<bool, Error> {
+		let sql = "SELECT EXISTS (SELECT 1 FROM oidc_clients WHERE $1 = ANY(allowed_origins))";
+		tracing::debug!(sql);
+		let stmt = self.prepare_typed_cached(sql, &[Type::TEXT]).await?;
+
+		Ok(self.query_one(&stmt, &[&origin]).await?.get(0))
 	}
 }
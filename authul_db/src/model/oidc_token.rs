@@ -15,8 +15,11 @@ pub struct OidcToken {
 	#[relation(belongs_to)]
 	oidc_client: OidcClient,
 	token: String,
+	access_token: String,
 	redirect_uri: String,
 	code_challenge: String,
+	// The refresh token that goes along with the ID token, if one was issued
+	refresh_token_id: Option<Uuid>,
 	#[column(default(OffsetDateTime::now_utc() + ONE_MINUTE))]
 	valid_before: OffsetDateTime,
 }
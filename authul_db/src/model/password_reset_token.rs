@@ -0,0 +1,68 @@
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::{Error, User};
+use authul_macros::authul_table;
+
+const ONE_HOUR: Duration = Duration::from_secs(3600);
+
+#[authul_table]
+#[derive(Debug)]
+pub struct PasswordResetToken {
+	#[column(v4_uuid)]
+	id: Uuid,
+	#[relation(belongs_to)]
+	user: User,
+	#[column(default(OffsetDateTime::now_utc() + ONE_HOUR))]
+	valid_before: OffsetDateTime,
+}
+
+impl PasswordResetToken {
+	pub fn is_expired(&self) -> bool {
+		self.valid_before < OffsetDateTime::now_utc()
+	}
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn delete_expired(&self) -> Result<(), Error> {
+		let sql = "DELETE FROM password_reset_tokens WHERE valid_before <= NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[]).await?;
+		self.execute(&stmt, &[]).await?;
+		Ok(())
+	}
+
+	/// Use up an unexpired token, returning the ID of the user it was issued to.
+	///
+	/// Since the token is removed in the same statement that checks for it, two concurrent
+	/// attempts to redeem the same token cannot both succeed.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn redeem(&self, id: &Uuid) -> Result<Uuid, Error> {
+		let sql = "DELETE FROM password_reset_tokens WHERE id=$1 AND valid_before > NOW() RETURNING user_id";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+		let row = self
+			.query_opt(&stmt, &[id])
+			.await?
+			.ok_or_else(|| Error::not_found("password_reset_tokens", "id", id.to_string()))?;
+
+		Ok(row.get("user_id"))
+	}
+
+	/// Remove every outstanding token for the given user, such as once they've changed their
+	/// password and any other links we sent them are no longer needed.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn delete_all_for_user(&self, user_id: &Uuid) -> Result<(), Error> {
+		let sql = "DELETE FROM password_reset_tokens WHERE user_id=$1";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+		self.execute(&stmt, &[user_id]).await?;
+		Ok(())
+	}
+}
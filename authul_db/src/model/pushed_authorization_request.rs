@@ -0,0 +1,61 @@
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::{Error, OidcClient};
+use authul_macros::authul_table;
+
+const NINETY_SECONDS: Duration = Duration::from_secs(90);
+
+// An authorization request that a client has sent us directly (RFC9126), rather than putting all
+// the params in the URL it sends the user's browser to
+#[authul_table]
+#[derive(Debug)]
+pub struct PushedAuthorizationRequest {
+	// This ends up in the request_uri, which goes through the user's browser, so it had better not
+	// be guessable
+	#[column(v4_uuid)]
+	id: Uuid,
+	#[relation(belongs_to)]
+	oidc_client: OidcClient,
+	// The authorization request params, form-urlencoded
+	params: String,
+	// RFC9126 s2.2 recommends the request_uri be short-lived, since it only has to last as long as
+	// it takes the client to redirect the user to us
+	#[column(default(OffsetDateTime::now_utc() + NINETY_SECONDS))]
+	valid_before: OffsetDateTime,
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn delete_expired(&self) -> Result<(), Error> {
+		let sql = "DELETE FROM pushed_authorization_requests WHERE valid_before <= NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[]).await?;
+		self.execute(&stmt, &[]).await?;
+		Ok(())
+	}
+
+	/// Remove the pushed request with the given ID, and return its params, as long as it was made
+	/// by the given client and hasn't expired.  A request can only be used once, so anyone who
+	/// gets hold of the request_uri after the user has been sent on their way gets nothing.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn take_valid(
+		&self,
+		id: &Uuid,
+		oidc_client_id: &Uuid,
+	) -> Result<Option<String>, Error> {
+		let sql = "DELETE FROM pushed_authorization_requests WHERE id=$1 AND oidc_client_id=$2 AND valid_before > NOW() RETURNING params";
+		tracing::debug!(sql);
+
+		let stmt = self
+			.prepare_typed_cached(sql, &[Type::UUID, Type::UUID])
+			.await?;
+		Ok(self
+			.query_opt(&stmt, &[id, oidc_client_id])
+			.await?
+			.map(|row| row.get(0)))
+	}
+}
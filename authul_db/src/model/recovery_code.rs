@@ -0,0 +1,110 @@
+use time::OffsetDateTime;
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::{Error, Principal};
+use authul_macros::authul_table;
+
+#[authul_table]
+#[derive(Debug)]
+pub struct RecoveryCode {
+	id: Uuid,
+	#[relation(belongs_to)]
+	principal: Principal,
+	// The code itself is only ever shown to the user, once; this is a hash of it
+	code_hash: Vec<u8>,
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	/// How many unused recovery codes the principal has left
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn count_by_principal_id(&self, id: &Uuid) -> Result<i64, Error> {
+		let sql = "SELECT COUNT(*) FROM recovery_codes WHERE principal_id=$1";
+		tracing::debug!(sql);
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+
+		Ok(self.query_one(&stmt, &[id]).await?.get(0))
+	}
+
+	/// Use up one of the principal's recovery codes, returning whether it was there to be used.
+	#[tracing::instrument(level = "debug", skip(self, code_hash))]
+	pub async fn redeem(&self, principal_id: &Uuid, code_hash: &[u8]) -> Result<bool, Error> {
+		let sql = "DELETE FROM recovery_codes WHERE principal_id=$1 AND code_hash=$2";
+		tracing::debug!(sql);
+		let stmt = self
+			.prepare_typed_cached(sql, &[Type::UUID, Type::BYTEA])
+			.await?;
+
+		Ok(self.execute(&stmt, &[principal_id, &code_hash]).await? == 1)
+	}
+
+	/// Count an attempt at entering one of the principal's codes, before it is checked, the same
+	/// way as for TOTP credentials.  A principal's codes are all tried at once, so the count is
+	/// kept for the principal, rather than for each code.  Once `max_attempts` have been made
+	/// without a right code (which resets the count, in `reset_attempts`), codes can't be used
+	/// until `locked_until`, and each attempt after that locks them again.
+	///
+	/// Returns `false` if the principal's codes are locked, in which case the code mustn't be
+	/// checked.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn start_attempt(
+		&self,
+		principal_id: &Uuid,
+		max_attempts: i32,
+		locked_until: OffsetDateTime,
+	) -> Result<bool, Error> {
+		let sql = "INSERT INTO recovery_code_attempts (principal_id, failed_attempts, locked_until) VALUES ($1, 1, CASE WHEN 1 >= $2 THEN $3 ELSE NULL END) ON CONFLICT (principal_id) DO UPDATE SET failed_attempts=recovery_code_attempts.failed_attempts + 1, locked_until=CASE WHEN recovery_code_attempts.failed_attempts + 1 >= $2 THEN $3 ELSE NULL END WHERE recovery_code_attempts.locked_until IS NULL OR recovery_code_attempts.locked_until <= NOW()";
+		tracing::debug!(sql);
+		let stmt = self
+			.prepare_typed_cached(sql, &[Type::UUID, Type::INT4, Type::TIMESTAMPTZ])
+			.await?;
+
+		Ok(self
+			.execute(&stmt, &[principal_id, &max_attempts, &locked_until])
+			.await? == 1)
+	}
+
+	/// Forget about any wrong codes the principal entered before getting one right.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn reset_attempts(&self, principal_id: &Uuid) -> Result<(), Error> {
+		let sql = "DELETE FROM recovery_code_attempts WHERE principal_id=$1";
+		tracing::debug!(sql);
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+
+		self.execute(&stmt, &[principal_id]).await?;
+		Ok(())
+	}
+
+	/// Get rid of all of the principal's recovery codes, returning how many there were.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn delete_all_by_principal_id(&self, id: &Uuid) -> Result<u64, Error> {
+		let sql = "DELETE FROM recovery_codes WHERE principal_id=$1";
+		tracing::debug!(sql);
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+
+		Ok(self.execute(&stmt, &[id]).await?)
+	}
+}
+
+impl Handle<deadpool_postgres::Client> {
+	/// Throw away whatever recovery codes the principal had, and give them a new set.
+	#[tracing::instrument(level = "debug", skip(self, code_hashes))]
+	pub async fn replace_all(
+		&mut self,
+		principal: &Principal,
+		code_hashes: impl IntoIterator<Item = Vec<u8>>,
+	) -> Result<(), Error> {
+		let txn = self.transaction().await?;
+
+		txn.delete_all_by_principal_id(principal.id()).await?;
+		for code_hash in code_hashes {
+			txn.new()
+				.with_principal(principal.clone())
+				.with_code_hash(code_hash)
+				.save()
+				.await?;
+		}
+
+		txn.commit().await
+	}
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::{Error, OidcClient, Principal};
+use authul_macros::authul_table;
+
+const THIRTY_DAYS: Duration = Duration::from_secs(30 * 86400);
+
+#[authul_table]
+#[derive(Debug)]
+pub struct RefreshToken {
+	// This is what the client gets as the refresh token, so it had better not be guessable
+	#[column(v4_uuid)]
+	id: Uuid,
+	// Every token that has been rotated out of the same original grant shares a family, so that
+	// they can all be revoked together if one of them gets replayed
+	#[column(default(Uuid::new_v4()))]
+	family_id: Uuid,
+	#[relation(belongs_to)]
+	oidc_client: OidcClient,
+	#[relation(belongs_to)]
+	principal: Principal,
+	// CBOR-encoded IdentityAttributes, as they were when the principal authenticated, encrypted
+	// like the session's copy (and carried over as-is when the token is rotated)
+	attrs: Vec<u8>,
+	auth_time: OffsetDateTime,
+	// Space-separated, as granted when the principal authenticated; refreshing doesn't get the
+	// client anything more
+	#[column(default("openid".to_string()))]
+	scope: String,
+	// The individual claims the client asked for with the `claims` request parameter, so that
+	// refreshed tokens carry the same ones
+	#[column(default(Vec::new()))]
+	id_token_claims: Vec<String>,
+	#[column(default(Vec::new()))]
+	userinfo_claims: Vec<String>,
+	// Set once the token has been exchanged; a used token is kept around until it expires, so that
+	// we can tell when someone tries to use it again
+	#[column(default(false))]
+	used: bool,
+	// The thumbprint of the key the token is bound to (RFC9449 s5), if the client made a DPoP
+	// proof when it was issued; every refresh after that has to come with a proof from that key
+	dpop_jkt: Option<String>,
+	#[column(default(OffsetDateTime::now_utc() + THIRTY_DAYS))]
+	valid_before: OffsetDateTime,
+}
+
+impl RefreshToken {
+	pub fn is_expired(&self) -> bool {
+		self.valid_before < OffsetDateTime::now_utc()
+	}
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn delete_expired(&self) -> Result<(), Error> {
+		let sql = "DELETE FROM refresh_tokens WHERE valid_before <= NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[]).await?;
+		self.execute(&stmt, &[]).await?;
+		Ok(())
+	}
+
+	/// Mark a token as having been exchanged, returning `false` if it had already been used.
+	///
+	/// Since the check and the update happen in the same statement, two concurrent attempts to
+	/// use the same token cannot both succeed.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn mark_used(&self, id: &Uuid) -> Result<bool, Error> {
+		let sql = "UPDATE refresh_tokens SET used=TRUE WHERE id=$1 AND NOT used";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+		Ok(self.execute(&stmt, &[id]).await? == 1)
+	}
+
+	/// Bind a token to the key with the given JWK thumbprint, once the client has shown that it
+	/// holds that key.  A token that is already bound stays bound to whatever key it was bound to.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn bind_dpop_key(&self, id: &Uuid, jkt: &str) -> Result<(), Error> {
+		let sql = "UPDATE refresh_tokens SET dpop_jkt=$2 WHERE id=$1 AND dpop_jkt IS NULL";
+		tracing::debug!(sql);
+
+		let stmt = self
+			.prepare_typed_cached(sql, &[Type::UUID, Type::TEXT])
+			.await?;
+		self.execute(&stmt, &[id, &jkt]).await?;
+		Ok(())
+	}
+
+	/// Remove every token in a family, used or not.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn revoke_family(&self, family_id: &Uuid) -> Result<(), Error> {
+		let sql = "DELETE FROM refresh_tokens WHERE family_id=$1";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+		self.execute(&stmt, &[family_id]).await?;
+		Ok(())
+	}
+}
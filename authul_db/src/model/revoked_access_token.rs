@@ -0,0 +1,52 @@
+use time::OffsetDateTime;
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::Error;
+use authul_macros::authul_table;
+
+// Access tokens are JWTs, and so are valid until they expire, unless we keep a note of the ones
+// that have been revoked
+#[authul_table]
+#[derive(Debug)]
+pub struct RevokedAccessToken {
+	// The jti of the access token that was revoked
+	id: Uuid,
+	// Once the access token has expired, nobody will accept it anyway, so we can forget about it
+	valid_before: OffsetDateTime,
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn delete_expired(&self) -> Result<(), Error> {
+		let sql = "DELETE FROM revoked_access_tokens WHERE valid_before <= NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[]).await?;
+		self.execute(&stmt, &[]).await?;
+		Ok(())
+	}
+
+	/// Record that the access token with the given `jti` is no longer to be accepted.  Revoking an
+	/// already-revoked token is fine.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn revoke(&self, jti: &Uuid, valid_before: &OffsetDateTime) -> Result<(), Error> {
+		let sql = "INSERT INTO revoked_access_tokens (id, valid_before) VALUES ($1, $2) ON CONFLICT (id) DO NOTHING";
+		tracing::debug!(sql);
+
+		let stmt = self
+			.prepare_typed_cached(sql, &[Type::UUID, Type::TIMESTAMPTZ])
+			.await?;
+		self.execute(&stmt, &[jti, valid_before]).await?;
+		Ok(())
+	}
+
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn is_revoked(&self, jti: &Uuid) -> Result<bool, Error> {
+		let sql = "SELECT 1 FROM revoked_access_tokens WHERE id=$1";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+		Ok(self.query_opt(&stmt, &[jti]).await?.is_some())
+	}
+}
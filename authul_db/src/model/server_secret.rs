@@ -0,0 +1,48 @@
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::Error;
+use authul_macros::authul_table;
+
+// A secret that has to stay the same for as long as the server is running, no matter how often
+// the root keys are rotated, and so is generated once and kept here (encrypted under the root
+// keys) rather than being derived from them
+#[authul_table]
+#[derive(Debug)]
+pub struct ServerSecret {
+	id: Uuid,
+	#[column(find_by)]
+	name: String,
+	secret: Vec<u8>,
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn all(&self) -> Result<Vec<ServerSecret>, Error> {
+		let sql = "SELECT * FROM server_secrets";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[]).await?;
+		Ok(self
+			.query(&stmt, &[])
+			.await?
+			.into_iter()
+			.map(|r| Ok::<ServerSecret, Error>(ServerSecret::from_row(&r)?))
+			.collect::<Result<Vec<_>, Error>>()?)
+	}
+
+	/// Store the secret under the given name, unless there's already one there, in which case
+	/// that one is kept, so that servers starting up together all end up with the same secret.
+	#[tracing::instrument(level = "debug", skip(self, secret))]
+	pub async fn insert_if_missing(&self, name: &str, secret: &[u8]) -> Result<(), Error> {
+		let sql = "INSERT INTO server_secrets (id, name, secret) VALUES ($1, $2, $3) ON CONFLICT (name) DO NOTHING";
+		tracing::debug!(sql);
+
+		let stmt = self
+			.prepare_typed_cached(sql, &[Type::UUID, Type::TEXT, Type::BYTEA])
+			.await?;
+		self.execute(&stmt, &[&Uuid::now_v7(), &name, &secret])
+			.await?;
+		Ok(())
+	}
+}
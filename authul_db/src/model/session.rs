@@ -0,0 +1,122 @@
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::{Error, OidcClient, Principal};
+use authul_macros::authul_table;
+
+const TWELVE_HOURS: Duration = Duration::from_secs(12 * 3600);
+
+#[authul_table]
+#[derive(Debug)]
+pub struct Session {
+	// This is what goes in the session cookie, so it had better not be guessable
+	#[column(v4_uuid)]
+	id: Uuid,
+	#[relation(belongs_to)]
+	principal: Principal,
+	// CBOR-encoded IdentityAttributes, as they were when the session was started, encrypted
+	// because they can include whatever tokens the upstream provider handed over
+	attrs: Vec<u8>,
+	// When the principal last proved who they were
+	auth_time: OffsetDateTime,
+	// When the principal last provided a second factor, if they ever did
+	mfa_time: Option<OffsetDateTime>,
+	#[column(default(OffsetDateTime::now_utc() + TWELVE_HOURS))]
+	valid_before: OffsetDateTime,
+}
+
+impl Session {
+	pub fn is_expired(&self) -> bool {
+		self.valid_before < OffsetDateTime::now_utc()
+	}
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn delete_expired(&self) -> Result<(), Error> {
+		let sql = "DELETE FROM sessions WHERE valid_before <= NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[]).await?;
+		self.execute(&stmt, &[]).await?;
+		Ok(())
+	}
+
+	/// Record that a client has been issued tokens on the strength of a session, so that it can be
+	/// told when the session ends.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn add_client(&self, id: &Uuid, oidc_client_id: &Uuid) -> Result<(), Error> {
+		let sql = "INSERT INTO session_clients (session_id, oidc_client_id) VALUES ($1, $2) ON CONFLICT DO NOTHING";
+		tracing::debug!(sql);
+
+		let stmt = self
+			.prepare_typed_cached(sql, &[Type::UUID, Type::UUID])
+			.await?;
+		self.execute(&stmt, &[id, oidc_client_id]).await?;
+		Ok(())
+	}
+
+	/// Get rid of a session because the principal has logged out (or been logged out), queueing up
+	/// a back-channel logout notification for every client that was issued tokens during the
+	/// session, and that wants to know about it.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn end(&self, session: &Session) -> Result<(), Error> {
+		let sql = "SELECT oidc_clients.* FROM session_clients JOIN oidc_clients ON session_clients.oidc_client_id=oidc_clients.id WHERE session_clients.session_id=$1 AND oidc_clients.backchannel_logout_uri IS NOT NULL";
+		tracing::debug!(sql);
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+
+		for row in self.query(&stmt, &[session.id()]).await? {
+			self.conn
+				.backchannel_logout_notification()
+				.new()
+				.with_oidc_client(OidcClient::from_row(&row)?)
+				.with_principal(session.principal().clone())
+				.save()
+				.await?;
+		}
+
+		let sql = "DELETE FROM sessions WHERE id=$1";
+		tracing::debug!(sql);
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+		self.execute(&stmt, &[session.id()]).await?;
+
+		Ok(())
+	}
+
+	/// End every session the principal has going, as though they'd logged out of each of them.
+	///
+	/// Returns the number of sessions that were ended.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn end_all_by_principal_id(&self, principal_id: &Uuid) -> Result<u64, Error> {
+		let sql = "SELECT principals AS principal,sessions.* FROM sessions JOIN principals ON sessions.principal_id=principals.id WHERE sessions.principal_id=$1 AND valid_before > NOW()";
+		tracing::debug!(sql);
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+
+		let mut count = 0;
+		for row in self.query(&stmt, &[principal_id]).await? {
+			let principal = Principal::from_composite_type(&row.get("principal"))?;
+			self.end(&Session::from_row(&row, principal)?).await?;
+			count += 1;
+		}
+
+		Ok(count)
+	}
+
+	/// Find a session that can still be used, if there is one.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn find_valid(&self, id: &Uuid) -> Result<Option<Session>, Error> {
+		let sql = "SELECT principals AS principal,sessions.* FROM sessions JOIN principals ON sessions.principal_id=principals.id WHERE sessions.id=$1 AND valid_before > NOW()";
+		tracing::debug!(sql);
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+
+		self.query_opt(&stmt, &[id])
+			.await?
+			.map(|row| {
+				let principal = Principal::from_composite_type(&row.get("principal"))?;
+				Session::from_row(&row, principal)
+			})
+			.transpose()
+	}
+}
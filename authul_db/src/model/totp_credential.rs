@@ -0,0 +1,82 @@
+use time::OffsetDateTime;
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::{Error, Principal};
+use authul_macros::authul_table;
+
+#[authul_table]
+#[derive(Debug)]
+pub struct TotpCredential {
+	id: Uuid,
+	#[relation(belongs_to)]
+	principal: Principal,
+	// Encrypted; the frontend config knows the key
+	secret: Vec<u8>,
+	// The most recent time step for which a code has been accepted, so that codes can't be
+	// replayed
+	#[column(default(0))]
+	last_used_step: i64,
+	// Codes entered since the last right one, so that codes can't be guessed by trying them all;
+	// once there have been too many, the credential can't be used until locked_until
+	#[column(default(0))]
+	failed_attempts: i32,
+	locked_until: Option<OffsetDateTime>,
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn find_by_principal_id(&self, id: &Uuid) -> Result<Option<TotpCredential>, Error> {
+		let sql = "SELECT principals AS principal,totp_credentials.* FROM totp_credentials JOIN principals ON totp_credentials.principal_id=principals.id WHERE principal_id=$1";
+		tracing::debug!(sql);
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+
+		self.query_opt(&stmt, &[id])
+			.await?
+			.map(|row| {
+				let principal = Principal::from_composite_type(&row.get("principal"))?;
+				TotpCredential::from_row(&row, principal)
+			})
+			.transpose()
+	}
+
+	/// Mark a time step as having been used to authenticate, which also forgets about any wrong
+	/// codes entered before it.
+	///
+	/// Returns `false` if that step (or a later one) has already been used, in which case the
+	/// code being presented is a replay and must be rejected.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn use_step(&self, id: &Uuid, step: i64) -> Result<bool, Error> {
+		let sql = "UPDATE totp_credentials SET last_used_step=$2, failed_attempts=0, locked_until=NULL WHERE id=$1 AND last_used_step < $2";
+		tracing::debug!(sql);
+		let stmt = self
+			.prepare_typed_cached(sql, &[Type::UUID, Type::INT8])
+			.await?;
+
+		Ok(self.execute(&stmt, &[id, &step]).await? == 1)
+	}
+
+	/// Count an attempt at entering a code, before it is checked, so that attempts made all at
+	/// once can't get past the limit.  Once `max_attempts` have been made without a right code
+	/// (which resets the count, in `use_step`), the credential is locked until `locked_until`, and
+	/// each attempt after that locks it again.
+	///
+	/// Returns `false` if the credential is locked, in which case the code mustn't be checked.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn start_attempt(
+		&self,
+		id: &Uuid,
+		max_attempts: i32,
+		locked_until: OffsetDateTime,
+	) -> Result<bool, Error> {
+		let sql = "UPDATE totp_credentials SET failed_attempts=failed_attempts + 1, locked_until=CASE WHEN failed_attempts + 1 >= $2 THEN $3 ELSE NULL END WHERE id=$1 AND (locked_until IS NULL OR locked_until <= NOW())";
+		tracing::debug!(sql);
+		let stmt = self
+			.prepare_typed_cached(sql, &[Type::UUID, Type::INT4, Type::TIMESTAMPTZ])
+			.await?;
+
+		Ok(self
+			.execute(&stmt, &[id, &max_attempts, &locked_until])
+			.await? == 1)
+	}
+}
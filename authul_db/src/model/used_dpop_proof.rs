@@ -0,0 +1,54 @@
+use time::OffsetDateTime;
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::Error;
+use authul_macros::authul_table;
+
+// A DPoP proof (RFC9449) that has already been presented, so that anyone who gets a copy of it
+// can't use it again
+#[authul_table]
+#[derive(Debug)]
+pub struct UsedDpopProof {
+	id: Uuid,
+	// The thumbprint of the key the proof was made with; jtis only have to be unique per key
+	jkt: String,
+	jti: String,
+	// Once the proof is too old to be accepted anyway, we can forget about it
+	valid_before: OffsetDateTime,
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn delete_expired(&self) -> Result<(), Error> {
+		let sql = "DELETE FROM used_dpop_proofs WHERE valid_before <= NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[]).await?;
+		self.execute(&stmt, &[]).await?;
+		Ok(())
+	}
+
+	/// Make a note that a proof with the given `jti` has been made with the given key.  Returns
+	/// `false` if it has already been seen, in which case the proof must not be accepted.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn record(
+		&self,
+		jkt: &str,
+		jti: &str,
+		valid_before: &OffsetDateTime,
+	) -> Result<bool, Error> {
+		let sql = "INSERT INTO used_dpop_proofs (id, jkt, jti, valid_before) VALUES ($1, $2, $3, $4) ON CONFLICT (jkt, jti) DO NOTHING";
+		tracing::debug!(sql);
+
+		let stmt = self
+			.prepare_typed_cached(
+				sql,
+				&[Type::UUID, Type::TEXT, Type::TEXT, Type::TIMESTAMPTZ],
+			)
+			.await?;
+		Ok(self
+			.execute(&stmt, &[&Uuid::now_v7(), &jkt, &jti, valid_before])
+			.await? == 1)
+	}
+}
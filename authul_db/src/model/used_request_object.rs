@@ -0,0 +1,57 @@
+use time::OffsetDateTime;
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::{Error, OidcClient};
+use authul_macros::authul_table;
+
+// A request object (RFC9101) that has already been used to start an authorization, so that anyone
+// who gets hold of a copy of it can't start another one with it
+#[authul_table]
+#[derive(Debug)]
+pub struct UsedRequestObject {
+	id: Uuid,
+	#[relation(belongs_to)]
+	oidc_client: OidcClient,
+	jti: String,
+	// Once the request object has expired, nobody will accept it anyway, so we can forget about it
+	valid_before: OffsetDateTime,
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn delete_expired(&self) -> Result<(), Error> {
+		let sql = "DELETE FROM used_request_objects WHERE valid_before <= NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[]).await?;
+		self.execute(&stmt, &[]).await?;
+		Ok(())
+	}
+
+	/// Make a note that the given client has sent a request object with the given `jti`.  Returns
+	/// `false` if it has already been used, in which case the request object must not be accepted.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn record(
+		&self,
+		oidc_client_id: &Uuid,
+		jti: &str,
+		valid_before: &OffsetDateTime,
+	) -> Result<bool, Error> {
+		let sql = "INSERT INTO used_request_objects (id, oidc_client_id, jti, valid_before) VALUES ($1, $2, $3, $4) ON CONFLICT (oidc_client_id, jti) DO NOTHING";
+		tracing::debug!(sql);
+
+		let stmt = self
+			.prepare_typed_cached(
+				sql,
+				&[Type::UUID, Type::UUID, Type::TEXT, Type::TIMESTAMPTZ],
+			)
+			.await?;
+		Ok(self
+			.execute(
+				&stmt,
+				&[&Uuid::now_v7(), oidc_client_id, &jti, valid_before],
+			)
+			.await? == 1)
+	}
+}
@@ -1,12 +1,49 @@
 use uuid::Uuid;
 
+use super::{Error, Principal};
 use authul_macros::authul_table;
 
 #[authul_table]
 #[derive(Debug)]
 pub struct User {
 	id: Uuid,
+	#[relation(belongs_to)]
+	principal: Principal,
 	#[column(find_by)]
 	email: String,
 	pwhash: String,
+	#[column(default(false))]
+	email_verified: bool,
+}
+
+impl Handle<deadpool_postgres::Client> {
+	/// Create a new user, along with the principal that they will be known as.
+	///
+	/// If a user with the given e-mail address already exists, the returned error will satisfy
+	/// `Error::is_unique_violation()`.
+	#[tracing::instrument(level = "debug", skip(self, pwhash))]
+	pub async fn create(
+		&mut self,
+		email: impl AsRef<str> + std::fmt::Debug,
+		pwhash: impl AsRef<str>,
+	) -> Result<User, Error> {
+		let txn = self.transaction().await?;
+
+		let sql = "INSERT INTO principals VALUES ($1) RETURNING *";
+		tracing::debug!(sql);
+		let stmt = txn.prepare_cached(sql).await?;
+		let principal = Principal::from_row(&txn.query_one(&stmt, &[&Uuid::now_v7()]).await?)?;
+
+		let user = txn
+			.new()
+			.with_principal(principal)
+			.with_email(email.as_ref())
+			.with_pwhash(pwhash.as_ref())
+			.save()
+			.await?;
+
+		txn.commit().await?;
+
+		Ok(user)
+	}
 }
@@ -0,0 +1,44 @@
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::Error;
+use authul_macros::authul_table;
+
+const FIVE_MINUTES: Duration = Duration::from_secs(300);
+
+#[authul_table]
+#[derive(Debug)]
+pub struct WebauthnChallenge {
+	#[column(v4_uuid)]
+	id: Uuid,
+	challenge: Vec<u8>,
+	#[column(default(OffsetDateTime::now_utc() + FIVE_MINUTES))]
+	valid_before: OffsetDateTime,
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn delete_expired(&self) -> Result<(), Error> {
+		let sql = "DELETE FROM webauthn_challenges WHERE valid_before <= NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[]).await?;
+		self.execute(&stmt, &[]).await?;
+		Ok(())
+	}
+
+	/// Use up an unexpired challenge, returning whether it was there to be used.
+	///
+	/// Each challenge can only be redeemed once, which is what stops a captured WebAuthn
+	/// response from being replayed.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn redeem(&self, challenge: &[u8]) -> Result<bool, Error> {
+		let sql = "DELETE FROM webauthn_challenges WHERE challenge=$1 AND valid_before > NOW()";
+		tracing::debug!(sql);
+
+		let stmt = self.prepare_typed_cached(sql, &[Type::BYTEA]).await?;
+		Ok(self.execute(&stmt, &[&challenge]).await? == 1)
+	}
+}
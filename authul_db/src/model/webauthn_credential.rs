@@ -0,0 +1,78 @@
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+use super::{Error, Principal};
+use authul_macros::authul_table;
+
+#[authul_table]
+#[derive(Debug)]
+pub struct WebauthnCredential {
+	id: Uuid,
+	#[relation(belongs_to)]
+	principal: Principal,
+	// The ID the authenticator chose for the credential, which is what it gives us back when
+	// it's used
+	credential_id: Vec<u8>,
+	// The credential's public key, in COSE_Key format, exactly as the authenticator sent it
+	public_key: Vec<u8>,
+	// The authenticator's signature counter as of the last time the credential was used, so
+	// that cloned authenticators can be detected
+	#[column(default(0))]
+	sign_count: i64,
+}
+
+impl<C: deadpool_postgres::GenericClient> Handle<C> {
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn find_by_credential_id(
+		&self,
+		credential_id: &[u8],
+	) -> Result<Option<WebauthnCredential>, Error> {
+		let sql = "SELECT principals AS principal,webauthn_credentials.* FROM webauthn_credentials JOIN principals ON webauthn_credentials.principal_id=principals.id WHERE credential_id=$1";
+		tracing::debug!(sql);
+		let stmt = self.prepare_typed_cached(sql, &[Type::BYTEA]).await?;
+
+		self.query_opt(&stmt, &[&credential_id])
+			.await?
+			.map(|row| {
+				let principal = Principal::from_composite_type(&row.get("principal"))?;
+				WebauthnCredential::from_row(&row, principal)
+			})
+			.transpose()
+	}
+
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn find_all_by_principal_id(
+		&self,
+		id: &Uuid,
+	) -> Result<Vec<WebauthnCredential>, Error> {
+		let sql = "SELECT principals AS principal,webauthn_credentials.* FROM webauthn_credentials JOIN principals ON webauthn_credentials.principal_id=principals.id WHERE principal_id=$1";
+		tracing::debug!(sql);
+		let stmt = self.prepare_typed_cached(sql, &[Type::UUID]).await?;
+
+		self.query(&stmt, &[id])
+			.await?
+			.into_iter()
+			.map(|row| {
+				let principal = Principal::from_composite_type(&row.get("principal"))?;
+				WebauthnCredential::from_row(&row, principal)
+			})
+			.collect()
+	}
+
+	/// Record the signature counter from a successful assertion.
+	///
+	/// Returns `false` if the counter hasn't gone up since last time, which means that there is
+	/// more than one authenticator with this credential in it, and the assertion must be
+	/// rejected.  Authenticators that don't implement a counter always send zero, and are let
+	/// through.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn update_sign_count(&self, id: &Uuid, sign_count: i64) -> Result<bool, Error> {
+		let sql = "UPDATE webauthn_credentials SET sign_count=$2 WHERE id=$1 AND (sign_count < $2 OR (sign_count = 0 AND $2 = 0))";
+		tracing::debug!(sql);
+		let stmt = self
+			.prepare_typed_cached(sql, &[Type::UUID, Type::INT8])
+			.await?;
+
+		Ok(self.execute(&stmt, &[id, &sign_count]).await? == 1)
+	}
+}
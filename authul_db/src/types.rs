@@ -18,6 +18,72 @@ impl From<&OAuthProviderKind> for OAuthProviderKind {
 	}
 }
 
+/// How a client proves who it is when it talks to us directly, named as per the OAuth Token
+/// Endpoint Authentication Methods registry
+#[derive(Debug, Clone, Copy, Eq, PartialEq, FromSql, ToSql, Serialize, Deserialize)]
+#[postgres(name = "client_auth_method")]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuthMethod {
+	#[postgres(name = "private_key_jwt")]
+	PrivateKeyJwt,
+	#[postgres(name = "client_secret_basic")]
+	ClientSecretBasic,
+	#[postgres(name = "client_secret_post")]
+	ClientSecretPost,
+	// Public clients, which can't keep a secret, and so rely on PKCE alone
+	#[postgres(name = "none")]
+	None,
+}
+
+impl ClientAuthMethod {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::PrivateKeyJwt => "private_key_jwt",
+			Self::ClientSecretBasic => "client_secret_basic",
+			Self::ClientSecretPost => "client_secret_post",
+			Self::None => "none",
+		}
+	}
+
+	/// Whether the client authenticates with a shared secret, rather than a key of its own
+	pub fn uses_secret(&self) -> bool {
+		matches!(self, Self::ClientSecretBasic | Self::ClientSecretPost)
+	}
+}
+
+impl std::fmt::Display for ClientAuthMethod {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
+
+impl std::str::FromStr for ClientAuthMethod {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"private_key_jwt" => Ok(Self::PrivateKeyJwt),
+			"client_secret_basic" => Ok(Self::ClientSecretBasic),
+			"client_secret_post" => Ok(Self::ClientSecretPost),
+			"none" => Ok(Self::None),
+			_ => Err(format!("unsupported client authentication method {s}")),
+		}
+	}
+}
+
+/// The ways in which a client's registration can fail to make sense
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum InvalidRegistration {
+	#[error("at least one redirect URI is required")]
+	NoRedirectUris,
+	#[error("a jwks_uri is required for clients using private_key_jwt")]
+	NoJwksUri,
+	#[error("redirect URI {0} is not permitted for public clients")]
+	PublicRedirectUri(String),
+	#[error("pairwise subject identifiers need all redirect URIs to be on the same host")]
+	NoSectorIdentifier,
+}
+
 pub type IdentityAttributes = Vec<IdentityAttribute>;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -28,6 +94,16 @@ pub struct IdentityAttribute {
 
 // A simple but ugly way to allow people to create an empty vec literal of identity attributes without
 // needing to paste a ridiculous amount of boilerplate
+impl IdentityAttribute {
+	pub fn kind(&self) -> &IdentityAttributeKind {
+		&self.kind
+	}
+
+	pub fn value(&self) -> &str {
+		&self.value
+	}
+}
+
 impl From<()> for IdentityAttribute {
 	fn from(_: ()) -> Self {
 		panic!("don't do this, please");
@@ -58,7 +134,8 @@ pub enum IdentityAttributeKind {
 	Username,
 	/// Our best guess at what the user would want to be called
 	DisplayName,
-	/// If the service indicates it, the user's preferred location for receiving email
+	/// If the service indicates it, the user's preferred location for receiving email.  This must
+	/// also be an email which the service has verified, because it gets handed to clients as such.
 	PrimaryEmail,
 	/// If the service suggests it verifies emails, this is an email which has been verified,
 	/// whatever that might mean
@@ -71,6 +148,20 @@ pub enum IdentityAttributeKind {
 	AccessToken,
 }
 
+impl IdentityAttributeKind {
+	/// The kind's name, as it appears in the `attrs` claim
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Username => "Username",
+			Self::DisplayName => "DisplayName",
+			Self::PrimaryEmail => "PrimaryEmail",
+			Self::VerifiedEmail => "VerifiedEmail",
+			Self::Email => "Email",
+			Self::AccessToken => "AccessToken",
+		}
+	}
+}
+
 #[cfg(test)]
 // A useful shorthand for test assembly; definitely not for production use
 impl From<&str> for IdentityAttributeKind {
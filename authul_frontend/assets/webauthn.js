@@ -0,0 +1,110 @@
+// Drives the WebAuthn ceremonies for any `button[data-webauthn]` on the page.
+//
+// The server renders everything the ceremony needs into the button's data attributes; all we do
+// is hand the options to the browser, and post whatever the authenticator gives back to the
+// button's action as an ordinary form submission, base64url-encoded.
+(function () {
+	if (window.authulWebauthn) {
+		return;
+	}
+	window.authulWebauthn = true;
+
+	function decode(s) {
+		const b64 = s.replace(/-/g, "+").replace(/_/g, "/");
+		const bin = atob(b64 + "===".slice((b64.length + 3) % 4));
+		return Uint8Array.from(bin, (c) => c.charCodeAt(0));
+	}
+
+	function encode(buf) {
+		const bin = String.fromCharCode(...new Uint8Array(buf));
+		return btoa(bin).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+	}
+
+	function options(json) {
+		const opts = JSON.parse(json);
+		const pk = opts.publicKey;
+
+		pk.challenge = decode(pk.challenge);
+		if (pk.user) {
+			pk.user.id = decode(pk.user.id);
+		}
+		for (const list of [pk.allowCredentials, pk.excludeCredentials]) {
+			for (const cred of list || []) {
+				cred.id = decode(cred.id);
+			}
+		}
+
+		return opts;
+	}
+
+	function fields(mode, cred) {
+		const r = cred.response;
+
+		if (mode === "create") {
+			return {
+				client_data_json: encode(r.clientDataJSON),
+				attestation_object: encode(r.attestationObject),
+			};
+		}
+
+		const f = {
+			credential_id: encode(cred.rawId),
+			client_data_json: encode(r.clientDataJSON),
+			authenticator_data: encode(r.authenticatorData),
+			signature: encode(r.signature),
+		};
+		if (r.userHandle) {
+			f.user_handle = encode(r.userHandle);
+		}
+		return f;
+	}
+
+	function submit(action, values) {
+		const form = document.createElement("form");
+		form.method = "post";
+		form.action = action;
+		form.hidden = true;
+
+		for (const [name, value] of Object.entries(values)) {
+			const input = document.createElement("input");
+			input.type = "hidden";
+			input.name = name;
+			input.value = value;
+			form.appendChild(input);
+		}
+
+		document.body.appendChild(form);
+		form.submit();
+	}
+
+	document.addEventListener("click", async (ev) => {
+		const button = ev.target.closest("button[data-webauthn]");
+		if (!button) {
+			return;
+		}
+		ev.preventDefault();
+
+		const mode = button.dataset.webauthn;
+		const error = button.parentElement.querySelector(".webauthn-error");
+		if (error) {
+			error.hidden = true;
+		}
+
+		try {
+			const opts = options(button.dataset.webauthnOptions);
+			const cred = await (mode === "create"
+				? navigator.credentials.create(opts)
+				: navigator.credentials.get(opts));
+
+			submit(button.dataset.webauthnAction, {
+				ctx: button.dataset.webauthnCtx,
+				...fields(mode, cred),
+			});
+		} catch (e) {
+			console.error("WebAuthn ceremony failed", e);
+			if (error) {
+				error.hidden = false;
+			}
+		}
+	});
+})();
@@ -8,7 +8,11 @@ use futures_util::future::FutureExt;
 use leptos::LeptosOptions;
 use leptos_actix::{generate_route_list, LeptosRoutes};
 
-use super::{assets, authenticate, middleware::Csrf, oidc, root_component, Config};
+use super::{
+	assets, authenticate,
+	middleware::{Csrf, Session},
+	oidc, root_component, Config,
+};
 
 const FRAME_OPTIONS: HeaderName = HeaderName::from_static("x-frame-options");
 const DENY: HeaderValue = HeaderValue::from_static("DENY");
@@ -38,6 +42,12 @@ pub fn actix_app(
 			.expect("base URL does not have a domain"),
 		cfg.base_url().path(),
 	);
+	let session_middleware = Session::new(
+		cfg.base_url()
+			.host_str()
+			.expect("base URL does not have a domain"),
+		cfg.base_url().path(),
+	);
 
 	App::new()
 		.wrap_fn(|req, srv| {
@@ -52,6 +62,7 @@ pub fn actix_app(
 		.wrap(middleware::Compress::default())
 		.wrap(middleware::NormalizePath::trim())
 		.wrap(csrf_middleware)
+		.wrap(session_middleware)
 		.app_data(web::Data::new(cfg))
 		.configure(oidc::routes)
 		.configure(authenticate::routes)
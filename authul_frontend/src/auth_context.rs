@@ -1,4 +1,4 @@
-use super::{Config, Error};
+use super::{db::types::IdentityAttributes, oidc::RequestedClaims, Config, Error};
 use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD as BASE64};
 use paste::paste;
 use serde::{Deserialize, Serialize};
@@ -17,6 +17,22 @@ struct Inner {
 	nonce: Option<String>,
 	state: Option<String>,
 	pwhash: Option<String>,
+	email: Option<String>,
+	auth_time: Option<i64>,
+	attrs: Option<IdentityAttributes>,
+	totp_secret: Option<Vec<u8>>,
+	webauthn_challenge: Option<Vec<u8>>,
+	mfa_time: Option<i64>,
+	// A freshly-made set of recovery codes, kept only until the user has been shown them
+	recovery_codes: Option<Vec<String>>,
+	session: Option<Uuid>,
+	// Set when the user is signing in a device with the device authorization grant, rather than
+	// being sent back to a client
+	device_code: Option<Uuid>,
+	// Space-separated, whittled down to the scopes we support
+	scope: Option<String>,
+	// Any individual claims the client asked for, on top of what its scopes get it
+	claims: Option<RequestedClaims>,
 }
 
 #[cfg_attr(authul_expose_privates, visibility::make(pub))]
@@ -91,6 +107,17 @@ impl AuthContext {
 				nonce: None,
 				state: None,
 				pwhash: None,
+				email: None,
+				auth_time: None,
+				attrs: None,
+				totp_secret: None,
+				webauthn_challenge: None,
+				mfa_time: None,
+				recovery_codes: None,
+				session: None,
+				device_code: None,
+				scope: None,
+				claims: None,
 			},
 			cfg,
 		}
@@ -113,6 +140,17 @@ impl AuthContext {
 	opt_param!(nonce, String);
 	opt_param!(state, String);
 	opt_param!(pwhash, String);
+	opt_param!(email, String);
+	opt_param!(auth_time, i64);
+	opt_param!(attrs, IdentityAttributes);
+	opt_param!(totp_secret, Vec<u8>);
+	opt_param!(webauthn_challenge, Vec<u8>);
+	opt_param!(mfa_time, i64);
+	opt_param!(recovery_codes, Vec<String>);
+	opt_param!(session, Uuid);
+	opt_param!(device_code, Uuid);
+	opt_param!(scope, String);
+	opt_param!(claims, RequestedClaims);
 
 	pub fn oidc_client_id(&self) -> &Uuid {
 		&self.inner.oidc_client_id
@@ -122,6 +160,13 @@ impl AuthContext {
 		&self.inner.code_challenge
 	}
 
+	/// Forget the recovery codes, once they've been shown, so they don't go any further than they
+	/// need to
+	#[allow(dead_code)]
+	pub fn clear_recovery_codes(&mut self) {
+		self.inner.recovery_codes = None;
+	}
+
 	fn strong_box(cfg: Arc<Config>) -> strong_box::RotatingStrongBox {
 		cfg.auth_context_strong_box()
 	}
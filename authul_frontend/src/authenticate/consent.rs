@@ -0,0 +1,341 @@
+use leptos::{
+	component, create_blocking_resource, create_server_action, server, view, IntoAttribute,
+	IntoSignal, IntoView, Params, ServerFnError, SignalGet as _, Suspense,
+};
+use leptos_router::{use_query, ActionForm, Params, Route, SsrMode};
+use serde::{Deserialize, Serialize};
+
+cfg_if::cfg_if! {
+	if #[cfg(feature = "ssr")] {
+		use actix_web::web::Data;
+		use leptos_actix::{extract, redirect};
+		use std::sync::Arc;
+		use tap::prelude::*;
+		use url::Url;
+		use uuid::Uuid;
+
+		use super::{may_enrol_second_factor, successful_authentication, AuthContext, Config, Error};
+		use crate::{
+			db::{self, model::OidcClient},
+			oidc::is_standard_claim,
+		};
+		use authul_oauth2::error_code::AuthorizeEndpoint as ErrCode;
+	}
+}
+
+use super::{BadContext, NoContext};
+
+#[component(transparent)]
+pub(crate) fn ConsentRoutes() -> impl IntoView {
+	view! {
+		<Route path="consent" view=Consent ssr=SsrMode::PartiallyBlocked />
+	}
+}
+
+/// What the user is being asked to hand over, and who to
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ConsentDetails {
+	client_name: String,
+	items: Vec<String>,
+	// The user has just signed in, and has no second factor, so this is their chance to set one up
+	// before going back to the client
+	offer_second_factor: bool,
+}
+
+/// Asks the user whether a client that isn't one of our own can have the details about them that
+/// it asked for
+#[component]
+pub(crate) fn Consent() -> impl IntoView {
+	#[derive(Clone, Debug, Default, Params, PartialEq)]
+	struct QueryParams {
+		ctx: Option<String>,
+		err: Option<String>,
+	}
+
+	let params = use_query::<QueryParams>();
+
+	let ctx = (move || params.get().map(|params| params.ctx).unwrap_or(None)).into_signal();
+	let err = (move || params.get().map(|params| params.err).unwrap_or(None)).into_signal();
+
+	let details = create_blocking_resource(
+		move || ctx.get(),
+		move |ctx| async move { consent_request(ctx).await.unwrap_or(None) },
+	);
+	let submit_consent = create_server_action::<SubmitConsent>();
+
+	view! {
+		<section class="container login-box">
+			{move || match (ctx.get().as_deref(), err.get().as_deref()) {
+				(None, _) | (Some(""), _) => view! { <NoContext /> }.into_view(),
+				(_, Some("no_context")) => view! { <NoContext /> }.into_view(),
+				(_, Some("invalid_context")) => view! { <BadContext /> }.into_view(),
+				_ => view! {
+					<Suspense fallback=|| view! {}>
+						{move || match details.get().flatten() {
+							Some(details) => view! {
+								<h1>{details.client_name} " would like to know"</h1>
+								<ul id="consent-items">
+									{details.items.into_iter().map(|item| view! { <li>{item}</li> }).collect::<Vec<_>>()}
+								</ul>
+								<p>"You won't be asked again unless it wants to know more."</p>
+								<ActionForm action=submit_consent attributes=vec![("id", "consent-allow-form".into_attribute())]>
+									<input type="hidden" name="ctx" value=move || ctx.get() />
+									<input type="hidden" name="decision" value="allow" />
+									<input type="submit" value="Allow" />
+								</ActionForm>
+								<ActionForm action=submit_consent attributes=vec![("id", "consent-deny-form".into_attribute())]>
+									<input type="hidden" name="ctx" value=move || ctx.get() />
+									<input type="hidden" name="decision" value="deny" />
+									<input type="submit" class="secondary" value="Don't allow" />
+								</ActionForm>
+								{details.offer_second_factor.then(|| view! {
+									<p>
+										<a id="totp-enrol-link" href=move || ctx.get().map(|ctx| format!("/authenticate/totp/enrol?ctx={ctx}"))>
+											"Set up two-factor authentication first"
+										</a>
+									</p>
+								})}
+							}.into_view(),
+							None => view! { <BadContext /> }.into_view(),
+						}}
+					</Suspense>
+				}.into_view(),
+			}}
+		</section>
+	}
+}
+
+#[server(ConsentRequest)]
+async fn consent_request(ctx: Option<String>) -> Result<Option<ConsentDetails>, ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	let Some(ctx) = ctx else {
+		tracing::debug!("No context");
+		return Ok(None);
+	};
+
+	let Ok(ctx) = AuthContext::from_str(&ctx, &cfg) else {
+		tracing::debug!("Busted context");
+		return Ok(None);
+	};
+
+	let oidc_client = cfg
+		.db()
+		.oidc_client()
+		.await?
+		.find(ctx.oidc_client_id())
+		.await?;
+
+	if authenticated_principal(&ctx, &oidc_client).is_none() {
+		tracing::debug!("Context has not completed authentication");
+		return Ok(None);
+	}
+
+	let (scopes, claims) = requested_consent(&ctx, &oidc_client);
+
+	let mut items: Vec<String> = vec![];
+	for desc in scopes
+		.iter()
+		.filter_map(|s| scope_description(s))
+		.chain(claims.iter().filter_map(|c| claim_description(c)))
+	{
+		if !items.iter().any(|i| i == desc) {
+			items.push(desc.to_string());
+		}
+	}
+
+	Ok(Some(ConsentDetails {
+		client_name: oidc_client.name().to_string(),
+		items,
+		offer_second_factor: may_enrol_second_factor(&cfg, &ctx).await?,
+	}))
+}
+
+#[server(SubmitConsent, "/authenticate", "Url", "submit_consent")]
+async fn submit_consent(ctx: String, decision: String) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_submit_consent(ctx, decision, cfg.into_inner())
+		.await
+		.tap_err(|e| tracing::warn!("failed to process consent decision: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_submit_consent(
+	ctx: String,
+	decision: String,
+	cfg: Arc<Config>,
+) -> Result<(), Error> {
+	let ctx = match AuthContext::from_str(&ctx, &cfg) {
+		Ok(ctx) => ctx,
+		Err(e) => {
+			tracing::debug!("invalid auth context: {e}");
+			return consent_failed(&cfg, &ctx, "invalid_context");
+		}
+	};
+
+	let oidc_client = cfg
+		.db()
+		.oidc_client()
+		.await?
+		.find(ctx.oidc_client_id())
+		.await?;
+
+	let Some(uid) = authenticated_principal(&ctx, &oidc_client) else {
+		tracing::debug!("consent submitted before authentication was completed");
+		return consent_failed(&cfg, &ctx.to_string(), "invalid_context");
+	};
+
+	if decision != "allow" {
+		tracing::debug!(client_id = %oidc_client.id(), "user refused consent");
+		redirect(consent_denied(&cfg, &ctx).await?.as_str());
+		return Ok(());
+	}
+
+	let (scopes, claims) = requested_consent(&ctx, &oidc_client);
+	cfg.db()
+		.consent_grant()
+		.await?
+		.grant(uid, oidc_client.id(), &scopes, &claims)
+		.await?;
+
+	redirect(
+		successful_authentication(&cfg, &ctx, ctx.attrs().cloned().unwrap_or_default())
+			.await?
+			.as_str(),
+	);
+
+	Ok(())
+}
+
+/// Where the user goes when they won't let the client have what it asked for.  A client that sent
+/// the user here gets told so (RFC6749 s4.1.2.1); a device just never gets its tokens.
+#[cfg(feature = "ssr")]
+async fn consent_denied(cfg: &Config, ctx: &AuthContext) -> Result<Url, Error> {
+	if let Some(device_code) = ctx.device_code() {
+		match cfg.db().device_code().await?.find(device_code).await {
+			Ok(dc) => cfg.db().delete(dc).await?,
+			Err(db::Error::NotFound(..)) => (),
+			Err(e) => return Err(e.into()),
+		}
+
+		let mut url = cfg.base_url().join("device")?;
+		url.query_pairs_mut().append_pair("status", "denied");
+		return Ok(url);
+	}
+
+	let mut redirect_uri = Url::parse(ctx.redirect_uri())?;
+	redirect_uri
+		.query_pairs_mut()
+		.append_pair("error", ErrCode::AccessDenied.as_str());
+
+	if let Some(state) = ctx.state() {
+		redirect_uri.query_pairs_mut().append_pair("state", state);
+	}
+	Ok(redirect_uri)
+}
+
+/// The principal in the context, provided it has been through everything the client needs before
+/// being sent here.  Only `successful_authentication` sets the session before sending the user to
+/// be asked for consent, so a context without one has come from an earlier step (such as a second
+/// factor page) that hasn't been finished yet.
+#[cfg(feature = "ssr")]
+fn authenticated_principal<'a>(ctx: &'a AuthContext, oidc_client: &OidcClient) -> Option<&'a Uuid> {
+	if ctx.auth_time().is_none() || ctx.session().is_none() {
+		return None;
+	}
+	if *oidc_client.require_mfa() && ctx.mfa_time().is_none() {
+		return None;
+	}
+
+	ctx.principal()
+}
+
+/// Whether the user has to be asked before the client gets what it asked for about them
+#[cfg(feature = "ssr")]
+pub(crate) async fn needs_consent(
+	cfg: &Config,
+	ctx: &AuthContext,
+	oidc_client: &OidcClient,
+) -> Result<bool, Error> {
+	if *oidc_client.skip_consent() {
+		return Ok(false);
+	}
+
+	let Some(uid) = ctx.principal() else {
+		return Err(Error::cant_happen(
+			"needs_consent called without principal in AuthContext",
+		));
+	};
+
+	let (scopes, claims) = requested_consent(ctx, oidc_client);
+	if scopes.is_empty() && claims.is_empty() {
+		return Ok(false);
+	}
+
+	Ok(!cfg
+		.db()
+		.consent_grant()
+		.await?
+		.covers(uid, oidc_client.id(), &scopes, &claims)
+		.await?)
+}
+
+/// The scopes and claims that the user has to agree to the client having.  The `openid` scope
+/// only gets the client the principal's `sub`, which it was always going to get, so that doesn't
+/// need asking about.
+#[cfg(feature = "ssr")]
+fn requested_consent(ctx: &AuthContext, oidc_client: &OidcClient) -> (Vec<String>, Vec<String>) {
+	let scopes = ctx
+		.scope()
+		.map(String::as_str)
+		.unwrap_or("openid")
+		.split(' ')
+		.filter(|s| !s.is_empty() && *s != "openid")
+		.map(str::to_string)
+		.collect::<Vec<_>>();
+
+	let mut claims: Vec<String> = vec![];
+	if let Some(requested) = ctx.claims() {
+		for claim in requested.id_token().iter().chain(requested.userinfo()) {
+			if is_standard_claim(claim) && oidc_client.may_release(claim) && !claims.contains(claim)
+			{
+				claims.push(claim.clone());
+			}
+		}
+	}
+
+	(scopes, claims)
+}
+
+#[cfg(feature = "ssr")]
+fn scope_description(scope: &str) -> Option<&'static str> {
+	match scope {
+		"email" => Some("Your email address"),
+		"profile" => Some("Your name and username"),
+		_ => None,
+	}
+}
+
+#[cfg(feature = "ssr")]
+fn claim_description(claim: &str) -> Option<&'static str> {
+	match claim {
+		"email" => Some("Your email address"),
+		"email_verified" => Some("Whether your email address has been verified"),
+		"name" => Some("Your name"),
+		"preferred_username" => Some("Your username"),
+		_ => None,
+	}
+}
+
+#[cfg(feature = "ssr")]
+fn consent_failed(cfg: &Config, ctx: &str, err: &str) -> Result<(), Error> {
+	let mut redirect_url: Url = cfg.base_url().join("authenticate/consent")?;
+	redirect_url
+		.query_pairs_mut()
+		.append_pair("ctx", ctx)
+		.append_pair("err", err);
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
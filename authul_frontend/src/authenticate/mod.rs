@@ -6,10 +6,11 @@ cfg_if::cfg_if! {
 	if #[cfg(feature = "ssr")] {
 		use actix_web::web::ServiceConfig;
 		use std::sync::Arc;
+		use time::OffsetDateTime;
 		use url::Url;
+		use uuid::Uuid;
 
 		use authul_db::types::IdentityAttributes;
-		use authul_crypto::Jwt;
 		use authul_util::Base64Uuid;
 		use super::{AuthContext, Config, Error};
 	}
@@ -17,8 +18,33 @@ cfg_if::cfg_if! {
 
 use super::RenderConfig;
 
+/// How long after providing a second factor that the user can change what second factors (and
+/// recovery codes) they have, without having to provide one again
+#[cfg(feature = "ssr")]
+const RECENT_SECOND_FACTOR_SECS: i64 = 600;
+
+/// How long after signing in that a user who has no second factor can choose to set one up
+#[cfg(feature = "ssr")]
+const RECENT_AUTHENTICATION_SECS: i64 = 600;
+
 mod password_auth;
 use password_auth::{AuthenticateWithEmail, PasswordAuthRoutes};
+mod register;
+use register::RegisterRoutes;
+mod reset_password;
+use reset_password::ResetPasswordRoutes;
+mod verify_email;
+use verify_email::VerifyEmailRoutes;
+mod recovery_code;
+use recovery_code::RecoveryCodeRoutes;
+mod consent;
+#[cfg(feature = "ssr")]
+pub(crate) use consent::needs_consent;
+use consent::ConsentRoutes;
+mod totp;
+use totp::TotpRoutes;
+mod webauthn;
+use webauthn::{AuthenticateWithPasskey, WebauthnRoutes};
 mod github_auth;
 use github_auth::AuthenticateWithGitHub;
 mod gitlab_auth;
@@ -39,6 +65,13 @@ pub(super) fn AuthenticateRoutes() -> impl IntoView {
 	view! {
 		<Route path="authenticate" view=move || view! { <Outlet/> }>
 			<PasswordAuthRoutes />
+			<RegisterRoutes />
+			<VerifyEmailRoutes />
+			<ResetPasswordRoutes />
+			<TotpRoutes />
+			<WebauthnRoutes />
+			<RecoveryCodeRoutes />
+			<ConsentRoutes />
 			<Route path="" view=Authenticate ssr=SsrMode::PartiallyBlocked />
 		</Route>
 	}
@@ -87,6 +120,7 @@ pub(super) fn Authenticate() -> impl IntoView {
 						}
 					}
 					<AuthenticateWithEmail ctx err email />
+					<AuthenticateWithPasskey ctx err />
 					<AuthSeparator />
 					<AuthenticateWithGitHub ctx />
 					<AuthenticateWithGitLab ctx />
@@ -111,8 +145,154 @@ fn AuthSeparator() -> impl IntoView {
 	}
 }
 
+/// Called once the user has proven who they are with their password (or OAuth provider, etc), to
+/// figure out where they need to go next.  If they need to provide a second factor, that's where
+/// they're sent; otherwise, they're done, and get sent back to the client.
 #[cfg(feature = "ssr")]
-async fn successful_authentication(
+async fn primary_authentication_complete(
+	cfg: &Arc<Config>,
+	ctx: &AuthContext,
+	attrs: IdentityAttributes,
+) -> Result<Url, Error> {
+	let Some(uid) = ctx.principal() else {
+		return Err(Error::cant_happen(
+			"primary_authentication_complete called without principal in AuthContext",
+		));
+	};
+
+	let mut ctx = ctx.clone();
+	ctx.set_auth_time(OffsetDateTime::now_utc().unix_timestamp());
+
+	let next_step = if !cfg
+		.db()
+		.webauthn_credential()
+		.await?
+		.find_all_by_principal_id(uid)
+		.await?
+		.is_empty()
+	{
+		"authenticate/webauthn"
+	} else if cfg
+		.db()
+		.totp_credential()
+		.await?
+		.find_by_principal_id(uid)
+		.await?
+		.is_some()
+	{
+		"authenticate/totp"
+	} else if *cfg
+		.db()
+		.oidc_client()
+		.await?
+		.find(ctx.oidc_client_id())
+		.await?
+		.require_mfa()
+	{
+		ctx.set_totp_secret(totp::new_totp_secret());
+		"authenticate/totp/enrol"
+	} else {
+		return successful_authentication(cfg, &ctx, attrs).await;
+	};
+
+	ctx.set_attrs(attrs);
+
+	let mut url = cfg.base_url().join(next_step)?;
+	url.query_pairs_mut().append_pair("ctx", &ctx.to_string());
+	Ok(url)
+}
+
+/// Whether the principal has already set up some form of second factor, in which case they can't
+/// set up another one without proving they have the first one (see
+/// [`has_recent_second_factor`]).
+#[cfg(feature = "ssr")]
+async fn has_second_factor(cfg: &Config, uid: &Uuid) -> Result<bool, Error> {
+	Ok(cfg
+		.db()
+		.totp_credential()
+		.await?
+		.find_by_principal_id(uid)
+		.await?
+		.is_some()
+		|| !cfg
+			.db()
+			.webauthn_credential()
+			.await?
+			.find_all_by_principal_id(uid)
+			.await?
+			.is_empty())
+}
+
+/// Whether the context is from someone who provided their second factor recently enough to be
+/// trusted with changing what second factors (and recovery codes) they have
+#[cfg(feature = "ssr")]
+fn has_recent_second_factor(ctx: &AuthContext) -> bool {
+	ctx.auth_time().is_some()
+		&& ctx.mfa_time().is_some_and(|mfa_time| {
+			OffsetDateTime::now_utc().unix_timestamp() - mfa_time <= RECENT_SECOND_FACTOR_SECS
+		})
+}
+
+/// Whether the context is from someone who signed in recently enough to set up their first second
+/// factor, without anything making them
+#[cfg(feature = "ssr")]
+fn has_recent_authentication(ctx: &AuthContext) -> bool {
+	ctx.auth_time().is_some_and(|auth_time| {
+		OffsetDateTime::now_utc().unix_timestamp() - auth_time <= RECENT_AUTHENTICATION_SECS
+	})
+}
+
+/// Whether the user can choose to set up their first second factor, when the client isn't making
+/// them; anyone who already has one has to go through [`has_recent_second_factor`] instead
+#[cfg(feature = "ssr")]
+async fn may_enrol_second_factor(cfg: &Config, ctx: &AuthContext) -> Result<bool, Error> {
+	Ok(match ctx.principal() {
+		Some(uid) => has_recent_authentication(ctx) && !has_second_factor(cfg, uid).await?,
+		None => false,
+	})
+}
+
+/// Called once the user has provided their second factor (or a recovery code, or has just set a
+/// second factor up), to send them back to the client.  If they've got no recovery codes left, they
+/// get a new set first, so they've got something to fall back on if they lose their device.
+#[cfg(feature = "ssr")]
+async fn second_factor_complete(cfg: &Arc<Config>, ctx: &AuthContext) -> Result<Url, Error> {
+	let Some(uid) = ctx.principal() else {
+		return Err(Error::cant_happen(
+			"second_factor_complete called without principal in AuthContext",
+		));
+	};
+
+	let mut ctx = ctx.clone();
+	ctx.set_mfa_time(OffsetDateTime::now_utc().unix_timestamp());
+
+	if cfg
+		.db()
+		.recovery_code()
+		.await?
+		.count_by_principal_id(uid)
+		.await?
+		== 0
+	{
+		ctx.set_recovery_codes(recovery_code::issue_recovery_codes(cfg, uid).await?);
+
+		let mut url = cfg.base_url().join("authenticate/recovery_codes")?;
+		url.query_pairs_mut().append_pair("ctx", &ctx.to_string());
+		Ok(url)
+	} else {
+		successful_authentication(cfg, &ctx, ctx.attrs().cloned().unwrap_or_default()).await
+	}
+}
+
+/// Hand the user back to the client with a shiny new authorization code, which it can exchange for
+/// an ID token, an access token, and a refresh token.  If the user didn't arrive here with a
+/// session already in hand, they get one, so that the next client they visit doesn't have to make
+/// them authenticate all over again.
+///
+/// If the user was signing in a device, the tokens are left for the device to pick up, and the
+/// user is told they can go back to it.
+#[cfg(feature = "ssr")]
+pub(crate) async fn successful_authentication(
 	cfg: &Arc<Config>,
 	ctx: &AuthContext,
 	attrs: IdentityAttributes,
@@ -123,30 +303,107 @@ async fn successful_authentication(
 		));
 	};
 
-	let k = cfg.current_oidc_signing_jwk().await?;
+	let session_id = match ctx.session() {
+		Some(id) => *id,
+		None => start_session(cfg, ctx, uid, &attrs).await?,
+	};
+
 	let oidc_client = cfg
 		.db()
 		.oidc_client()
 		.await?
 		.find(ctx.oidc_client_id())
 		.await?;
-	let mut jwt = Jwt::new()
-		.with_iss(cfg.base_url().to_string())
-		.with_sub(uid.to_string())
-		.with_aud(oidc_client.id().to_base64())
-		.with_attrs(serde_json::value::to_value(attrs)?);
-	if let Some(nonce) = ctx.nonce() {
-		jwt.set_nonce(nonce);
+
+	// The user gets asked before a third-party client gets to know anything about them; the
+	// session and attributes go along for the ride, so there's nothing to redo once they agree
+	if needs_consent(cfg, ctx, &oidc_client).await? {
+		let mut ctx = ctx.clone();
+		ctx.set_session(session_id);
+		ctx.set_attrs(attrs);
+
+		let mut url = cfg.base_url().join("authenticate/consent")?;
+		url.query_pairs_mut().append_pair("ctx", &ctx.to_string());
+		return Ok(url);
 	}
 
-	let jwt = jwt.sign(&k)?;
+	// So the client can be told when the user logs out
+	cfg.db()
+		.session()
+		.await?
+		.add_client(&session_id, oidc_client.id())
+		.await?;
+	// A context that didn't come with a scope is from before there was anything but openid
+	let scope = ctx.scope().map(String::as_str).unwrap_or("openid");
+	let requested = ctx.claims().cloned().unwrap_or_default();
+	let tokens = crate::oidc::issue_tokens(
+		cfg,
+		&oidc_client,
+		uid,
+		&attrs,
+		crate::oidc::ClaimsGrant {
+			scope,
+			requested: &requested,
+		},
+		ctx.nonce().map(String::as_str),
+		ctx.auth_time().copied(),
+	)
+	.await?;
+
+	let mut encoded_attrs: Vec<u8> = vec![];
+	ciborium::into_writer(&attrs, &mut encoded_attrs)?;
+	let refresh_token = cfg
+		.db()
+		.refresh_token()
+		.await?
+		.new()
+		.with_oidc_client(oidc_client.clone())
+		.with_principal(cfg.db().principal().await?.find(uid).await?)
+		.with_attrs(
+			cfg.refresh_token_attrs_strong_box()
+				.encrypt(encoded_attrs, b"")?,
+		)
+		.with_auth_time(match ctx.auth_time() {
+			Some(t) => OffsetDateTime::from_unix_timestamp(*t)?,
+			None => OffsetDateTime::now_utc(),
+		})
+		.with_scope(scope)
+		.with_id_token_claims(requested.id_token())
+		.with_userinfo_claims(requested.userinfo())
+		.save()
+		.await?;
+
+	// A device signing in doesn't have anywhere for us to send the user back to, so the tokens
+	// wait for the device to come and collect them instead
+	if let Some(device_code) = ctx.device_code() {
+		let mut url = cfg.base_url().join("device")?;
+		if cfg
+			.db()
+			.device_code()
+			.await?
+			.approve(
+				device_code,
+				&tokens.id_token,
+				&tokens.access_token,
+				refresh_token.id(),
+			)
+			.await?
+		{
+			url.query_pairs_mut().append_pair("status", "approved");
+		} else {
+			url.query_pairs_mut().append_pair("err", "expired_code");
+		}
+		return Ok(url);
+	}
 
 	let token = cfg
 		.db()
 		.oidc_token()
 		.await?
 		.new()
-		.with_token(jwt)
+		.with_token(tokens.id_token)
+		.with_access_token(tokens.access_token)
+		.with_refresh_token_id(Some(*refresh_token.id()))
 		.with_oidc_client(oidc_client)
 		.with_redirect_uri(ctx.redirect_uri())
 		.with_code_challenge(ctx.code_challenge())
@@ -164,6 +421,45 @@ async fn successful_authentication(
 	Ok(redirect_uri)
 }
 
+#[cfg(feature = "ssr")]
+async fn start_session(
+	cfg: &Config,
+	ctx: &AuthContext,
+	uid: &Uuid,
+	attrs: &IdentityAttributes,
+) -> Result<Uuid, Error> {
+	let mut encoded_attrs: Vec<u8> = vec![];
+	ciborium::into_writer(attrs, &mut encoded_attrs)?;
+
+	let session = cfg
+		.db()
+		.session()
+		.await?
+		.new()
+		.with_principal(cfg.db().principal().await?.find(uid).await?)
+		.with_attrs(cfg.session_attrs_strong_box().encrypt(encoded_attrs, b"")?)
+		.with_auth_time(match ctx.auth_time() {
+			Some(t) => OffsetDateTime::from_unix_timestamp(*t)?,
+			None => OffsetDateTime::now_utc(),
+		})
+		.with_mfa_time(
+			ctx.mfa_time()
+				.map(|t| OffsetDateTime::from_unix_timestamp(*t))
+				.transpose()?,
+		)
+		.save()
+		.await?;
+
+	if !super::middleware::session::started(*session.id()) {
+		tracing::debug!(
+			session_id = %session.id(),
+			"session started outside of session middleware; no cookie will be set"
+		);
+	}
+
+	Ok(*session.id())
+}
+
 #[component]
 fn NoContext() -> impl IntoView {
 	view! {
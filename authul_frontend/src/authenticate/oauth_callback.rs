@@ -4,7 +4,7 @@ use actix_web::{
 };
 use std::collections::HashMap;
 
-use super::{successful_authentication, AuthContext, Config, Error};
+use super::{primary_authentication_complete, AuthContext, Config, Error};
 use authul_oauth2::error_code::Callback;
 
 pub(super) fn routes(cfg: &mut ServiceConfig) {
@@ -64,7 +64,9 @@ pub(super) async fn get_oauth_callback(
 	Ok(HttpResponse::Found()
 		.insert_header((
 			"location",
-			successful_authentication(&cfg, &ctx, attrs).await?.as_str(),
+			primary_authentication_complete(&cfg, &ctx, attrs)
+				.await?
+				.as_str(),
 		))
 		.finish())
 }
@@ -11,14 +11,14 @@ cfg_if::cfg_if! {
 		use leptos_actix::{extract, redirect};
 		use std::sync::Arc;
 		use tap::prelude::*;
-		use crate::db;
+		use crate::db::{self, model::User, types::{IdentityAttributeKind, IdentityAttributes}};
 		use super::{
-			successful_authentication, Config, AuthContext, Error,
+			primary_authentication_complete, Config, AuthContext, Error,
 		};
 	}
 }
 
-use super::{BadContext, NoContext, RenderConfig};
+use super::{reset_password::ForgotPassword, BadContext, NoContext, RenderConfig};
 
 #[component(transparent)]
 pub(crate) fn PasswordAuthRoutes() -> impl IntoView {
@@ -115,8 +115,9 @@ async fn submit_email(email: Option<String>, ctx: Option<String>) -> Result<(),
 				match cfg.db().user().await?.find_by_email(email).await {
 					Ok(user) => {
 						tracing::debug!("Known user");
-						ctx.set_principal(*user.id());
+						ctx.set_principal(*user.principal().id());
 						ctx.set_pwhash(user.pwhash());
+						ctx.set_email(user.email());
 					}
 					Err(db::Error::NotFound(..)) => {
 						tracing::debug!("Unknown user");
@@ -210,8 +211,10 @@ pub(crate) fn AuthenticatePassword() -> impl IntoView {
 						}}
 						<input type="submit" value="Next" />
 						<a href={move || format!("..?ctx={}", ctx.get().unwrap_or_default())}>Change email address</a>
+						<a href={move || format!("register?ctx={}", ctx.get().unwrap_or_default())}>Create an account</a>
 					</ActionForm>
-				}
+					<ForgotPassword ctx />
+				}.into_view()
 			}}
 		</section>
 	}
@@ -241,8 +244,20 @@ async fn process_submit_password(
 					&& ctx.principal().is_some()
 					&& ctx.principal() != Some(&AuthContext::UNKNOWN_USER)
 				{
+					let attrs = match ctx.email() {
+						Some(email) => {
+							let user = cfg.db().user().await?.find_by_email(email).await?;
+							if Some(user.principal().id()) != ctx.principal() {
+								return Err(Error::invalid_state(
+									"user's principal changed during authentication",
+								));
+							}
+							user_attributes(&user)
+						}
+						None => Default::default(),
+					};
 					redirect(
-						successful_authentication(&cfg, &ctx, Default::default())
+						primary_authentication_complete(&cfg, &ctx, attrs)
 							.await?
 							.as_str(),
 					);
@@ -277,3 +292,15 @@ async fn process_submit_password(
 
 	Ok(())
 }
+
+/// The identity attributes we can vouch for, for a user who authenticated with a password
+#[cfg(feature = "ssr")]
+pub(super) fn user_attributes(user: &User) -> IdentityAttributes {
+	let kind = if *user.email_verified() {
+		IdentityAttributeKind::VerifiedEmail
+	} else {
+		IdentityAttributeKind::Email
+	};
+
+	vec![(kind, user.email().as_str()).into()]
+}
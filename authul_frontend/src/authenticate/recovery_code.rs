@@ -0,0 +1,490 @@
+use leptos::{
+	component, create_blocking_resource, create_server_action, server, view, IntoAttribute,
+	IntoSignal, IntoView, Params, ServerFnError, Signal, SignalGet as _, Suspense,
+};
+use leptos_router::{use_query, ActionForm, Outlet, Params, Route, SsrMode};
+use serde::{Deserialize, Serialize};
+
+cfg_if::cfg_if! {
+	if #[cfg(feature = "ssr")] {
+		use actix_web::web::Data;
+		use leptos_actix::{extract, redirect};
+		use rand::Rng as _;
+		use std::{sync::Arc, time::Duration};
+		use tap::prelude::*;
+		use time::OffsetDateTime;
+		use url::Url;
+
+		use uuid::Uuid;
+
+		use super::{
+			has_recent_second_factor, has_second_factor, second_factor_complete,
+			successful_authentication, AuthContext, Config, Error,
+		};
+	}
+}
+
+use super::{BadContext, NoContext};
+
+/// How many recovery codes each principal gets
+#[cfg(feature = "ssr")]
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// The characters a recovery code is made of; Crockford's base32, so there's nothing that can be
+/// mistaken for something else when it's written down
+#[cfg(feature = "ssr")]
+const RECOVERY_CODE_ALPHABET: &[u8] = b"0123456789abcdefghjkmnpqrstvwxyz";
+
+/// How many codes can be tried without getting one right before the principal's codes are locked;
+/// it's the same limit as for TOTP codes, even though there's far less chance of guessing one of
+/// these, because a recovery code gets someone just as far
+#[cfg(feature = "ssr")]
+const RECOVERY_CODE_MAX_ATTEMPTS: i32 = 5;
+
+/// How long codes stay locked once too many wrong ones have been tried
+#[cfg(feature = "ssr")]
+const RECOVERY_CODE_LOCKOUT: Duration = Duration::from_secs(900);
+
+#[component(transparent)]
+pub(crate) fn RecoveryCodeRoutes() -> impl IntoView {
+	view! {
+		<Route path="recovery_codes" view=move || view! { <Outlet/> }>
+			<Route path="use" view=RecoveryCode />
+			<Route path="" view=RecoveryCodes ssr=SsrMode::PartiallyBlocked />
+		</Route>
+	}
+}
+
+/// The way out for users who can't get at their second factor
+#[component]
+pub(crate) fn UseRecoveryCode(ctx: Signal<Option<String>>) -> impl IntoView {
+	view! {
+		<p>
+			<a id="recovery-code-link" href=move || ctx.get().map(|ctx| format!("/authenticate/recovery_codes/use?ctx={ctx}"))>
+				"Lost your device?  Use a recovery code"
+			</a>
+		</p>
+	}
+}
+
+#[component]
+pub(crate) fn RecoveryCode() -> impl IntoView {
+	#[derive(Clone, Debug, Default, Params, PartialEq)]
+	struct QueryParams {
+		ctx: Option<String>,
+		err: Option<String>,
+	}
+
+	let params = use_query::<QueryParams>();
+
+	let ctx = (move || params.get().map(|params| params.ctx).unwrap_or(None)).into_signal();
+	let err = (move || params.get().map(|params| params.err).unwrap_or(None)).into_signal();
+
+	let error_desc = move || {
+		err.get().map_or(None, |s| match s.as_str() {
+			"wrong_code" => Some("That recovery code is not correct, or has already been used"),
+			"locked_out" => Some("Too many incorrect recovery codes have been entered.  Please wait a while before trying again."),
+			e => {
+				tracing::debug!("unhandled err: {e}");
+				None
+			}
+		})
+	};
+	let show_error = move || error_desc().is_some();
+	let submit_recovery_code = create_server_action::<SubmitRecoveryCode>();
+
+	view! {
+		<section class="container login-box">
+			{move || match (ctx.get().as_deref(), err.get().as_deref()) {
+				(None, _) | (Some(""), _) => view! { <NoContext /> }.into_view(),
+				(_, Some("no_context")) => view! { <NoContext /> }.into_view(),
+				(_, Some("invalid_context")) => view! { <BadContext /> }.into_view(),
+				_ => view! {
+					<ActionForm action=submit_recovery_code attributes=vec![("id", "recovery-code-form".into_attribute())]>
+						<input type="hidden" name="ctx" value=move || ctx.get() />
+						<label for="recovery-code-input">"Enter one of the recovery codes you saved when you set up your second factor"</label>
+						<input id="recovery-code-input" type="text" name="code"
+							autocomplete="off" autocapitalize="off" spellcheck="false"
+							required
+							aria-invalid={move || if show_error() { "true" } else { "false" }}
+							aria-errormessage={move || if show_error() { "recovery-code-error" } else { "" }}
+						/>
+						{move || error_desc().map(|desc| view! {
+							<small id="recovery-code-error" class="error-text">{desc}</small>
+						})}
+						<input type="submit" value="Continue" />
+					</ActionForm>
+				}.into_view(),
+			}}
+		</section>
+	}
+}
+
+#[server(SubmitRecoveryCode, "/authenticate", "Url", "submit_recovery_code")]
+async fn submit_recovery_code(ctx: String, code: String) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_submit_recovery_code(ctx, code, cfg.into_inner())
+		.await
+		.tap_err(|e| tracing::warn!("failed to process submitted recovery code: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_submit_recovery_code(
+	ctx: String,
+	code: String,
+	cfg: Arc<Config>,
+) -> Result<(), Error> {
+	let mut ctx = match AuthContext::from_str(&ctx, &cfg) {
+		Ok(ctx) => ctx,
+		Err(e) => {
+			tracing::debug!("invalid auth context: {e}");
+			return recovery_code_failed(&cfg, &ctx, "invalid_context");
+		}
+	};
+
+	let (Some(uid), Some(_)) = (ctx.principal().copied(), ctx.auth_time()) else {
+		tracing::debug!("recovery code submitted before primary authentication was completed");
+		return recovery_code_failed(&cfg, &ctx.to_string(), "invalid_context");
+	};
+
+	let recovery_codes = cfg.db().recovery_code().await?;
+	if !recovery_codes
+		.start_attempt(
+			&uid,
+			RECOVERY_CODE_MAX_ATTEMPTS,
+			OffsetDateTime::now_utc() + RECOVERY_CODE_LOCKOUT,
+		)
+		.await?
+	{
+		tracing::debug!("recovery code submitted while codes are locked out");
+		return recovery_code_failed(&cfg, &ctx.to_string(), "locked_out");
+	}
+
+	if !recovery_codes
+		.redeem(&uid, &hash_code(&cfg, &code).await?)
+		.await?
+	{
+		return recovery_code_failed(&cfg, &ctx.to_string(), "wrong_code");
+	}
+	recovery_codes.reset_attempts(&uid).await?;
+
+	// The user's other codes still work.  Someone who needed one has probably lost their device,
+	// though, so rather than going straight back to the client, they're shown where to get a new
+	// set (and a new second factor); anyone who has just used their last code gets a new set
+	// regardless, same as after any other second factor
+	if recovery_codes.count_by_principal_id(&uid).await? == 0 {
+		redirect(second_factor_complete(&cfg, &ctx).await?.as_str());
+		return Ok(());
+	}
+
+	ctx.set_mfa_time(OffsetDateTime::now_utc().unix_timestamp());
+
+	let mut redirect_url = cfg.base_url().join("authenticate/recovery_codes")?;
+	redirect_url
+		.query_pairs_mut()
+		.append_pair("ctx", &ctx.to_string());
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
+
+/// For users who have just provided their second factor, and would like another one to fall back on
+#[component]
+fn AddSecurityKey(ctx: Signal<Option<String>>) -> impl IntoView {
+	view! {
+		<p>
+			<a id="webauthn-register-link" href=move || ctx.get().map(|ctx| format!("/authenticate/webauthn/register?ctx={ctx}"))>
+				"Add a security key or passkey"
+			</a>
+		</p>
+	}
+}
+
+/// What the recovery codes page has to show
+#[derive(Clone, Debug, Deserialize, Serialize)]
+enum RecoveryCodesView {
+	/// A brand new set of codes, which the user won't get to see again once they move on
+	New(Vec<String>),
+	/// Nothing new to show, but the user has recently enough provided a second factor that they
+	/// can ask for a new set
+	Regenerate,
+	Unavailable,
+}
+
+/// Shows the user the brand new set of recovery codes that they were just given, which replaces
+/// any they had before.  This is the only time the codes are ever shown; a user who wants to see
+/// some again has to ask for a whole new set.
+#[component]
+pub(crate) fn RecoveryCodes() -> impl IntoView {
+	#[derive(Clone, Debug, Default, Params, PartialEq)]
+	struct QueryParams {
+		ctx: Option<String>,
+		err: Option<String>,
+	}
+
+	let params = use_query::<QueryParams>();
+
+	let ctx = (move || params.get().map(|params| params.ctx).unwrap_or(None)).into_signal();
+	let err = (move || params.get().map(|params| params.err).unwrap_or(None)).into_signal();
+
+	let codes = create_blocking_resource(
+		move || ctx.get(),
+		move |ctx| async move {
+			recovery_codes_view(ctx)
+				.await
+				.unwrap_or(RecoveryCodesView::Unavailable)
+		},
+	);
+	let acknowledge = create_server_action::<AcknowledgeRecoveryCodes>();
+	let regenerate = create_server_action::<RegenerateRecoveryCodes>();
+
+	view! {
+		<section class="container login-box">
+			{move || match (ctx.get().as_deref(), err.get().as_deref()) {
+				(None, _) | (Some(""), _) => view! { <NoContext /> }.into_view(),
+				(_, Some("no_context")) => view! { <NoContext /> }.into_view(),
+				(_, Some("invalid_context")) => view! { <BadContext /> }.into_view(),
+				_ => view! {
+					<h1>"Your recovery codes"</h1>
+					<Suspense fallback=|| view! {}>
+						{move || match codes.get() {
+							Some(RecoveryCodesView::New(codes)) => view! {
+								<p>
+									"If you ever lose the device you use as a second factor, you can use one of these codes instead. "
+									"Each code can only be used once.  Keep them somewhere safe, because you won't be shown them again."
+								</p>
+								<p>"Any recovery codes you had before will no longer work."</p>
+								<ol id="recovery-codes">
+									{codes.into_iter().map(|code| view! { <li><code class="recovery-code">{code}</code></li> }).collect::<Vec<_>>()}
+								</ol>
+								<ActionForm action=acknowledge attributes=vec![("id", "recovery-codes-form".into_attribute())]>
+									<input type="hidden" name="ctx" value=move || ctx.get() />
+									<input type="submit" value="I've saved my recovery codes" />
+								</ActionForm>
+								<AddSecurityKey ctx />
+							}.into_view(),
+							Some(RecoveryCodesView::Regenerate) => view! {
+								<p>"If you've lost your device or your recovery codes, or used most of them up, you can get a new set.  Any you had before will stop working."</p>
+								<ActionForm action=regenerate attributes=vec![("id", "regenerate-recovery-codes-form".into_attribute())]>
+									<input type="hidden" name="ctx" value=move || ctx.get() />
+									<input type="submit" value="Get new recovery codes" />
+								</ActionForm>
+								<AddSecurityKey ctx />
+								<ActionForm action=acknowledge attributes=vec![("id", "recovery-codes-continue-form".into_attribute())]>
+									<input type="hidden" name="ctx" value=move || ctx.get() />
+									<input type="submit" class="secondary" value="Continue with the codes I have" />
+								</ActionForm>
+							}.into_view(),
+							Some(RecoveryCodesView::Unavailable) | None => view! {
+								<p id="recovery-codes-unavailable">"You can't get new recovery codes right now."</p>
+							}.into_view(),
+						}}
+					</Suspense>
+				}.into_view(),
+			}}
+		</section>
+	}
+}
+
+#[server(GetRecoveryCodesView)]
+async fn recovery_codes_view(ctx: Option<String>) -> Result<RecoveryCodesView, ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	let Some(ctx) = ctx else {
+		tracing::debug!("No context");
+		return Ok(RecoveryCodesView::Unavailable);
+	};
+
+	let Ok(ctx) = AuthContext::from_str(&ctx, &cfg) else {
+		tracing::debug!("Busted context");
+		return Ok(RecoveryCodesView::Unavailable);
+	};
+
+	let (Some(_), Some(_), Some(_)) = (ctx.principal(), ctx.auth_time(), ctx.mfa_time()) else {
+		tracing::debug!("Context has not completed a second factor");
+		return Ok(RecoveryCodesView::Unavailable);
+	};
+
+	Ok(match ctx.recovery_codes() {
+		Some(codes) => RecoveryCodesView::New(codes.clone()),
+		None if may_regenerate(&cfg, &ctx).await? => RecoveryCodesView::Regenerate,
+		None => RecoveryCodesView::Unavailable,
+	})
+}
+
+#[server(
+	RegenerateRecoveryCodes,
+	"/authenticate",
+	"Url",
+	"regenerate_recovery_codes"
+)]
+async fn regenerate_recovery_codes(ctx: String) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_regenerate_recovery_codes(ctx, cfg.into_inner())
+		.await
+		.tap_err(|e| tracing::warn!("failed to regenerate recovery codes: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_regenerate_recovery_codes(ctx: String, cfg: Arc<Config>) -> Result<(), Error> {
+	let mut ctx = match AuthContext::from_str(&ctx, &cfg) {
+		Ok(ctx) => ctx,
+		Err(e) => {
+			tracing::debug!("invalid auth context: {e}");
+			return recovery_codes_failed(&cfg, &ctx, "invalid_context");
+		}
+	};
+
+	let Some(uid) = ctx.principal().copied() else {
+		tracing::debug!("recovery codes requested without a principal");
+		return recovery_codes_failed(&cfg, &ctx.to_string(), "invalid_context");
+	};
+
+	if !may_regenerate(&cfg, &ctx).await? {
+		tracing::debug!("recovery codes requested without a recent second factor");
+		return recovery_codes_failed(&cfg, &ctx.to_string(), "invalid_context");
+	}
+
+	ctx.set_recovery_codes(issue_recovery_codes(&cfg, &uid).await?);
+
+	let mut redirect_url = cfg.base_url().join("authenticate/recovery_codes")?;
+	redirect_url
+		.query_pairs_mut()
+		.append_pair("ctx", &ctx.to_string());
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
+
+/// Whether the context is from someone who has recently enough proven they've got a second factor
+/// to be trusted with a new set of recovery codes
+#[cfg(feature = "ssr")]
+async fn may_regenerate(cfg: &Config, ctx: &AuthContext) -> Result<bool, Error> {
+	let Some(uid) = ctx.principal() else {
+		return Ok(false);
+	};
+
+	if !has_recent_second_factor(ctx) {
+		tracing::debug!("Second factor was not provided recently enough");
+		return Ok(false);
+	}
+
+	// Recovery codes are a stand-in for a second factor, so there's no point having them without
+	// one
+	has_second_factor(cfg, uid).await
+}
+
+/// Give the principal a brand new set of recovery codes, replacing any they had before, and return
+/// the codes so they can be shown to the user
+#[cfg(feature = "ssr")]
+pub(super) async fn issue_recovery_codes(cfg: &Config, uid: &Uuid) -> Result<Vec<String>, Error> {
+	let codes = (0..RECOVERY_CODE_COUNT)
+		.map(|_| generate_code())
+		.collect::<Vec<_>>();
+
+	let mut code_hashes = Vec::with_capacity(codes.len());
+	for code in &codes {
+		code_hashes.push(hash_code(cfg, code).await?);
+	}
+
+	let principal = cfg.db().principal().await?.find(uid).await?;
+	cfg.db()
+		.recovery_code()
+		.await?
+		.replace_all(&principal, code_hashes)
+		.await?;
+
+	Ok(codes)
+}
+
+#[server(
+	AcknowledgeRecoveryCodes,
+	"/authenticate",
+	"Url",
+	"acknowledge_recovery_codes"
+)]
+async fn acknowledge_recovery_codes(ctx: String) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_acknowledge_recovery_codes(ctx, cfg.into_inner())
+		.await
+		.tap_err(|e| tracing::warn!("failed to process recovery codes acknowledgement: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_acknowledge_recovery_codes(ctx: String, cfg: Arc<Config>) -> Result<(), Error> {
+	let mut ctx = match AuthContext::from_str(&ctx, &cfg) {
+		Ok(ctx) => ctx,
+		Err(e) => {
+			tracing::debug!("invalid auth context: {e}");
+			return recovery_codes_failed(&cfg, &ctx, "invalid_context");
+		}
+	};
+
+	let (Some(_), Some(_), Some(_)) = (ctx.principal(), ctx.auth_time(), ctx.mfa_time()) else {
+		tracing::debug!("recovery codes acknowledged without completing a second factor");
+		return recovery_codes_failed(&cfg, &ctx.to_string(), "invalid_context");
+	};
+
+	ctx.clear_recovery_codes();
+	redirect(
+		successful_authentication(&cfg, &ctx, ctx.attrs().cloned().unwrap_or_default())
+			.await?
+			.as_str(),
+	);
+
+	Ok(())
+}
+
+#[cfg(feature = "ssr")]
+fn generate_code() -> String {
+	let mut rng = rand::thread_rng();
+
+	(0..16)
+		.map(|i| {
+			let c =
+				char::from(RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())]);
+			if i > 0 && i % 4 == 0 {
+				format!("-{c}")
+			} else {
+				c.to_string()
+			}
+		})
+		.collect()
+}
+
+/// Users will type codes in however they like, so case and punctuation are ignored
+#[cfg(feature = "ssr")]
+async fn hash_code(cfg: &Config, code: &str) -> Result<Vec<u8>, Error> {
+	let normalised = code
+		.chars()
+		.filter(char::is_ascii_alphanumeric)
+		.map(|c| c.to_ascii_lowercase())
+		.collect::<String>();
+
+	cfg.recovery_code_hash(&normalised).await
+}
+
+#[cfg(feature = "ssr")]
+fn recovery_code_failed(cfg: &Config, ctx: &str, err: &str) -> Result<(), Error> {
+	failed(cfg, "authenticate/recovery_codes/use", ctx, err)
+}
+
+#[cfg(feature = "ssr")]
+fn recovery_codes_failed(cfg: &Config, ctx: &str, err: &str) -> Result<(), Error> {
+	failed(cfg, "authenticate/recovery_codes", ctx, err)
+}
+
+#[cfg(feature = "ssr")]
+fn failed(cfg: &Config, path: &str, ctx: &str, err: &str) -> Result<(), Error> {
+	let mut redirect_url: Url = cfg.base_url().join(path)?;
+	redirect_url
+		.query_pairs_mut()
+		.append_pair("ctx", ctx)
+		.append_pair("err", err);
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
@@ -0,0 +1,280 @@
+use leptos::{
+	component, create_server_action, server, use_context, view, IntoAttribute, IntoSignal,
+	IntoView, Params, ServerFnError, SignalGet as _,
+};
+use leptos_router::{use_query, ActionForm, Params, Route};
+
+cfg_if::cfg_if! {
+	if #[cfg(feature = "ssr")] {
+		use actix_web::{rt::task::spawn_blocking, web::Data};
+		use email_address::EmailAddress;
+		use leptos_actix::{extract, redirect};
+		use std::sync::Arc;
+		use tap::prelude::*;
+		use zxcvbn::zxcvbn;
+		use super::{verify_email::send_verification_email, Config, AuthContext, Error};
+	}
+}
+
+use super::{BadContext, NoContext, RenderConfig};
+
+/// The lowest zxcvbn score (on its 0-4 scale) that we'll accept for a new password
+#[cfg(feature = "ssr")]
+const MINIMUM_PASSWORD_SCORE: u8 = 3;
+
+#[component(transparent)]
+pub(crate) fn RegisterRoutes() -> impl IntoView {
+	let render_config = use_context::<RenderConfig>().expect("no RenderConfig available");
+
+	if render_config.password_auth {
+		view! {
+			<Route path="register" view=Register />
+		}
+		.into_view()
+	} else {
+		view! {}.into_view()
+	}
+}
+
+#[component]
+pub(crate) fn Register() -> impl IntoView {
+	#[derive(Clone, Debug, Default, Params, PartialEq)]
+	struct QueryParams {
+		ctx: Option<String>,
+		err: Option<String>,
+		email: Option<String>,
+		status: Option<String>,
+	}
+
+	let params = use_query::<QueryParams>();
+
+	let ctx = (move || params.get().map(|params| params.ctx).unwrap_or(None)).into_signal();
+	let err = (move || params.get().map(|params| params.err).unwrap_or(None)).into_signal();
+	let email = (move || params.get().map(|params| params.email).unwrap_or(None)).into_signal();
+	let status = (move || params.get().map(|params| params.status).unwrap_or(None)).into_signal();
+
+	let email_error_desc = move || {
+		err.get().map_or(None, |s| match s.as_str() {
+			"invalid_email" => Some("Invalid email address"),
+			_ => None,
+		})
+	};
+	let show_email_error = move || email_error_desc().is_some();
+
+	let password_error_desc = move || {
+		err.get().map_or(None, |s| match s.as_str() {
+			"password_mismatch" => Some("The passwords you entered do not match"),
+			"weak_password" => {
+				Some("That password is too easy to guess; please choose a stronger one")
+			}
+			e => {
+				tracing::debug!("unhandled err: {e}");
+				None
+			}
+		})
+	};
+	let show_password_error = move || password_error_desc().is_some();
+
+	let submit_registration = create_server_action::<SubmitRegistration>();
+
+	view! {
+		<section class="container login-box">
+			{move || match (ctx.get().as_ref().map(|s| s.as_str()), err.get().as_ref().map(|s| s.as_str())) {
+				(None, _) | (Some(""), _) => view! { <NoContext /> }.into_view(),
+				(_, Some("no_context")) => view! { <NoContext /> }.into_view(),
+				(_, Some("invalid_context")) => view! { <BadContext /> }.into_view(),
+				_ if status.get().as_deref() == Some("check_email") => view! {
+					<h1>"Check your email"</h1>
+					<p id="check-email">
+						"We have sent a message to " {move || email.get()} ".  Please follow the link in it to confirm your email address, then sign in."
+					</p>
+					<a href={move || format!("../authenticate?ctx={}", ctx.get().unwrap_or_default())}>"Sign in"</a>
+				}.into_view(),
+				_ => view! {
+					<h1>"Create an account"</h1>
+					<ActionForm action=submit_registration attributes=vec![("id", "registration-form".into_attribute())]>
+						<input type="hidden" name="ctx" value=move || ctx.get() />
+						<label for="email-input">"Email address"</label>
+						<input id="email-input" type="email" name="email"
+							required autocomplete="email" placeholder="user@example.com"
+							value=move || email.get()
+							aria-invalid={move || if show_email_error() { "true" } else { "false" }}
+							aria-errormessage={move || if show_email_error() { "email-error" } else { "" }}
+						/>
+						{move || if show_email_error() {
+							view! {
+								<small id="email-error" class="error-text">{move || email_error_desc()}</small>
+							}.into_view()
+						} else {
+							view! {}.into_view()
+						}}
+						<label for="password-input">"Choose a password"</label>
+						<input id="password-input" type="password" name="password"
+							autocomplete="new-password"
+							required
+							aria-invalid={move || if show_password_error() { "true" } else { "false" }}
+							aria-errormessage={move || if show_password_error() { "password-error" } else { "" }}
+						/>
+						<label for="password-confirmation-input">"Enter your password again"</label>
+						<input id="password-confirmation-input" type="password" name="password_confirmation"
+							autocomplete="new-password"
+							required
+						/>
+						{move || if show_password_error() {
+							view! {
+								<small id="password-error" class="error-text">{move || password_error_desc()}</small>
+							}.into_view()
+						} else {
+							view! {}.into_view()
+						}}
+						<input type="submit" value="Create account" />
+						<a href={move || format!("../authenticate?ctx={}", ctx.get().unwrap_or_default())}>"Sign in with an existing account"</a>
+					</ActionForm>
+				}.into_view()
+			}}
+		</section>
+	}
+}
+
+#[server(SubmitRegistration, "/authenticate", "Url", "submit_registration")]
+async fn submit_registration(
+	email: String,
+	password: String,
+	password_confirmation: String,
+	ctx: String,
+) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_submit_registration(
+		email,
+		password,
+		password_confirmation,
+		ctx,
+		cfg.into_inner(),
+	)
+	.await
+	.tap_err(|e| tracing::warn!("failed to process registration: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_submit_registration(
+	email: String,
+	password: String,
+	password_confirmation: String,
+	ctx: String,
+	cfg: Arc<Config>,
+) -> Result<(), Error> {
+	let ctx = match AuthContext::from_str(&ctx, &cfg) {
+		Ok(ctx) => ctx,
+		Err(e) => {
+			tracing::debug!("invalid auth context: {e}");
+			return registration_failed(&cfg, &ctx, "invalid_context", None);
+		}
+	};
+
+	if !EmailAddress::is_valid(&email) {
+		tracing::debug!("invalid email");
+		return registration_failed(&cfg, &ctx.to_string(), "invalid_email", Some(&email));
+	}
+
+	if password != password_confirmation {
+		tracing::debug!("password confirmation mismatch");
+		return registration_failed(&cfg, &ctx.to_string(), "password_mismatch", Some(&email));
+	}
+
+	if !password_is_strong_enough(&password, &email) {
+		tracing::debug!("weak password rejected");
+		return registration_failed(&cfg, &ctx.to_string(), "weak_password", Some(&email));
+	}
+
+	let cost = cfg.pwhash_cost();
+	let pwhash = spawn_blocking(move || bcrypt::hash(&password, cost)).await??;
+
+	// Whether or not there's already an account for the address, the person registering gets told
+	// the same thing, so that the registration form can't be used to find out who has an account;
+	// only whoever reads the email gets to know which it was
+	match cfg.db().user().await?.create(&email, pwhash).await {
+		Ok(user) => {
+			tracing::debug!("created user {}", user.id());
+			if let Err(e) = send_verification_email(&cfg, &user).await {
+				tracing::warn!("failed to send verification email: {e}");
+			}
+		}
+		Err(e) if e.is_unique_violation() => {
+			tracing::debug!("account already exists for {email}");
+			if let Err(e) = send_account_exists_email(&cfg, &email).await {
+				tracing::warn!("failed to send account exists email: {e}");
+			}
+		}
+		Err(e) => return Err(e.into()),
+	};
+
+	let mut redirect_url = cfg.base_url().join("authenticate/register")?;
+	redirect_url
+		.query_pairs_mut()
+		.append_pair("ctx", &ctx.to_string())
+		.append_pair("status", "check_email")
+		.append_pair("email", &email);
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
+
+/// Let the owner of an existing account know that someone tried to register with their address,
+/// since the person registering doesn't get told
+#[cfg(feature = "ssr")]
+#[tracing::instrument(level = "debug", skip(cfg))]
+async fn send_account_exists_email(cfg: &Config, email: &str) -> Result<(), Error> {
+	let Some(mailer) = cfg.mailer() else {
+		tracing::debug!("no mail transport configured; not sending account exists email");
+		return Ok(());
+	};
+
+	mailer
+		.send(
+			email,
+			"You already have an account",
+			account_exists_email_body(),
+		)
+		.await
+}
+
+#[cfg(feature = "ssr")]
+fn account_exists_email_body() -> String {
+	"Hello!\n\
+	\n\
+	Someone just tried to create an account using this email address, but there is already an \
+	account for it.  If that was you, you can sign in with your existing account instead, and \
+	if you have forgotten your password, you can reset it from the sign in page.\n\
+	\n\
+	If it wasn't you, you can safely ignore this message; nothing about your account has been \
+	changed.\n"
+		.to_string()
+}
+
+/// Whether a proposed new password is hard enough to guess, given what else we know about the
+/// user it's for
+#[cfg(feature = "ssr")]
+pub(super) fn password_is_strong_enough(password: &str, email: &str) -> bool {
+	zxcvbn(password, &[email]).map(|e| e.score()).unwrap_or(0) >= MINIMUM_PASSWORD_SCORE
+}
+
+#[cfg(feature = "ssr")]
+fn registration_failed(
+	cfg: &Config,
+	ctx: &str,
+	err: &str,
+	email: Option<&str>,
+) -> Result<(), Error> {
+	let mut redirect_url = cfg.base_url().join("authenticate/register")?;
+	redirect_url
+		.query_pairs_mut()
+		.append_pair("ctx", ctx)
+		.append_pair("err", err);
+	if let Some(email) = email {
+		redirect_url.query_pairs_mut().append_pair("email", email);
+	}
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
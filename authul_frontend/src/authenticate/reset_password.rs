@@ -0,0 +1,328 @@
+use leptos::{
+	component, create_server_action, server, use_context, view, IntoAttribute, IntoSignal,
+	IntoView, Params, ServerFnError, Signal, SignalGet as _,
+};
+use leptos_router::{use_query, ActionForm, Params, Route};
+
+cfg_if::cfg_if! {
+	if #[cfg(feature = "ssr")] {
+		use actix_web::{rt::{spawn, task::spawn_blocking}, web::Data};
+		use leptos_actix::{extract, redirect};
+		use std::sync::Arc;
+		use tap::prelude::*;
+		use url::Url;
+		use uuid::Uuid;
+
+		use authul_util::Base64Uuid;
+		use crate::db;
+		use super::{register::password_is_strong_enough, Config, AuthContext, Error};
+	}
+}
+
+use super::RenderConfig;
+
+#[component(transparent)]
+pub(crate) fn ResetPasswordRoutes() -> impl IntoView {
+	let render_config = use_context::<RenderConfig>().expect("no RenderConfig available");
+
+	if render_config.password_reset {
+		view! {
+			<Route path="reset_password" view=ResetPassword />
+		}
+		.into_view()
+	} else {
+		view! {}.into_view()
+	}
+}
+
+/// The "I forgot my password" button that lives on the password page
+#[component]
+pub(crate) fn ForgotPassword(ctx: Signal<Option<String>>) -> impl IntoView {
+	#[derive(Clone, Debug, Default, Params, PartialEq)]
+	struct QueryParams {
+		status: Option<String>,
+	}
+
+	let render_config = use_context::<RenderConfig>().expect("no RenderConfig available");
+
+	if !render_config.password_reset {
+		return view! {}.into_view();
+	}
+
+	let params = use_query::<QueryParams>();
+	let status = (move || params.get().map(|params| params.status).unwrap_or(None)).into_signal();
+
+	let request_password_reset = create_server_action::<RequestPasswordReset>();
+
+	view! {
+		{move || if status.get().as_deref() == Some("reset_sent") {
+			view! {
+				<p id="reset-sent">
+					"If there is an account for that email address, we have sent it a link you can use to reset your password."
+				</p>
+			}.into_view()
+		} else {
+			view! {
+				<ActionForm action=request_password_reset attributes=vec![("id", "forgot-password-form".into_attribute())]>
+					<input type="hidden" name="ctx" value=move || ctx.get() />
+					<input type="submit" class="secondary" value="Forgot your password?" />
+				</ActionForm>
+			}.into_view()
+		}}
+	}
+	.into_view()
+}
+
+#[server(RequestPasswordReset, "/authenticate", "Url", "request_password_reset")]
+async fn request_password_reset(ctx: String) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_request_password_reset(ctx, cfg.into_inner())
+		.await
+		.tap_err(|e| tracing::warn!("failed to process password reset request: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_request_password_reset(ctx: String, cfg: Arc<Config>) -> Result<(), Error> {
+	let ctx = match AuthContext::from_str(&ctx, &cfg) {
+		Ok(ctx) => ctx,
+		Err(e) => {
+			tracing::debug!("invalid auth context: {e}");
+			let mut redirect_url = cfg.base_url().join("authenticate/pw")?;
+			redirect_url
+				.query_pairs_mut()
+				.append_pair("ctx", &ctx)
+				.append_pair("err", "invalid_context");
+			redirect(redirect_url.as_str());
+			return Ok(());
+		}
+	};
+
+	// Only known users get an email address put in their context, but the response has to look
+	// exactly the same either way, so everything that depends on whether the user exists is
+	// done after we've already responded
+	if let Some(email) = ctx.email() {
+		let email = email.clone();
+		let cfg = cfg.clone();
+		spawn(async move {
+			if let Err(e) = send_password_reset_email(&cfg, &email).await {
+				tracing::warn!("failed to send password reset email: {e}");
+			}
+		});
+	}
+
+	let mut redirect_url = cfg.base_url().join("authenticate/pw")?;
+	redirect_url
+		.query_pairs_mut()
+		.append_pair("ctx", &ctx.to_string())
+		.append_pair("status", "reset_sent");
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
+
+#[cfg(feature = "ssr")]
+#[tracing::instrument(level = "debug", skip(cfg))]
+async fn send_password_reset_email(cfg: &Arc<Config>, email: &str) -> Result<(), Error> {
+	let Some(mailer) = cfg.mailer() else {
+		tracing::debug!("no mail transport configured; not sending password reset email");
+		return Ok(());
+	};
+
+	let user = cfg.db().user().await?.find_by_email(email).await?;
+	let token = cfg
+		.db()
+		.password_reset_token()
+		.await?
+		.new()
+		.with_user(user)
+		.save()
+		.await?;
+
+	let mut link = cfg.base_url().join("authenticate/reset_password")?;
+	link.query_pairs_mut()
+		.append_pair("token", &token.id().to_base64());
+
+	mailer
+		.send(
+			email,
+			"Reset your password",
+			password_reset_email_body(&link),
+		)
+		.await
+}
+
+#[cfg(feature = "ssr")]
+fn password_reset_email_body(link: &Url) -> String {
+	format!(
+		"Hello!\n\
+		\n\
+		Someone asked to reset the password for the account with this email address.  To choose \
+		a new password, please visit the following link:\n\
+		\n\
+		{link}\n\
+		\n\
+		The link will stop working after one hour, and can only be used once.  If you didn't ask \
+		to reset your password, you can safely ignore this message; your password has not been \
+		changed.\n"
+	)
+}
+
+#[component]
+pub(crate) fn ResetPassword() -> impl IntoView {
+	#[derive(Clone, Debug, Default, Params, PartialEq)]
+	struct QueryParams {
+		token: Option<String>,
+		err: Option<String>,
+		status: Option<String>,
+	}
+
+	let params = use_query::<QueryParams>();
+
+	let token = (move || params.get().map(|params| params.token).unwrap_or(None)).into_signal();
+	let err = (move || params.get().map(|params| params.err).unwrap_or(None)).into_signal();
+	let status = (move || params.get().map(|params| params.status).unwrap_or(None)).into_signal();
+
+	let password_error_desc = move || {
+		err.get().map_or(None, |s| match s.as_str() {
+			"password_mismatch" => Some("The passwords you entered do not match"),
+			"weak_password" => {
+				Some("That password is too easy to guess; please choose a stronger one")
+			}
+			e => {
+				tracing::debug!("unhandled err: {e}");
+				None
+			}
+		})
+	};
+	let show_password_error = move || password_error_desc().is_some();
+
+	let submit_password_reset = create_server_action::<SubmitPasswordReset>();
+
+	view! {
+		<section class="container login-box">
+			{move || match (status.get().as_deref(), err.get().as_deref(), token.get().as_deref()) {
+				(Some("reset"), _, _) => view! {
+					<p id="reset-success">
+						"Your password has been changed.  Please return to the site you were signing in to, and sign in with your new password."
+					</p>
+				}.into_view(),
+				(_, Some("invalid_token"), _) | (_, _, None) | (_, _, Some("")) => view! {
+					<p id="reset-error" class="error-text">
+						"That password reset link is not valid.  It may have expired, or already been used."
+					</p>
+				}.into_view(),
+				_ => view! {
+					<h1>"Choose a new password"</h1>
+					<ActionForm action=submit_password_reset attributes=vec![("id", "reset-password-form".into_attribute())]>
+						<input type="hidden" name="token" value=move || token.get() />
+						<label for="password-input">"New password"</label>
+						<input id="password-input" type="password" name="password"
+							autocomplete="new-password"
+							required
+							aria-invalid={move || if show_password_error() { "true" } else { "false" }}
+							aria-errormessage={move || if show_password_error() { "password-error" } else { "" }}
+						/>
+						<label for="password-confirmation-input">"Enter your new password again"</label>
+						<input id="password-confirmation-input" type="password" name="password_confirmation"
+							autocomplete="new-password"
+							required
+						/>
+						{move || if show_password_error() {
+							view! {
+								<small id="password-error" class="error-text">{move || password_error_desc()}</small>
+							}.into_view()
+						} else {
+							view! {}.into_view()
+						}}
+						<input type="submit" value="Change password" />
+					</ActionForm>
+				}.into_view(),
+			}}
+		</section>
+	}
+}
+
+#[server(SubmitPasswordReset, "/authenticate", "Url", "submit_password_reset")]
+async fn submit_password_reset(
+	token: String,
+	password: String,
+	password_confirmation: String,
+) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(
+		process_submit_password_reset(token, password, password_confirmation, cfg.into_inner())
+			.await
+			.tap_err(|e| tracing::warn!("failed to process password reset: {e}"))?,
+	)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_submit_password_reset(
+	token: String,
+	password: String,
+	password_confirmation: String,
+	cfg: Arc<Config>,
+) -> Result<(), Error> {
+	let Ok(token_id) = Uuid::from_base64(&token) else {
+		tracing::debug!("unparseable password reset token");
+		return reset_result(&cfg, None, "err", "invalid_token");
+	};
+
+	let reset_tokens = cfg.db().password_reset_token().await?;
+	let reset_token = match reset_tokens.find(&token_id).await {
+		Ok(t) if !t.is_expired() => t,
+		Ok(_) | Err(db::Error::NotFound(..)) => {
+			tracing::debug!("unknown or expired password reset token");
+			return reset_result(&cfg, None, "err", "invalid_token");
+		}
+		Err(e) => return Err(e.into()),
+	};
+
+	if password != password_confirmation {
+		tracing::debug!("password confirmation mismatch");
+		return reset_result(&cfg, Some(&token), "err", "password_mismatch");
+	}
+
+	if !password_is_strong_enough(&password, reset_token.user().email()) {
+		tracing::debug!("weak password rejected");
+		return reset_result(&cfg, Some(&token), "err", "weak_password");
+	}
+
+	let cost = cfg.pwhash_cost();
+	let pwhash = spawn_blocking(move || bcrypt::hash(&password, cost)).await??;
+
+	let user_id = match reset_tokens.redeem(&token_id).await {
+		Ok(user_id) => user_id,
+		Err(db::Error::NotFound(..)) => {
+			tracing::debug!("password reset token was used or expired while we were busy");
+			return reset_result(&cfg, None, "err", "invalid_token");
+		}
+		Err(e) => return Err(e.into()),
+	};
+
+	let users = cfg.db().user().await?;
+	let mut user = users.find(&user_id).await?;
+	user.update_pwhash(pwhash);
+	// Following the link in the email is every bit as good a proof of control of the address as
+	// the verification email
+	user.update_email_verified(true);
+	user.save(&users).await?;
+
+	reset_tokens.delete_all_for_user(&user_id).await?;
+
+	reset_result(&cfg, None, "status", "reset")
+}
+
+#[cfg(feature = "ssr")]
+fn reset_result(cfg: &Config, token: Option<&str>, k: &str, v: &str) -> Result<(), Error> {
+	let mut redirect_url = cfg.base_url().join("authenticate/reset_password")?;
+	if let Some(token) = token {
+		redirect_url.query_pairs_mut().append_pair("token", token);
+	}
+	redirect_url.query_pairs_mut().append_pair(k, v);
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
@@ -0,0 +1,486 @@
+use leptos::{
+	component, create_blocking_resource, create_server_action, server, view, IntoAttribute,
+	IntoSignal, IntoView, Params, ServerFnError, SignalGet as _, Suspense,
+};
+use leptos_router::{use_query, ActionForm, Outlet, Params, Route, SsrMode};
+use serde::{Deserialize, Serialize};
+
+cfg_if::cfg_if! {
+	if #[cfg(feature = "ssr")] {
+		use actix_web::web::Data;
+		use leptos_actix::{extract, redirect};
+		use rand::RngCore as _;
+		use std::{sync::Arc, time::Duration};
+		use tap::prelude::*;
+		use time::OffsetDateTime;
+		use totp_rs::{Algorithm, TOTP};
+		use url::Url;
+
+		use super::{
+			has_second_factor, may_enrol_second_factor, primary_authentication_complete,
+			second_factor_complete, AuthContext, Config, Error,
+		};
+	}
+}
+
+use super::{recovery_code::UseRecoveryCode, BadContext, NoContext};
+
+/// How many seconds each TOTP code is valid for
+#[cfg(feature = "ssr")]
+const TOTP_STEP: u64 = 30;
+
+/// How many codes can be tried without getting one right before the credential is locked; with
+/// three steps' worth of codes accepted at once, that's a one in 66,666 chance of guessing a code
+/// before being locked out
+#[cfg(feature = "ssr")]
+const TOTP_MAX_ATTEMPTS: i32 = 5;
+
+/// How long a credential stays locked once too many wrong codes have been tried
+#[cfg(feature = "ssr")]
+const TOTP_LOCKOUT: Duration = Duration::from_secs(900);
+
+#[component(transparent)]
+pub(crate) fn TotpRoutes() -> impl IntoView {
+	view! {
+		<Route path="totp" view=move || view! { <Outlet/> }>
+			<Route path="enrol" view=TotpEnrol ssr=SsrMode::PartiallyBlocked />
+			<Route path="" view=Totp />
+		</Route>
+	}
+}
+
+#[component]
+pub(crate) fn Totp() -> impl IntoView {
+	#[derive(Clone, Debug, Default, Params, PartialEq)]
+	struct QueryParams {
+		ctx: Option<String>,
+		err: Option<String>,
+	}
+
+	let params = use_query::<QueryParams>();
+
+	let ctx = (move || params.get().map(|params| params.ctx).unwrap_or(None)).into_signal();
+	let err = (move || params.get().map(|params| params.err).unwrap_or(None)).into_signal();
+
+	let error_desc = move || {
+		err.get().map_or(None, |s| match s.as_str() {
+			"wrong_code" => Some("That code is not correct"),
+			"locked_out" => Some("Too many incorrect codes have been entered.  Please wait a while before trying again."),
+			e => {
+				tracing::debug!("unhandled err: {e}");
+				None
+			}
+		})
+	};
+	let show_error = move || error_desc().is_some();
+	let submit_totp = create_server_action::<SubmitTotp>();
+
+	view! {
+		<section class="container login-box">
+			{move || match (ctx.get().as_deref(), err.get().as_deref()) {
+				(None, _) | (Some(""), _) => view! { <NoContext /> }.into_view(),
+				(_, Some("no_context")) => view! { <NoContext /> }.into_view(),
+				(_, Some("invalid_context")) => view! { <BadContext /> }.into_view(),
+				_ => view! {
+					<ActionForm action=submit_totp attributes=vec![("id", "totp-form".into_attribute())]>
+						<input type="hidden" name="ctx" value=move || ctx.get() />
+						<label for="totp-input">"Enter the code from your authenticator app"</label>
+						<TotpInput show_error />
+						{move || if show_error() {
+							view! {
+								<small id="totp-error" class="error-text">{move || error_desc()}</small>
+							}.into_view()
+						} else {
+							view! {}.into_view()
+						}}
+						<input type="submit" value="Verify" />
+					</ActionForm>
+					<UseRecoveryCode ctx />
+				}.into_view(),
+			}}
+		</section>
+	}
+}
+
+#[component]
+fn TotpInput<F>(show_error: F) -> impl IntoView
+where
+	F: Fn() -> bool + Copy + 'static,
+{
+	view! {
+		<input id="totp-input" type="text" name="code"
+			inputmode="numeric" pattern="[0-9]*" autocomplete="one-time-code"
+			required
+			aria-invalid={move || if show_error() { "true" } else { "false" }}
+			aria-errormessage={move || if show_error() { "totp-error" } else { "" }}
+		/>
+	}
+}
+
+#[server(SubmitTotp, "/authenticate", "Url", "submit_totp")]
+async fn submit_totp(ctx: String, code: String) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_submit_totp(ctx, code, cfg.into_inner())
+		.await
+		.tap_err(|e| tracing::warn!("failed to process submitted TOTP code: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_submit_totp(ctx: String, code: String, cfg: Arc<Config>) -> Result<(), Error> {
+	let ctx = match AuthContext::from_str(&ctx, &cfg) {
+		Ok(ctx) => ctx,
+		Err(e) => {
+			tracing::debug!("invalid auth context: {e}");
+			return totp_failed(&cfg, "authenticate/totp", &ctx, "invalid_context");
+		}
+	};
+
+	// Having a principal in the context isn't enough; the email form puts one there before the
+	// password has been checked
+	let (Some(uid), Some(_)) = (ctx.principal(), ctx.auth_time()) else {
+		tracing::debug!("TOTP code submitted before primary authentication was completed");
+		return totp_failed(
+			&cfg,
+			"authenticate/totp",
+			&ctx.to_string(),
+			"invalid_context",
+		);
+	};
+
+	let creds = cfg.db().totp_credential().await?;
+	let Some(cred) = creds.find_by_principal_id(uid).await? else {
+		tracing::debug!("TOTP code submitted for principal with no TOTP credential");
+		return totp_failed(
+			&cfg,
+			"authenticate/totp",
+			&ctx.to_string(),
+			"invalid_context",
+		);
+	};
+
+	if !creds
+		.start_attempt(
+			cred.id(),
+			TOTP_MAX_ATTEMPTS,
+			OffsetDateTime::now_utc() + TOTP_LOCKOUT,
+		)
+		.await?
+	{
+		tracing::debug!("TOTP code submitted while credential is locked out");
+		return totp_failed(&cfg, "authenticate/totp", &ctx.to_string(), "locked_out");
+	}
+
+	let secret = cfg.totp_secret_strong_box().decrypt(cred.secret(), b"")?;
+
+	match matching_step(&totp(secret, &ctx)?, &code) {
+		Some(step) if creds.use_step(cred.id(), step).await? => {
+			redirect(second_factor_complete(&cfg, &ctx).await?.as_str());
+			Ok(())
+		}
+		Some(_) => {
+			tracing::debug!("rejecting replayed TOTP code");
+			totp_failed(&cfg, "authenticate/totp", &ctx.to_string(), "wrong_code")
+		}
+		None => totp_failed(&cfg, "authenticate/totp", &ctx.to_string(), "wrong_code"),
+	}
+}
+
+/// What the user needs to know to add their new TOTP credential to their authenticator app
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct TotpEnrolment {
+	secret: String,
+	url: String,
+}
+
+#[component]
+pub(crate) fn TotpEnrol() -> impl IntoView {
+	#[derive(Clone, Debug, Default, Params, PartialEq)]
+	struct QueryParams {
+		ctx: Option<String>,
+		err: Option<String>,
+	}
+
+	let params = use_query::<QueryParams>();
+
+	let ctx = (move || params.get().map(|params| params.ctx).unwrap_or(None)).into_signal();
+	let err = (move || params.get().map(|params| params.err).unwrap_or(None)).into_signal();
+
+	let enrolment = create_blocking_resource(
+		move || ctx.get(),
+		move |ctx| async move { totp_enrolment(ctx).await.unwrap_or(None) },
+	);
+
+	let error_desc = move || {
+		err.get().map_or(None, |s| match s.as_str() {
+			"wrong_code" => Some(
+				"That code is not correct; please check your authenticator app is set up correctly",
+			),
+			e => {
+				tracing::debug!("unhandled err: {e}");
+				None
+			}
+		})
+	};
+	let show_error = move || error_desc().is_some();
+	let start_totp_enrolment = create_server_action::<StartTotpEnrolment>();
+	let confirm_totp_enrolment = create_server_action::<ConfirmTotpEnrolment>();
+
+	view! {
+		<section class="container login-box">
+			{move || match (ctx.get().as_deref(), err.get().as_deref()) {
+				(None, _) | (Some(""), _) => view! { <NoContext /> }.into_view(),
+				(_, Some("no_context")) => view! { <NoContext /> }.into_view(),
+				(_, Some("invalid_context")) => view! { <BadContext /> }.into_view(),
+				_ => view! {
+					<h1>"Set up two-factor authentication"</h1>
+					<Suspense fallback=|| view! {}>
+						{move || match enrolment.get().flatten() {
+							Some(e) => view! {
+								<p>
+									"To use an authenticator app, add your account to the app using the link or the secret key below, then enter the code it shows you."
+								</p>
+								<p><a id="totp-url" href=e.url>"Add to authenticator app"</a></p>
+								<p>"Secret key: " <code id="totp-secret">{e.secret}</code></p>
+								<ActionForm action=confirm_totp_enrolment attributes=vec![("id", "totp-enrol-form".into_attribute())]>
+									<input type="hidden" name="ctx" value=move || ctx.get() />
+									<label for="totp-input">"Enter the code from your authenticator app"</label>
+									<TotpInput show_error />
+									{move || if show_error() {
+										view! {
+											<small id="totp-error" class="error-text">{move || error_desc()}</small>
+										}.into_view()
+									} else {
+										view! {}.into_view()
+									}}
+									<input type="submit" value="Verify" />
+								</ActionForm>
+							}.into_view(),
+							// Someone who has chosen to set up a second factor, rather than being
+							// made to, doesn't get a secret until they ask for one
+							None => view! {
+								<p>"With a second factor, knowing your password isn't enough to sign in as you."</p>
+								<ActionForm action=start_totp_enrolment attributes=vec![("id", "totp-start-form".into_attribute())]>
+									<input type="hidden" name="ctx" value=move || ctx.get() />
+									<input type="submit" value="Use an authenticator app" />
+								</ActionForm>
+							}.into_view(),
+						}}
+					</Suspense>
+					<p>
+						<a id="webauthn-register-link" href=move || ctx.get().map(|ctx| format!("/authenticate/webauthn/register?ctx={ctx}"))>
+							"Use a security key or passkey instead"
+						</a>
+					</p>
+				}.into_view(),
+			}}
+		</section>
+	}
+}
+
+#[server(TotpEnrolmentDetails)]
+async fn totp_enrolment(ctx: Option<String>) -> Result<Option<TotpEnrolment>, ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	let Some(ctx) = ctx else {
+		tracing::debug!("No context");
+		return Ok(None);
+	};
+
+	let Ok(ctx) = AuthContext::from_str(&ctx, &cfg) else {
+		tracing::debug!("Busted context");
+		return Ok(None);
+	};
+
+	let (Some(_), Some(secret)) = (ctx.auth_time(), ctx.totp_secret()) else {
+		tracing::debug!("Context not ready for TOTP enrolment");
+		return Ok(None);
+	};
+
+	let totp = totp(secret.clone(), &ctx)?;
+
+	Ok(Some(TotpEnrolment {
+		secret: totp.get_secret_base32(),
+		url: totp.get_url(),
+	}))
+}
+
+#[server(StartTotpEnrolment, "/authenticate", "Url", "start_totp_enrolment")]
+async fn start_totp_enrolment(ctx: String) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_start_totp_enrolment(ctx, cfg.into_inner())
+		.await
+		.tap_err(|e| tracing::warn!("failed to start TOTP enrolment: {e}"))?)
+}
+
+/// For users who want a second factor even though no client is making them have one.  Only
+/// someone who has only just signed in gets to, so that an old context that has been left lying
+/// around can't be used to put someone else's authenticator on the account.
+#[cfg(feature = "ssr")]
+async fn process_start_totp_enrolment(ctx: String, cfg: Arc<Config>) -> Result<(), Error> {
+	let mut ctx = match AuthContext::from_str(&ctx, &cfg) {
+		Ok(ctx) => ctx,
+		Err(e) => {
+			tracing::debug!("invalid auth context: {e}");
+			return totp_failed(&cfg, "authenticate/totp/enrol", &ctx, "invalid_context");
+		}
+	};
+
+	if !may_enrol_second_factor(&cfg, &ctx).await? {
+		tracing::debug!("context cannot start TOTP enrolment");
+		return totp_failed(
+			&cfg,
+			"authenticate/totp/enrol",
+			&ctx.to_string(),
+			"invalid_context",
+		);
+	}
+
+	ctx.set_totp_secret(new_totp_secret());
+
+	let mut redirect_url = cfg.base_url().join("authenticate/totp/enrol")?;
+	redirect_url
+		.query_pairs_mut()
+		.append_pair("ctx", &ctx.to_string());
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
+
+#[server(ConfirmTotpEnrolment, "/authenticate", "Url", "confirm_totp_enrolment")]
+async fn confirm_totp_enrolment(ctx: String, code: String) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_confirm_totp_enrolment(ctx, code, cfg.into_inner())
+		.await
+		.tap_err(|e| tracing::warn!("failed to process TOTP enrolment: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_confirm_totp_enrolment(
+	ctx: String,
+	code: String,
+	cfg: Arc<Config>,
+) -> Result<(), Error> {
+	let ctx = match AuthContext::from_str(&ctx, &cfg) {
+		Ok(ctx) => ctx,
+		Err(e) => {
+			tracing::debug!("invalid auth context: {e}");
+			return totp_failed(&cfg, "authenticate/totp/enrol", &ctx, "invalid_context");
+		}
+	};
+
+	let (Some(uid), Some(_), Some(secret)) = (ctx.principal(), ctx.auth_time(), ctx.totp_secret())
+	else {
+		tracing::debug!("TOTP enrolment submitted with incomplete context");
+		return totp_failed(
+			&cfg,
+			"authenticate/totp/enrol",
+			&ctx.to_string(),
+			"invalid_context",
+		);
+	};
+
+	let Some(step) = matching_step(&totp(secret.clone(), &ctx)?, &code) else {
+		return totp_failed(
+			&cfg,
+			"authenticate/totp/enrol",
+			&ctx.to_string(),
+			"wrong_code",
+		);
+	};
+
+	// Enrolment must never add to (or replace) an existing second factor, otherwise anyone who
+	// knew the password could swap in their own authenticator
+	if has_second_factor(&cfg, uid).await? {
+		tracing::debug!("principal already has a second factor");
+		redirect(
+			primary_authentication_complete(&cfg, &ctx, ctx.attrs().cloned().unwrap_or_default())
+				.await?
+				.as_str(),
+		);
+		return Ok(());
+	}
+
+	let principal = cfg.db().principal().await?.find(uid).await?;
+	let saved = cfg
+		.db()
+		.totp_credential()
+		.await?
+		.new()
+		.with_principal(principal)
+		.with_secret(cfg.totp_secret_strong_box().encrypt(secret, b"")?)
+		.with_last_used_step(step)
+		.save()
+		.await;
+
+	match saved {
+		Ok(_) => {
+			redirect(second_factor_complete(&cfg, &ctx).await?.as_str());
+			Ok(())
+		}
+		// Lost a race with another enrolment
+		Err(e) if e.is_unique_violation() => {
+			tracing::debug!("principal already has a TOTP credential");
+			let mut redirect_url = cfg.base_url().join("authenticate/totp")?;
+			redirect_url
+				.query_pairs_mut()
+				.append_pair("ctx", &ctx.to_string());
+			redirect(redirect_url.as_str());
+			Ok(())
+		}
+		Err(e) => Err(e.into()),
+	}
+}
+
+/// Generate a secret for a brand-new TOTP credential
+#[cfg(feature = "ssr")]
+pub(super) fn new_totp_secret() -> Vec<u8> {
+	let mut secret = vec![0u8; 20];
+	rand::thread_rng().fill_bytes(&mut secret);
+	secret
+}
+
+#[cfg(feature = "ssr")]
+fn totp(secret: Vec<u8>, ctx: &AuthContext) -> Result<TOTP, Error> {
+	// Authenticator apps show the account name to tell credentials apart, and it isn't allowed
+	// to contain colons
+	let account = ctx
+		.email()
+		.map_or_else(|| "Authul".to_string(), |e| e.replace(':', ""));
+
+	Ok(TOTP::new(
+		Algorithm::SHA1,
+		6,
+		0,
+		TOTP_STEP,
+		secret,
+		Some("Authul".to_string()),
+		account,
+	)?)
+}
+
+/// Find the time step for which the given code is valid, allowing for one step of clock skew in
+/// either direction
+#[cfg(feature = "ssr")]
+fn matching_step(totp: &TOTP, code: &str) -> Option<i64> {
+	let code = code.trim();
+	let now = OffsetDateTime::now_utc().unix_timestamp() / TOTP_STEP as i64;
+
+	[now - 1, now, now + 1]
+		.into_iter()
+		.find(|step| totp.check(code, *step as u64 * TOTP_STEP))
+}
+
+#[cfg(feature = "ssr")]
+fn totp_failed(cfg: &Config, path: &str, ctx: &str, err: &str) -> Result<(), Error> {
+	let mut redirect_url: Url = cfg.base_url().join(path)?;
+	redirect_url
+		.query_pairs_mut()
+		.append_pair("ctx", ctx)
+		.append_pair("err", err);
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
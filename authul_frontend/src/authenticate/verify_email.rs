@@ -0,0 +1,217 @@
+use leptos::{
+	component, create_server_action, server, use_context, view, IntoAttribute, IntoSignal,
+	IntoView, Params, ServerFnError, SignalGet as _,
+};
+use leptos_router::{use_query, ActionForm, Params, Route};
+
+cfg_if::cfg_if! {
+	if #[cfg(feature = "ssr")] {
+		use actix_web::web::Data;
+		use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD as BASE64};
+		use leptos_actix::{extract, redirect};
+		use serde::{Deserialize, Serialize};
+		use std::sync::Arc;
+		use tap::prelude::*;
+		use time::OffsetDateTime;
+		use url::Url;
+		use uuid::Uuid;
+
+		use crate::db::{self, model::User};
+		use super::{Config, Error};
+	}
+}
+
+use super::RenderConfig;
+
+#[component(transparent)]
+pub(crate) fn VerifyEmailRoutes() -> impl IntoView {
+	let render_config = use_context::<RenderConfig>().expect("no RenderConfig available");
+
+	if render_config.password_auth {
+		view! {
+			<Route path="verify_email" view=VerifyEmail />
+		}
+		.into_view()
+	} else {
+		view! {}.into_view()
+	}
+}
+
+#[component]
+pub(crate) fn VerifyEmail() -> impl IntoView {
+	#[derive(Clone, Debug, Default, Params, PartialEq)]
+	struct QueryParams {
+		token: Option<String>,
+		err: Option<String>,
+		status: Option<String>,
+	}
+
+	let params = use_query::<QueryParams>();
+
+	let token = (move || params.get().map(|params| params.token).unwrap_or(None)).into_signal();
+	let err = (move || params.get().map(|params| params.err).unwrap_or(None)).into_signal();
+	let status = (move || params.get().map(|params| params.status).unwrap_or(None)).into_signal();
+
+	let confirm_email = create_server_action::<ConfirmEmail>();
+
+	view! {
+		<section class="container login-box">
+			{move || match (status.get().as_deref(), err.get().as_deref(), token.get().as_deref()) {
+				(Some("verified"), _, _) => view! {
+					<p id="verification-success">
+						"Thanks!  Your email address has been confirmed."
+					</p>
+				}.into_view(),
+				(_, Some("expired_token"), _) => view! {
+					<p id="verification-error" class="error-text">
+						"That confirmation link has expired."
+					</p>
+				}.into_view(),
+				(_, Some(_), _) | (_, _, None) | (_, _, Some("")) => view! {
+					<p id="verification-error" class="error-text">
+						"That confirmation link is not valid.  Please check that you copied the whole link from the email we sent you."
+					</p>
+				}.into_view(),
+				_ => view! {
+					<ActionForm action=confirm_email attributes=vec![("id", "verify-email-form".into_attribute())]>
+						<input type="hidden" name="token" value=move || token.get() />
+						<p>"Please confirm that you want to verify your email address."</p>
+						<input type="submit" value="Confirm email address" />
+					</ActionForm>
+				}.into_view(),
+			}}
+		</section>
+	}
+}
+
+#[server(ConfirmEmail, "/authenticate", "Url", "confirm_email")]
+async fn confirm_email(token: String) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_confirm_email(token, cfg.into_inner())
+		.await
+		.tap_err(|e| tracing::warn!("failed to process email confirmation: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_confirm_email(token: String, cfg: Arc<Config>) -> Result<(), Error> {
+	let token = match VerificationToken::from_str(&token, &cfg) {
+		Ok(token) => token,
+		Err(e) => {
+			tracing::debug!("invalid verification token: {e}");
+			return verification_result(&cfg, "err", "invalid_token");
+		}
+	};
+
+	if token.expires_at < OffsetDateTime::now_utc().unix_timestamp() {
+		tracing::debug!("verification token for {} has expired", token.email);
+		return verification_result(&cfg, "err", "expired_token");
+	}
+
+	let users = cfg.db().user().await?;
+	let mut user = match users.find(&token.user_id).await {
+		Ok(user) => user,
+		Err(db::Error::NotFound(..)) => {
+			tracing::debug!("verification token for unknown user {}", token.user_id);
+			return verification_result(&cfg, "err", "invalid_token");
+		}
+		Err(e) => return Err(e.into()),
+	};
+
+	// If the user has changed their address since the link was sent, the link is no longer
+	// proof of anything
+	if user.email() != &token.email {
+		tracing::debug!("verification token email does not match user's current email");
+		return verification_result(&cfg, "err", "invalid_token");
+	}
+
+	user.update_email_verified(true);
+	user.save(&users).await?;
+
+	verification_result(&cfg, "status", "verified")
+}
+
+#[cfg(feature = "ssr")]
+fn verification_result(cfg: &Config, k: &str, v: &str) -> Result<(), Error> {
+	let mut redirect_url = cfg.base_url().join("authenticate/verify_email")?;
+	redirect_url.query_pairs_mut().append_pair(k, v);
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
+
+/// Send the user an email containing a link they can follow to prove that they control the
+/// address they registered with.
+#[cfg(feature = "ssr")]
+#[tracing::instrument(level = "debug", skip(cfg, user), fields(user_id = %user.id()))]
+pub(super) async fn send_verification_email(cfg: &Arc<Config>, user: &User) -> Result<(), Error> {
+	let Some(mailer) = cfg.mailer() else {
+		tracing::debug!("no mail transport configured; not sending verification email");
+		return Ok(());
+	};
+
+	let token = VerificationToken {
+		user_id: *user.id(),
+		email: user.email().clone(),
+		expires_at: (OffsetDateTime::now_utc() + Config::EMAIL_VERIFICATION_LINK_VALIDITY)
+			.unix_timestamp(),
+	};
+
+	let mut link = cfg.base_url().join("authenticate/verify_email")?;
+	link.query_pairs_mut()
+		.append_pair("token", &token.to_string(cfg)?);
+
+	mailer
+		.send(
+			user.email(),
+			"Please confirm your email address",
+			verification_email_body(&link),
+		)
+		.await
+}
+
+#[cfg(feature = "ssr")]
+fn verification_email_body(link: &Url) -> String {
+	format!(
+		"Hello!\n\
+		\n\
+		An account was just created using this email address.  To confirm that the address \
+		belongs to you, please visit the following link:\n\
+		\n\
+		{link}\n\
+		\n\
+		The link will stop working after one day.  If you didn't create an account, you can \
+		safely ignore this message.\n"
+	)
+}
+
+/// The (encrypted) contents of the link we send to users to verify their email address
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct VerificationToken {
+	user_id: Uuid,
+	email: String,
+	expires_at: i64,
+}
+
+#[cfg(feature = "ssr")]
+impl VerificationToken {
+	fn from_str(s: &str, cfg: &Config) -> Result<Self, Error> {
+		let ciphertext = BASE64.decode(s)?;
+		let serialized = cfg
+			.email_verification_strong_box()
+			.decrypt(&ciphertext, b"")?;
+
+		Ok(ciborium::from_reader(&serialized[..])?)
+	}
+
+	fn to_string(&self, cfg: &Config) -> Result<String, Error> {
+		let mut serialized = vec![];
+		ciborium::into_writer(self, &mut serialized)?;
+
+		Ok(BASE64.encode(
+			cfg.email_verification_strong_box()
+				.encrypt(serialized, b"")?,
+		))
+	}
+}
@@ -0,0 +1,739 @@
+use leptos::{
+	component, create_blocking_resource, server, view, IntoSignal, IntoView, Params, ServerFnError,
+	Signal, SignalGet as _, Suspense,
+};
+use leptos_router::{use_query, Outlet, Params, Route, SsrMode};
+use serde::{Deserialize, Serialize};
+
+cfg_if::cfg_if! {
+	if #[cfg(feature = "ssr")] {
+		use actix_web::web::Data;
+		use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD as BASE64};
+		use leptos_actix::{extract, redirect};
+		use rand::RngCore as _;
+		use serde_json::json;
+		use std::sync::Arc;
+		use tap::prelude::*;
+		use time::OffsetDateTime;
+		use url::Url;
+		use uuid::Uuid;
+
+		use authul_crypto::CoseKey;
+		use crate::webauthn;
+		use super::{
+			has_recent_second_factor, has_second_factor, password_auth::user_attributes,
+			second_factor_complete, AuthContext, Config, Error,
+		};
+	}
+}
+
+use super::{recovery_code::UseRecoveryCode, BadContext, NoContext};
+
+/// How long the browser should give the user to deal with their authenticator
+#[cfg(feature = "ssr")]
+const CEREMONY_TIMEOUT_MS: u32 = 300_000;
+
+#[component(transparent)]
+pub(crate) fn WebauthnRoutes() -> impl IntoView {
+	view! {
+		<Route path="webauthn" view=move || view! { <Outlet/> }>
+			<Route path="register" view=WebauthnRegister ssr=SsrMode::PartiallyBlocked />
+			<Route path="" view=Webauthn ssr=SsrMode::PartiallyBlocked />
+		</Route>
+	}
+}
+
+/// Everything the browser needs to perform a WebAuthn ceremony, and send us the result
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Ceremony {
+	// The auth context, with the ceremony's challenge in it
+	ctx: String,
+	// What to pass to `navigator.credentials.create()` or `.get()`, as JSON
+	options: String,
+}
+
+/// The button that kicks off a ceremony.  All the real work is done by `webauthn.js`, which
+/// submits the authenticator's response to `action` as an ordinary form post.
+#[component]
+fn CeremonyButton(
+	id: &'static str,
+	mode: &'static str,
+	action: &'static str,
+	label: &'static str,
+	ceremony: Ceremony,
+) -> impl IntoView {
+	view! {
+		<button type="button" id=id
+			data-webauthn=mode
+			data-webauthn-action=action
+			data-webauthn-ctx=ceremony.ctx
+			data-webauthn-options=ceremony.options
+		>
+			{label}
+		</button>
+		<small class="error-text webauthn-error" hidden>
+			"Your browser wasn't able to use your security key or passkey.  Please try again."
+		</small>
+		<script src="/webauthn.js" defer></script>
+	}
+}
+
+/// The "Sign in with a passkey" button on the main authentication page
+#[component]
+pub(crate) fn AuthenticateWithPasskey(
+	ctx: Signal<Option<String>>,
+	err: Signal<Option<String>>,
+) -> impl IntoView {
+	let ceremony = create_blocking_resource(
+		move || ctx.get(),
+		move |ctx| async move { passkey_ceremony(ctx).await.unwrap_or(None) },
+	);
+
+	view! {
+		<Suspense fallback=|| view! {}>
+			{move || ceremony.get().flatten().map(|ceremony| view! {
+				<div class="passkey">
+					<CeremonyButton
+						id="passkey-button"
+						mode="get"
+						action="/authenticate/submit_passkey"
+						label="Sign in with a passkey"
+						ceremony
+					/>
+					{move || (err.get().as_deref() == Some("passkey_failed")).then(|| view! {
+						<small id="passkey-error" class="error-text">
+							"We couldn't sign you in with that passkey.  Please try again, or sign in another way."
+						</small>
+					})}
+				</div>
+			})}
+		</Suspense>
+	}
+}
+
+#[server(PasskeyCeremony)]
+async fn passkey_ceremony(ctx: Option<String>) -> Result<Option<Ceremony>, ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	let Some(ctx) = ctx else {
+		tracing::debug!("No context");
+		return Ok(None);
+	};
+
+	let Ok(ctx) = AuthContext::from_str(&ctx, &cfg) else {
+		tracing::debug!("Busted context");
+		return Ok(None);
+	};
+
+	// No allowCredentials, because we don't know who the user is yet; the authenticator
+	// offers them whatever passkeys they have for this site
+	Ok(Some(
+		start_ceremony(&cfg, ctx, |challenge, rp_id| {
+			json!({
+				"challenge": challenge,
+				"rpId": rp_id,
+				"userVerification": "required",
+				"timeout": CEREMONY_TIMEOUT_MS,
+			})
+		})
+		.await?,
+	))
+}
+
+#[server(SubmitPasskey, "/authenticate", "Url", "submit_passkey")]
+async fn submit_passkey(
+	ctx: String,
+	credential_id: String,
+	client_data_json: String,
+	authenticator_data: String,
+	signature: String,
+	user_handle: Option<String>,
+) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_submit_passkey(
+		ctx,
+		Assertion {
+			credential_id,
+			client_data_json,
+			authenticator_data,
+			signature,
+		},
+		user_handle,
+		cfg.into_inner(),
+	)
+	.await
+	.tap_err(|e| tracing::warn!("failed to process passkey sign-in: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_submit_passkey(
+	ctx: String,
+	assertion: Assertion,
+	user_handle: Option<String>,
+	cfg: Arc<Config>,
+) -> Result<(), Error> {
+	let mut ctx = match AuthContext::from_str(&ctx, &cfg) {
+		Ok(ctx) => ctx,
+		Err(e) => {
+			tracing::debug!("invalid auth context: {e}");
+			return webauthn_failed(&cfg, "authenticate", &ctx, "invalid_context");
+		}
+	};
+
+	let Some(cred) = check_assertion(&cfg, &ctx, &assertion, true).await? else {
+		return webauthn_failed(&cfg, "authenticate", &ctx.to_string(), "passkey_failed");
+	};
+
+	// The user handle is the principal ID we gave the authenticator when the passkey was
+	// registered, so it had better match up with the credential
+	if let Some(user_handle) = user_handle {
+		if BASE64.decode(user_handle).ok().as_deref() != Some(&cred.principal().id().as_bytes()[..])
+		{
+			tracing::debug!("passkey user handle does not match credential's principal");
+			return webauthn_failed(&cfg, "authenticate", &ctx.to_string(), "passkey_failed");
+		}
+	}
+
+	let users = cfg
+		.db()
+		.user()
+		.await?
+		.find_all_by_principal(cred.principal())
+		.await?;
+
+	ctx.set_principal(*cred.principal().id());
+	ctx.set_auth_time(OffsetDateTime::now_utc().unix_timestamp());
+	if let Some(user) = users.first() {
+		ctx.set_email(user.email());
+		ctx.set_attrs(user_attributes(user));
+	}
+
+	// A passkey is something you have, unlocked by something you know or are, so it counts as
+	// both factors at once
+	redirect(second_factor_complete(&cfg, &ctx).await?.as_str());
+
+	Ok(())
+}
+
+/// The page for using a security key or passkey as a second factor
+#[component]
+pub(crate) fn Webauthn() -> impl IntoView {
+	#[derive(Clone, Debug, Default, Params, PartialEq)]
+	struct QueryParams {
+		ctx: Option<String>,
+		err: Option<String>,
+	}
+
+	let params = use_query::<QueryParams>();
+
+	let ctx = (move || params.get().map(|params| params.ctx).unwrap_or(None)).into_signal();
+	let err = (move || params.get().map(|params| params.err).unwrap_or(None)).into_signal();
+
+	let ceremony = create_blocking_resource(
+		move || ctx.get(),
+		move |ctx| async move { webauthn_assertion_ceremony(ctx).await.unwrap_or(None) },
+	);
+
+	view! {
+		<section class="container login-box">
+			{move || match (ctx.get().as_deref(), err.get().as_deref()) {
+				(None, _) | (Some(""), _) => view! { <NoContext /> }.into_view(),
+				(_, Some("no_context")) => view! { <NoContext /> }.into_view(),
+				(_, Some("invalid_context")) => view! { <BadContext /> }.into_view(),
+				_ => view! {
+					<h1>"Use your security key"</h1>
+					<p>"To finish signing in, please use the security key or passkey you set up for this account."</p>
+					{move || (err.get().as_deref() == Some("webauthn_failed")).then(|| view! {
+						<p id="webauthn-failed" class="error-text">"That didn't work.  Please try again."</p>
+					})}
+					<Suspense fallback=|| view! {}>
+						{move || ceremony.get().flatten().map(|ceremony| view! {
+							<CeremonyButton
+								id="webauthn-button"
+								mode="get"
+								action="/authenticate/submit_webauthn"
+								label="Use security key"
+								ceremony
+							/>
+						})}
+					</Suspense>
+					<UseRecoveryCode ctx />
+				}.into_view(),
+			}}
+		</section>
+	}
+}
+
+#[server(WebauthnAssertionCeremony)]
+async fn webauthn_assertion_ceremony(
+	ctx: Option<String>,
+) -> Result<Option<Ceremony>, ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	let Some(ctx) = ctx else {
+		tracing::debug!("No context");
+		return Ok(None);
+	};
+
+	let Ok(ctx) = AuthContext::from_str(&ctx, &cfg) else {
+		tracing::debug!("Busted context");
+		return Ok(None);
+	};
+
+	let (Some(uid), Some(_)) = (ctx.principal(), ctx.auth_time()) else {
+		tracing::debug!("Context not ready for second factor");
+		return Ok(None);
+	};
+
+	let allowed = cfg
+		.db()
+		.webauthn_credential()
+		.await?
+		.find_all_by_principal_id(uid)
+		.await?
+		.iter()
+		.map(|c| json!({ "type": "public-key", "id": BASE64.encode(c.credential_id()) }))
+		.collect::<Vec<_>>();
+
+	if allowed.is_empty() {
+		tracing::debug!("principal has no WebAuthn credentials");
+		return Ok(None);
+	}
+
+	Ok(Some(
+		start_ceremony(&cfg, ctx, |challenge, rp_id| {
+			json!({
+				"challenge": challenge,
+				"rpId": rp_id,
+				"allowCredentials": allowed,
+				"userVerification": "discouraged",
+				"timeout": CEREMONY_TIMEOUT_MS,
+			})
+		})
+		.await?,
+	))
+}
+
+#[server(SubmitWebauthn, "/authenticate", "Url", "submit_webauthn")]
+async fn submit_webauthn(
+	ctx: String,
+	credential_id: String,
+	client_data_json: String,
+	authenticator_data: String,
+	signature: String,
+) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_submit_webauthn(
+		ctx,
+		Assertion {
+			credential_id,
+			client_data_json,
+			authenticator_data,
+			signature,
+		},
+		cfg.into_inner(),
+	)
+	.await
+	.tap_err(|e| tracing::warn!("failed to process WebAuthn second factor: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_submit_webauthn(
+	ctx: String,
+	assertion: Assertion,
+	cfg: Arc<Config>,
+) -> Result<(), Error> {
+	let ctx = match AuthContext::from_str(&ctx, &cfg) {
+		Ok(ctx) => ctx,
+		Err(e) => {
+			tracing::debug!("invalid auth context: {e}");
+			return webauthn_failed(&cfg, "authenticate/webauthn", &ctx, "invalid_context");
+		}
+	};
+
+	let (Some(uid), Some(_)) = (ctx.principal(), ctx.auth_time()) else {
+		tracing::debug!("WebAuthn assertion submitted before primary authentication was completed");
+		return webauthn_failed(
+			&cfg,
+			"authenticate/webauthn",
+			&ctx.to_string(),
+			"invalid_context",
+		);
+	};
+
+	match check_assertion(&cfg, &ctx, &assertion, false).await? {
+		Some(cred) if cred.principal().id() == uid => {
+			redirect(second_factor_complete(&cfg, &ctx).await?.as_str());
+			Ok(())
+		}
+		Some(_) => {
+			tracing::debug!("WebAuthn credential belongs to a different principal");
+			webauthn_failed(
+				&cfg,
+				"authenticate/webauthn",
+				&ctx.to_string(),
+				"webauthn_failed",
+			)
+		}
+		None => webauthn_failed(
+			&cfg,
+			"authenticate/webauthn",
+			&ctx.to_string(),
+			"webauthn_failed",
+		),
+	}
+}
+
+/// The page for setting up a security key or passkey
+#[component]
+pub(crate) fn WebauthnRegister() -> impl IntoView {
+	#[derive(Clone, Debug, Default, Params, PartialEq)]
+	struct QueryParams {
+		ctx: Option<String>,
+		err: Option<String>,
+	}
+
+	let params = use_query::<QueryParams>();
+
+	let ctx = (move || params.get().map(|params| params.ctx).unwrap_or(None)).into_signal();
+	let err = (move || params.get().map(|params| params.err).unwrap_or(None)).into_signal();
+
+	let ceremony = create_blocking_resource(
+		move || ctx.get(),
+		move |ctx| async move { webauthn_registration_ceremony(ctx).await.unwrap_or(None) },
+	);
+
+	view! {
+		<section class="container login-box">
+			{move || match (ctx.get().as_deref(), err.get().as_deref()) {
+				(None, _) | (Some(""), _) => view! { <NoContext /> }.into_view(),
+				(_, Some("no_context")) => view! { <NoContext /> }.into_view(),
+				(_, Some("invalid_context")) => view! { <BadContext /> }.into_view(),
+				_ => view! {
+					<h1>"Set up a security key or passkey"</h1>
+					{move || (err.get().as_deref() == Some("webauthn_failed")).then(|| view! {
+						<p id="webauthn-failed" class="error-text">"That didn't work.  Please try again."</p>
+					})}
+					<Suspense fallback=|| view! {}>
+						{move || match ceremony.get().flatten() {
+							Some(ceremony) => view! {
+								<p>"You'll be asked to use your security key or passkey whenever you sign in."</p>
+								<CeremonyButton
+									id="webauthn-register-button"
+									mode="create"
+									action="/authenticate/submit_webauthn_registration"
+									label="Set up security key"
+									ceremony
+								/>
+							}.into_view(),
+							None => view! {
+								<p id="webauthn-unavailable">"You can't set up a security key or passkey right now."</p>
+							}.into_view(),
+						}}
+					</Suspense>
+				}.into_view(),
+			}}
+		</section>
+	}
+}
+
+#[server(WebauthnRegistrationCeremony)]
+async fn webauthn_registration_ceremony(
+	ctx: Option<String>,
+) -> Result<Option<Ceremony>, ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	let Some(ctx) = ctx else {
+		tracing::debug!("No context");
+		return Ok(None);
+	};
+
+	let Ok(ctx) = AuthContext::from_str(&ctx, &cfg) else {
+		tracing::debug!("Busted context");
+		return Ok(None);
+	};
+
+	let (Some(uid), Some(_)) = (ctx.principal().copied(), ctx.auth_time()) else {
+		tracing::debug!("Context not ready for WebAuthn registration");
+		return Ok(None);
+	};
+
+	if !may_register(&cfg, &ctx, &uid).await? {
+		tracing::debug!("principal already has a second factor, and hasn't just provided it");
+		return Ok(None);
+	}
+
+	// The browser won't let the user register an authenticator that they've already registered
+	let exclude_credentials = cfg
+		.db()
+		.webauthn_credential()
+		.await?
+		.find_all_by_principal_id(&uid)
+		.await?
+		.iter()
+		.map(|c| json!({ "type": "public-key", "id": BASE64.encode(c.credential_id()) }))
+		.collect::<Vec<_>>();
+
+	let name = ctx
+		.email()
+		.cloned()
+		.unwrap_or_else(|| "Authul user".to_string());
+
+	Ok(Some(
+		start_ceremony(&cfg, ctx, |challenge, rp_id| {
+			json!({
+				"rp": { "id": rp_id, "name": "Authul" },
+				"user": {
+					"id": BASE64.encode(uid.as_bytes()),
+					"name": name,
+					"displayName": name,
+				},
+				"challenge": challenge,
+				"excludeCredentials": exclude_credentials,
+				"pubKeyCredParams": [
+					{ "type": "public-key", "alg": CoseKey::ES256 },
+					{ "type": "public-key", "alg": CoseKey::EDDSA },
+				],
+				"authenticatorSelection": {
+					"residentKey": "preferred",
+					"userVerification": "preferred",
+				},
+				"attestation": "none",
+				"timeout": CEREMONY_TIMEOUT_MS,
+			})
+		})
+		.await?,
+	))
+}
+
+#[server(
+	SubmitWebauthnRegistration,
+	"/authenticate",
+	"Url",
+	"submit_webauthn_registration"
+)]
+async fn submit_webauthn_registration(
+	ctx: String,
+	client_data_json: String,
+	attestation_object: String,
+) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_submit_webauthn_registration(
+		ctx,
+		client_data_json,
+		attestation_object,
+		cfg.into_inner(),
+	)
+	.await
+	.tap_err(|e| tracing::warn!("failed to process WebAuthn registration: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_submit_webauthn_registration(
+	ctx: String,
+	client_data_json: String,
+	attestation_object: String,
+	cfg: Arc<Config>,
+) -> Result<(), Error> {
+	const PAGE: &str = "authenticate/webauthn/register";
+
+	let ctx = match AuthContext::from_str(&ctx, &cfg) {
+		Ok(ctx) => ctx,
+		Err(e) => {
+			tracing::debug!("invalid auth context: {e}");
+			return webauthn_failed(&cfg, PAGE, &ctx, "invalid_context");
+		}
+	};
+
+	let (Some(uid), Some(_), Some(challenge)) =
+		(ctx.principal(), ctx.auth_time(), ctx.webauthn_challenge())
+	else {
+		tracing::debug!("WebAuthn registration submitted with incomplete context");
+		return webauthn_failed(&cfg, PAGE, &ctx.to_string(), "invalid_context");
+	};
+
+	// Registration must never add to an existing second factor unless the user has just provided
+	// it, otherwise anyone who knew the password could add their own authenticator
+	if !may_register(&cfg, &ctx, uid).await? {
+		tracing::debug!("principal already has a second factor, and hasn't just provided it");
+		return webauthn_failed(&cfg, PAGE, &ctx.to_string(), "invalid_context");
+	}
+
+	let new_cred = match webauthn::verify_registration(
+		&cfg,
+		challenge,
+		&client_data_json,
+		&attestation_object,
+	) {
+		Ok(c) => c,
+		Err(e) => {
+			tracing::debug!("WebAuthn registration rejected: {e}");
+			return webauthn_failed(&cfg, PAGE, &ctx.to_string(), "webauthn_failed");
+		}
+	};
+
+	if !cfg
+		.db()
+		.webauthn_challenge()
+		.await?
+		.redeem(challenge)
+		.await?
+	{
+		tracing::debug!("WebAuthn challenge was already used, or has expired");
+		return webauthn_failed(&cfg, PAGE, &ctx.to_string(), "webauthn_failed");
+	}
+
+	let principal = cfg.db().principal().await?.find(uid).await?;
+	let saved = cfg
+		.db()
+		.webauthn_credential()
+		.await?
+		.new()
+		.with_principal(principal)
+		.with_credential_id(new_cred.credential_id)
+		.with_public_key(new_cred.public_key)
+		.with_sign_count(i64::from(new_cred.sign_count))
+		.save()
+		.await;
+
+	match saved {
+		Ok(_) => {
+			let mut ctx = ctx.clone();
+			ctx.clear_recovery_codes();
+			redirect(second_factor_complete(&cfg, &ctx).await?.as_str());
+			Ok(())
+		}
+		Err(e) if e.is_unique_violation() => {
+			tracing::debug!("WebAuthn credential ID is already registered");
+			webauthn_failed(&cfg, PAGE, &ctx.to_string(), "webauthn_failed")
+		}
+		Err(e) => Err(e.into()),
+	}
+}
+
+/// Whether the user can set up a security key or passkey: either they're setting up their first
+/// second factor, or they've just provided one, and are adding another
+#[cfg(feature = "ssr")]
+async fn may_register(cfg: &Config, ctx: &AuthContext, uid: &Uuid) -> Result<bool, Error> {
+	Ok(has_recent_second_factor(ctx) || !has_second_factor(cfg, uid).await?)
+}
+
+/// What the browser sends back from `navigator.credentials.get()`, base64url-encoded
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+struct Assertion {
+	credential_id: String,
+	client_data_json: String,
+	authenticator_data: String,
+	signature: String,
+}
+
+/// Verify an assertion against the challenge in the context, and the credential the browser says
+/// it used.  Returns the credential if everything checks out, or `None` if the assertion should be
+/// rejected.
+#[cfg(feature = "ssr")]
+async fn check_assertion(
+	cfg: &Config,
+	ctx: &AuthContext,
+	assertion: &Assertion,
+	require_user_verification: bool,
+) -> Result<Option<crate::db::model::WebauthnCredential>, Error> {
+	let Some(challenge) = ctx.webauthn_challenge() else {
+		tracing::debug!("WebAuthn assertion submitted without a challenge in the context");
+		return Ok(None);
+	};
+
+	let Ok(credential_id) = BASE64.decode(&assertion.credential_id) else {
+		tracing::debug!("unparseable credential ID");
+		return Ok(None);
+	};
+
+	let creds = cfg.db().webauthn_credential().await?;
+	let Some(cred) = creds.find_by_credential_id(&credential_id).await? else {
+		tracing::debug!("unknown WebAuthn credential");
+		return Ok(None);
+	};
+
+	let sign_count = match webauthn::verify_assertion(
+		cfg,
+		challenge,
+		cred.public_key(),
+		&assertion.client_data_json,
+		&assertion.authenticator_data,
+		&assertion.signature,
+		require_user_verification,
+	) {
+		Ok(n) => n,
+		Err(e) => {
+			tracing::debug!("WebAuthn assertion rejected: {e}");
+			return Ok(None);
+		}
+	};
+
+	if !cfg
+		.db()
+		.webauthn_challenge()
+		.await?
+		.redeem(challenge)
+		.await?
+	{
+		tracing::debug!("WebAuthn challenge was already used, or has expired");
+		return Ok(None);
+	}
+
+	if !creds
+		.update_sign_count(cred.id(), i64::from(sign_count))
+		.await?
+	{
+		tracing::warn!(
+			"signature counter for WebAuthn credential {} went backwards; it may have been cloned",
+			cred.id()
+		);
+		return Ok(None);
+	}
+
+	Ok(Some(cred))
+}
+
+/// Generate and record a new challenge, and put together everything the browser needs to
+/// perform a ceremony with it
+#[cfg(feature = "ssr")]
+async fn start_ceremony(
+	cfg: &Config,
+	mut ctx: AuthContext,
+	options: impl FnOnce(String, String) -> serde_json::Value,
+) -> Result<Ceremony, Error> {
+	let mut challenge = vec![0u8; 32];
+	rand::thread_rng().fill_bytes(&mut challenge);
+
+	cfg.db()
+		.webauthn_challenge()
+		.await?
+		.new()
+		.with_challenge(challenge.clone())
+		.save()
+		.await?;
+
+	let options = json!({ "publicKey": options(BASE64.encode(&challenge), webauthn::rp_id(cfg)?) });
+	ctx.set_webauthn_challenge(challenge);
+
+	Ok(Ceremony {
+		ctx: ctx.to_string(),
+		options: options.to_string(),
+	})
+}
+
+#[cfg(feature = "ssr")]
+fn webauthn_failed(cfg: &Config, path: &str, ctx: &str, err: &str) -> Result<(), Error> {
+	let mut redirect_url: Url = cfg.base_url().join(path)?;
+	redirect_url
+		.query_pairs_mut()
+		.append_pair("ctx", ctx)
+		.append_pair("err", err);
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
@@ -1,16 +1,22 @@
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac as _};
 use rand::Rng as _;
 use reqwest_middleware::ClientWithMiddleware;
 use reqwest_tracing::TracingMiddleware;
 use secrecy::{ExposeSecret, Secret};
 use sha2::{Digest, Sha256};
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 use strong_box::{RotatingStrongBox, StemStrongBox, StrongBox};
 use time::OffsetDateTime;
 use url::Url;
+use uuid::Uuid;
 use zxcvbn::zxcvbn;
 
 use super::Error;
-use crate::db;
+use crate::{
+	db::{self, model::OidcClient},
+	mail::{MailTransport, Mailer},
+};
 use authul_crypto::{Jwk, PublicJwk};
 use authul_oauth2::{provider, OAuthClientBuilder, OAuthProviderMap};
 
@@ -25,8 +31,9 @@ pub struct Config {
 
 	password_auth: bool,
 	dummy_pwhash: String,
-	//pwhash_cost: u32,
+	pwhash_cost: u32,
 	oauth_provider_map: OAuthProviderMap,
+	mailer: Option<Mailer>,
 }
 
 /// Associated constants
@@ -35,6 +42,18 @@ impl Config {
 	pub const AUTH_CONTEXT_ENCRYPTION_KEY_LIFESPAN: Duration = Duration::from_secs(3600); // aka "one hour"
 	pub const OAUTH_STATE_KEY_LIFESPAN: Duration = Duration::from_secs(3600); // aka "one hour"
 	pub const OAUTH_STATE_KEY_BACKTRACK: u16 = 4; // allow us to decrypt oauth states at least four hours old
+	pub const EMAIL_VERIFICATION_KEY_LIFESPAN: Duration = Duration::from_secs(86_400); // aka "one day"
+	pub const EMAIL_VERIFICATION_LINK_VALIDITY: Duration = Duration::from_secs(86_400); // aka "one day"
+	pub const ACCESS_TOKEN_VALIDITY_PERIOD: Duration = Duration::from_secs(3600); // aka "one hour"
+	pub const DEVICE_CODE_POLLING_INTERVAL: Duration = Duration::from_secs(5); // as per RFC8628 s3.2
+	/// All the different things we keep signing keys for, which are never used for anything else
+	pub const SIGNING_KEY_USAGES: [&'static str; 2] = ["oidc", "access_token"];
+	/// The name of the server secret that pairwise subject identifiers are made with
+	pub const PAIRWISE_SUBJECT_SECRET: &'static str = "pairwise_subject";
+	/// The name of the server secret that recovery codes are hashed with
+	pub const RECOVERY_CODE_SECRET: &'static str = "recovery_code";
+	/// Keeps more than one server from re-encrypting the server secrets at once
+	const SERVER_SECRETS_LOCK_ID: i32 = 1397420718;
 }
 
 impl Config {
@@ -58,6 +77,14 @@ impl Config {
 		&self.dummy_pwhash
 	}
 
+	pub fn pwhash_cost(&self) -> u32 {
+		self.pwhash_cost
+	}
+
+	pub fn mailer(&self) -> Option<&Mailer> {
+		self.mailer.as_ref()
+	}
+
 	pub fn db(&self) -> db::Pool {
 		self.db.clone()
 	}
@@ -85,6 +112,137 @@ impl Config {
 	pub fn oauth_identity_attribute_strong_box(&self) -> StrongBox {
 		self.root_keys.derive(b"OauthIdentity::Attribute")
 	}
+
+	pub fn totp_secret_strong_box(&self) -> StrongBox {
+		self.root_keys.derive(b"TotpCredential::secret")
+	}
+
+	pub fn email_verification_strong_box(&self) -> RotatingStrongBox {
+		self.root_keys.derive_rotating(
+			b"EmailVerification",
+			Config::EMAIL_VERIFICATION_KEY_LIFESPAN,
+			1,
+		)
+	}
+
+	pub fn access_token_principal_strong_box(&self) -> StrongBox {
+		self.root_keys.derive(b"AccessToken::principal")
+	}
+
+	pub fn session_attrs_strong_box(&self) -> StrongBox {
+		self.root_keys.derive(b"Session::attrs")
+	}
+
+	pub fn refresh_token_attrs_strong_box(&self) -> StrongBox {
+		self.root_keys.derive(b"RefreshToken::attrs")
+	}
+}
+
+/// Subject identifiers
+impl Config {
+	/// What the given client knows the principal as.  That's the principal's ID, unless the client
+	/// has a sector identifier, in which case it's a pairwise subject identifier (OIDC Core 1.0
+	/// s8.1), which is the same for every client in the sector, and means nothing to anyone else.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn subject_identifier(
+		&self,
+		oidc_client: &OidcClient,
+		principal_id: &Uuid,
+	) -> Result<String, Error> {
+		let Some(sector_identifier) = oidc_client.sector_identifier() else {
+			return Ok(principal_id.to_string());
+		};
+
+		let key = self.server_secret(Config::PAIRWISE_SUBJECT_SECRET).await?;
+		let mut mac = Hmac::<Sha256>::new_from_slice(key.expose_secret())
+			.map_err(|_| Error::cant_happen("HMAC-SHA256 rejected a key"))?;
+		// The principal's ID goes in first, as it's always the same length, so there's no way to
+		// shuffle bytes between it and the sector identifier
+		mac.update(principal_id.as_bytes());
+		mac.update(sector_identifier.as_bytes());
+
+		Ok(BASE64_URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+	}
+}
+
+/// Recovery codes
+impl Config {
+	/// What gets kept in the database in place of a recovery code.  The codes are random enough
+	/// that there's no need for a slow hash, but without a key, anyone with a copy of the database
+	/// could work through every possible code at their leisure.
+	pub async fn recovery_code_hash(&self, code: &str) -> Result<Vec<u8>, Error> {
+		let key = self.server_secret(Config::RECOVERY_CODE_SECRET).await?;
+		let mut mac = Hmac::<Sha256>::new_from_slice(key.expose_secret())
+			.map_err(|_| Error::cant_happen("HMAC-SHA256 rejected a key"))?;
+		mac.update(code.as_bytes());
+
+		Ok(mac.finalize().into_bytes().to_vec())
+	}
+}
+
+/// Server secrets
+///
+/// Some things (like pairwise subject identifiers) have to come out the same for as long as
+/// anyone remembers them, which rules out deriving them from the root keys, as those get rotated.
+/// Instead, a random secret is generated the first time it's needed, and kept in the database,
+/// encrypted under the root keys, and re-encrypted whenever they change.
+impl Config {
+	#[tracing::instrument(level = "debug", skip(self))]
+	async fn server_secret(&self, name: &str) -> Result<Secret<Vec<u8>>, Error> {
+		let strong_box = self.server_secret_strong_box();
+		let secrets = self.db.server_secret().await?;
+
+		let secret = match secrets.find_by_name(name).await {
+			Ok(secret) => secret,
+			Err(db::Error::NotFound(..)) => {
+				// If another server got in first, we go with theirs
+				secrets
+					.insert_if_missing(
+						name,
+						&strong_box.encrypt(strong_box::generate_key(), name.as_bytes())?,
+					)
+					.await?;
+				secrets.find_by_name(name).await?
+			}
+			Err(e) => return Err(e.into()),
+		};
+
+		Ok(Secret::new(
+			strong_box.decrypt(secret.secret(), name.as_bytes())?,
+		))
+	}
+
+	/// Make sure every server secret is encrypted under the current root encryption key, so that
+	/// they're still readable once the old root keys are gone.  Root keys only change when the
+	/// server is restarted, so this needs to be done once, at startup, before serving requests.
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn re_encrypt_server_secrets(&self) -> Result<(), Error> {
+		let strong_box = self.server_secret_strong_box();
+		let mut db = self.db.conn().await?;
+		let txn = db.transaction().await?;
+
+		if txn
+			.try_advisory_lock(self.lock_space, Config::SERVER_SECRETS_LOCK_ID)
+			.await?
+		{
+			let ss = txn.server_secret();
+
+			for mut s in ss.all().await? {
+				let name = s.name().as_bytes();
+				let secret = strong_box.encrypt(strong_box.decrypt(s.secret(), name)?, name)?;
+				s.update_secret(secret);
+				s.save(&ss).await?;
+			}
+		}
+
+		txn.commit().await?;
+
+		Ok(())
+	}
+
+	fn server_secret_strong_box(&self) -> StrongBox {
+		self.root_keys.derive(b"ServerSecret")
+	}
 }
 
 /// Signing key functionality
@@ -101,6 +259,26 @@ impl Config {
 
 	#[tracing::instrument(level = "debug", skip(self))]
 	pub async fn oidc_jwks(&self) -> Result<Vec<PublicJwk>, Error> {
+		self.jwks_for("oidc").await
+	}
+
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn current_oidc_signing_jwk(&self) -> Result<Jwk, Error> {
+		self.current_signing_jwk_for("oidc").await
+	}
+
+	/// Access tokens are only ever verified by us, so these keys don't get published anywhere
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn access_token_jwks(&self) -> Result<Vec<PublicJwk>, Error> {
+		self.jwks_for("access_token").await
+	}
+
+	#[tracing::instrument(level = "debug", skip(self))]
+	pub async fn current_access_token_signing_jwk(&self) -> Result<Jwk, Error> {
+		self.current_signing_jwk_for("access_token").await
+	}
+
+	async fn jwks_for(&self, usage: &str) -> Result<Vec<PublicJwk>, Error> {
 		let strong_box = self.signing_key_strong_box();
 		let now = OffsetDateTime::now_utc();
 
@@ -108,7 +286,7 @@ impl Config {
 			.db
 			.signing_key()
 			.await?
-			.find_all_by_usage("oidc")
+			.find_all_by_usage(usage)
 			.await?
 			.into_iter()
 			.filter(|k| k.expired_from() > &now)
@@ -121,8 +299,7 @@ impl Config {
 			.collect::<Result<Vec<_>, _>>()?)
 	}
 
-	#[tracing::instrument(level = "debug", skip(self))]
-	pub async fn current_oidc_signing_jwk(&self) -> Result<Jwk, Error> {
+	async fn current_signing_jwk_for(&self, usage: &str) -> Result<Jwk, Error> {
 		let strong_box = self.signing_key_strong_box();
 
 		let now = OffsetDateTime::now_utc();
@@ -133,11 +310,11 @@ impl Config {
 					.db
 					.signing_key()
 					.await?
-					.find_all_by_usage("oidc")
+					.find_all_by_usage(usage)
 					.await?
 					.into_iter()
 					.find(|k| k.used_from() <= &now && k.not_used_from() > &now)
-					.ok_or(Error::no_signing_key("OIDC"))?
+					.ok_or(Error::no_signing_key(usage))?
 					.key(),
 				b"",
 			)?,
@@ -169,6 +346,8 @@ pub struct ConfigBuilder {
 	github_oauth_client: Option<OAuthClientBuilder<provider::GitHub>>,
 	gitlab_oauth_client: Option<OAuthClientBuilder<provider::GitLab>>,
 	google_oauth_client: Option<OAuthClientBuilder<provider::Google>>,
+	mail_transport: Option<Arc<dyn MailTransport>>,
+	mail_from: Option<String>,
 }
 
 impl ConfigBuilder {
@@ -280,17 +459,26 @@ impl ConfigBuilder {
 		self
 	}
 
+	pub fn mail_transport(mut self, t: Arc<dyn MailTransport>) -> Self {
+		self.mail_transport = Some(t);
+		self
+	}
+
+	pub fn mail_from(mut self, from: impl Into<String>) -> Self {
+		self.mail_from = Some(from.into());
+		self
+	}
+
 	pub fn build(self) -> Result<Config, Error> {
-		let (dummy_pwhash, _pwhash_cost) = Self::bcrypt_params()?;
+		let (dummy_pwhash, pwhash_cost) = Self::bcrypt_params()?;
 
 		let base_url = self
 			.base_url
 			.ok_or_else(|| Error::missing_parameter("base_url"))?;
-		let root_keys = StemStrongBox::new(
-			self.root_encryption_key
-				.ok_or_else(|| Error::missing_parameter("root_encryption_key"))?,
-			self.root_decryption_keys,
-		);
+		let root_encryption_key = self
+			.root_encryption_key
+			.ok_or_else(|| Error::missing_parameter("root_encryption_key"))?;
+		let root_keys = StemStrongBox::new(root_encryption_key, self.root_decryption_keys);
 
 		let http_client = self.http_client.unwrap_or_else(|| {
 			reqwest_middleware::ClientBuilder::new(
@@ -336,6 +524,21 @@ impl ConfigBuilder {
 			oauth_provider_map.insert::<provider::Google>(c);
 		}
 
+		let mailer = self
+			.mail_transport
+			.map(|transport| {
+				let from = match self.mail_from {
+					Some(from) => from.parse()?,
+					None => format!(
+						"Authul <noreply@{}>",
+						base_url.host_str().unwrap_or("localhost")
+					)
+					.parse()?,
+				};
+				Ok::<_, Error>(Mailer::new(transport, from))
+			})
+			.transpose()?;
+
 		Ok(Config {
 			base_url,
 			root_keys,
@@ -346,8 +549,9 @@ impl ConfigBuilder {
 
 			password_auth: self.password_auth,
 			dummy_pwhash,
-			//pwhash_cost,
+			pwhash_cost,
 			oauth_provider_map,
+			mailer,
 		})
 	}
 }
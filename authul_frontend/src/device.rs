@@ -0,0 +1,131 @@
+//! Where the user comes to sign in a device that can't sign itself in (a TV, a CLI tool, etc),
+//! using the user code the device showed them
+use leptos::{
+	component, create_server_action, server, view, IntoAttribute, IntoSignal, IntoView, Params,
+	ServerFnError, SignalGet as _,
+};
+use leptos_router::{use_query, ActionForm, Params, Route};
+
+cfg_if::cfg_if! {
+	if #[cfg(feature = "ssr")] {
+		use actix_web::web::Data;
+		use leptos_actix::{extract, redirect};
+		use std::sync::Arc;
+		use tap::prelude::*;
+
+		use super::{oidc::normalise_user_code, AuthContext, Config, Error};
+	}
+}
+
+#[component(transparent)]
+pub(super) fn DeviceRoutes() -> impl IntoView {
+	view! {
+		<Route path="device" view=Device />
+	}
+}
+
+#[component]
+fn Device() -> impl IntoView {
+	#[derive(Clone, Debug, Default, Params, PartialEq)]
+	struct QueryParams {
+		user_code: Option<String>,
+		err: Option<String>,
+		status: Option<String>,
+	}
+
+	let params = use_query::<QueryParams>();
+
+	let user_code =
+		(move || params.get().map(|params| params.user_code).unwrap_or(None)).into_signal();
+	let err = (move || params.get().map(|params| params.err).unwrap_or(None)).into_signal();
+	let status = (move || params.get().map(|params| params.status).unwrap_or(None)).into_signal();
+
+	let error_desc = move || match err.get().as_deref() {
+		Some("invalid_code") => {
+			Some("That code is not valid.  Please check it against the one shown on your device.")
+		}
+		Some("expired_code") => Some("That code has expired.  Please start again on your device."),
+		_ => None,
+	};
+	let show_error = move || error_desc().is_some();
+	let submit_user_code = create_server_action::<SubmitUserCode>();
+
+	view! {
+		<section class="container login-box">
+			{move || match status.get().as_deref() {
+				Some("approved") => view! {
+					<p id="device-approved">
+						"You're signed in.  You can go back to your device now."
+					</p>
+				}.into_view(),
+				Some("denied") => view! {
+					<p id="device-denied">
+						"Your device has not been signed in.  You can close this page."
+					</p>
+				}.into_view(),
+				_ => view! {
+					<ActionForm action=submit_user_code attributes=vec![("id", "user-code-form".into_attribute())]>
+						<label for="user-code-input">"Enter the code shown on your device"</label>
+						<input id="user-code-input" type="text" name="user_code"
+							autocomplete="off" autocapitalize="characters" spellcheck="false"
+							required placeholder="BCDF-GHJK"
+							value=move || user_code.get()
+							aria-invalid={move || if show_error() { "true" } else { "false" }}
+							aria-errormessage={move || if show_error() { "user-code-error" } else { "" }}
+						/>
+						{move || show_error().then(|| view! {
+							<small id="user-code-error" class="error-text">{move || error_desc()}</small>
+						})}
+						<input type="submit" value="Continue" />
+					</ActionForm>
+				}.into_view(),
+			}}
+		</section>
+	}
+}
+
+#[server(SubmitUserCode, "/device", "Url", "submit_user_code")]
+async fn submit_user_code(user_code: String) -> Result<(), ServerFnError> {
+	let cfg: Data<Config> = extract().await?;
+
+	Ok(process_submit_user_code(user_code, cfg.into_inner())
+		.await
+		.tap_err(|e| tracing::warn!("failed to process submitted user code: {e}"))?)
+}
+
+#[cfg(feature = "ssr")]
+async fn process_submit_user_code(user_code: String, cfg: Arc<Config>) -> Result<(), Error> {
+	let user_code = normalise_user_code(&user_code);
+
+	let Some(device_code) = cfg
+		.db()
+		.device_code()
+		.await?
+		.find_pending_by_user_code(&user_code)
+		.await?
+	else {
+		tracing::debug!("no pending device code for user code {user_code}");
+		let mut redirect_url = cfg.base_url().join("device")?;
+		redirect_url
+			.query_pairs_mut()
+			.append_pair("user_code", &user_code)
+			.append_pair("err", "invalid_code");
+		redirect(redirect_url.as_str());
+		return Ok(());
+	};
+
+	// There's no redirect_uri or code_challenge for a device, because the tokens get handed over
+	// when the device comes asking for them, rather than via the user's browser
+	let ctx = AuthContext::new(cfg.clone(), device_code.oidc_client().id(), "", "")
+		.with_device_code(*device_code.id())
+		.with_scope(device_code.scope());
+
+	let mut redirect_url = cfg.base_url().join("authenticate")?;
+	redirect_url
+		.query_pairs_mut()
+		.append_pair("ctx", &ctx.to_string())
+		.append_pair("target", device_code.oidc_client().name());
+	redirect(redirect_url.as_str());
+
+	Ok(())
+}
@@ -73,6 +73,22 @@ pub enum Error {
 		location: &'static std::panic::Location<'static>,
 	},
 
+	#[cfg(feature = "ssr")]
+	#[error("rejected OIDC userinfo request because {reason}")]
+	OidcUserinfo {
+		reason: String,
+		error_code: authul_oauth2::error_code::UserinfoEndpoint,
+		location: &'static std::panic::Location<'static>,
+	},
+
+	#[cfg(feature = "ssr")]
+	#[error("rejected OIDC client registration request because {reason}")]
+	OidcRegistration {
+		reason: String,
+		error_code: authul_oauth2::error_code::RegistrationEndpoint,
+		location: &'static std::panic::Location<'static>,
+	},
+
 	#[cfg(feature = "ssr")]
 	#[error("failure during OAuth: {0}")]
 	Oauth(
@@ -145,6 +161,46 @@ pub enum Error {
 		&'static std::panic::Location<'static>,
 	),
 
+	#[cfg(feature = "ssr")]
+	#[error("failed to construct email: {0}")]
+	MailBuild(
+		#[from] lettre::error::Error,
+		&'static std::panic::Location<'static>,
+	),
+
+	#[cfg(feature = "ssr")]
+	#[error("invalid email address: {0}")]
+	MailAddress(
+		#[from] lettre::address::AddressError,
+		&'static std::panic::Location<'static>,
+	),
+
+	#[cfg(feature = "ssr")]
+	#[error("invalid mail transport: {0}")]
+	MailTransport(String, &'static std::panic::Location<'static>),
+
+	#[cfg(feature = "ssr")]
+	#[error("failed to send email: {0}")]
+	MailDelivery(String, &'static std::panic::Location<'static>),
+
+	#[cfg(feature = "ssr")]
+	#[error("TOTP failure: {0}")]
+	Totp(
+		#[from] totp_rs::TotpUrlError,
+		&'static std::panic::Location<'static>,
+	),
+
+	#[cfg(feature = "ssr")]
+	#[error("invalid timestamp: {0}")]
+	Timestamp(
+		#[from] time::error::ComponentRange,
+		&'static std::panic::Location<'static>,
+	),
+
+	#[cfg(feature = "ssr")]
+	#[error("WebAuthn ceremony failed: {0}")]
+	Webauthn(String, &'static std::panic::Location<'static>),
+
 	#[error("CAN'T HAPPEN: {0}")]
 	CantHappen(String, &'static std::panic::Location<'static>),
 }
@@ -161,8 +217,56 @@ impl ResponseError for Error {
 				HttpResponse::BadRequest().json(serde_json::json!({ "error": error_code.as_str() }))
 			}
 			Error::OidcToken { error_code, .. } => {
+				use authul_oauth2::error_code::TokenEndpoint as ErrCode;
+
 				tracing::debug!("{self}");
-				HttpResponse::BadRequest().json(serde_json::json!({ "error": error_code.as_str() }))
+				// RFC6749 s5.2: a client that failed to authenticate gets challenged to do it
+				// properly, and Basic is the only HTTP authentication scheme we take for clients
+				let mut res = match error_code {
+					ErrCode::InvalidClient => {
+						let mut res = HttpResponse::Unauthorized();
+						res.insert_header(("www-authenticate", "Basic realm=\"authul\""));
+						res
+					}
+					_ => HttpResponse::BadRequest(),
+				};
+				res.json(serde_json::json!({ "error": error_code.as_str() }))
+			}
+			Error::OidcUserinfo { error_code, .. } => {
+				use authul_oauth2::error_code::UserinfoEndpoint as ErrCode;
+
+				tracing::debug!("{self}");
+				let mut res = match error_code {
+					ErrCode::InvalidRequest => HttpResponse::BadRequest(),
+					ErrCode::InsufficientScope => HttpResponse::Forbidden(),
+					_ => HttpResponse::Unauthorized(),
+				};
+				// A bad DPoP proof can only have come from a client that knows to use DPoP, so
+				// that's the scheme to challenge it with
+				let scheme = match error_code {
+					ErrCode::InvalidDpopProof => "DPoP",
+					_ => "Bearer",
+				};
+				res.insert_header((
+					"www-authenticate",
+					format!("{scheme} error=\"{}\"", error_code.as_str()),
+				))
+				.finish()
+			}
+			Error::OidcRegistration { error_code, .. } => {
+				use authul_oauth2::error_code::RegistrationEndpoint as ErrCode;
+
+				tracing::debug!("{self}");
+				match error_code {
+					ErrCode::InvalidToken => HttpResponse::Unauthorized()
+						.insert_header((
+							"www-authenticate",
+							format!("Bearer error=\"{}\"", error_code.as_str()),
+						))
+						.finish(),
+					_ => HttpResponse::BadRequest()
+						.json(serde_json::json!({ "error": error_code.as_str() })),
+				}
 			}
 			Error::OidcAuthorize { error_code, .. } => {
 				tracing::debug!("{self}");
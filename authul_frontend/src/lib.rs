@@ -7,14 +7,19 @@ mod auth_context;
 mod authenticate;
 #[cfg(feature = "ssr")]
 mod config;
+mod device;
 mod error;
 #[cfg(feature = "ssr")]
+pub mod mail;
+#[cfg(feature = "ssr")]
 mod middleware;
 #[cfg(feature = "ssr")]
 mod oidc;
 #[cfg(feature = "ssr")]
 pub mod periodic_tasks;
 mod render_config;
+#[cfg(feature = "ssr")]
+mod webauthn;
 
 #[cfg(feature = "ssr")]
 pub use actix_app::actix_app;
@@ -27,6 +32,7 @@ use authenticate::AuthenticateRoutes;
 use authul_db as db;
 #[cfg(feature = "ssr")]
 pub use config::{Config, ConfigBuilder};
+use device::DeviceRoutes;
 pub use error::Error;
 pub use render_config::RenderConfig;
 
@@ -72,6 +78,7 @@ fn root_component(cfg: RenderConfig) -> impl Fn() -> leptos::View + Clone + 'sta
 					<Routes>
 						<Route path="/" view=HomePage />
 						<AuthenticateRoutes />
+						<DeviceRoutes />
 					</Routes>
 				</main>
 			</Router>
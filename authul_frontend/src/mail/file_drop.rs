@@ -0,0 +1,27 @@
+use futures_util::future::BoxFuture;
+use lettre::{AsyncFileTransport, AsyncTransport as _, Message, Tokio1Executor};
+use std::path::Path;
+
+use super::{Error, MailTransport};
+
+/// Write each message into its own `.eml` file in a directory, rather than actually sending it
+#[derive(Debug)]
+pub struct FileDrop(AsyncFileTransport<Tokio1Executor>);
+
+impl FileDrop {
+	pub fn new(dir: impl AsRef<Path>) -> Self {
+		Self(AsyncFileTransport::<Tokio1Executor>::new(dir))
+	}
+}
+
+impl MailTransport for FileDrop {
+	fn send(&self, msg: Message) -> BoxFuture<'_, Result<(), Error>> {
+		Box::pin(async move {
+			self.0
+				.send(msg)
+				.await
+				.map_err(|e| Error::mail_delivery(e.to_string()))?;
+			Ok(())
+		})
+	}
+}
@@ -0,0 +1,77 @@
+//! Sending e-mail to users
+//!
+//! Messages are handed off to a [`MailTransport`], which takes care of actually getting them to
+//! where they need to go.  The transport to use is chosen at startup from a URL-ish description:
+//!
+//! * `smtp://[user:password@]host[:port]` or `smtps://...`, to relay via an SMTP server;
+//! * `sendmail:` or `sendmail:///path/to/sendmail`, to pipe messages into a local sendmail; or
+//! * `file:///some/directory`, to drop each message into a file (mostly useful for testing).
+use futures_util::future::BoxFuture;
+use lettre::{
+	message::{header::ContentType, Mailbox},
+	Message,
+};
+use std::{fmt::Debug, sync::Arc};
+use url::Url;
+
+use super::Error;
+
+mod file_drop;
+mod sendmail;
+mod smtp;
+
+pub use file_drop::FileDrop;
+pub use sendmail::Sendmail;
+pub use smtp::Smtp;
+
+/// Something which can deliver a fully-formed message
+pub trait MailTransport: Debug + Send + Sync {
+	fn send(&self, msg: Message) -> BoxFuture<'_, Result<(), Error>>;
+}
+
+/// Create a transport from its URL description
+pub fn transport_from_url(s: &str) -> Result<Arc<dyn MailTransport>, Error> {
+	let url = Url::parse(s)?;
+
+	Ok(match url.scheme() {
+		"smtp" | "smtps" => Arc::new(Smtp::new(s)?),
+		"sendmail" => {
+			if url.path().is_empty() {
+				Arc::new(Sendmail::new())
+			} else {
+				Arc::new(Sendmail::with_command(url.path()))
+			}
+		}
+		"file" => Arc::new(FileDrop::new(url.path())),
+		other => {
+			return Err(Error::mail_transport(format!(
+				"unsupported mail transport scheme {other:?}"
+			)))
+		}
+	})
+}
+
+/// The thing that the rest of the app uses to send mail
+#[derive(Clone, Debug)]
+pub struct Mailer {
+	transport: Arc<dyn MailTransport>,
+	from: Mailbox,
+}
+
+impl Mailer {
+	pub(crate) fn new(transport: Arc<dyn MailTransport>, from: Mailbox) -> Self {
+		Self { transport, from }
+	}
+
+	#[tracing::instrument(level = "debug", skip(self, body))]
+	pub async fn send(&self, to: &str, subject: &str, body: String) -> Result<(), Error> {
+		let msg = Message::builder()
+			.from(self.from.clone())
+			.to(to.parse()?)
+			.subject(subject)
+			.header(ContentType::TEXT_PLAIN)
+			.body(body)?;
+
+		self.transport.send(msg).await
+	}
+}
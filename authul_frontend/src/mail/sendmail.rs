@@ -0,0 +1,41 @@
+use futures_util::future::BoxFuture;
+use lettre::{AsyncSendmailTransport, AsyncTransport as _, Message, Tokio1Executor};
+use std::ffi::OsString;
+
+use super::{Error, MailTransport};
+
+/// Pipe mail into a local `sendmail` (or compatible) program
+#[derive(Clone, Debug)]
+pub struct Sendmail(AsyncSendmailTransport<Tokio1Executor>);
+
+impl Sendmail {
+	/// Use whatever `sendmail` is found in the `PATH`
+	pub fn new() -> Self {
+		Self(AsyncSendmailTransport::<Tokio1Executor>::new())
+	}
+
+	/// Use the program at the given path
+	pub fn with_command(cmd: impl Into<OsString>) -> Self {
+		Self(AsyncSendmailTransport::<Tokio1Executor>::new_with_command(
+			cmd,
+		))
+	}
+}
+
+impl Default for Sendmail {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl MailTransport for Sendmail {
+	fn send(&self, msg: Message) -> BoxFuture<'_, Result<(), Error>> {
+		Box::pin(async move {
+			self.0
+				.send(msg)
+				.await
+				.map_err(|e| Error::mail_delivery(e.to_string()))?;
+			Ok(())
+		})
+	}
+}
@@ -0,0 +1,32 @@
+use futures_util::future::BoxFuture;
+use lettre::{AsyncSmtpTransport, AsyncTransport as _, Message, Tokio1Executor};
+
+use super::{Error, MailTransport};
+
+/// Relay mail through an SMTP server
+#[derive(Clone, Debug)]
+pub struct Smtp(AsyncSmtpTransport<Tokio1Executor>);
+
+impl Smtp {
+	/// Configure the transport from a URL of the form
+	/// `smtp[s]://[user:password@]host[:port][?tls=required]`
+	pub fn new(url: &str) -> Result<Self, Error> {
+		Ok(Self(
+			AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+				.map_err(|e| Error::mail_transport(e.to_string()))?
+				.build(),
+		))
+	}
+}
+
+impl MailTransport for Smtp {
+	fn send(&self, msg: Message) -> BoxFuture<'_, Result<(), Error>> {
+		Box::pin(async move {
+			self.0
+				.send(msg)
+				.await
+				.map_err(|e| Error::mail_delivery(e.to_string()))?;
+			Ok(())
+		})
+	}
+}
@@ -1,30 +1,61 @@
 use actix_web::{
 	dev::{Service, ServiceRequest, ServiceResponse, Transform},
 	http::{
-		header::{HeaderName, HeaderValue},
+		header::{HeaderName, HeaderValue, ORIGIN, VARY},
 		Method, StatusCode,
 	},
-	Error as ActixError,
-};
-use pin_project::pin_project;
-use std::{
-	future::{ready, Future, Ready},
-	marker::PhantomData,
-	pin::Pin,
-	task::{ready, Context, Poll},
+	web, Error as ActixError, HttpRequest,
 };
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+
+use crate::{db::model::OidcClient, Config};
 
 const HEADER_ACAO: HeaderName = HeaderName::from_static("access-control-allow-origin");
 const VALUE_WILDCARD: HeaderValue = HeaderValue::from_static("*");
 const HEADER_ACAM: HeaderName = HeaderName::from_static("access-control-allow-methods");
 const VALUE_GET_METHODS: HeaderValue = HeaderValue::from_static("GET, HEAD, OPTIONS");
 const VALUE_POST_METHODS: HeaderValue = HeaderValue::from_static("POST, OPTIONS");
+const VALUE_GET_POST_METHODS: HeaderValue = HeaderValue::from_static("GET, POST, OPTIONS");
+const HEADER_ACAH: HeaderName = HeaderName::from_static("access-control-allow-headers");
+const VALUE_CLIENT_HEADERS: HeaderValue = HeaderValue::from_static("authorization, dpop");
 const HEADER_ACMA: HeaderName = HeaderName::from_static("access-control-max-age");
 const VALUE_ONE_WEEK: HeaderValue = HeaderValue::from_static("604800");
+const VALUE_ORIGIN: HeaderValue = HeaderValue::from_static("origin");
 
 pub(crate) enum Cors {
 	GET,
 	POST,
+	/// POST, for endpoints that browser-based public clients call directly.  Only the client that
+	/// the request turns out to be from can say which origins get to see the response, so the
+	/// handler has to [`allow_client_origin`] once it knows who that is.
+	ClientOrigins,
+	/// GET or POST, with an access token, from the origins of whichever client the token was
+	/// issued to; again, the handler has to [`allow_client_origin`]
+	ClientOriginsWithToken,
+}
+
+/// The origin that a handler has decided can see its response
+#[derive(Clone, Debug)]
+struct AllowedOrigin(HeaderValue);
+
+/// Let the page that made the request see the response, if it is on one of the client's
+/// registered origins.  Returns whether it is; a request without an `Origin` isn't from a
+/// browser, and so isn't any of CORS' business.
+pub(crate) fn allow_client_origin(req: &HttpRequest, oidc_client: &OidcClient) -> bool {
+	let Some(origin) = req.headers().get(ORIGIN) else {
+		return true;
+	};
+
+	if !origin
+		.to_str()
+		.is_ok_and(|o| oidc_client.has_allowed_origin(o))
+	{
+		return false;
+	}
+
+	req.extensions_mut().insert(AllowedOrigin(origin.clone()));
+	true
 }
 
 impl<S, B> Transform<S, ServiceRequest> for Cors
@@ -41,15 +72,21 @@ where
 	fn new_transform(&self, service: S) -> Self::Future {
 		let methods = match self {
 			Cors::GET => VALUE_GET_METHODS,
-			Cors::POST => VALUE_POST_METHODS,
+			Cors::POST | Cors::ClientOrigins => VALUE_POST_METHODS,
+			Cors::ClientOriginsWithToken => VALUE_GET_POST_METHODS,
 		};
-		ready(Ok(CorsMiddleware { service, methods }))
+		ready(Ok(CorsMiddleware {
+			service,
+			methods,
+			client_origins_only: matches!(self, Cors::ClientOrigins | Cors::ClientOriginsWithToken),
+		}))
 	}
 }
 
 pub(crate) struct CorsMiddleware<S> {
 	service: S,
 	methods: HeaderValue,
+	client_origins_only: bool,
 }
 
 impl<S, B> Service<ServiceRequest> for CorsMiddleware<S>
@@ -59,47 +96,79 @@ where
 {
 	type Response = S::Response;
 	type Error = S::Error;
-	type Future = CorsFuture<S, B>;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
 	actix_web::dev::forward_ready!(service);
 
 	fn call(&self, req: ServiceRequest) -> Self::Future {
-		CorsFuture {
-			options_request: req.method() == Method::OPTIONS,
-			methods: self.methods.clone(),
-			call_future: self.service.call(req),
-			_body: PhantomData,
-		}
-	}
-}
+		let options_request = req.method() == Method::OPTIONS;
+		let methods = self.methods.clone();
+		let client_origins_only = self.client_origins_only;
+		// A preflight doesn't say which client it's for, so all we can do is check that the
+		// origin belongs to somebody; that means a trip to the database, which we can't do until
+		// we're in the future.  The request proper only gets to see the response if the handler
+		// has checked the origin against the client it actually came from.
+		let origin_check = (self.client_origins_only && options_request).then(|| {
+			(
+				req.headers().get(ORIGIN).cloned(),
+				req.app_data::<web::Data<Config>>().cloned(),
+			)
+		});
+		let call_future = self.service.call(req);
 
-#[pin_project]
-pub(crate) struct CorsFuture<S: Service<ServiceRequest>, B> {
-	#[pin]
-	call_future: S::Future,
-	methods: HeaderValue,
-	options_request: bool,
-	_body: PhantomData<B>,
-}
+		Box::pin(async move {
+			let mut res = call_future.await?;
 
-impl<S, B> Future for CorsFuture<S, B>
-where
-	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
-{
-	type Output = <S::Future as Future>::Output;
+			if res.status().as_u16() == 405 && options_request {
+				*res.response_mut().status_mut() = StatusCode::NO_CONTENT;
+			}
 
-	fn poll(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
-		let this = self.as_mut().project();
-		let mut res = ready!(this.call_future.poll(ctx))?;
+			let allowed_origin = match origin_check {
+				None if client_origins_only => res
+					.request()
+					.extensions()
+					.get::<AllowedOrigin>()
+					.map(|o| o.0.clone()),
+				None => Some(VALUE_WILDCARD),
+				Some((Some(origin), Some(cfg))) => registered_origin(&cfg, origin).await,
+				Some(_) => None,
+			};
 
-		if res.status().as_u16() == 405 && self.options_request {
-			*res.response_mut().status_mut() = StatusCode::NO_CONTENT;
-		}
+			if let Some(origin) = allowed_origin {
+				res.headers_mut().insert(HEADER_ACAO, origin);
+				res.headers_mut().insert(HEADER_ACAM, methods);
+				res.headers_mut().insert(HEADER_ACMA, VALUE_ONE_WEEK);
+				if client_origins_only {
+					res.headers_mut().insert(HEADER_ACAH, VALUE_CLIENT_HEADERS);
+				}
+			}
+			// Caches need to know that the response depends on who's asking
+			if client_origins_only {
+				res.headers_mut().append(VARY, VALUE_ORIGIN);
+			}
 
-		res.headers_mut().insert(HEADER_ACAO, VALUE_WILDCARD);
-		res.headers_mut().insert(HEADER_ACAM, self.methods.clone());
-		res.headers_mut().insert(HEADER_ACMA, VALUE_ONE_WEEK);
+			Ok(res)
+		})
+	}
+}
 
-		Poll::Ready(Ok(res))
+async fn registered_origin(cfg: &Config, origin: HeaderValue) -> Option<HeaderValue> {
+	let Ok(origin_str) = origin.to_str() else {
+		return None;
+	};
+
+	match cfg.db().oidc_client().await {
+		Ok(handle) => match handle.origin_is_registered(origin_str).await {
+			Ok(true) => Some(origin),
+			Ok(false) => None,
+			Err(e) => {
+				tracing::error!("failed to check CORS origin {origin_str}: {e}");
+				None
+			}
+		},
+		Err(e) => {
+			tracing::error!("failed to check CORS origin {origin_str}: {e}");
+			None
+		}
 	}
 }
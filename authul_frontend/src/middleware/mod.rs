@@ -1,5 +1,7 @@
 mod cors;
 mod csrf;
+pub(crate) mod session;
 
-pub(super) use cors::Cors;
+pub(super) use cors::{allow_client_origin, Cors};
 pub(super) use csrf::Csrf;
+pub(super) use session::Session;
@@ -0,0 +1,169 @@
+use actix_web::{
+	cookie::{Cookie, SameSite},
+	dev::{Service, ServiceRequest, ServiceResponse, Transform},
+	Error as ActixError,
+};
+use pin_project::pin_project;
+use std::{
+	cell::Cell,
+	future::{ready, Future, Ready},
+	marker::PhantomData,
+	pin::Pin,
+	rc::Rc,
+	task::{ready, Context, Poll},
+};
+use tokio::task::futures::TaskLocalFuture;
+use uuid::Uuid;
+
+pub(crate) const COOKIE_NAME: &str = "authul_session";
+
+#[derive(Clone, Copy, Debug)]
+enum SessionChange {
+	Started(Uuid),
+	Ended,
+}
+
+tokio::task_local! {
+	// Where a request handler leaves a note of what it did to the user's session, so that the
+	// middleware can set (or clear) the cookie accordingly
+	static SESSION_CHANGE: Rc<Cell<Option<SessionChange>>>;
+}
+
+/// Record that a session has been started in the course of handling the current request, so the
+/// user gets a session cookie in the response.
+///
+/// Returns `false` if we're not being called from within a request that has passed through the
+/// session middleware, in which case nobody is going to get a cookie.
+pub(crate) fn started(id: Uuid) -> bool {
+	SESSION_CHANGE
+		.try_with(|s| s.set(Some(SessionChange::Started(id))))
+		.is_ok()
+}
+
+/// Record that the user's session has been ended, so their session cookie gets removed.
+///
+/// As with [`started`], returns `false` if there's no session middleware around to do anything
+/// about it.
+pub(crate) fn ended() -> bool {
+	SESSION_CHANGE
+		.try_with(|s| s.set(Some(SessionChange::Ended)))
+		.is_ok()
+}
+
+pub(crate) struct Session {
+	domain: String,
+	path: String,
+}
+
+impl Session {
+	pub(crate) fn new(domain: impl Into<String>, path: impl Into<String>) -> Self {
+		Self {
+			domain: domain.into(),
+			path: path.into(),
+		}
+	}
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Session
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+	S::Future: 'static,
+{
+	type Response = ServiceResponse<B>;
+	type Error = ActixError;
+	type Transform = SessionMiddleware<S>;
+	type InitError = ();
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ready(Ok(SessionMiddleware {
+			service,
+			domain: self.domain.clone(),
+			path: self.path.clone(),
+		}))
+	}
+}
+
+pub(crate) struct SessionMiddleware<S> {
+	service: S,
+	domain: String,
+	path: String,
+}
+
+impl<S, B> Service<ServiceRequest> for SessionMiddleware<S>
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+	S::Future: 'static,
+{
+	type Response = S::Response;
+	type Error = S::Error;
+	type Future = SessionFuture<S, B>;
+
+	actix_web::dev::forward_ready!(service);
+
+	fn call(&self, req: ServiceRequest) -> Self::Future {
+		let session_change = Rc::new(Cell::new(None));
+
+		SessionFuture {
+			call_future: SESSION_CHANGE.scope(session_change.clone(), self.service.call(req)),
+			session_change,
+			domain: self.domain.clone(),
+			path: self.path.clone(),
+			_body: PhantomData,
+		}
+	}
+}
+
+#[pin_project]
+pub(crate) struct SessionFuture<S: Service<ServiceRequest>, B> {
+	#[pin]
+	call_future: TaskLocalFuture<Rc<Cell<Option<SessionChange>>>, S::Future>,
+	session_change: Rc<Cell<Option<SessionChange>>>,
+	domain: String,
+	path: String,
+	_body: PhantomData<B>,
+}
+
+impl<S, B> Future for SessionFuture<S, B>
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+{
+	type Output = <S::Future as Future>::Output;
+
+	fn poll(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = self.as_mut().project();
+		let mut res = ready!(this.call_future.poll(ctx))?;
+
+		match self.session_change.get() {
+			Some(SessionChange::Started(id)) => (*res.response_mut())
+				.add_cookie(&cookie(&self.domain, &self.path, id))
+				.expect("add_cookie failed somehow"),
+			Some(SessionChange::Ended) => {
+				let mut c = cookie(&self.domain, &self.path, Uuid::nil());
+				c.make_removal();
+				(*res.response_mut())
+					.add_cookie(&c)
+					.expect("add_cookie failed somehow")
+			}
+			None => (),
+		}
+
+		Poll::Ready(Ok(res))
+	}
+}
+
+pub(crate) fn cookie(
+	domain: impl Into<String>,
+	path: impl Into<String>,
+	id: Uuid,
+) -> Cookie<'static> {
+	// Lax, rather than Strict, because the cookie has to come along when the user is sent here
+	// from a client's site, otherwise there'd be no point having it
+	Cookie::build(COOKIE_NAME, id.to_string())
+		.domain(domain.into())
+		.path(path.into())
+		.http_only(true)
+		.secure(true)
+		.same_site(SameSite::Lax)
+		.finish()
+}
@@ -3,12 +3,28 @@ use actix_web::{
 	web::{self, ServiceConfig},
 	HttpRequest, HttpResponse,
 };
+use email_address::EmailAddress;
+use serde_json::Value as JsonValue;
 use std::collections::HashMap;
-use url::Url;
+use time::OffsetDateTime;
+use url::{form_urlencoded, Url};
 use uuid::Uuid;
 
-use super::{AuthContext, Error};
-use crate::db;
+use super::{
+	claims::{granted_scope, RequestedClaims},
+	pushed_authorization::REQUEST_URI_PREFIX,
+	AuthContext, Error,
+};
+use crate::{
+	authenticate::{needs_consent, successful_authentication},
+	db::{
+		self,
+		model::{OidcClient, Session},
+		types::IdentityAttributes,
+	},
+	middleware::session,
+};
+use authul_crypto::{JwkSet, Jwt, REQUEST_OBJECT_TYP};
 use authul_oauth2::error_code::AuthorizeEndpoint as ErrCode;
 use authul_util::Base64Uuid;
 
@@ -65,32 +81,47 @@ async fn do_authorize(
 			ErrCode::InvalidRequest,
 		));
 	};
+	let client_uuid = Uuid::from_base64(&client_id).map_err(|e| {
+		Error::oidc_authorize(format!("invalid client_id: {e}"), ErrCode::InvalidRequest)
+	})?;
 
-	let Some(redirect_uri) = params.get("redirect_uri") else {
-		return Err(Error::oidc_authorize(
-			"missing redirect_uri",
-			ErrCode::InvalidRequest,
-		));
+	// A client that has pushed its request to us only sends the client_id and request_uri via
+	// the browser; everything else comes from what it pushed
+	let request_uri = params.get("request_uri").cloned();
+	let params = match &request_uri {
+		Some(request_uri) => pushed_params(&cfg, request_uri, &client_uuid).await?,
+		None => params,
 	};
 
-	let client = match cfg
-		.db()
-		.oidc_client()
-		.await?
-		.find(&Uuid::from_base64(&client_id).map_err(|e| {
-			Error::oidc_authorize(format!("invalid client_id: {e}"), ErrCode::InvalidRequest)
-		})?)
-		.await
-	{
+	let client = match cfg.db().oidc_client().await?.find(&client_uuid).await {
 		Ok(c) => c,
 		Err(db::Error::NotFound(..)) => {
 			return Err(Error::oidc_authorize(
-				format!("unknown client_id {client_id}"),
+				format!("unknown client_id {}", client_uuid.to_base64()),
 				ErrCode::InvalidRequest,
 			));
 		}
 		Err(e) => return Err(e.into()),
 	};
+	if *client.require_pushed_authorization_requests() && request_uri.is_none() {
+		return Err(Error::oidc_authorize(
+			"client must use pushed authorization requests",
+			ErrCode::InvalidRequest,
+		));
+	}
+
+	// RFC9101 s6.3: whatever's in a signed request object wins over the plain old params
+	let mut params = params;
+	if let Some(request) = params.remove("request") {
+		params.extend(request_object_params(&cfg, &client, &request).await?);
+	}
+
+	let Some(redirect_uri) = params.get("redirect_uri") else {
+		return Err(Error::oidc_authorize(
+			"missing redirect_uri",
+			ErrCode::InvalidRequest,
+		));
+	};
 	if !client.has_redirect_uri(&redirect_uri) {
 		return Err(Error::oidc_authorize(
 			format!("invalid redirect_uri {redirect_uri}"),
@@ -172,17 +203,21 @@ async fn do_authorize(
 		}
 	}
 
+	let requested_claims = match params.get("claims").map(|c| c.parse::<RequestedClaims>()) {
+		None => None,
+		Some(Ok(c)) => Some(c),
+		Some(Err(e)) => {
+			return Err(Error::oidc_authorize_redirect(
+				redirect_uri,
+				format!("invalid claims: {e}"),
+				ErrCode::InvalidRequest,
+			));
+		}
+	};
+
 	// Reject all the otherwise valid params we don't (yet) support
 	// This seems more polite than silently accepting them and then not doing what the RP wanted
-	for param in [
-		"display",
-		"prompt",
-		"max_age",
-		"ui_locales",
-		"token_hint",
-		"login_hint",
-		"acr",
-	] {
+	for param in ["display", "ui_locales", "token_hint", "acr"] {
 		if params.contains_key(param) {
 			tracing::debug!("/authorize request rejected for invalid {param}");
 			return Err(Error::oidc_authorize_redirect(
@@ -193,8 +228,52 @@ async fn do_authorize(
 		}
 	}
 
+	let prompt: Vec<&str> = params
+		.get("prompt")
+		.map(|p| p.split(' ').filter(|v| !v.is_empty()).collect())
+		.unwrap_or_default();
+	for value in &prompt {
+		if !["none", "login", "select_account"].contains(value) {
+			return Err(Error::oidc_authorize_redirect(
+				redirect_uri,
+				format!("unsupported prompt {value}"),
+				ErrCode::InvalidRequest,
+			));
+		}
+	}
+	// OIDC Core 1.0 s3.1.2.1: "If this parameter contains none with any other value, an error is
+	// returned"
+	let silent = prompt.contains(&"none");
+	if silent && prompt.len() > 1 {
+		return Err(Error::oidc_authorize_redirect(
+			redirect_uri,
+			"prompt=none cannot be combined with other prompt values",
+			ErrCode::InvalidRequest,
+		));
+	}
+	// We only ever have one session, so the only way to select a different account is to login
+	// again
+	let force_login = prompt.contains(&"login") || prompt.contains(&"select_account");
+
+	let max_age = match params.get("max_age").map(|a| a.parse::<i64>()) {
+		None => None,
+		Some(Ok(a)) if a >= 0 => Some(a),
+		Some(_) => {
+			return Err(Error::oidc_authorize_redirect(
+				redirect_uri,
+				"invalid max_age",
+				ErrCode::InvalidRequest,
+			));
+		}
+	};
+
 	let cfg = cfg.into_inner();
-	let mut ctx = AuthContext::new(cfg.clone(), client.id(), redirect_uri, code_challenge);
+	let mut ctx = AuthContext::new(
+		cfg.clone(),
+		client.id(),
+		redirect_uri.as_str(),
+		code_challenge,
+	);
 
 	if let Some(nonce) = params.get("nonce") {
 		ctx.set_nonce(nonce.clone());
@@ -202,6 +281,59 @@ async fn do_authorize(
 	if let Some(state) = params.get("state") {
 		ctx.set_state(state.clone());
 	}
+	if let Some(scope) = params.get("scope") {
+		ctx.set_scope(granted_scope(scope));
+	}
+	if let Some(claims) = requested_claims {
+		ctx.set_claims(claims);
+	}
+
+	if let Some(session) = current_session(&cfg, &req).await? {
+		let auth_age =
+			OffsetDateTime::now_utc().unix_timestamp() - session.auth_time().unix_timestamp();
+
+		if force_login {
+			tracing::debug!("ignoring session because the client wants the user to login again");
+		} else if max_age.is_some_and(|a| auth_age > a) {
+			tracing::debug!("ignoring session because it is older than max_age");
+		} else if *client.require_mfa() && session.mfa_time().is_none() {
+			// A client that insists on MFA doesn't get to piggyback on a session that didn't have it
+			tracing::debug!("ignoring session because the client requires MFA");
+		} else {
+			ctx.set_principal(*session.principal().id());
+			ctx.set_auth_time(session.auth_time().unix_timestamp());
+			if let Some(mfa_time) = session.mfa_time() {
+				ctx.set_mfa_time(mfa_time.unix_timestamp());
+			}
+			ctx.set_session(*session.id());
+
+			if silent && needs_consent(&cfg, &ctx, &client).await? {
+				return Err(Error::oidc_authorize_redirect(
+					redirect_uri,
+					"prompt=none, but the user would have to consent",
+					ErrCode::ConsentRequired,
+				));
+			}
+
+			let attrs: IdentityAttributes = ciborium::from_reader(
+				&cfg.session_attrs_strong_box()
+					.decrypt(session.attrs(), b"")?[..],
+			)?;
+			let redirect_url = successful_authentication(&cfg, &ctx, attrs).await?;
+
+			return Ok(HttpResponse::SeeOther()
+				.insert_header(("location", redirect_url.as_str()))
+				.finish());
+		}
+	}
+
+	if silent {
+		return Err(Error::oidc_authorize_redirect(
+			redirect_uri,
+			"prompt=none, but the user would have to login",
+			ErrCode::LoginRequired,
+		));
+	}
 
 	let mut redirect_url = cfg.base_url().join("authenticate")?;
 	redirect_url
@@ -209,11 +341,192 @@ async fn do_authorize(
 		.append_pair("ctx", &ctx.to_string())
 		.append_pair("target", client.name());
 
+	if let Some(login_hint) = params.get("login_hint") {
+		// Email is the only kind of login hint we know what to do with
+		if EmailAddress::is_valid(login_hint) {
+			redirect_url
+				.query_pairs_mut()
+				.append_pair("email", login_hint);
+		} else {
+			tracing::debug!("ignoring login_hint that isn't an email address");
+		}
+	}
+
 	Ok(HttpResponse::SeeOther()
 		.insert_header(("location", redirect_url.as_str()))
 		.finish())
 }
 
+/// The params that a client pushed to us (RFC9126) in exchange for the given `request_uri`.  Each
+/// pushed request can only be used once, and only by the client that pushed it.
+async fn pushed_params(
+	cfg: &super::Config,
+	request_uri: &str,
+	client_id: &Uuid,
+) -> Result<HashMap<String, String>, Error> {
+	let Some(id) = request_uri
+		.strip_prefix(REQUEST_URI_PREFIX)
+		.and_then(|id| Uuid::from_base64(id).ok())
+	else {
+		return Err(Error::oidc_authorize(
+			format!("invalid request_uri {request_uri}"),
+			ErrCode::InvalidRequestUri,
+		));
+	};
+
+	let Some(params) = cfg
+		.db()
+		.pushed_authorization_request()
+		.await?
+		.take_valid(&id, client_id)
+		.await?
+	else {
+		return Err(Error::oidc_authorize(
+			format!("unknown or expired request_uri {request_uri}"),
+			ErrCode::InvalidRequestUri,
+		));
+	};
+
+	let mut params: HashMap<String, String> = form_urlencoded::parse(params.as_bytes())
+		.into_owned()
+		.collect();
+	// The client's credentials (including client_id) get pulled out before the request is stored
+	params.insert("client_id".to_string(), client_id.to_base64());
+
+	Ok(params)
+}
+
+/// How long a request object can be valid for.  RFC9101 doesn't set a limit, but one that lasts
+/// for days is one that leaks for days, and the client is meant to be making them as it goes.
+const REQUEST_OBJECT_MAX_LIFETIME: u64 = 600;
+
+/// The authorization request params in a request object (RFC9101) that the given client has
+/// signed with one of the keys in its JWK Set.
+async fn request_object_params(
+	cfg: &super::Config,
+	client: &OidcClient,
+	request: &str,
+) -> Result<HashMap<String, String>, Error> {
+	let invalid = |reason: &str| Error::oidc_authorize(reason, ErrCode::InvalidRequestObject);
+
+	let Ok(request_object) = Jwt::parse_with_typ(request, &["JWT", REQUEST_OBJECT_TYP]) else {
+		return Err(invalid("unparseable request object"));
+	};
+
+	let Some(jwks_uri) = client.jwks_uri() else {
+		return Err(invalid(
+			"client has no jwks_uri to verify request object with",
+		));
+	};
+	if !JwkSet::from_url(jwks_uri, cfg.http_client())
+		.await?
+		.iter()
+		.any(|k| request_object.verify(k))
+	{
+		return Err(invalid("invalid request object signature"));
+	}
+
+	// RFC9101 s4 says these are optional, but a request object that could have been meant for some
+	// other server, or made by some other client, is not one we want to act on
+	let client_id = client.id().to_base64();
+	if request_object.peek_iss() != Some(client_id.as_str()) {
+		return Err(invalid("request object iss is not the client"));
+	}
+	// Whether or not the client put a trailing slash on our issuer URL is neither here nor there
+	if request_object
+		.peek_aud()
+		.map(|aud| aud.trim_end_matches('/'))
+		!= Some(cfg.base_url().as_str().trim_end_matches('/'))
+	{
+		return Err(invalid("request object aud is not us"));
+	}
+
+	if request_object
+		.peek_exp()
+		.saturating_sub(request_object.peek_iat())
+		> REQUEST_OBJECT_MAX_LIFETIME
+	{
+		return Err(invalid("request object is valid for too long"));
+	}
+
+	// These all have fields of their own in a Jwt, so they'd never make it into the params below,
+	// and none of them are anything a client gets to tell us about the user
+	if request_object.peek_sub().is_some()
+		|| request_object.peek_auth_time().is_some()
+		|| request_object.peek_attrs().is_some()
+		|| request_object.peek_events().is_some()
+		|| request_object.peek_cnf_jkt().is_some()
+	{
+		return Err(invalid(
+			"request object contains claims that are not authorization request params",
+		));
+	}
+
+	let mut params = HashMap::new();
+	if let Some(scope) = request_object.peek_scope() {
+		params.insert("scope".to_string(), scope.to_string());
+	}
+	if let Some(nonce) = request_object.peek_nonce() {
+		params.insert("nonce".to_string(), nonce.to_string());
+	}
+	for (name, value) in request_object.peek_claims() {
+		match name.as_str() {
+			"request" | "request_uri" => {
+				return Err(invalid(
+					"request object cannot contain another request object",
+				));
+			}
+			"client_id" if value.as_str() != Some(client_id.as_str()) => {
+				return Err(invalid("request object client_id does not match"));
+			}
+			_ => (),
+		}
+
+		// Params are all strings once they've been through a URL, so that's what everything else
+		// is expecting
+		let value = match value {
+			JsonValue::String(s) => s.clone(),
+			v => v.to_string(),
+		};
+		params.insert(name.clone(), value);
+	}
+
+	// Anyone who sees the authorization URL (browser history, proxy logs, Referer headers) sees
+	// the request object, so it can only be used the once
+	let Some(jti) = request_object.peek_jti() else {
+		return Err(invalid("request object has no jti"));
+	};
+	let valid_before = i64::try_from(request_object.peek_exp())
+		.ok()
+		.and_then(|exp| OffsetDateTime::from_unix_timestamp(exp).ok())
+		.ok_or_else(|| invalid("request object exp out of range"))?;
+	if !cfg
+		.db()
+		.used_request_object()
+		.await?
+		.record(client.id(), jti, &valid_before)
+		.await?
+	{
+		return Err(invalid("request object already used"));
+	}
+
+	Ok(params)
+}
+
+/// The still-valid session the user has come to us with, if any.  A session cookie that doesn't
+/// correspond to a usable session is just ignored, and the user gets to log in the old-fashioned way.
+async fn current_session(cfg: &super::Config, req: &HttpRequest) -> Result<Option<Session>, Error> {
+	let Some(cookie) = req.cookie(session::COOKIE_NAME) else {
+		return Ok(None);
+	};
+	let Ok(id) = Uuid::parse_str(cookie.value()) else {
+		tracing::debug!("ignoring unparseable session cookie");
+		return Ok(None);
+	};
+
+	Ok(cfg.db().session().await?.find_valid(&id).await?)
+}
+
 pub async fn cookie_check(
 	cfg: web::Data<super::Config>,
 	req: HttpRequest,
@@ -254,11 +567,19 @@ pub async fn cookie_check(
 				</p>
 				<h2>Our Cookies</h2>
 				<p>
-				There is only one cookie we set: <tt>csrf_token</tt>.
+				There are only two cookies we set.
+				</p>
+				<p>
+				The first is <tt>csrf_token</tt>.
 				This token contains a small, random string that we use to protect against <a href="https://owasp.org/www-community/attacks/csrf">Cross-Site Request Forgery</a> attacks during authentication.
 				</p>
 				<p>
-				The cookie's value is not logged anywhere, is not accessible from outside the authentication process, and is not otherwise used for any purpose other than to protect the security of your authentication process.
+				The second is <tt>authul_session</tt>, which is only set once you have successfully logged in.
+				It contains a random identifier that lets us recognise you when another website sends you here to login, so you don't have to enter your password all over again.
+				It is discarded when you close your browser.
+				</p>
+				<p>
+				Neither cookie's value is logged anywhere, is accessible from outside the authentication process, or is otherwise used for any purpose other than to protect the security of your authentication process.
 				</p>
 			</body>
 			</html>
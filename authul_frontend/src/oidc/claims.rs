@@ -0,0 +1,148 @@
+//! Turning what we know about a principal into the standard claims from OIDC Core 1.0 s5.1, so
+//! that clients can read them with any old OIDC library, rather than having to know about our
+//! identity attributes
+use serde::{Deserialize, Serialize};
+use serde_json::{Map as JsonMap, Value as JsonValue};
+use std::str::FromStr;
+
+use authul_db::types::{IdentityAttributeKind as AttrKind, IdentityAttributes};
+
+/// The scopes a client can be granted; anything else it asks for is ignored, as per RFC6749 s3.3
+pub(super) const SUPPORTED_SCOPES: &[&str] = &["openid", "email", "profile"];
+
+/// Which claims each scope gets the client (OIDC Core 1.0 s5.4), for the ones we have something
+/// to say about
+const SCOPE_CLAIMS: &[(&str, &[&str])] = &[
+	("email", &["email", "email_verified"]),
+	("profile", &["name", "preferred_username"]),
+];
+
+/// Every claim that might turn up in an ID token, for the provider metadata
+pub(super) const SUPPORTED_CLAIMS: &[&str] = &[
+	"iss",
+	"sub",
+	"aud",
+	"exp",
+	"iat",
+	"auth_time",
+	"nonce",
+	"email",
+	"email_verified",
+	"name",
+	"preferred_username",
+];
+
+/// Whittle down the scopes a client asked for to the ones we can give it.
+pub(super) fn granted_scope(requested: &str) -> String {
+	let mut granted: Vec<&str> = vec![];
+	for scope in requested.split(' ') {
+		if SUPPORTED_SCOPES.contains(&scope) && !granted.contains(&scope) {
+			granted.push(scope);
+		}
+	}
+
+	granted.join(" ")
+}
+
+/// The individual claims a client asked for with the `claims` request parameter (OIDC Core 1.0
+/// s5.5), on top of whatever its scopes get it
+#[cfg_attr(authul_expose_privates, visibility::make(pub))]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct RequestedClaims {
+	id_token: Vec<String>,
+	userinfo: Vec<String>,
+}
+
+impl RequestedClaims {
+	pub(crate) fn new(id_token: Vec<String>, userinfo: Vec<String>) -> Self {
+		Self { id_token, userinfo }
+	}
+
+	/// The claims the client wants in the ID token
+	pub(crate) fn id_token(&self) -> &[String] {
+		&self.id_token
+	}
+
+	/// The claims the client wants from the userinfo endpoint, which gets them from the access
+	/// token
+	pub(crate) fn userinfo(&self) -> &[String] {
+		&self.userinfo
+	}
+}
+
+impl FromStr for RequestedClaims {
+	type Err = serde_json::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		// Each claim can say whether it's essential, or what value the client would like it to
+		// have, but we hand over whatever we've got regardless, so only the names matter
+		#[derive(Deserialize)]
+		struct Param {
+			#[serde(default)]
+			id_token: JsonMap<String, JsonValue>,
+			#[serde(default)]
+			userinfo: JsonMap<String, JsonValue>,
+		}
+		let param: Param = serde_json::from_str(s)?;
+
+		Ok(Self {
+			id_token: param.id_token.keys().cloned().collect(),
+			userinfo: param.userinfo.keys().cloned().collect(),
+		})
+	}
+}
+
+/// Everything that decides which claims about a principal go in a client's tokens
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ClaimsGrant<'a> {
+	/// The (space-separated) scopes the client was granted
+	pub(crate) scope: &'a str,
+	pub(crate) requested: &'a RequestedClaims,
+}
+
+/// Every standard claim we can make about the principal, before working out which of them the
+/// client is entitled to.
+pub(super) fn standard_claims(attrs: &IdentityAttributes) -> JsonMap<String, JsonValue> {
+	let mut claims = JsonMap::new();
+
+	// A principal can have any number of email addresses, but there's only room for one, so it
+	// had better be the one they'd most like to be contacted at
+	let email = [
+		AttrKind::PrimaryEmail,
+		AttrKind::VerifiedEmail,
+		AttrKind::Email,
+	]
+	.iter()
+	.find_map(|kind| attrs.iter().find(|a| a.kind() == kind));
+	if let Some(email) = email {
+		claims.insert("email".to_string(), email.value().into());
+		claims.insert(
+			"email_verified".to_string(),
+			(email.kind() != &AttrKind::Email).into(),
+		);
+	}
+
+	for (kind, claim) in [
+		(AttrKind::DisplayName, "name"),
+		(AttrKind::Username, "preferred_username"),
+	] {
+		if let Some(attr) = attrs.iter().find(|a| a.kind() == &kind) {
+			claims.insert(claim.to_string(), attr.value().into());
+		}
+	}
+
+	claims
+}
+
+/// Whether any of the (space-separated) scopes entitles the client to the given claim
+pub(super) fn scope_grants(scope: &str, claim: &str) -> bool {
+	let scopes = scope.split(' ').collect::<Vec<_>>();
+	SCOPE_CLAIMS
+		.iter()
+		.any(|(s, c)| scopes.contains(s) && c.contains(&claim))
+}
+
+/// Whether the given claim is one of the standard ones we hand out about a principal
+pub(crate) fn is_standard_claim(claim: &str) -> bool {
+	SCOPE_CLAIMS.iter().any(|(_, c)| c.contains(&claim))
+}
@@ -0,0 +1,318 @@
+//! Figuring out which client we're talking to, at the endpoints where clients come to us directly
+//! rather than via the user's browser
+use actix_web::{rt::task::spawn_blocking, HttpRequest};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use serde::Deserialize;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{Config, Error};
+use crate::{db, middleware::allow_client_origin};
+use authul_crypto::{JwkSet, Jwt};
+use authul_db::{model::OidcClient, types::ClientAuthMethod};
+use authul_oauth2::error_code::TokenEndpoint as TokenErrCode;
+use authul_util::Base64Uuid;
+
+const JWT_BEARER_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+/// The client authentication parameters that can turn up in a request body.  Flatten this into
+/// the endpoint's request type.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(super) struct ClientCredentials {
+	client_id: Option<String>,
+	client_secret: Option<String>,
+	client_assertion_type: Option<String>,
+	client_assertion: Option<String>,
+}
+
+/// However it is the client says it's going to prove who it is
+#[derive(Clone, Debug)]
+pub(super) struct ClientAuth {
+	credentials: Credentials,
+	// The request itself, for checking where it came from once we know who it's from
+	req: HttpRequest,
+}
+
+#[derive(Clone, Debug)]
+enum Credentials {
+	PrivateKeyJwt {
+		assertion: String,
+	},
+	ClientSecret {
+		method: ClientAuthMethod,
+		client_id: String,
+		client_secret: String,
+	},
+	// A public client, which has nothing to offer but its name
+	None {
+		client_id: String,
+	},
+}
+
+impl ClientAuth {
+	/// Work out which authentication method the client is using.  This only checks that the
+	/// client has sent everything the method needs; nothing gets verified until
+	/// [`authenticate`](Self::authenticate).
+	pub(super) fn from_request(
+		req: &HttpRequest,
+		basic_auth: Option<BasicAuth>,
+		creds: ClientCredentials,
+	) -> Result<Self, Error> {
+		Ok(Self {
+			credentials: Credentials::new(basic_auth, creds)?,
+			req: req.clone(),
+		})
+	}
+
+	/// Verify the client's credentials, and return the client they belong to.
+	///
+	/// `token` is whatever the request is about (code, refresh token, etc).  A client assertion
+	/// JWT has to have that as its `jti`, which stops an assertion being replayed to do anything
+	/// other than what it was made for.  Requests that aren't about anything in particular pass
+	/// `None`, and then the assertion can only ever be used once.
+	pub(super) async fn authenticate(
+		self,
+		cfg: &Config,
+		token: Option<&str>,
+	) -> Result<OidcClient, Error> {
+		let oidc_client = match self.credentials {
+			Credentials::PrivateKeyJwt { assertion } => {
+				authenticate_jwt(cfg, &assertion, token).await?
+			}
+			Credentials::ClientSecret {
+				method,
+				client_id,
+				client_secret,
+			} => authenticate_secret(cfg, method, &client_id, client_secret).await?,
+			Credentials::None { client_id } => {
+				find_client(cfg, &client_id, ClientAuthMethod::None).await?
+			}
+		};
+
+		// A browser will only show the response to a page on an origin the client has
+		// registered, and there's no point doing the work (and burning the code) for anyone else
+		if !allow_client_origin(&self.req, &oidc_client) {
+			return Err(Error::oidc_token(
+				"origin not allowed for client",
+				TokenErrCode::InvalidClient,
+			));
+		}
+
+		Ok(oidc_client)
+	}
+}
+
+impl Credentials {
+	fn new(basic_auth: Option<BasicAuth>, creds: ClientCredentials) -> Result<Self, Error> {
+		let ClientCredentials {
+			client_id,
+			client_secret,
+			client_assertion_type,
+			client_assertion,
+		} = creds;
+
+		// RFC6749 s2.3 says a client MUST NOT use more than one method in a request, and we
+		// certainly don't want to have to decide which one to believe
+		match (basic_auth, client_secret, client_assertion) {
+			(Some(basic), None, None) => {
+				let Some(client_secret) = basic.password() else {
+					return Err(Error::oidc_token(
+						"no client_secret in Authorization header",
+						TokenErrCode::InvalidClient,
+					));
+				};
+				if client_id.is_some_and(|id| id != basic.user_id()) {
+					return Err(Error::oidc_token(
+						"client_id does not match Authorization header",
+						TokenErrCode::InvalidRequest,
+					));
+				}
+
+				// RFC6749 s2.3.1 has the client form-encode its ID and secret before they go into
+				// the header, but we only ever hand out base64url, which encoding leaves alone
+				Ok(Self::ClientSecret {
+					method: ClientAuthMethod::ClientSecretBasic,
+					client_id: basic.user_id().to_string(),
+					client_secret: client_secret.to_string(),
+				})
+			}
+			(None, Some(client_secret), None) => Ok(Self::ClientSecret {
+				method: ClientAuthMethod::ClientSecretPost,
+				client_id: client_id.ok_or_else(|| {
+					Error::oidc_token("no client_id", TokenErrCode::InvalidRequest)
+				})?,
+				client_secret,
+			}),
+			(None, None, Some(assertion)) => {
+				let client_assertion_type = client_assertion_type.ok_or_else(|| {
+					Error::oidc_token("no client_assertion_type", TokenErrCode::InvalidRequest)
+				})?;
+				if client_assertion_type != JWT_BEARER_ASSERTION_TYPE {
+					return Err(Error::oidc_token(
+						format!("unsupported client_assertion_type {client_assertion_type}"),
+						TokenErrCode::InvalidClient,
+					));
+				}
+
+				Ok(Self::PrivateKeyJwt { assertion })
+			}
+			(None, None, None) => Ok(Self::None {
+				client_id: client_id.ok_or_else(|| {
+					Error::oidc_token("no client authentication", TokenErrCode::InvalidClient)
+				})?,
+			}),
+			_ => Err(Error::oidc_token(
+				"more than one client authentication method used",
+				TokenErrCode::InvalidRequest,
+			)),
+		}
+	}
+}
+
+async fn authenticate_jwt(
+	cfg: &Config,
+	client_assertion: &str,
+	token: Option<&str>,
+) -> Result<OidcClient, Error> {
+	let Ok(client_jwt): Result<Jwt, _> = client_assertion.parse() else {
+		return Err(Error::oidc_token(
+			"invalid client JWT",
+			TokenErrCode::InvalidClient,
+		));
+	};
+
+	// What I love(*) about JWTs is that in order to be able to trust them, you have to pull them
+	// apart before you trust them.
+	let Some(claimed_client_id) = client_jwt.peek_sub() else {
+		return Err(Error::oidc_token(
+			"client JWT lacks sub",
+			TokenErrCode::InvalidClient,
+		));
+	};
+
+	let claimed_oidc_client =
+		find_client(cfg, claimed_client_id, ClientAuthMethod::PrivateKeyJwt).await?;
+
+	let Some(jwks_uri) = claimed_oidc_client.jwks_uri() else {
+		return Err(Error::cant_happen("private_key_jwt client has no jwks_uri"));
+	};
+
+	if !JwkSet::from_url(jwks_uri, cfg.http_client())
+		.await?
+		.iter()
+		.any(|k| client_jwt.verify(k))
+	{
+		return Err(Error::oidc_token(
+			"invalid client JWT signature",
+			TokenErrCode::InvalidClient,
+		));
+	};
+
+	let Some(jti) = client_jwt.peek_jti() else {
+		return Err(Error::oidc_token(
+			"client JWT lacks jti",
+			TokenErrCode::InvalidClient,
+		));
+	};
+
+	match token {
+		Some(token) => {
+			if jti != token {
+				return Err(Error::oidc_token(
+					"client JWT jti not token",
+					TokenErrCode::InvalidGrant,
+				));
+			}
+		}
+		None => {
+			// We only need to remember the jti for as long as the assertion would be accepted
+			let valid_before = i64::try_from(client_jwt.peek_exp())
+				.ok()
+				.and_then(|exp| OffsetDateTime::from_unix_timestamp(exp).ok())
+				.ok_or_else(|| {
+					Error::oidc_token("client JWT exp out of range", TokenErrCode::InvalidClient)
+				})?;
+
+			if !cfg
+				.db()
+				.used_client_assertion()
+				.await?
+				.record(claimed_oidc_client.id(), jti, &valid_before)
+				.await?
+			{
+				return Err(Error::oidc_token(
+					"client JWT already used",
+					TokenErrCode::InvalidClient,
+				));
+			}
+		}
+	}
+
+	// Houston, we have verification!
+	Ok(claimed_oidc_client)
+}
+
+async fn authenticate_secret(
+	cfg: &Config,
+	method: ClientAuthMethod,
+	client_id: &str,
+	client_secret: String,
+) -> Result<OidcClient, Error> {
+	let oidc_client = find_client(cfg, client_id, method).await?;
+
+	let Some(hash) = oidc_client.client_secret_hash().clone() else {
+		return Err(Error::cant_happen(format!(
+			"{method} client {client_id} has no client_secret_hash"
+		)));
+	};
+
+	if !spawn_blocking(move || bcrypt::verify(&client_secret, &hash)).await?? {
+		return Err(Error::oidc_token(
+			"incorrect client_secret",
+			TokenErrCode::InvalidClient,
+		));
+	}
+
+	Ok(oidc_client)
+}
+
+/// Load up the client the request claims to come from, and make sure that it's authenticating
+/// itself the way it said it would when it was registered.
+async fn find_client(
+	cfg: &Config,
+	client_id: &str,
+	method: ClientAuthMethod,
+) -> Result<OidcClient, Error> {
+	let oidc_client = cfg
+		.db()
+		.oidc_client()
+		.await?
+		.find(&Uuid::from_base64(client_id).map_err(|e| {
+			Error::oidc_token(
+				format!("invalid client_id: {e}"),
+				TokenErrCode::InvalidClient,
+			)
+		})?)
+		.await
+		.map_err(|e| match e {
+			db::Error::NotFound { .. } => Error::oidc_token(
+				format!("unknown client_id {client_id}"),
+				TokenErrCode::InvalidClient,
+			),
+			_ => e.into(),
+		})?;
+
+	// A client only gets to authenticate the way it was registered to, so that (for instance) a
+	// client that's meant to be using private_key_jwt can't be got at by guessing secrets
+	if oidc_client.token_endpoint_auth_method() != &method {
+		return Err(Error::oidc_token(
+			format!(
+				"client {client_id} must authenticate with {}, not {method}",
+				oidc_client.token_endpoint_auth_method()
+			),
+			TokenErrCode::InvalidClient,
+		));
+	}
+
+	Ok(oidc_client)
+}
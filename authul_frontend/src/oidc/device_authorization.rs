@@ -0,0 +1,133 @@
+//! The device authorization endpoint, as per RFC8628, for devices (TVs, CLI tools, and the like)
+//! which can't send the user off to a browser to sign in, and so have to ask the user to go and
+//! do it somewhere else
+use actix_web::{
+	web::{self, ServiceConfig},
+	HttpRequest, HttpResponse,
+};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use rand::Rng as _;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::{
+	claims::granted_scope,
+	client_auth::{ClientAuth, ClientCredentials},
+	Config, Error,
+};
+use authul_oauth2::error_code::TokenEndpoint as TokenErrCode;
+use authul_util::Base64Uuid;
+
+/// The characters a user code is made of; consonants only, as suggested by RFC8628 s6.1, so that
+/// it can't accidentally spell anything, and there's nothing that can be mistaken for a digit
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+/// How many characters from `USER_CODE_ALPHABET` make up a user code (not counting the hyphen)
+const USER_CODE_LENGTH: usize = 8;
+
+pub(super) fn routes(cfg: &mut ServiceConfig) {
+	cfg.service(
+		web::resource("/oidc/device_authorization")
+			.route(web::post().to(post_device_authorization))
+			.route(web::to(|| HttpResponse::MethodNotAllowed())),
+	);
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct DeviceAuthorizationRequest {
+	scope: Option<String>,
+	#[serde(flatten)]
+	client_credentials: ClientCredentials,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct DeviceAuthorizationResponse {
+	device_code: String,
+	user_code: String,
+	verification_uri: String,
+	verification_uri_complete: String,
+	expires_in: i64,
+	interval: u64,
+}
+
+pub(super) async fn post_device_authorization(
+	cfg: web::Data<Config>,
+	req: HttpRequest,
+	basic_auth: Option<BasicAuth>,
+	device_req: web::Form<DeviceAuthorizationRequest>,
+) -> Result<HttpResponse, Error> {
+	let device_req = device_req.into_inner();
+
+	// There's nothing for a client assertion to be bound to yet, so it has to be a fresh one
+	let oidc_client = ClientAuth::from_request(&req, basic_auth, device_req.client_credentials)?
+		.authenticate(&cfg, None)
+		.await?;
+
+	// As with the authorization endpoint, we only hand out ID tokens, so that's what the client
+	// had better be asking for
+	let scope = device_req.scope.as_deref().unwrap_or("openid");
+	if !scope.split(' ').any(|s| s == "openid") {
+		return Err(Error::oidc_token(
+			format!("invalid scope {scope}"),
+			TokenErrCode::InvalidScope,
+		));
+	}
+
+	let device_code = cfg
+		.db()
+		.device_code()
+		.await?
+		.new()
+		.with_oidc_client(oidc_client)
+		.with_user_code(new_user_code())
+		.with_scope(granted_scope(scope))
+		.save()
+		.await?;
+
+	let verification_uri = cfg.base_url().join("device")?;
+	let mut verification_uri_complete = verification_uri.clone();
+	verification_uri_complete
+		.query_pairs_mut()
+		.append_pair("user_code", device_code.user_code());
+
+	Ok(HttpResponse::Ok().json(DeviceAuthorizationResponse {
+		device_code: device_code.id().to_base64(),
+		user_code: device_code.user_code().to_string(),
+		verification_uri: verification_uri.to_string(),
+		verification_uri_complete: verification_uri_complete.to_string(),
+		expires_in: (*device_code.valid_before() - OffsetDateTime::now_utc()).whole_seconds(),
+		interval: Config::DEVICE_CODE_POLLING_INTERVAL.as_secs(),
+	}))
+}
+
+fn new_user_code() -> String {
+	let mut rng = rand::thread_rng();
+	let chars = (0..USER_CODE_LENGTH)
+		.map(|_| USER_CODE_ALPHABET[rng.gen_range(0..USER_CODE_ALPHABET.len())] as char)
+		.collect::<String>();
+
+	format!(
+		"{}-{}",
+		&chars[..USER_CODE_LENGTH / 2],
+		&chars[USER_CODE_LENGTH / 2..]
+	)
+}
+
+/// Turn whatever the user typed in into the form we handed out, so that they don't get told a
+/// code is wrong just because they left out the hyphen, or didn't use capitals.
+pub(crate) fn normalise_user_code(s: &str) -> String {
+	let chars = s
+		.chars()
+		.map(|c| c.to_ascii_uppercase())
+		.filter(|c| c.is_ascii() && USER_CODE_ALPHABET.contains(&(*c as u8)))
+		.collect::<String>();
+
+	if chars.len() == USER_CODE_LENGTH {
+		format!(
+			"{}-{}",
+			&chars[..USER_CODE_LENGTH / 2],
+			&chars[USER_CODE_LENGTH / 2..]
+		)
+	} else {
+		chars
+	}
+}
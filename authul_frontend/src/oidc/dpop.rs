@@ -0,0 +1,61 @@
+//! Checking the DPoP proofs (RFC9449) that clients send along when they want their access tokens
+//! bound to a key of their own, and then whenever they use those tokens
+use actix_web::HttpRequest;
+use time::OffsetDateTime;
+
+use super::{Config, Error};
+use authul_crypto::DpopProof;
+
+const DPOP_HEADER: &str = "dpop";
+
+/// Verify the DPoP proof that came with the request, if there was one, and return the thumbprint of
+/// the key it was made with.
+///
+/// `access_token` is the token the request was made with, for when we're acting as a resource
+/// server.  `invalid` turns the reason a proof was no good into whatever error the endpoint
+/// reports bad proofs with.
+pub(super) async fn verify_proof(
+	cfg: &Config,
+	req: &HttpRequest,
+	access_token: Option<&str>,
+	invalid: impl Fn(String) -> Error,
+) -> Result<Option<String>, Error> {
+	let mut headers = req.headers().get_all(DPOP_HEADER);
+	let Some(header) = headers.next() else {
+		return Ok(None);
+	};
+	// RFC9449 s4.3: there can be only one
+	if headers.next().is_some() {
+		return Err(invalid("more than one DPoP header".to_string()));
+	}
+
+	let proof: DpopProof = header
+		.to_str()
+		.map_err(|e| invalid(e.to_string()))?
+		.parse()
+		.map_err(|e: authul_crypto::Error| invalid(e.to_string()))?;
+
+	let htu = cfg.base_url().join(req.path().trim_start_matches('/'))?;
+	let jkt = proof
+		.verify(req.method().as_str(), htu.as_str(), access_token)
+		.map_err(|e| invalid(e.to_string()))?;
+
+	// The proof would have been turned away once it was too old, so there's no need to remember
+	// it for any longer than that
+	let valid_before = i64::try_from(proof.peek_iat())
+		.ok()
+		.and_then(|iat| OffsetDateTime::from_unix_timestamp(iat).ok())
+		.ok_or_else(|| invalid("iat out of range".to_string()))?
+		+ DpopProof::MAX_AGE;
+	if !cfg
+		.db()
+		.used_dpop_proof()
+		.await?
+		.record(&jkt, proof.peek_jti(), &valid_before)
+		.await?
+	{
+		return Err(invalid("DPoP proof already used".to_string()));
+	}
+
+	Ok(Some(jkt))
+}
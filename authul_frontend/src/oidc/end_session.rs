@@ -0,0 +1,184 @@
+//! RP-initiated logout, as per OpenID Connect RP-Initiated Logout 1.0
+use actix_web::{
+	web::{self, ServiceConfig},
+	HttpRequest, HttpResponse,
+};
+use serde::Deserialize;
+use url::Url;
+use uuid::Uuid;
+
+use super::{Config, Error};
+use crate::{db, middleware::session, periodic_tasks::deliver_backchannel_logouts};
+use authul_crypto::Jwt;
+use authul_util::Base64Uuid;
+
+pub(super) fn routes(cfg: &mut ServiceConfig) {
+	cfg.service(
+		web::resource("/oidc/end_session")
+			.route(web::get().to(get_end_session))
+			.route(web::post().to(post_end_session))
+			.route(web::to(|| HttpResponse::MethodNotAllowed())),
+	);
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct EndSessionRequest {
+	id_token_hint: Option<String>,
+	client_id: Option<String>,
+	post_logout_redirect_uri: Option<String>,
+	state: Option<String>,
+}
+
+pub(super) async fn get_end_session(
+	cfg: web::Data<Config>,
+	req: HttpRequest,
+	params: web::Query<EndSessionRequest>,
+) -> Result<HttpResponse, Error> {
+	do_end_session(cfg, req, params.into_inner()).await
+}
+
+pub(super) async fn post_end_session(
+	cfg: web::Data<Config>,
+	req: HttpRequest,
+	params: web::Form<EndSessionRequest>,
+) -> Result<HttpResponse, Error> {
+	do_end_session(cfg, req, params.into_inner()).await
+}
+
+async fn do_end_session(
+	cfg: web::Data<Config>,
+	req: HttpRequest,
+	params: EndSessionRequest,
+) -> Result<HttpResponse, Error> {
+	let hinted_client_id = match params.id_token_hint {
+		Some(ref hint) => Some(id_token_hint_audience(&cfg, hint).await?),
+		None => None,
+	};
+
+	let client_id = match (hinted_client_id, params.client_id) {
+		(Some(hinted), Some(given)) if hinted != given => {
+			return Err(Error::bad_request("client_id does not match id_token_hint"));
+		}
+		(Some(hinted), _) => Some(hinted),
+		(None, given) => given,
+	};
+
+	// Check where we're sending the user *before* logging them out, so that a bad request
+	// doesn't leave them logged out with nowhere to go
+	let redirect_uri = match params.post_logout_redirect_uri {
+		None => None,
+		Some(uri) => {
+			let Some(client_id) = client_id else {
+				return Err(Error::bad_request(
+					"post_logout_redirect_uri requires id_token_hint or client_id",
+				));
+			};
+			let client =
+				match cfg
+					.db()
+					.oidc_client()
+					.await?
+					.find(&Uuid::from_base64(&client_id).map_err(|_| {
+						Error::bad_request(format!("invalid client_id {client_id}"))
+					})?)
+					.await
+				{
+					Ok(c) => c,
+					Err(db::Error::NotFound(..)) => {
+						return Err(Error::bad_request(format!("unknown client_id {client_id}")));
+					}
+					Err(e) => return Err(e.into()),
+				};
+			if !client.has_post_logout_redirect_uri(&uri) {
+				return Err(Error::bad_request(format!(
+					"invalid post_logout_redirect_uri {uri}"
+				)));
+			}
+
+			let mut uri = Url::parse(&uri).map_err(|_| {
+				Error::bad_request(format!("unparseable post_logout_redirect_uri {uri}"))
+			})?;
+			if let Some(state) = params.state {
+				uri.query_pairs_mut().append_pair("state", &state);
+			}
+			Some(uri)
+		}
+	};
+
+	if let Some(cookie) = req.cookie(session::COOKIE_NAME) {
+		if let Ok(id) = Uuid::parse_str(cookie.value()) {
+			if let Some(s) = cfg.db().session().await?.find_valid(&id).await? {
+				cfg.db().session().await?.end(&s).await?;
+
+				// No point making the clients wait for the next scheduled delivery run to find
+				// out; anything that fails here will get picked up then anyway
+				let cfg = cfg.clone().into_inner();
+				actix_web::rt::spawn(async move {
+					if let Err(e) = deliver_backchannel_logouts(&cfg).await {
+						tracing::error!("failed to deliver back-channel logout notifications: {e}");
+					}
+				});
+			}
+		}
+
+		if !session::ended() {
+			tracing::debug!(
+				"session ended outside of session middleware; cookie will not be removed"
+			);
+		}
+	}
+
+	if let Some(uri) = redirect_uri {
+		Ok(HttpResponse::SeeOther()
+			.insert_header(("location", uri.as_str()))
+			.finish())
+	} else {
+		let logged_out = r#"
+			<!DOCTYPE html>
+			<html style="display: flex; font-family: sans-serif; justify-content: center">
+			<head>
+				<meta charset="utf-8">
+				<meta name="viewport" content="width=device-width, initial-scale=1"/>
+				<title>Logged Out</title>
+			</head>
+			<body style="width: 80%; max-width: 60rem">
+				<h1>You have been logged out</h1>
+				<p>
+				Next time a website sends you here, you'll need to login again.
+				</p>
+				<p>
+				You can close this window now.
+				</p>
+			</body>
+			</html>
+		"#;
+
+		Ok(HttpResponse::Ok()
+			.content_type("text/html")
+			.body(logged_out))
+	}
+}
+
+/// Which client an ID token we issued was for.
+///
+/// The hint will almost certainly have expired by the time the user logs out, since our ID tokens
+/// don't live long, so we only care that it's one of ours.
+async fn id_token_hint_audience(cfg: &Config, hint: &str) -> Result<String, Error> {
+	let Ok(id_token): Result<Jwt, _> = hint.parse() else {
+		return Err(Error::bad_request("unparseable id_token_hint"));
+	};
+
+	if !cfg
+		.oidc_jwks()
+		.await?
+		.iter()
+		.any(|k| id_token.verify_signature(k))
+	{
+		return Err(Error::bad_request("id_token_hint has invalid signature"));
+	}
+
+	id_token
+		.peek_aud()
+		.map(str::to_string)
+		.ok_or_else(|| Error::bad_request("id_token_hint lacks aud"))
+}
@@ -0,0 +1,158 @@
+//! Token introspection, as per RFC7662, so that an API which has been handed a token can find out
+//! whether it's still any good, and who it's for
+use actix_web::{
+	web::{self, ServiceConfig},
+	HttpRequest, HttpResponse,
+};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{
+	client_auth::{ClientAuth, ClientCredentials},
+	middleware::Cors,
+	token::{access_token_principal, verify_access_token},
+	Config, Error,
+};
+use crate::db;
+use authul_oauth2::error_code::TokenEndpoint as TokenErrCode;
+use authul_util::Base64Uuid;
+
+pub(super) fn routes(cfg: &mut ServiceConfig) {
+	cfg.service(
+		web::resource("/oidc/introspect")
+			.wrap(Cors::POST)
+			.route(web::post().to(post_oidc_introspect))
+			.route(web::to(|| HttpResponse::MethodNotAllowed())),
+	);
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct IntrospectRequest {
+	token: Option<String>,
+	#[serde(flatten)]
+	client_credentials: ClientCredentials,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+struct IntrospectResponse {
+	active: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	client_id: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	sub: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	iss: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	scope: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	token_type: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	exp: Option<i64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	iat: Option<i64>,
+	// RFC9449 s6.2: so that a resource server knows to check the DPoP proof
+	#[serde(skip_serializing_if = "Option::is_none")]
+	cnf: Option<serde_json::Value>,
+}
+
+pub(super) async fn post_oidc_introspect(
+	cfg: web::Data<Config>,
+	req: HttpRequest,
+	basic_auth: Option<BasicAuth>,
+	introspect_req: web::Form<IntrospectRequest>,
+) -> Result<HttpResponse, Error> {
+	let introspect_req = introspect_req.into_inner();
+
+	let token = introspect_req
+		.token
+		.ok_or_else(|| Error::oidc_token("no token", TokenErrCode::InvalidRequest))?;
+	let oidc_client =
+		ClientAuth::from_request(&req, basic_auth, introspect_req.client_credentials)?
+			.authenticate(&cfg, Some(&token))
+			.await?;
+
+	// RFC7662 s2.1 wants introspection to be restricted to those who are allowed to know about
+	// tokens, and anyone at all can claim to be a public client
+	if oidc_client.is_public() {
+		return Err(Error::oidc_token(
+			"public clients cannot introspect tokens",
+			TokenErrCode::InvalidClient,
+		));
+	}
+
+	let mut response = IntrospectResponse::default();
+
+	if let Ok(id) = Uuid::from_base64(&token) {
+		match cfg.db().refresh_token().await?.find(&id).await {
+			// Refresh tokens are only any use to the client they were issued to, so nobody else
+			// needs to know anything about them
+			Ok(refresh_token)
+				if refresh_token.oidc_client().id() == oidc_client.id()
+					&& !refresh_token.used()
+					&& !refresh_token.is_expired() =>
+			{
+				response = IntrospectResponse {
+					active: true,
+					client_id: Some(oidc_client.id().to_base64()),
+					sub: Some(
+						cfg.subject_identifier(&oidc_client, refresh_token.principal().id())
+							.await?,
+					),
+					iss: Some(cfg.base_url().to_string()),
+					exp: Some(refresh_token.valid_before().unix_timestamp()),
+					..IntrospectResponse::default()
+				};
+			}
+			Ok(_) | Err(db::Error::NotFound(..)) => (),
+			Err(e) => return Err(e.into()),
+		}
+	} else if let Some(access_token) = verify_access_token(&cfg, &token).await? {
+		// Unlike refresh tokens, access tokens are *meant* to be handed to other parties, who will
+		// want to check them
+		let subject_exists = match access_token.peek_sub() {
+			// Tokens from the client credentials grant are about the client itself
+			Some(sub) if Some(sub) == access_token.peek_aud() => match Uuid::from_base64(sub) {
+				Ok(id) => match cfg.db().oidc_client().await?.find(&id).await {
+					Ok(_) => true,
+					Err(db::Error::NotFound(..)) => false,
+					Err(e) => return Err(e.into()),
+				},
+				Err(_) => false,
+			},
+			Some(_) => match access_token_principal(&cfg, &access_token) {
+				Some(uid) => match cfg.db().principal().await?.find(&uid).await {
+					Ok(_) => true,
+					Err(db::Error::NotFound(..)) => false,
+					Err(e) => return Err(e.into()),
+				},
+				None => false,
+			},
+			None => false,
+		};
+
+		if subject_exists {
+			response = IntrospectResponse {
+				active: true,
+				client_id: access_token.peek_aud().map(str::to_string),
+				sub: access_token.peek_sub().map(str::to_string),
+				iss: access_token.peek_iss().map(str::to_string),
+				scope: access_token.peek_scope().map(str::to_string),
+				token_type: Some(
+					match access_token.peek_cnf_jkt() {
+						Some(_) => "DPoP",
+						None => "Bearer",
+					}
+					.to_string(),
+				),
+				exp: i64::try_from(access_token.peek_exp()).ok(),
+				iat: i64::try_from(access_token.peek_iat()).ok(),
+				cnf: access_token
+					.peek_cnf_jkt()
+					.map(|jkt| serde_json::json!({ "jkt": jkt })),
+			};
+		}
+	}
+
+	Ok(HttpResponse::Ok().json(response))
+}
@@ -2,11 +2,32 @@ use super::{middleware, AuthContext, Config, Error};
 use actix_web::web::ServiceConfig;
 
 mod authorize;
+mod claims;
+mod client_auth;
+mod device_authorization;
+mod dpop;
+mod end_session;
+mod introspect;
 mod provider_metadata;
+mod pushed_authorization;
+mod registration;
+mod revoke;
 mod token;
+mod userinfo;
+
+pub(crate) use claims::{is_standard_claim, ClaimsGrant, RequestedClaims};
+pub(crate) use device_authorization::normalise_user_code;
+pub(crate) use token::issue_tokens;
 
 pub(super) fn routes(cfg: &mut ServiceConfig) {
 	authorize::routes(cfg);
 	provider_metadata::routes(cfg);
 	token::routes(cfg);
+	device_authorization::routes(cfg);
+	pushed_authorization::routes(cfg);
+	registration::routes(cfg);
+	revoke::routes(cfg);
+	introspect::routes(cfg);
+	userinfo::routes(cfg);
+	end_session::routes(cfg);
 }
@@ -5,7 +5,24 @@ use actix_web::{
 use serde::Serialize;
 use serde_json::json;
 
-use super::{middleware::Cors, Error};
+use super::{
+	claims::{SUPPORTED_CLAIMS, SUPPORTED_SCOPES},
+	middleware::Cors,
+	Error,
+};
+
+const CONFIDENTIAL_CLIENT_AUTH_METHODS: &[&str] = &[
+	"private_key_jwt",
+	"client_secret_basic",
+	"client_secret_post",
+];
+// Public clients can get and revoke tokens, but they don't get to introspect them
+const ALL_CLIENT_AUTH_METHODS: &[&str] = &[
+	"private_key_jwt",
+	"client_secret_basic",
+	"client_secret_post",
+	"none",
+];
 
 pub(super) fn routes(cfg: &mut ServiceConfig) {
 	cfg.service(
@@ -27,8 +44,18 @@ struct ProviderMetadata {
 	issuer: String,
 	authorization_endpoint: String,
 	token_endpoint: String,
+	device_authorization_endpoint: String,
+	pushed_authorization_request_endpoint: String,
+	require_pushed_authorization_requests: bool,
+	registration_endpoint: String,
+	userinfo_endpoint: String,
+	end_session_endpoint: String,
+	backchannel_logout_supported: bool,
+	backchannel_logout_session_supported: bool,
 	jwks_uri: String,
 	scopes_supported: Vec<&'static str>,
+	claims_supported: Vec<&'static str>,
+	claims_parameter_supported: bool,
 	response_types_supported: Vec<&'static str>,
 	response_modes_supported: Vec<&'static str>,
 	grant_types_supported: Vec<&'static str>,
@@ -36,6 +63,15 @@ struct ProviderMetadata {
 	id_token_signing_alg_values_supported: Vec<&'static str>,
 	token_endpoint_auth_methods_supported: Vec<&'static str>,
 	token_endpoint_auth_signing_alg_values_supported: Vec<&'static str>,
+	revocation_endpoint: String,
+	revocation_endpoint_auth_methods_supported: Vec<&'static str>,
+	revocation_endpoint_auth_signing_alg_values_supported: Vec<&'static str>,
+	introspection_endpoint: String,
+	introspection_endpoint_auth_methods_supported: Vec<&'static str>,
+	introspection_endpoint_auth_signing_alg_values_supported: Vec<&'static str>,
+	dpop_signing_alg_values_supported: Vec<&'static str>,
+	request_parameter_supported: bool,
+	request_object_signing_alg_values_supported: Vec<&'static str>,
 	request_uri_parameter_supported: bool,
 }
 
@@ -46,15 +82,48 @@ pub(super) async fn get_openid_configuration(
 		issuer: cfg.base_url().to_string(),
 		authorization_endpoint: cfg.base_url().join("oidc/authorize")?.to_string(),
 		token_endpoint: cfg.base_url().join("oidc/token")?.to_string(),
+		device_authorization_endpoint: cfg
+			.base_url()
+			.join("oidc/device_authorization")?
+			.to_string(),
+		pushed_authorization_request_endpoint: cfg.base_url().join("oidc/par")?.to_string(),
+		// Individual clients can be made to push their requests, but it's not for everyone
+		require_pushed_authorization_requests: false,
+		registration_endpoint: cfg.base_url().join("oidc/register")?.to_string(),
+		userinfo_endpoint: cfg.base_url().join("oidc/userinfo")?.to_string(),
+		end_session_endpoint: cfg.base_url().join("oidc/end_session")?.to_string(),
+		backchannel_logout_supported: true,
+		// Logout tokens identify the user by sub alone; we don't hand out sids
+		backchannel_logout_session_supported: false,
 		jwks_uri: cfg.base_url().join("oidc/jwks.json")?.to_string(),
-		scopes_supported: vec!["openid"],
+		scopes_supported: SUPPORTED_SCOPES.to_vec(),
+		claims_supported: SUPPORTED_CLAIMS.to_vec(),
+		claims_parameter_supported: true,
 		response_types_supported: vec!["code"],
 		response_modes_supported: vec!["query"],
-		grant_types_supported: vec!["authorization_code"],
-		subject_types_supported: vec!["public"],
+		grant_types_supported: vec![
+			"authorization_code",
+			"refresh_token",
+			"client_credentials",
+			super::token::DEVICE_CODE_GRANT_TYPE,
+		],
+		// Which one a client gets is up to whoever registers it
+		subject_types_supported: vec!["public", "pairwise"],
 		id_token_signing_alg_values_supported: vec!["EdDSA"],
-		token_endpoint_auth_methods_supported: vec!["private_key_jwt"],
+		token_endpoint_auth_methods_supported: ALL_CLIENT_AUTH_METHODS.to_vec(),
 		token_endpoint_auth_signing_alg_values_supported: vec!["EdDSA"],
+		revocation_endpoint: cfg.base_url().join("oidc/revoke")?.to_string(),
+		revocation_endpoint_auth_methods_supported: ALL_CLIENT_AUTH_METHODS.to_vec(),
+		revocation_endpoint_auth_signing_alg_values_supported: vec!["EdDSA"],
+		introspection_endpoint: cfg.base_url().join("oidc/introspect")?.to_string(),
+		introspection_endpoint_auth_methods_supported: CONFIDENTIAL_CLIENT_AUTH_METHODS.to_vec(),
+		introspection_endpoint_auth_signing_alg_values_supported: vec!["EdDSA"],
+		dpop_signing_alg_values_supported: vec!["EdDSA"],
+		request_parameter_supported: true,
+		request_object_signing_alg_values_supported: vec!["EdDSA"],
+		// This is about fetching request objects from wherever the client says they are, which we
+		// don't do; the request_uris handed out by the PAR endpoint are covered by
+		// pushed_authorization_request_endpoint
 		request_uri_parameter_supported: false,
 	}))
 }
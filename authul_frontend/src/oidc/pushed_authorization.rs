@@ -0,0 +1,106 @@
+//! The pushed authorization request endpoint, as per RFC9126, where a client can hand us its
+//! authorization request directly, and only has to send a reference to it through the user's
+//! browser
+use actix_web::{
+	web::{self, ServiceConfig},
+	HttpRequest, HttpResponse,
+};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use time::OffsetDateTime;
+use url::form_urlencoded;
+
+use super::{
+	client_auth::{ClientAuth, ClientCredentials},
+	Config, Error,
+};
+use authul_oauth2::error_code::TokenEndpoint as TokenErrCode;
+use authul_util::Base64Uuid;
+
+/// What goes on the front of a pushed request's ID to make a `request_uri` out of it (RFC9126 s2.2)
+pub(super) const REQUEST_URI_PREFIX: &str = "urn:ietf:params:oauth:request_uri:";
+
+pub(super) fn routes(cfg: &mut ServiceConfig) {
+	cfg.service(
+		web::resource("/oidc/par")
+			.route(web::post().to(post_par))
+			.route(web::to(|| HttpResponse::MethodNotAllowed())),
+	);
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct PushedAuthorizationRequest {
+	// This has to come first, so that the client's credentials don't end up in the stored params
+	#[serde(flatten)]
+	client_credentials: ClientCredentials,
+	#[serde(flatten)]
+	params: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct PushedAuthorizationResponse {
+	request_uri: String,
+	expires_in: i64,
+}
+
+pub(super) async fn post_par(
+	cfg: web::Data<Config>,
+	req: HttpRequest,
+	basic_auth: Option<BasicAuth>,
+	par_req: web::Form<PushedAuthorizationRequest>,
+) -> Result<HttpResponse, Error> {
+	let par_req = par_req.into_inner();
+
+	let oidc_client = ClientAuth::from_request(&req, basic_auth, par_req.client_credentials)?
+		.authenticate(&cfg, None)
+		.await?;
+
+	let params = par_req.params;
+
+	// RFC9126 s2.1: a pushed request can't refer to another pushed request
+	if params.contains_key("request_uri") {
+		return Err(Error::oidc_token(
+			"request_uri cannot be pushed",
+			TokenErrCode::InvalidRequest,
+		));
+	}
+
+	// Everything else gets checked when the user turns up at the authorization endpoint, same as
+	// for any other request, but the redirect_uri is worth checking now, because by then it's too
+	// late to tell the client it got it wrong
+	match params.get("redirect_uri") {
+		Some(redirect_uri) if oidc_client.has_redirect_uri(redirect_uri) => (),
+		Some(redirect_uri) => {
+			return Err(Error::oidc_token(
+				format!("invalid redirect_uri {redirect_uri}"),
+				TokenErrCode::InvalidRequest,
+			));
+		}
+		None => {
+			return Err(Error::oidc_token(
+				"missing redirect_uri",
+				TokenErrCode::InvalidRequest,
+			));
+		}
+	}
+
+	let pushed_request = cfg
+		.db()
+		.pushed_authorization_request()
+		.await?
+		.new()
+		.with_oidc_client(oidc_client)
+		.with_params(
+			form_urlencoded::Serializer::new(String::new())
+				.extend_pairs(&params)
+				.finish(),
+		)
+		.save()
+		.await?;
+
+	Ok(HttpResponse::Created().json(PushedAuthorizationResponse {
+		request_uri: format!("{REQUEST_URI_PREFIX}{}", pushed_request.id().to_base64()),
+		expires_in: (*pushed_request.valid_before() - OffsetDateTime::now_utc()).whole_seconds(),
+	}))
+}
@@ -0,0 +1,581 @@
+//! Dynamic client registration, as per RFC7591, so that clients can be created by whoever holds an
+//! initial access token, rather than only by someone with access to the database; and the
+//! management protocol from RFC7592, so that a client registered that way can look after its own
+//! registration afterwards
+use actix_web::{
+	rt::task::spawn_blocking,
+	web::{self, ServiceConfig},
+	HttpResponse,
+};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use rand::RngCore as _;
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use url::{Host, Url};
+use uuid::Uuid;
+
+use super::{Config, Error};
+use crate::db;
+use authul_db::{
+	model::OidcClient,
+	types::{ClientAuthMethod, InvalidRegistration},
+};
+use authul_oauth2::error_code::RegistrationEndpoint as ErrCode;
+use authul_util::Base64Uuid;
+
+pub(super) fn routes(cfg: &mut ServiceConfig) {
+	cfg.service(
+		web::resource("/oidc/register")
+			.route(web::post().to(post_register))
+			.route(web::to(|| HttpResponse::MethodNotAllowed())),
+	)
+	.service(
+		web::resource("/oidc/register/{client_id}")
+			.route(web::get().to(get_registration))
+			.route(web::put().to(put_registration))
+			.route(web::delete().to(delete_registration))
+			.route(web::to(|| HttpResponse::MethodNotAllowed())),
+	);
+}
+
+/// The client metadata (RFC7591 s2) that we know what to do with.  Anything else the client sends
+/// along is ignored, as s3.1 says it should be.
+#[derive(Clone, Debug, Deserialize)]
+struct ClientMetadata {
+	// Only sent when updating a registration (RFC7592 s2.2)
+	client_id: Option<String>,
+	client_name: Option<String>,
+	#[serde(default)]
+	redirect_uris: Vec<String>,
+	#[serde(default)]
+	post_logout_redirect_uris: Vec<String>,
+	backchannel_logout_uri: Option<String>,
+	// RFC7591 s2 says that a client which doesn't say how it will authenticate gets a client
+	// secret, even though `authul client add` defaults to private_key_jwt
+	#[serde(default = "default_token_endpoint_auth_method")]
+	token_endpoint_auth_method: ClientAuthMethod,
+	jwks_uri: Option<String>,
+	// Not a registered metadata name, but browser-based public clients can't have HTTPS redirect
+	// URIs without it
+	#[serde(default)]
+	allowed_origins: Vec<String>,
+	scope: Option<String>,
+	// RFC9126 s6
+	#[serde(default)]
+	require_pushed_authorization_requests: bool,
+	// OIDC Dynamic Client Registration 1.0 s2; there's no sector_identifier_uri, so a pairwise
+	// client's sector is the host its redirect URIs are on
+	subject_type: Option<String>,
+}
+
+fn default_token_endpoint_auth_method() -> ClientAuthMethod {
+	ClientAuthMethod::ClientSecretBasic
+}
+
+/// Client metadata that has been checked over, and is fit to be saved
+#[derive(Clone, Debug)]
+struct Registration {
+	name: String,
+	redirect_uris: Vec<String>,
+	post_logout_redirect_uris: Vec<String>,
+	backchannel_logout_uri: Option<String>,
+	token_endpoint_auth_method: ClientAuthMethod,
+	jwks_uri: Option<String>,
+	allowed_origins: Vec<String>,
+	allowed_scopes: Vec<String>,
+	require_pushed_authorization_requests: bool,
+	sector_identifier: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct RegistrationResponse {
+	client_id: String,
+	// We only keep a hash of the client secret, so it can only be handed over when it's first
+	// made; RFC7591 s3.2.1 then needs us to say that it doesn't expire
+	#[serde(skip_serializing_if = "Option::is_none")]
+	client_secret: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	client_secret_expires_at: Option<u64>,
+	registration_access_token: String,
+	registration_client_uri: String,
+	client_name: String,
+	redirect_uris: Vec<String>,
+	post_logout_redirect_uris: Vec<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	backchannel_logout_uri: Option<String>,
+	token_endpoint_auth_method: ClientAuthMethod,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	jwks_uri: Option<String>,
+	allowed_origins: Vec<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	scope: Option<String>,
+	require_pushed_authorization_requests: bool,
+	subject_type: &'static str,
+}
+
+async fn post_register(
+	cfg: web::Data<Config>,
+	bearer_auth: Option<BearerAuth>,
+	body: web::Bytes,
+) -> Result<HttpResponse, Error> {
+	let Some(bearer_auth) = bearer_auth else {
+		return Err(Error::oidc_registration(
+			"no initial access token",
+			ErrCode::InvalidToken,
+		));
+	};
+	let invalid_token = |reason: String| Error::oidc_registration(reason, ErrCode::InvalidToken);
+	let Some((token_id, secret)) = bearer_auth.token().split_once('.') else {
+		return Err(invalid_token("malformed initial access token".to_string()));
+	};
+	let token_id = Uuid::from_base64(token_id)
+		.map_err(|e| invalid_token(format!("invalid initial access token: {e}")))?;
+	let initial_access_token = cfg
+		.db()
+		.initial_access_token()
+		.await?
+		.find(&token_id)
+		.await
+		.map_err(|e| match e {
+			db::Error::NotFound { .. } => invalid_token("unknown initial access token".to_string()),
+			_ => e.into(),
+		})?;
+
+	let secret = secret.to_string();
+	let hash = initial_access_token.secret_hash().clone();
+	if !spawn_blocking(move || bcrypt::verify(&secret, &hash)).await?? {
+		return Err(invalid_token(format!(
+			"incorrect secret for initial access token {}",
+			token_id.to_base64()
+		)));
+	}
+
+	let registration = parse_metadata(&body)?.validate()?;
+	check_scopes(
+		&registration.allowed_scopes,
+		initial_access_token.allowed_scopes(),
+	)?;
+
+	let client_secret = registration
+		.token_endpoint_auth_method
+		.uses_secret()
+		.then(new_secret);
+	let client_secret_hash = match &client_secret {
+		Some(secret) => Some(hash_secret(&cfg, secret.clone()).await?),
+		None => None,
+	};
+	let registration_access_token = new_secret();
+
+	let client = cfg
+		.db()
+		.oidc_client()
+		.await?
+		.new()
+		.with_name(registration.name)
+		.with_redirect_uris(registration.redirect_uris)
+		.with_post_logout_redirect_uris(registration.post_logout_redirect_uris)
+		.with_token_endpoint_auth_method(registration.token_endpoint_auth_method)
+		.with_client_secret_hash(client_secret_hash)
+		.with_allowed_origins(registration.allowed_origins)
+		.with_allowed_scopes(registration.allowed_scopes)
+		.with_jwks_uri(registration.jwks_uri)
+		.with_backchannel_logout_uri(registration.backchannel_logout_uri)
+		.with_require_pushed_authorization_requests(
+			registration.require_pushed_authorization_requests,
+		)
+		.with_sector_identifier(registration.sector_identifier)
+		.with_registration_access_token_hash(Some(
+			hash_secret(&cfg, registration_access_token.clone()).await?,
+		))
+		.save()
+		.await?;
+
+	tracing::debug!(
+		"registered client {} with initial access token {}",
+		client.id().to_base64(),
+		token_id.to_base64()
+	);
+
+	Ok(HttpResponse::Created().json(registration_response(
+		&cfg,
+		&client,
+		client_secret,
+		registration_access_token,
+	)?))
+}
+
+async fn get_registration(
+	cfg: web::Data<Config>,
+	bearer_auth: Option<BearerAuth>,
+	client_id: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+	// Looking at the registration doesn't change anything, so the client keeps the registration
+	// access token it already has, rather than having to keep up with a new one every time
+	let registration_access_token = bearer_auth
+		.as_ref()
+		.map(|b| b.token().to_string())
+		.unwrap_or_default();
+	let client = registered_client(&cfg, bearer_auth, &client_id).await?;
+
+	Ok(HttpResponse::Ok().json(registration_response(
+		&cfg,
+		&client,
+		None,
+		registration_access_token,
+	)?))
+}
+
+async fn put_registration(
+	cfg: web::Data<Config>,
+	bearer_auth: Option<BearerAuth>,
+	client_id: web::Path<String>,
+	body: web::Bytes,
+) -> Result<HttpResponse, Error> {
+	let mut client = registered_client(&cfg, bearer_auth, &client_id).await?;
+
+	let metadata = parse_metadata(&body)?;
+	// RFC7592 s2.2
+	if metadata.client_id.as_deref() != Some(client_id.as_str()) {
+		return Err(Error::oidc_registration(
+			"client_id missing or does not match registration",
+			ErrCode::InvalidClientMetadata,
+		));
+	}
+	let registration = metadata.validate()?;
+	// There's no going back to the initial access token to see what else the client could have
+	// had, so it can only ever give up scopes, not gain them
+	check_scopes(&registration.allowed_scopes, client.allowed_scopes())?;
+
+	// A client that already has a secret keeps it, unless it stops needing one
+	let was_using_secret = client.token_endpoint_auth_method().uses_secret();
+	let client_secret = (registration.token_endpoint_auth_method.uses_secret()
+		&& !was_using_secret)
+		.then(new_secret);
+	if let Some(secret) = &client_secret {
+		client.update_client_secret_hash(Some(hash_secret(&cfg, secret.clone()).await?));
+	} else if !registration.token_endpoint_auth_method.uses_secret() {
+		client.update_client_secret_hash(None::<String>);
+	}
+
+	client.update_name(registration.name);
+	client.update_redirect_uris(registration.redirect_uris);
+	client.update_post_logout_redirect_uris(registration.post_logout_redirect_uris);
+	client.update_backchannel_logout_uri(registration.backchannel_logout_uri);
+	client.update_token_endpoint_auth_method(registration.token_endpoint_auth_method);
+	client.update_jwks_uri(registration.jwks_uri);
+	client.update_allowed_origins(registration.allowed_origins);
+	client.update_allowed_scopes(registration.allowed_scopes);
+	client.update_require_pushed_authorization_requests(
+		registration.require_pushed_authorization_requests,
+	);
+	client.update_sector_identifier(registration.sector_identifier);
+
+	let registration_access_token = rotate_registration_access_token(&cfg, &mut client).await?;
+	client.save(&cfg.db().oidc_client().await?).await?;
+
+	Ok(HttpResponse::Ok().json(registration_response(
+		&cfg,
+		&client,
+		client_secret,
+		registration_access_token,
+	)?))
+}
+
+async fn delete_registration(
+	cfg: web::Data<Config>,
+	bearer_auth: Option<BearerAuth>,
+	client_id: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+	let client = registered_client(&cfg, bearer_auth, &client_id).await?;
+
+	cfg.db().delete(client).await?;
+
+	Ok(HttpResponse::NoContent().finish())
+}
+
+fn parse_metadata(body: &[u8]) -> Result<ClientMetadata, Error> {
+	serde_json::from_slice(body).map_err(|e| {
+		Error::oidc_registration(
+			format!("invalid client metadata: {e}"),
+			ErrCode::InvalidClientMetadata,
+		)
+	})
+}
+
+impl ClientMetadata {
+	fn validate(self) -> Result<Registration, Error> {
+		let invalid =
+			|reason: String| Error::oidc_registration(reason, ErrCode::InvalidClientMetadata);
+
+		let name = match self.client_name {
+			Some(name) if !name.trim().is_empty() => name,
+			_ => return Err(invalid("missing client_name".to_string())),
+		};
+
+		let redirect_uris = self
+			.redirect_uris
+			.iter()
+			.map(|u| {
+				Url::parse(u).map(|u| u.to_string()).map_err(|e| {
+					Error::oidc_registration(
+						format!("invalid redirect URI {u}: {e}"),
+						ErrCode::InvalidRedirectUri,
+					)
+				})
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		let post_logout_redirect_uris = self
+			.post_logout_redirect_uris
+			.iter()
+			.map(|u| parse_url(u))
+			.collect::<Result<Vec<_>, _>>()?;
+		let backchannel_logout_uri = self
+			.backchannel_logout_uri
+			.as_deref()
+			.map(parse_fetchable_url)
+			.transpose()?;
+		let jwks_uri = self
+			.jwks_uri
+			.as_deref()
+			.map(parse_fetchable_url)
+			.transpose()?;
+		let allowed_origins = self
+			.allowed_origins
+			.iter()
+			.map(|o| {
+				Url::parse(o)
+					.map(|u| u.origin().ascii_serialization())
+					.map_err(|e| invalid(format!("invalid allowed origin {o}: {e}")))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		// RFC6749 s3.3
+		let allowed_scopes = self
+			.scope
+			.as_deref()
+			.unwrap_or_default()
+			.split(' ')
+			.filter(|s| !s.is_empty())
+			.map(|s| {
+				if s.chars()
+					.all(|c| c.is_ascii_graphic() && c != '"' && c != '\\')
+				{
+					Ok(s.to_string())
+				} else {
+					Err(invalid(format!("{s:?} is not a valid scope")))
+				}
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		OidcClient::check_registration(
+			self.token_endpoint_auth_method,
+			&redirect_uris,
+			&allowed_origins,
+			jwks_uri.as_deref(),
+		)
+		.map_err(|e| {
+			let error_code = match e {
+				InvalidRegistration::NoRedirectUris | InvalidRegistration::PublicRedirectUri(_) => {
+					ErrCode::InvalidRedirectUri
+				}
+				_ => ErrCode::InvalidClientMetadata,
+			};
+			Error::oidc_registration(e.to_string(), error_code)
+		})?;
+
+		let sector_identifier = match self.subject_type.as_deref() {
+			None | Some("public") => None,
+			Some("pairwise") => Some(
+				OidcClient::sector_identifier_for(&redirect_uris)
+					.map_err(|e| invalid(e.to_string()))?,
+			),
+			Some(other) => return Err(invalid(format!("unsupported subject_type {other}"))),
+		};
+
+		Ok(Registration {
+			name,
+			redirect_uris,
+			post_logout_redirect_uris,
+			backchannel_logout_uri,
+			token_endpoint_auth_method: self.token_endpoint_auth_method,
+			jwks_uri,
+			allowed_origins,
+			allowed_scopes,
+			require_pushed_authorization_requests: self.require_pushed_authorization_requests,
+			sector_identifier,
+		})
+	}
+}
+
+fn parse_url(u: &str) -> Result<String, Error> {
+	Url::parse(u).map(|u| u.to_string()).map_err(|e| {
+		Error::oidc_registration(
+			format!("invalid URL {u}: {e}"),
+			ErrCode::InvalidClientMetadata,
+		)
+	})
+}
+
+/// A URL that we're going to be making requests to ourselves, which (unlike one that only the
+/// user's browser gets sent to) mustn't let whoever registered the client aim us at anything on
+/// our own network.
+///
+/// This only looks at the URL itself; a name that resolves to a private address isn't caught
+/// here.
+fn parse_fetchable_url(u: &str) -> Result<String, Error> {
+	let invalid = |reason: String| Error::oidc_registration(reason, ErrCode::InvalidClientMetadata);
+
+	let url = Url::parse(u).map_err(|e| invalid(format!("invalid URL {u}: {e}")))?;
+	if url.scheme() != "https" {
+		return Err(invalid(format!("URL {u} is not https")));
+	}
+
+	let internal = match url.host() {
+		None => true,
+		Some(Host::Domain(name)) => {
+			let name = name.trim_end_matches('.').to_ascii_lowercase();
+			name == "localhost" || name.ends_with(".localhost")
+		}
+		Some(Host::Ipv4(ip)) => is_internal_ipv4(ip),
+		Some(Host::Ipv6(ip)) => match ip.to_ipv4_mapped() {
+			Some(ip) => is_internal_ipv4(ip),
+			None => {
+				let first = ip.segments()[0];
+				ip.is_loopback()
+					|| ip.is_unspecified()
+					// Unique local (fc00::/7) and link-local (fe80::/10)
+					|| first & 0xfe00 == 0xfc00
+					|| first & 0xffc0 == 0xfe80
+			}
+		},
+	};
+	if internal {
+		return Err(invalid(format!("URL {u} is not on the public internet")));
+	}
+
+	Ok(url.to_string())
+}
+
+fn is_internal_ipv4(ip: Ipv4Addr) -> bool {
+	ip.is_loopback()
+		|| ip.is_private()
+		|| ip.is_link_local()
+		|| ip.is_unspecified()
+		|| ip.is_broadcast()
+		// Carrier-grade NAT (100.64.0.0/10)
+		|| (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)
+}
+
+/// Clients can only be allowed the scopes that an administrator has said they can have, since
+/// the client credentials grant hands over whatever a client is allowed without asking anyone
+fn check_scopes(requested: &[String], permitted: &[String]) -> Result<(), Error> {
+	match requested.iter().find(|s| !permitted.contains(s)) {
+		Some(scope) => Err(Error::oidc_registration(
+			format!("client is not permitted to register for scope {scope}"),
+			ErrCode::InvalidClientMetadata,
+		)),
+		None => Ok(()),
+	}
+}
+
+/// Find the client whose registration is being managed, and make sure that whoever is asking has
+/// the registration access token we gave it.
+///
+/// Every way this can go wrong looks the same from outside, so that nobody can use this endpoint
+/// to find out which client IDs exist (RFC7592 s2.1).
+async fn registered_client(
+	cfg: &Config,
+	bearer_auth: Option<BearerAuth>,
+	client_id: &str,
+) -> Result<OidcClient, Error> {
+	let invalid = |reason: String| Error::oidc_registration(reason, ErrCode::InvalidToken);
+
+	let Some(bearer_auth) = bearer_auth else {
+		return Err(invalid("no registration access token".to_string()));
+	};
+
+	let client = cfg
+		.db()
+		.oidc_client()
+		.await?
+		.find(
+			&Uuid::from_base64(client_id)
+				.map_err(|e| invalid(format!("invalid client_id: {e}")))?,
+		)
+		.await
+		.map_err(|e| match e {
+			db::Error::NotFound { .. } => invalid(format!("unknown client_id {client_id}")),
+			_ => e.into(),
+		})?;
+
+	// Clients added from the command line don't get to manage themselves
+	let Some(hash) = client.registration_access_token_hash().clone() else {
+		return Err(invalid(format!(
+			"client {client_id} was not dynamically registered"
+		)));
+	};
+
+	let token = bearer_auth.token().to_string();
+	if !spawn_blocking(move || bcrypt::verify(&token, &hash)).await?? {
+		return Err(invalid("incorrect registration access token".to_string()));
+	}
+
+	Ok(client)
+}
+
+/// We only keep a hash of the registration access token, so when a registration is changed, and
+/// the response needs to include a token (RFC7592 s3), it gets a new one, which replaces the old
+async fn rotate_registration_access_token(
+	cfg: &Config,
+	client: &mut OidcClient,
+) -> Result<String, Error> {
+	let token = new_secret();
+	client.update_registration_access_token_hash(Some(hash_secret(cfg, token.clone()).await?));
+
+	Ok(token)
+}
+
+fn registration_response(
+	cfg: &Config,
+	client: &OidcClient,
+	client_secret: Option<String>,
+	registration_access_token: String,
+) -> Result<RegistrationResponse, Error> {
+	let client_id = client.id().to_base64();
+
+	Ok(RegistrationResponse {
+		registration_client_uri: cfg
+			.base_url()
+			.join(&format!("oidc/register/{client_id}"))?
+			.to_string(),
+		client_id,
+		client_secret_expires_at: client_secret.as_ref().map(|_| 0),
+		client_secret,
+		registration_access_token,
+		client_name: client.name().clone(),
+		redirect_uris: client.redirect_uris().clone(),
+		post_logout_redirect_uris: client.post_logout_redirect_uris().clone(),
+		backchannel_logout_uri: client.backchannel_logout_uri().clone(),
+		token_endpoint_auth_method: *client.token_endpoint_auth_method(),
+		jwks_uri: client.jwks_uri().clone(),
+		allowed_origins: client.allowed_origins().clone(),
+		scope: (!client.allowed_scopes().is_empty()).then(|| client.allowed_scopes().join(" ")),
+		require_pushed_authorization_requests: *client.require_pushed_authorization_requests(),
+		subject_type: match client.sector_identifier() {
+			Some(_) => "pairwise",
+			None => "public",
+		},
+	})
+}
+
+fn new_secret() -> String {
+	let mut secret = [0u8; 32];
+	rand::thread_rng().fill_bytes(&mut secret);
+	BASE64_URL_SAFE_NO_PAD.encode(secret)
+}
+
+async fn hash_secret(cfg: &Config, secret: String) -> Result<String, Error> {
+	let cost = cfg.pwhash_cost();
+	Ok(spawn_blocking(move || bcrypt::hash(&secret, cost)).await??)
+}
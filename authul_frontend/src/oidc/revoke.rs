@@ -0,0 +1,92 @@
+//! Token revocation, as per RFC7009, so that a client can tell us it's done with a token
+use actix_web::{
+	web::{self, ServiceConfig},
+	HttpRequest, HttpResponse,
+};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use serde::Deserialize;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{
+	client_auth::{ClientAuth, ClientCredentials},
+	middleware::Cors,
+	token::verify_access_token,
+	Config, Error,
+};
+use crate::db;
+use authul_oauth2::error_code::TokenEndpoint as TokenErrCode;
+use authul_util::Base64Uuid;
+
+pub(super) fn routes(cfg: &mut ServiceConfig) {
+	cfg.service(
+		web::resource("/oidc/revoke")
+			.wrap(Cors::POST)
+			.route(web::post().to(post_oidc_revoke))
+			.route(web::to(|| HttpResponse::MethodNotAllowed())),
+	);
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct RevokeRequest {
+	token: Option<String>,
+	#[serde(flatten)]
+	client_credentials: ClientCredentials,
+}
+
+pub(super) async fn post_oidc_revoke(
+	cfg: web::Data<Config>,
+	req: HttpRequest,
+	basic_auth: Option<BasicAuth>,
+	revoke_req: web::Form<RevokeRequest>,
+) -> Result<HttpResponse, Error> {
+	let revoke_req = revoke_req.into_inner();
+
+	let token = revoke_req
+		.token
+		.ok_or_else(|| Error::oidc_token("no token", TokenErrCode::InvalidRequest))?;
+	let oidc_client = ClientAuth::from_request(&req, basic_auth, revoke_req.client_credentials)?
+		.authenticate(&cfg, Some(&token))
+		.await?;
+
+	// Refresh tokens and access tokens look nothing alike, so we don't need to bother with any
+	// token_type_hint the client might have sent; we can just try both.
+	if let Ok(id) = Uuid::from_base64(&token) {
+		match cfg.db().refresh_token().await?.find(&id).await {
+			Ok(refresh_token) => {
+				// The whole family goes, because a refresh token that has been rotated is still the
+				// same grant, as far as the client is concerned
+				if refresh_token.oidc_client().id() == oidc_client.id() {
+					cfg.db()
+						.refresh_token()
+						.await?
+						.revoke_family(refresh_token.family_id())
+						.await?;
+				}
+			}
+			Err(db::Error::NotFound(..)) => (),
+			Err(e) => return Err(e.into()),
+		}
+	} else if let Some(access_token) = verify_access_token(&cfg, &token).await? {
+		if access_token.peek_aud() == Some(oidc_client.id().to_base64().as_str()) {
+			let jti = access_token
+				.peek_jti()
+				.and_then(|jti| Uuid::from_base64(jti).ok())
+				.ok_or_else(|| Error::cant_happen("verified access token has no valid jti"))?;
+			let valid_before = OffsetDateTime::from_unix_timestamp(
+				i64::try_from(access_token.peek_exp())
+					.map_err(|_| Error::cant_happen("access token exp out of range"))?,
+			)?;
+
+			cfg.db()
+				.revoked_access_token()
+				.await?
+				.revoke(&jti, &valid_before)
+				.await?;
+		}
+	}
+
+	// RFC7009 says that tokens which are invalid, or belong to someone else, get the same
+	// response as a successful revocation, so that nobody can go fishing for valid tokens
+	Ok(HttpResponse::Ok().finish())
+}
@@ -1,22 +1,36 @@
 use actix_web::{
 	web::{self, ServiceConfig},
-	HttpResponse,
+	HttpRequest, HttpResponse,
 };
+use actix_web_httpauth::extractors::basic::BasicAuth;
 use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
-use super::{middleware::Cors, Config, Error};
+use super::{
+	claims::{scope_grants, standard_claims, ClaimsGrant, RequestedClaims},
+	client_auth::{ClientAuth, ClientCredentials},
+	dpop::verify_proof,
+	middleware::Cors,
+	Config, Error,
+};
 use crate::db;
-use authul_crypto::{JwkSet, Jwt};
+use authul_crypto::Jwt;
+use authul_db::{model::OidcClient, types::IdentityAttributes};
 use authul_oauth2::error_code::TokenEndpoint as TokenErrCode;
 use authul_util::Base64Uuid;
 
+pub(super) const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Where an access token with a pairwise sub keeps the (encrypted) principal ID, since there's no
+/// getting it back from the sub
+const PRINCIPAL_CLAIM: &str = "authul_principal";
+
 pub(super) fn routes(cfg: &mut ServiceConfig) {
 	cfg.service(
 		web::resource("/oidc/token")
-			.wrap(Cors::POST)
+			.wrap(Cors::ClientOrigins)
 			.route(web::post().to(post_oidc_token))
 			.route(web::to(|| HttpResponse::MethodNotAllowed())),
 	);
@@ -27,39 +41,234 @@ pub(super) struct TokenRequest {
 	grant_type: Option<String>,
 	code: Option<String>,
 	redirect_uri: Option<String>,
-	client_assertion_type: Option<String>,
-	client_assertion: Option<String>,
+	refresh_token: Option<String>,
 	code_verifier: Option<String>,
+	scope: Option<String>,
+	device_code: Option<String>,
+	#[serde(flatten)]
+	client_credentials: ClientCredentials,
 }
 
 #[derive(Clone, Debug, Serialize)]
 struct TokenResponse {
-	id_token: String,
+	// Only issued when there's a principal for the token to identify
+	#[serde(skip_serializing_if = "Option::is_none")]
+	id_token: Option<String>,
+	access_token: String,
 	token_type: String,
-	expires_in: u32,
+	expires_in: u64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	refresh_token: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	scope: Option<String>,
+}
+
+/// The tokens we hand to a client once a principal has been authenticated to it
+#[derive(Clone, Debug)]
+pub(crate) struct IssuedTokens {
+	pub(crate) id_token: String,
+	pub(crate) access_token: String,
+}
+
+/// Mint a signed ID token and access token for the given principal, on behalf of the given
+/// client, with whatever claims its scopes entitle it to and the ones it asked for by name, so
+/// long as the client is allowed to have them.
+pub(crate) async fn issue_tokens(
+	cfg: &Config,
+	oidc_client: &OidcClient,
+	uid: &Uuid,
+	attrs: &IdentityAttributes,
+	grant: ClaimsGrant<'_>,
+	nonce: Option<&str>,
+	auth_time: Option<i64>,
+) -> Result<IssuedTokens, Error> {
+	let sub = cfg.subject_identifier(oidc_client, uid).await?;
+
+	let mut id_token = Jwt::new()
+		.with_iss(cfg.base_url().to_string())
+		.with_sub(sub.clone())
+		.with_aud(oidc_client.id().to_base64());
+	if let Some(nonce) = nonce {
+		id_token.set_nonce(nonce);
+	}
+	if let Some(auth_time) = auth_time {
+		id_token.set_auth_time(auth_time);
+	}
+
+	let mut access_token = Jwt::new()
+		.with_iss(cfg.base_url().to_string())
+		.with_sub(sub)
+		.with_aud(oidc_client.id().to_base64())
+		.with_jti(Uuid::new_v4().to_base64())
+		.with_scope(grant.scope)
+		.with_validity_period(Config::ACCESS_TOKEN_VALIDITY_PERIOD);
+	if oidc_client.sector_identifier().is_some() {
+		access_token = access_token.with_claim(
+			PRINCIPAL_CLAIM,
+			BASE64_URL_SAFE_NO_PAD.encode(
+				cfg.access_token_principal_strong_box()
+					.encrypt(uid.as_bytes(), b"")?,
+			),
+		);
+	}
+
+	for (claim, value) in standard_claims(attrs) {
+		if !oidc_client.may_release(&claim) {
+			continue;
+		}
+
+		let scoped = scope_grants(grant.scope, &claim);
+		if scoped || grant.requested.id_token().contains(&claim) {
+			id_token = id_token.with_claim(claim.clone(), value.clone());
+		}
+		if scoped || grant.requested.userinfo().contains(&claim) {
+			access_token = access_token.with_claim(claim, value);
+		}
+	}
+
+	if *oidc_client.include_attrs_claim() {
+		let attrs = attrs
+			.iter()
+			.filter(|a| oidc_client.may_release(a.kind().as_str()))
+			.collect::<Vec<_>>();
+		id_token = id_token.with_attrs(serde_json::value::to_value(&attrs)?);
+		access_token = access_token.with_attrs(serde_json::value::to_value(&attrs)?);
+	}
+
+	Ok(IssuedTokens {
+		id_token: id_token.sign(&cfg.current_oidc_signing_jwk().await?)?,
+		access_token: access_token.sign(&cfg.current_access_token_signing_jwk().await?)?,
+	})
+}
+
+/// The ID of the principal an access token was issued for, if it was issued for one at all.
+pub(super) fn access_token_principal(cfg: &Config, access_token: &Jwt) -> Option<Uuid> {
+	match access_token.peek_claims().get(PRINCIPAL_CLAIM) {
+		Some(encrypted) => {
+			let ciphertext = BASE64_URL_SAFE_NO_PAD.decode(encrypted.as_str()?).ok()?;
+			let plaintext = cfg
+				.access_token_principal_strong_box()
+				.decrypt(&ciphertext, b"")
+				.ok()?;
+			Uuid::from_slice(&plaintext).ok()
+		}
+		None => Uuid::parse_str(access_token.peek_sub()?).ok(),
+	}
+}
+
+/// Check that an access token is one of ours, and that it hasn't expired or been revoked.
+pub(super) async fn verify_access_token(
+	cfg: &Config,
+	access_token: &str,
+) -> Result<Option<Jwt>, Error> {
+	let Ok(jwt): Result<Jwt, _> = access_token.parse() else {
+		return Ok(None);
+	};
+
+	if !cfg.access_token_jwks().await?.iter().any(|k| jwt.verify(k)) {
+		return Ok(None);
+	}
+
+	// Every access token we issue has a jti, so that it can be revoked
+	let Some(jti) = jwt.peek_jti().and_then(|jti| Uuid::from_base64(jti).ok()) else {
+		return Ok(None);
+	};
+	if cfg
+		.db()
+		.revoked_access_token()
+		.await?
+		.is_revoked(&jti)
+		.await?
+	{
+		return Ok(None);
+	}
+
+	Ok(Some(jwt))
 }
 
 pub(super) async fn post_oidc_token(
 	cfg: web::Data<Config>,
+	req: HttpRequest,
+	basic_auth: Option<BasicAuth>,
 	token_req: web::Form<TokenRequest>,
 ) -> Result<HttpResponse, Error> {
-	let token_req = token_req.into_inner();
+	let mut token_req = token_req.into_inner();
 
 	let grant_type = token_req
 		.grant_type
+		.take()
 		.ok_or_else(|| Error::oidc_token("no grant_type", TokenErrCode::InvalidRequest))?;
+
+	let client_auth = ClientAuth::from_request(
+		&req,
+		basic_auth,
+		std::mem::take(&mut token_req.client_credentials),
+	)?;
+
+	let dpop_jkt = verify_proof(&cfg, &req, None, |reason| {
+		Error::oidc_token(reason, TokenErrCode::InvalidDpopProof)
+	})
+	.await?;
+
+	let mut response = match grant_type.as_str() {
+		"authorization_code" => authorization_code_grant(&cfg, client_auth, token_req).await?,
+		"refresh_token" => {
+			refresh_token_grant(&cfg, client_auth, token_req, dpop_jkt.as_deref()).await?
+		}
+		"client_credentials" => client_credentials_grant(&cfg, client_auth, token_req).await?,
+		DEVICE_CODE_GRANT_TYPE => device_code_grant(&cfg, client_auth, token_req).await?,
+		_ => {
+			return Err(Error::oidc_token(
+				format!("unsupported grant_type {grant_type}"),
+				TokenErrCode::UnsupportedGrantType,
+			))
+		}
+	};
+
+	// Codes and device codes come with an access token that was minted before the client said
+	// anything about a key, so binding happens here, after the fact, for every grant alike.  The
+	// refresh token gets bound too (RFC9449 s5), so that refreshing needs a proof from the same key.
+	if let Some(jkt) = dpop_jkt {
+		if let Some(refresh_token) = &response.refresh_token {
+			let Ok(id) = Uuid::from_base64(refresh_token) else {
+				return Err(Error::cant_happen("we issued an unparseable refresh token"));
+			};
+			cfg.db()
+				.refresh_token()
+				.await?
+				.bind_dpop_key(&id, &jkt)
+				.await?;
+		}
+		response.access_token = bind_access_token(&cfg, &response.access_token, jkt).await?;
+		response.token_type = "DPoP".to_string();
+	}
+
+	Ok(HttpResponse::Ok().json(response))
+}
+
+/// Re-sign one of our access tokens so that it's bound to the key with the given JWK thumbprint,
+/// as per RFC9449 s6.
+async fn bind_access_token(cfg: &Config, access_token: &str, jkt: String) -> Result<String, Error> {
+	let Ok(jwt): Result<Jwt, _> = access_token.parse() else {
+		return Err(Error::cant_happen("we issued an unparseable access token"));
+	};
+
+	Ok(jwt
+		.with_cnf_jkt(jkt)
+		.sign(&cfg.current_access_token_signing_jwk().await?)?)
+}
+
+async fn authorization_code_grant(
+	cfg: &Config,
+	client_auth: ClientAuth,
+	token_req: TokenRequest,
+) -> Result<TokenResponse, Error> {
 	let code = token_req
 		.code
 		.ok_or_else(|| Error::oidc_token("no code", TokenErrCode::InvalidRequest))?;
 	let redirect_uri = token_req
 		.redirect_uri
 		.ok_or_else(|| Error::oidc_token("no redirect_uri", TokenErrCode::InvalidRequest))?;
-	let client_assertion_type = token_req.client_assertion_type.ok_or_else(|| {
-		Error::oidc_token("no client_assertion_type", TokenErrCode::InvalidRequest)
-	})?;
-	let client_assertion = token_req
-		.client_assertion
-		.ok_or_else(|| Error::oidc_token("no client_assertion", TokenErrCode::InvalidClient))?;
 	let code_verifier = token_req
 		.code_verifier
 		.ok_or_else(|| Error::oidc_token("no code_challenge", TokenErrCode::InvalidRequest))?;
@@ -80,116 +289,311 @@ pub(super) async fn post_oidc_token(
 			e => e.into(),
 		})?;
 
-	if grant_type != "authorization_code" {
+	if &BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier)) != token.code_challenge() {
 		return Err(Error::oidc_token(
-			format!("unsupported grant_type {grant_type}"),
-			TokenErrCode::UnsupportedGrantType,
+			"incorrect code_verifier",
+			TokenErrCode::InvalidGrant,
 		));
 	}
-	if client_assertion_type != "urn:ietf:params:oauth:client-assertion-type:jwt-bearer" {
+
+	let oidc_client = client_auth.authenticate(cfg, Some(&code)).await?;
+
+	if token.is_expired() {
 		return Err(Error::oidc_token(
-			format!("unsupported client_assertion_type {client_assertion_type}"),
-			TokenErrCode::InvalidClient,
+			"grant expired",
+			TokenErrCode::InvalidGrant,
 		));
 	}
 
-	if &BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier)) != token.code_challenge() {
+	if token.redirect_uri() != &redirect_uri {
 		return Err(Error::oidc_token(
-			"incorrect code_verifier",
+			"incorrect redirect_uri",
 			TokenErrCode::InvalidGrant,
 		));
 	}
 
-	let Ok(client_jwt): Result<Jwt, _> = client_assertion.parse() else {
+	if token.oidc_client().id() != oidc_client.id() {
 		return Err(Error::oidc_token(
-			"invalid client JWT",
-			TokenErrCode::InvalidClient,
+			"incorrect client_id",
+			TokenErrCode::InvalidGrant,
 		));
-	};
+	}
+
+	let token_string = token.token().to_string();
+	let access_token = token.access_token().to_string();
+	let refresh_token = token.refresh_token_id().map(|id| id.to_base64());
+	cfg.db().delete(token).await?;
+
+	Ok(TokenResponse {
+		id_token: Some(token_string),
+		access_token,
+		token_type: "Bearer".to_string(),
+		expires_in: Config::ACCESS_TOKEN_VALIDITY_PERIOD.as_secs(),
+		refresh_token,
+		scope: None,
+	})
+}
+
+async fn refresh_token_grant(
+	cfg: &Config,
+	client_auth: ClientAuth,
+	token_req: TokenRequest,
+	dpop_jkt: Option<&str>,
+) -> Result<TokenResponse, Error> {
+	let refresh_token = token_req
+		.refresh_token
+		.ok_or_else(|| Error::oidc_token("no refresh_token", TokenErrCode::InvalidRequest))?;
 
-	// What I love(*) about JWTs is that in order to be able to trust them, you have to pull them
-	// apart before you trust them.
-	let Some(claimed_client_id) = client_jwt.peek_sub() else {
+	let token =
+		cfg.db()
+			.refresh_token()
+			.await?
+			.find(&Uuid::from_base64(&refresh_token).map_err(|_| {
+				Error::oidc_token("invalid refresh_token", TokenErrCode::InvalidGrant)
+			})?)
+			.await
+			.map_err(|e| match e {
+				db::Error::NotFound(..) => {
+					Error::oidc_token("unknown refresh_token", TokenErrCode::InvalidGrant)
+				}
+				e => e.into(),
+			})?;
+
+	let oidc_client = client_auth.authenticate(cfg, Some(&refresh_token)).await?;
+
+	if token.oidc_client().id() != oidc_client.id() {
 		return Err(Error::oidc_token(
-			"client JWT lacks sub",
-			TokenErrCode::InvalidClient,
+			"incorrect client_id",
+			TokenErrCode::InvalidGrant,
 		));
-	};
+	}
+
+	if token.is_expired() {
+		return Err(Error::oidc_token(
+			"refresh_token expired",
+			TokenErrCode::InvalidGrant,
+		));
+	}
+
+	// A token that was bound to a key is no use to anyone who can't prove they hold it, so someone
+	// who stole one doesn't even get as far as using it up
+	if let Some(bound_jkt) = token.dpop_jkt() {
+		if dpop_jkt != Some(bound_jkt.as_str()) {
+			return Err(Error::oidc_token(
+				"refresh_token is bound to a different DPoP key",
+				TokenErrCode::InvalidDpopProof,
+			));
+		}
+	}
 
-	let claimed_oidc_client = cfg
+	// A refresh token should only ever be presented once, because the client gets a new one
+	// every time.  If we see one again, either the client is broken or someone has stolen it, and
+	// since we can't tell which copy is the legitimate one, none of them get to live.
+	if !cfg
 		.db()
-		.oidc_client()
+		.refresh_token()
 		.await?
-		.find(&Uuid::from_base64(claimed_client_id).map_err(|e| {
-			Error::oidc_token(
-				format!("invalid client_id: {e}"),
-				TokenErrCode::InvalidClient,
-			)
-		})?)
-		.await
-		.map_err(|e| match e {
-			authul_db::Error::NotFound { .. } => Error::oidc_token(
-				format!("unknown client_id {claimed_client_id}"),
-				TokenErrCode::InvalidClient,
-			),
-			_ => e.into(),
-		})?;
-
-	if !JwkSet::from_url(claimed_oidc_client.jwks_uri(), cfg.http_client())
+		.mark_used(token.id())
 		.await?
-		.iter()
-		.any(|k| client_jwt.verify(k))
 	{
+		tracing::warn!(
+			family_id = %token.family_id(),
+			oidc_client_id = %oidc_client.id(),
+			"refresh token reused; revoking token family"
+		);
+		cfg.db()
+			.refresh_token()
+			.await?
+			.revoke_family(token.family_id())
+			.await?;
+
 		return Err(Error::oidc_token(
-			"invalid client JWT signature",
-			TokenErrCode::InvalidClient,
+			"refresh_token already used",
+			TokenErrCode::InvalidGrant,
 		));
-	};
+	}
+
+	let attrs: IdentityAttributes = ciborium::from_reader(
+		&cfg.refresh_token_attrs_strong_box()
+			.decrypt(token.attrs(), b"")?[..],
+	)?;
+	let requested = RequestedClaims::new(
+		token.id_token_claims().clone(),
+		token.userinfo_claims().clone(),
+	);
+	let issued = issue_tokens(
+		cfg,
+		&oidc_client,
+		token.principal().id(),
+		&attrs,
+		ClaimsGrant {
+			scope: token.scope(),
+			requested: &requested,
+		},
+		None,
+		Some(token.auth_time().unix_timestamp()),
+	)
+	.await?;
 
-	// Houston, we have verification!
-	let oidc_client = claimed_oidc_client;
+	let new_refresh_token = cfg
+		.db()
+		.refresh_token()
+		.await?
+		.new()
+		.with_family_id(*token.family_id())
+		.with_oidc_client(oidc_client)
+		.with_principal(token.principal().clone())
+		.with_attrs(token.attrs().clone())
+		.with_auth_time(*token.auth_time())
+		.with_scope(token.scope().clone())
+		.with_id_token_claims(requested.id_token())
+		.with_userinfo_claims(requested.userinfo())
+		.with_dpop_jkt(token.dpop_jkt().clone())
+		.save()
+		.await?;
 
-	let Some(jti) = client_jwt.peek_jti() else {
+	Ok(TokenResponse {
+		id_token: Some(issued.id_token),
+		access_token: issued.access_token,
+		token_type: "Bearer".to_string(),
+		expires_in: Config::ACCESS_TOKEN_VALIDITY_PERIOD.as_secs(),
+		refresh_token: Some(new_refresh_token.id().to_base64()),
+		scope: None,
+	})
+}
+
+/// For when a client wants to act on its own behalf, rather than for a principal, as per RFC6749
+/// s4.4.  The access token it gets has the client itself as the subject.
+async fn client_credentials_grant(
+	cfg: &Config,
+	client_auth: ClientAuth,
+	token_req: TokenRequest,
+) -> Result<TokenResponse, Error> {
+	// There's no code or refresh token for a client assertion to be bound to, so the assertion
+	// has to be one that's never been seen before
+	let oidc_client = client_auth.authenticate(cfg, None).await?;
+
+	// RFC6749 s4.4 says this grant is for confidential clients only; a public client has nothing
+	// to prove that it's who it says it is
+	if oidc_client.is_public() || oidc_client.allowed_scopes().is_empty() {
 		return Err(Error::oidc_token(
-			"client JWT lacks jti",
-			TokenErrCode::InvalidClient,
+			format!(
+				"client {} is not allowed to use the client_credentials grant",
+				oidc_client.id().to_base64()
+			),
+			TokenErrCode::UnauthorizedClient,
 		));
+	}
+
+	// If the client doesn't say what it wants, it gets everything it's allowed, which RFC6749 s3.3
+	// permits as long as we tell it what it got
+	let scopes = match token_req.scope {
+		Some(scope) => {
+			let scopes = scope
+				.split(' ')
+				.filter(|s| !s.is_empty())
+				.collect::<Vec<_>>();
+			if let Some(bad) = scopes.iter().find(|s| !oidc_client.has_allowed_scope(s)) {
+				return Err(Error::oidc_token(
+					format!("scope {bad} not allowed for client"),
+					TokenErrCode::InvalidScope,
+				));
+			}
+			scopes.join(" ")
+		}
+		None => oidc_client.allowed_scopes().join(" "),
 	};
 
-	if jti != code {
+	let client_id = oidc_client.id().to_base64();
+	let access_token = Jwt::new()
+		.with_iss(cfg.base_url().to_string())
+		.with_sub(client_id.clone())
+		.with_aud(client_id)
+		.with_jti(Uuid::new_v4().to_base64())
+		.with_scope(scopes.clone())
+		.with_validity_period(Config::ACCESS_TOKEN_VALIDITY_PERIOD);
+
+	Ok(TokenResponse {
+		id_token: None,
+		access_token: access_token.sign(&cfg.current_access_token_signing_jwk().await?)?,
+		token_type: "Bearer".to_string(),
+		expires_in: Config::ACCESS_TOKEN_VALIDITY_PERIOD.as_secs(),
+		refresh_token: None,
+		scope: Some(scopes),
+	})
+}
+
+/// For a device that has had the user sign in somewhere else, as per RFC8628 s3.4.  Until the user
+/// has done so, the device gets told to keep waiting (and, if it's impatient, to wait longer).
+async fn device_code_grant(
+	cfg: &Config,
+	client_auth: ClientAuth,
+	token_req: TokenRequest,
+) -> Result<TokenResponse, Error> {
+	let device_code = token_req
+		.device_code
+		.ok_or_else(|| Error::oidc_token("no device_code", TokenErrCode::InvalidRequest))?;
+
+	let dc =
+		cfg.db()
+			.device_code()
+			.await?
+			.find(&Uuid::from_base64(&device_code).map_err(|_| {
+				Error::oidc_token("invalid device_code", TokenErrCode::InvalidGrant)
+			})?)
+			.await
+			.map_err(|e| match e {
+				db::Error::NotFound(..) => {
+					Error::oidc_token("unknown device_code", TokenErrCode::InvalidGrant)
+				}
+				e => e.into(),
+			})?;
+
+	let oidc_client = client_auth.authenticate(cfg, Some(&device_code)).await?;
+
+	if dc.oidc_client().id() != oidc_client.id() {
 		return Err(Error::oidc_token(
-			"client JWT jti not code",
+			"incorrect client_id",
 			TokenErrCode::InvalidGrant,
 		));
 	}
 
-	if token.is_expired() {
+	if dc.is_expired() {
 		return Err(Error::oidc_token(
-			"grant expired",
-			TokenErrCode::InvalidGrant,
+			"device_code expired",
+			TokenErrCode::ExpiredToken,
 		));
 	}
 
-	if token.redirect_uri() != &redirect_uri {
+	if !cfg
+		.db()
+		.device_code()
+		.await?
+		.mark_polled(dc.id(), Config::DEVICE_CODE_POLLING_INTERVAL)
+		.await?
+	{
 		return Err(Error::oidc_token(
-			"incorrect redirect_uri",
-			TokenErrCode::InvalidGrant,
+			"device polling too often",
+			TokenErrCode::SlowDown,
 		));
 	}
 
-	if token.oidc_client().id() != oidc_client.id() {
+	let (Some(token_string), Some(access_token)) = (dc.token().clone(), dc.access_token().clone())
+	else {
 		return Err(Error::oidc_token(
-			"incorrect client_id",
-			TokenErrCode::InvalidGrant,
+			"user has not yet signed in",
+			TokenErrCode::AuthorizationPending,
 		));
-	}
-
-	let token_string = token.token().to_string();
-	cfg.db().delete(token).await?;
+	};
+	let refresh_token = dc.refresh_token_id().map(|id| id.to_base64());
+	cfg.db().delete(dc).await?;
 
-	Ok(HttpResponse::Ok().json(TokenResponse {
-		id_token: token_string,
+	Ok(TokenResponse {
+		id_token: Some(token_string),
+		access_token,
 		token_type: "Bearer".to_string(),
-		expires_in: 60,
-	}))
+		expires_in: Config::ACCESS_TOKEN_VALIDITY_PERIOD.as_secs(),
+		refresh_token,
+		scope: None,
+	})
 }
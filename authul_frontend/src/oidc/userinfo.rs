@@ -0,0 +1,168 @@
+//! The OIDC UserInfo endpoint, which hands out claims about whoever an access token was issued for
+use actix_web::{
+	http::header::AUTHORIZATION,
+	web::{self, ServiceConfig},
+	HttpRequest, HttpResponse,
+};
+use serde::Serialize;
+use serde_json::{Map as JsonMap, Value as JsonValue};
+use uuid::Uuid;
+
+use super::{
+	claims::is_standard_claim, dpop::verify_proof, token::access_token_principal, Config, Error,
+};
+use crate::{
+	db,
+	middleware::{allow_client_origin, Cors},
+};
+use authul_oauth2::error_code::UserinfoEndpoint as ErrCode;
+use authul_util::Base64Uuid;
+
+pub(super) fn routes(cfg: &mut ServiceConfig) {
+	cfg.service(
+		web::resource("/oidc/userinfo")
+			.wrap(Cors::ClientOriginsWithToken)
+			.route(web::get().to(userinfo))
+			.route(web::post().to(userinfo))
+			.route(web::to(|| HttpResponse::MethodNotAllowed())),
+	);
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct UserinfoResponse {
+	sub: String,
+	// Whichever standard claims the access token was issued with, which depends on the scopes the
+	// client was granted
+	#[serde(flatten)]
+	claims: JsonMap<String, JsonValue>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	attrs: Option<JsonValue>,
+}
+
+pub(super) async fn userinfo(
+	cfg: web::Data<Config>,
+	req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+	let Some((scheme, token)) = authorization(&req) else {
+		return Err(Error::oidc_userinfo(
+			"no access token",
+			ErrCode::InvalidToken,
+		));
+	};
+
+	let Some(access_token) = super::token::verify_access_token(&cfg, token).await? else {
+		return Err(Error::oidc_userinfo(
+			"access token is invalid, expired, or revoked",
+			ErrCode::InvalidToken,
+		));
+	};
+
+	// Browser-based clients can call us directly, but only from their own origins
+	if let Some(client_id) = access_token
+		.peek_aud()
+		.and_then(|aud| Uuid::from_base64(aud).ok())
+	{
+		match cfg.db().oidc_client().await?.find(&client_id).await {
+			Ok(oidc_client) => {
+				allow_client_origin(&req, &oidc_client);
+			}
+			Err(db::Error::NotFound(..)) => (),
+			Err(e) => return Err(e.into()),
+		}
+	}
+
+	// A DPoP-bound token is only any good alongside a proof made with the key it's bound to, and
+	// has to say so, so that it can't be mistaken for a bearer token (RFC9449 s7.1)
+	match (access_token.peek_cnf_jkt(), scheme) {
+		(None, Scheme::Bearer) => (),
+		(Some(jkt), Scheme::Dpop) => {
+			let proof_jkt = verify_proof(&cfg, &req, Some(token), |reason| {
+				Error::oidc_userinfo(reason, ErrCode::InvalidDpopProof)
+			})
+			.await?
+			.ok_or_else(|| Error::oidc_userinfo("no DPoP proof", ErrCode::InvalidDpopProof))?;
+			if proof_jkt != jkt {
+				return Err(Error::oidc_userinfo(
+					"DPoP proof made with the wrong key",
+					ErrCode::InvalidDpopProof,
+				));
+			}
+		}
+		(Some(_), Scheme::Bearer) => {
+			return Err(Error::oidc_userinfo(
+				"DPoP-bound access token used as a bearer token",
+				ErrCode::InvalidToken,
+			));
+		}
+		(None, Scheme::Dpop) => {
+			return Err(Error::oidc_userinfo(
+				"bearer access token used as a DPoP-bound token",
+				ErrCode::InvalidToken,
+			));
+		}
+	}
+
+	let Some(sub) = access_token.peek_sub() else {
+		return Err(Error::oidc_userinfo(
+			"access token lacks sub",
+			ErrCode::InvalidToken,
+		));
+	};
+
+	// The principal might have been deleted since the access token was issued, in which case
+	// there's nobody to tell the client about
+	let Some(uid) = access_token_principal(&cfg, &access_token) else {
+		return Err(Error::oidc_userinfo(
+			format!("invalid sub {sub}"),
+			ErrCode::InvalidToken,
+		));
+	};
+	match cfg.db().principal().await?.find(&uid).await {
+		Ok(_) => (),
+		Err(db::Error::NotFound(..)) => {
+			return Err(Error::oidc_userinfo(
+				format!("principal {sub} no longer exists"),
+				ErrCode::InvalidToken,
+			));
+		}
+		Err(e) => return Err(e.into()),
+	}
+
+	Ok(HttpResponse::Ok().json(UserinfoResponse {
+		sub: sub.to_string(),
+		claims: access_token
+			.peek_claims()
+			.iter()
+			.filter(|(claim, _)| is_standard_claim(claim))
+			.map(|(claim, value)| (claim.clone(), value.clone()))
+			.collect(),
+		attrs: access_token.peek_attrs().cloned(),
+	}))
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Scheme {
+	Bearer,
+	Dpop,
+}
+
+/// The access token in the request's `Authorization` header, and how it was presented
+fn authorization(req: &HttpRequest) -> Option<(Scheme, &str)> {
+	let (scheme, token) = req
+		.headers()
+		.get(AUTHORIZATION)?
+		.to_str()
+		.ok()?
+		.split_once(' ')?;
+
+	// Auth schemes are case-insensitive, as per RFC9110 s11.1
+	let scheme = if scheme.eq_ignore_ascii_case("bearer") {
+		Scheme::Bearer
+	} else if scheme.eq_ignore_ascii_case("dpop") {
+		Scheme::Dpop
+	} else {
+		return None;
+	};
+
+	Some((scheme, token.trim()))
+}
@@ -0,0 +1,95 @@
+use actix_web::rt::{spawn as spawn_task, time::interval};
+use rand::Rng;
+use serde_json::json;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::{Config, Error};
+use authul_crypto::Jwt;
+use authul_util::Base64Uuid;
+
+pub(super) async fn spawn(cfg: Config) -> Result<(), Error> {
+	let mut rng = rand::thread_rng();
+	let splay = rng.gen_range(1..10);
+
+	// Clients should hear about logouts promptly, so this runs a lot more often than the rest of
+	// the cleanup tasks; it's cheap when there's nothing to do
+	spawn_task(async move {
+		let mut interval = interval(Duration::from_secs(60 + splay));
+		loop {
+			interval.tick().await;
+			if let Err(e) = deliver_backchannel_logouts(&cfg).await {
+				tracing::error!("failed to deliver back-channel logout notifications: {e}");
+			}
+			if let Err(e) = remove_expired_notifications(&cfg).await {
+				tracing::error!("failed to remove expired back-channel logout notifications: {e}");
+			}
+		}
+	});
+
+	Ok(())
+}
+
+/// Send a logout token to every client that is due to be told about a logout.  Anything that
+/// can't be delivered right now will be retried later.
+#[tracing::instrument(level = "debug", skip(cfg))]
+pub async fn deliver_backchannel_logouts(cfg: &Config) -> Result<(), Error> {
+	for notification in cfg
+		.db()
+		.backchannel_logout_notification()
+		.await?
+		.claim_due()
+		.await?
+	{
+		let Some(uri) = notification.oidc_client().backchannel_logout_uri() else {
+			// The client must have stopped caring since the notification was queued
+			cfg.db().delete(notification).await?;
+			continue;
+		};
+
+		// As per OpenID Connect Back-Channel Logout 1.0 s2.4
+		let logout_token = Jwt::new()
+			.with_iss(cfg.base_url().to_string())
+			.with_sub(
+				cfg.subject_identifier(notification.oidc_client(), notification.principal().id())
+					.await?,
+			)
+			.with_aud(notification.oidc_client().id().to_base64())
+			.with_jti(Uuid::new_v4().to_base64())
+			.with_events(json!({ "http://schemas.openid.net/event/backchannel-logout": {} }))
+			.sign(&cfg.current_oidc_signing_jwk().await?)?;
+
+		match cfg
+			.http_client()
+			.post(uri)
+			.form(&[("logout_token", logout_token)])
+			.send()
+			.await
+		{
+			Ok(res) if res.status().is_success() => cfg.db().delete(notification).await?,
+			Ok(res) => tracing::warn!(
+				oidc_client_id = %notification.oidc_client().id(),
+				status = %res.status(),
+				attempts = notification.attempts(),
+				"back-channel logout notification rejected; will retry"
+			),
+			Err(e) => tracing::warn!(
+				oidc_client_id = %notification.oidc_client().id(),
+				attempts = notification.attempts(),
+				"back-channel logout notification failed ({e}); will retry"
+			),
+		}
+	}
+
+	Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip(cfg))]
+async fn remove_expired_notifications(cfg: &Config) -> Result<(), Error> {
+	cfg.db()
+		.backchannel_logout_notification()
+		.await?
+		.delete_expired()
+		.await?;
+	Ok(())
+}
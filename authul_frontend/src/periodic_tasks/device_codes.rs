@@ -0,0 +1,29 @@
+use actix_web::rt::{spawn as spawn_task, time::interval};
+use rand::Rng;
+use std::time::Duration;
+
+use super::{Config, Error};
+
+pub(super) async fn spawn(cfg: Config) -> Result<(), Error> {
+	let mut rng = rand::thread_rng();
+	let splay = rng.gen_range(10..100);
+
+	// Clear out device codes that nobody came back for every hour or so
+	spawn_task(async move {
+		let mut interval = interval(Duration::from_secs(3600 + splay));
+		loop {
+			interval.tick().await;
+			if let Err(e) = remove_expired_device_codes(&cfg).await {
+				tracing::error!("failed to remove expired device codes: {e}");
+			}
+		}
+	});
+
+	Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip(cfg))]
+async fn remove_expired_device_codes(cfg: &Config) -> Result<(), Error> {
+	cfg.db().device_code().await?.delete_expired().await?;
+	Ok(())
+}
@@ -1,13 +1,37 @@
+mod backchannel_logouts;
+mod device_codes;
 mod oauth_callback_states;
 mod oidc_tokens;
+mod password_reset_tokens;
+mod pushed_authorization_requests;
+mod refresh_tokens;
+mod revoked_access_tokens;
+mod sessions;
 mod signing_keys;
+mod used_client_assertions;
+mod used_dpop_proofs;
+mod used_request_objects;
+mod webauthn_challenges;
 
 use super::{Config, Error};
 
+pub use backchannel_logouts::deliver_backchannel_logouts;
+
 pub async fn spawn(cfg: Config) -> Result<(), Error> {
+	backchannel_logouts::spawn(cfg.clone()).await?;
+	device_codes::spawn(cfg.clone()).await?;
 	oauth_callback_states::spawn(cfg.clone()).await?;
 	oidc_tokens::spawn(cfg.clone()).await?;
+	password_reset_tokens::spawn(cfg.clone()).await?;
+	pushed_authorization_requests::spawn(cfg.clone()).await?;
+	refresh_tokens::spawn(cfg.clone()).await?;
+	revoked_access_tokens::spawn(cfg.clone()).await?;
+	sessions::spawn(cfg.clone()).await?;
 	signing_keys::spawn(cfg.clone()).await?;
+	used_client_assertions::spawn(cfg.clone()).await?;
+	used_dpop_proofs::spawn(cfg.clone()).await?;
+	used_request_objects::spawn(cfg.clone()).await?;
+	webauthn_challenges::spawn(cfg.clone()).await?;
 
 	Ok(())
 }
@@ -0,0 +1,34 @@
+use actix_web::rt::{spawn as spawn_task, time::interval};
+use rand::Rng;
+use std::time::Duration;
+
+use super::{Config, Error};
+
+pub(super) async fn spawn(cfg: Config) -> Result<(), Error> {
+	let mut rng = rand::thread_rng();
+	let splay = rng.gen_range(10..100);
+
+	// Pushed requests only last a minute or two, but clients that never send the user along to
+	// use them leave them lying around
+	spawn_task(async move {
+		let mut interval = interval(Duration::from_secs(600 + splay));
+		loop {
+			interval.tick().await;
+			if let Err(e) = remove_expired_pushed_authorization_requests(&cfg).await {
+				tracing::error!("failed to remove expired pushed authorization requests: {e}");
+			}
+		}
+	});
+
+	Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip(cfg))]
+async fn remove_expired_pushed_authorization_requests(cfg: &Config) -> Result<(), Error> {
+	cfg.db()
+		.pushed_authorization_request()
+		.await?
+		.delete_expired()
+		.await?;
+	Ok(())
+}
@@ -0,0 +1,33 @@
+use actix_web::rt::{spawn as spawn_task, time::interval};
+use rand::Rng;
+use std::time::Duration;
+
+use super::{Config, Error};
+
+pub(super) async fn spawn(cfg: Config) -> Result<(), Error> {
+	let mut rng = rand::thread_rng();
+	let splay = rng.gen_range(10..100);
+
+	// Once a revoked access token has expired, there's no need to remember it was revoked
+	spawn_task(async move {
+		let mut interval = interval(Duration::from_secs(3600 + splay));
+		loop {
+			interval.tick().await;
+			if let Err(e) = remove_expired_access_token_revocations(&cfg).await {
+				tracing::error!("failed to remove expired access token revocations: {e}");
+			}
+		}
+	});
+
+	Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip(cfg))]
+async fn remove_expired_access_token_revocations(cfg: &Config) -> Result<(), Error> {
+	cfg.db()
+		.revoked_access_token()
+		.await?
+		.delete_expired()
+		.await?;
+	Ok(())
+}
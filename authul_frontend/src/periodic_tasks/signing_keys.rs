@@ -25,16 +25,16 @@ pub(super) async fn spawn(cfg: Config) -> Result<(), Error> {
 			k.save(&sk).await?;
 		}
 
-		// Next, make sure that we have both a current OIDC signing key, as well as one for the next signing
-		// period
-		{
+		// Next, make sure that we have both a current signing key for everything we sign, as well as
+		// one for the next signing period
+		for usage in Config::SIGNING_KEY_USAGES {
 			let now = OffsetDateTime::now_utc();
 
 			let mut have_current = false;
 			let mut current_end: Option<OffsetDateTime> = None;
 			let mut have_next = false;
 
-			for k in sk.find_all_by_usage("oidc").await? {
+			for k in sk.find_all_by_usage(usage).await? {
 				if k.used_from() <= &now && k.not_used_from() > &now {
 					have_current = true;
 					current_end = Some(k.not_used_from().clone());
@@ -45,7 +45,7 @@ pub(super) async fn spawn(cfg: Config) -> Result<(), Error> {
 
 			if !have_current {
 				sk.new()
-					.with_usage("oidc")
+					.with_usage(usage)
 					.with_used_from(now.clone())
 					.with_not_used_from(now + Config::OIDC_SIGNING_KEY_ROTATION_PERIOD)
 					.with_expired_from(now + 2 * Config::OIDC_SIGNING_KEY_ROTATION_PERIOD)
@@ -59,7 +59,7 @@ pub(super) async fn spawn(cfg: Config) -> Result<(), Error> {
 				let current_end = current_end.unwrap_or(now);
 
 				sk.new()
-					.with_usage("oidc")
+					.with_usage(usage)
 					.with_used_from(current_end.clone())
 					.with_not_used_from(current_end + Config::OIDC_SIGNING_KEY_ROTATION_PERIOD)
 					.with_expired_from(current_end + 2 * Config::OIDC_SIGNING_KEY_ROTATION_PERIOD)
@@ -106,17 +106,23 @@ async fn refresh_signing_keys(cfg: &Config) -> Result<(), Error> {
 
 	let now = OffsetDateTime::now_utc();
 
-	let uncovered_period_from = sk.no_keys_valid_from_for("oidc").await?;
-
-	if uncovered_period_from < now {
-		sk.new()
-			.with_usage("oidc")
-			.with_used_from(uncovered_period_from.clone())
-			.with_not_used_from(uncovered_period_from + Config::OIDC_SIGNING_KEY_ROTATION_PERIOD)
-			.with_expired_from(uncovered_period_from + 2 * Config::OIDC_SIGNING_KEY_ROTATION_PERIOD)
-			.with_key(cfg.new_oidc_signing_key()?)
-			.save()
-			.await?;
+	for usage in Config::SIGNING_KEY_USAGES {
+		let uncovered_period_from = sk.no_keys_valid_from_for(usage).await?;
+
+		if uncovered_period_from < now {
+			sk.new()
+				.with_usage(usage)
+				.with_used_from(uncovered_period_from.clone())
+				.with_not_used_from(
+					uncovered_period_from + Config::OIDC_SIGNING_KEY_ROTATION_PERIOD,
+				)
+				.with_expired_from(
+					uncovered_period_from + 2 * Config::OIDC_SIGNING_KEY_ROTATION_PERIOD,
+				)
+				.with_key(cfg.new_oidc_signing_key()?)
+				.save()
+				.await?;
+		}
 	}
 
 	sk.delete_expired().await?;
@@ -0,0 +1,29 @@
+use actix_web::rt::{spawn as spawn_task, time::interval};
+use rand::Rng;
+use std::time::Duration;
+
+use super::{Config, Error};
+
+pub(super) async fn spawn(cfg: Config) -> Result<(), Error> {
+	let mut rng = rand::thread_rng();
+	let splay = rng.gen_range(10..100);
+
+	// Once a DPoP proof is too old to be accepted, there's no need to remember it was used
+	spawn_task(async move {
+		let mut interval = interval(Duration::from_secs(600 + splay));
+		loop {
+			interval.tick().await;
+			if let Err(e) = remove_expired_dpop_proofs(&cfg).await {
+				tracing::error!("failed to remove expired used DPoP proofs: {e}");
+			}
+		}
+	});
+
+	Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip(cfg))]
+async fn remove_expired_dpop_proofs(cfg: &Config) -> Result<(), Error> {
+	cfg.db().used_dpop_proof().await?.delete_expired().await?;
+	Ok(())
+}
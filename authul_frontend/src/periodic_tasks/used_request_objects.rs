@@ -0,0 +1,33 @@
+use actix_web::rt::{spawn as spawn_task, time::interval};
+use rand::Rng;
+use std::time::Duration;
+
+use super::{Config, Error};
+
+pub(super) async fn spawn(cfg: Config) -> Result<(), Error> {
+	let mut rng = rand::thread_rng();
+	let splay = rng.gen_range(10..100);
+
+	// Once a request object has expired, there's no need to remember it was used
+	spawn_task(async move {
+		let mut interval = interval(Duration::from_secs(600 + splay));
+		loop {
+			interval.tick().await;
+			if let Err(e) = remove_expired_request_objects(&cfg).await {
+				tracing::error!("failed to remove expired used request objects: {e}");
+			}
+		}
+	});
+
+	Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip(cfg))]
+async fn remove_expired_request_objects(cfg: &Config) -> Result<(), Error> {
+	cfg.db()
+		.used_request_object()
+		.await?
+		.delete_expired()
+		.await?;
+	Ok(())
+}
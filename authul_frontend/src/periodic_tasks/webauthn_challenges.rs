@@ -0,0 +1,33 @@
+use actix_web::rt::{spawn as spawn_task, time::interval};
+use rand::Rng;
+use std::time::Duration;
+
+use super::{Config, Error};
+
+pub(super) async fn spawn(cfg: Config) -> Result<(), Error> {
+	let mut rng = rand::thread_rng();
+	let splay = rng.gen_range(10..100);
+
+	// Nuke expired WebAuthn challenges every few minutes
+	spawn_task(async move {
+		let mut interval = interval(Duration::from_secs(300 + splay));
+		loop {
+			interval.tick().await;
+			if let Err(e) = remove_expired_webauthn_challenges(&cfg).await {
+				tracing::error!("failed to remove expired WebAuthn challenges: {e}");
+			}
+		}
+	});
+
+	Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip(cfg))]
+async fn remove_expired_webauthn_challenges(cfg: &Config) -> Result<(), Error> {
+	cfg.db()
+		.webauthn_challenge()
+		.await?
+		.delete_expired()
+		.await?;
+	Ok(())
+}
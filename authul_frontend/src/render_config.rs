@@ -10,6 +10,7 @@ cfg_if::cfg_if! {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct RenderConfig {
 	pub password_auth: bool,
+	pub password_reset: bool,
 	pub github_auth: bool,
 	pub gitlab_auth: bool,
 	pub google_auth: bool,
@@ -31,6 +32,7 @@ impl From<&Config> for RenderConfig {
 	fn from(cfg: &Config) -> Self {
 		Self {
 			password_auth: cfg.password_auth(),
+			password_reset: cfg.password_auth() && cfg.mailer().is_some(),
 			github_auth: cfg.oauth_provider_map().has::<provider::GitHub>(),
 			gitlab_auth: cfg.oauth_provider_map().has::<provider::GitLab>(),
 			google_auth: cfg.oauth_provider_map().has::<provider::Google>(),
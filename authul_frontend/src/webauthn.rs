@@ -0,0 +1,214 @@
+//! Just enough of the WebAuthn relying party rules to register credentials and check assertions.
+//!
+//! Everything in here takes the browser's responses base64url-encoded, which is how the
+//! `webauthn.js` asset submits them.  Any error returned means that the response wasn't
+//! acceptable, and the ceremony has failed.
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD as BASE64};
+use ciborium::value::Value;
+use serde::Deserialize;
+use sha2::{Digest as _, Sha256};
+
+use super::{Config, Error};
+use authul_crypto::CoseKey;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// The relying party ID that all our credentials are scoped to
+pub(crate) fn rp_id(cfg: &Config) -> Result<String, Error> {
+	cfg.base_url()
+		.host_str()
+		.map(str::to_string)
+		.ok_or_else(|| Error::invalid_state("base URL has no host"))
+}
+
+/// A freshly-registered credential, ready to be stored
+#[derive(Clone, Debug)]
+pub(crate) struct NewCredential {
+	pub(crate) credential_id: Vec<u8>,
+	pub(crate) public_key: Vec<u8>,
+	pub(crate) sign_count: u32,
+}
+
+/// Check the response to a `navigator.credentials.create()` call that was made with the given
+/// challenge.
+///
+/// We always ask for `"none"` attestation, because we have no interest in which make and model
+/// of authenticator the user has, so any attestation statement is ignored.
+pub(crate) fn verify_registration(
+	cfg: &Config,
+	challenge: &[u8],
+	client_data_json: &str,
+	attestation_object: &str,
+) -> Result<NewCredential, Error> {
+	verify_client_data(
+		cfg,
+		"webauthn.create",
+		challenge,
+		&BASE64.decode(client_data_json)?,
+	)?;
+
+	let attestation_object: Value = ciborium::from_reader(&BASE64.decode(attestation_object)?[..])?;
+	let auth_data = attestation_object
+		.as_map()
+		.and_then(|m| {
+			m.iter()
+				.find(|(k, _)| k.as_text() == Some("authData"))
+				.map(|(_, v)| v)
+		})
+		.and_then(Value::as_bytes)
+		.ok_or_else(|| Error::webauthn("attestation object has no authData"))?;
+
+	let auth_data = AuthenticatorData::parse(auth_data)?;
+	auth_data.verify(cfg, false)?;
+
+	let (credential_id, public_key) = auth_data
+		.attested_credential
+		.ok_or_else(|| Error::webauthn("no credential in registration response"))?;
+
+	Ok(NewCredential {
+		credential_id,
+		public_key,
+		sign_count: auth_data.sign_count,
+	})
+}
+
+/// Check the response to a `navigator.credentials.get()` call that was made with the given
+/// challenge, against the stored public key of the credential the browser says it used.
+///
+/// Returns the authenticator's signature counter, which the caller needs to check has gone up.
+pub(crate) fn verify_assertion(
+	cfg: &Config,
+	challenge: &[u8],
+	public_key: &[u8],
+	client_data_json: &str,
+	authenticator_data: &str,
+	signature: &str,
+	require_user_verification: bool,
+) -> Result<u32, Error> {
+	let client_data_json = BASE64.decode(client_data_json)?;
+	verify_client_data(cfg, "webauthn.get", challenge, &client_data_json)?;
+
+	let authenticator_data = BASE64.decode(authenticator_data)?;
+	let parsed = AuthenticatorData::parse(&authenticator_data)?;
+	parsed.verify(cfg, require_user_verification)?;
+
+	let mut signed = authenticator_data.clone();
+	signed.extend_from_slice(&Sha256::digest(&client_data_json));
+
+	CoseKey::from_cbor(public_key)?.verify(&signed, &BASE64.decode(signature)?)?;
+
+	Ok(parsed.sign_count)
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+	#[serde(rename = "type")]
+	kind: String,
+	challenge: String,
+	origin: String,
+	#[serde(rename = "crossOrigin", default)]
+	cross_origin: bool,
+}
+
+fn verify_client_data(
+	cfg: &Config,
+	kind: &str,
+	challenge: &[u8],
+	client_data_json: &[u8],
+) -> Result<(), Error> {
+	let client_data: ClientData = serde_json::from_slice(client_data_json)?;
+
+	if client_data.kind != kind {
+		return Err(Error::webauthn(format!(
+			"expected {kind} client data, got {}",
+			client_data.kind
+		)));
+	}
+
+	if BASE64.decode(&client_data.challenge)? != challenge {
+		return Err(Error::webauthn("challenge mismatch"));
+	}
+
+	let origin = cfg.base_url().origin().ascii_serialization();
+	if client_data.origin != origin || client_data.cross_origin {
+		return Err(Error::webauthn(format!(
+			"response came from {}, not {origin}",
+			client_data.origin
+		)));
+	}
+
+	Ok(())
+}
+
+#[derive(Debug)]
+struct AuthenticatorData<'a> {
+	rp_id_hash: &'a [u8],
+	flags: u8,
+	sign_count: u32,
+	// Credential ID and COSE_Key
+	attested_credential: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<'a> AuthenticatorData<'a> {
+	fn parse(mut bytes: &'a [u8]) -> Result<Self, Error> {
+		let rp_id_hash = take(&mut bytes, 32)?;
+		let flags = u8::from_be_bytes(take_array(&mut bytes)?);
+		let sign_count = u32::from_be_bytes(take_array(&mut bytes)?);
+
+		let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL == 0 {
+			None
+		} else {
+			let _aaguid = take(&mut bytes, 16)?;
+			let len = u16::from_be_bytes(take_array(&mut bytes)?);
+			let credential_id = take(&mut bytes, len.into())?.to_vec();
+
+			let key_start = bytes;
+			CoseKey::read(&mut bytes)?;
+			// Whatever the key didn't consume is extensions, which we don't care about
+			let public_key = key_start[..key_start.len() - bytes.len()].to_vec();
+
+			Some((credential_id, public_key))
+		};
+
+		Ok(Self {
+			rp_id_hash,
+			flags,
+			sign_count,
+			attested_credential,
+		})
+	}
+
+	fn verify(&self, cfg: &Config, require_user_verification: bool) -> Result<(), Error> {
+		if self.rp_id_hash != Sha256::digest(rp_id(cfg)?).as_slice() {
+			return Err(Error::webauthn("RP ID hash mismatch"));
+		}
+
+		if self.flags & FLAG_USER_PRESENT == 0 {
+			return Err(Error::webauthn("user was not present"));
+		}
+
+		if require_user_verification && self.flags & FLAG_USER_VERIFIED == 0 {
+			return Err(Error::webauthn("user was not verified"));
+		}
+
+		Ok(())
+	}
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], Error> {
+	if bytes.len() < n {
+		return Err(Error::webauthn("authenticator data is truncated"));
+	}
+
+	let (head, tail) = bytes.split_at(n);
+	*bytes = tail;
+	Ok(head)
+}
+
+fn take_array<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], Error> {
+	take(bytes, N)?
+		.try_into()
+		.map_err(|_| Error::cant_happen("take returned the wrong number of bytes"))
+}
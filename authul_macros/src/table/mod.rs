@@ -222,9 +222,12 @@ impl Struct {
 						tracing::debug!(value=v.to_string(), sql=#query);
 						let stmt = self.conn.prepare_cached(#query).await?;
 
+						let res = self.conn.query(&stmt, &[&v.as_ref()]).await?;
+						let row = res.get(0).ok_or(crate::Error::not_found(#table_name, #field_name_as_string, v.as_ref()))?;
+
 						#relation_loads
 
-						#struct_name::from_row(self.conn.query(&stmt, &[&v.as_ref()]).await?.get(0).ok_or(crate::Error::not_found(#table_name, #field_name_as_string, v.as_ref()))?, #(#relation_values),*)
+						#struct_name::from_row(row, #(#relation_values),*)
 					}
 				});
 			}
@@ -240,22 +243,30 @@ impl Struct {
 					format!("{select_query_root} WHERE {field_name} = $1")
 				};
 
-				let (val_type, val_fetch, referent) =
-					if field_type.to_token_stream().to_string() == "String" {
-						(
-							quote! { impl AsRef<str> + std::fmt::Debug + Clone },
-							quote! { v.as_ref() },
-							quote! {},
-						)
-					} else if f.belongs_to() {
-						(
-							quote! { &#field_type },
-							quote! { v.id() },
-							quote! { v.clone() },
-						)
+				let (val_type, val_fetch) = if field_type.to_token_stream().to_string() == "String"
+				{
+					(
+						quote! { impl AsRef<str> + std::fmt::Debug + Clone },
+						quote! { v.as_ref() },
+					)
+				} else if f.belongs_to() {
+					(quote! { &#field_type }, quote! { v.id() })
+				} else {
+					(quote! { &#field_type }, quote! { v })
+				};
+
+				// The record we're searching by (if it's a relation) we've already got, but any
+				// *other* relations need to be loaded for each row
+				let mut row_relation_loads = TokenStream::new();
+				let mut row_relation_values: Vec<TokenStream> = Vec::new();
+				for r in self.fields.iter().filter(|r| r.belongs_to()) {
+					if f.belongs_to() && r.field_name() == f.field_name() {
+						row_relation_values.push(quote! { v.clone() });
 					} else {
-						(quote! { &#field_type }, quote! { v }, quote! {})
-					};
+						row_relation_loads.extend(r.relation_load());
+						row_relation_values.extend(r.relation_value());
+					}
+				}
 
 				find_by_methods.extend(quote! {
 					#[tracing::instrument(level = "debug", skip(self))]
@@ -265,7 +276,14 @@ impl Struct {
 						tracing::debug!(?value, sql=#query);
 						let stmt = self.conn.prepare_cached(#query).await?;
 
-						Ok(self.conn.query(&stmt, &[&value]).await?.into_iter().map(|row| #struct_name::from_row(&row, #referent)).collect::<Result<Vec<_>, _>>()?)
+						let mut records = Vec::new();
+						for row in self.conn.query(&stmt, &[&value]).await? {
+							#row_relation_loads
+
+							records.push(#struct_name::from_row(&row, #(#row_relation_values),*)?);
+						}
+
+						Ok(records)
 					}
 				});
 			}
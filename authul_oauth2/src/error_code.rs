@@ -37,8 +37,17 @@ pub enum AuthorizeEndpoint {
 	InvalidRequest,
 	UnsupportedResponseType,
 	InvalidScope,
+	AccessDenied,
 	ServerError,
 	TemporarilyUnavailable,
+	// The rest are from OIDC Core 1.0, s3.1.2.6
+	InteractionRequired,
+	LoginRequired,
+	AccountSelectionRequired,
+	ConsentRequired,
+	// OIDC Core 1.0, s3.1.2.6 again, but used by RFC9101 and RFC9126 too
+	InvalidRequestUri,
+	InvalidRequestObject,
 }
 
 impl AuthorizeEndpoint {
@@ -47,8 +56,15 @@ impl AuthorizeEndpoint {
 			Self::InvalidRequest => "invalid_request",
 			Self::UnsupportedResponseType => "unsupported_response_type",
 			Self::InvalidScope => "invalid_scope",
+			Self::AccessDenied => "access_denied",
 			Self::ServerError => "server_error",
 			Self::TemporarilyUnavailable => "temporarily_unavailable",
+			Self::InteractionRequired => "interaction_required",
+			Self::LoginRequired => "login_required",
+			Self::AccountSelectionRequired => "account_selection_required",
+			Self::ConsentRequired => "consent_required",
+			Self::InvalidRequestUri => "invalid_request_uri",
+			Self::InvalidRequestObject => "invalid_request_object",
 		}
 	}
 }
@@ -66,7 +82,15 @@ pub enum TokenEndpoint {
 	InvalidRequest,
 	InvalidClient,
 	InvalidGrant,
+	UnauthorizedClient,
 	UnsupportedGrantType,
+	InvalidScope,
+	// The rest are from RFC8628 s3.5
+	AuthorizationPending,
+	SlowDown,
+	ExpiredToken,
+	// RFC9449 s5
+	InvalidDpopProof,
 }
 
 impl TokenEndpoint {
@@ -75,7 +99,66 @@ impl TokenEndpoint {
 			Self::InvalidRequest => "invalid_request",
 			Self::InvalidClient => "invalid_client",
 			Self::InvalidGrant => "invalid_grant",
+			Self::UnauthorizedClient => "unauthorized_client",
 			Self::UnsupportedGrantType => "unsupported_grant_type",
+			Self::InvalidScope => "invalid_scope",
+			Self::AuthorizationPending => "authorization_pending",
+			Self::SlowDown => "slow_down",
+			Self::ExpiredToken => "expired_token",
+			Self::InvalidDpopProof => "invalid_dpop_proof",
+		}
+	}
+}
+
+/// Errors that our `/oidc/userinfo` endpoint can return, which is called by an OIDC Client (or
+/// anyone else who has been handed an access token) to find out about the user the access token
+/// was issued for.
+///
+/// Semantics defined in <https://www.rfc-editor.org/rfc/rfc6750.html#section-3.1>, as referenced
+/// by <https://openid.net/specs/openid-connect-core-1_0.html#UserInfoError>.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug)]
+pub enum UserinfoEndpoint {
+	InvalidRequest,
+	InvalidToken,
+	InsufficientScope,
+	// RFC9449 s7.1
+	InvalidDpopProof,
+}
+
+impl UserinfoEndpoint {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::InvalidRequest => "invalid_request",
+			Self::InvalidToken => "invalid_token",
+			Self::InsufficientScope => "insufficient_scope",
+			Self::InvalidDpopProof => "invalid_dpop_proof",
+		}
+	}
+}
+
+/// Errors that our `/oidc/register` endpoint can return, which is called by someone who wants to
+/// register a new OIDC Client (or manage one they registered earlier) without going through an
+/// administrator.
+///
+/// Semantics defined in <https://www.rfc-editor.org/rfc/rfc7591.html#section-3.2.2>, and (for
+/// problems with the initial or registration access token) in
+/// <https://www.rfc-editor.org/rfc/rfc6750.html#section-3.1>, as referenced by
+/// <https://www.rfc-editor.org/rfc/rfc7592.html#section-2>.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug)]
+pub enum RegistrationEndpoint {
+	InvalidToken,
+	InvalidRedirectUri,
+	InvalidClientMetadata,
+}
+
+impl RegistrationEndpoint {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::InvalidToken => "invalid_token",
+			Self::InvalidRedirectUri => "invalid_redirect_uri",
+			Self::InvalidClientMetadata => "invalid_client_metadata",
 		}
 	}
 }
@@ -1,12 +1,28 @@
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
 use clap::{Args, Subcommand};
+use rand::RngCore as _;
 use url::Url;
+use uuid::Uuid;
 
+use authul_db::{model::OidcClient, types::ClientAuthMethod};
 use authul_util::Base64Uuid;
 
 #[derive(Clone, Debug, Subcommand)]
 pub(super) enum Command {
 	/// Add a new OIDC client (a website that uses us for authentication)
 	Add(Add),
+	/// Change the scopes a client may request with the client credentials grant
+	SetScopes(SetScopes),
+	/// Change whether a client gets the principal's identity attributes in an `attrs` claim
+	SetAttrsClaim(SetAttrsClaim),
+	/// Change which claims and identity attributes a client may be given about a user
+	SetAllowedClaims(SetAllowedClaims),
+	/// Change whether users are asked before a client is given their details
+	SetSkipConsent(SetSkipConsent),
+	/// Issue a token that allows clients to be registered through the registration endpoint
+	IssueInitialAccessToken(IssueInitialAccessToken),
+	/// Stop a previously issued initial access token from being used to register any more clients
+	RevokeInitialAccessToken(RevokeInitialAccessToken),
 }
 
 #[derive(Clone, Debug, Args)]
@@ -21,6 +37,12 @@ pub(super) async fn main(
 ) -> Result<(), Box<dyn std::error::Error>> {
 	match cfg.subcommand {
 		Command::Add(add) => add.run(db).await,
+		Command::SetScopes(set) => set.run(db).await,
+		Command::SetAttrsClaim(set) => set.run(db).await,
+		Command::SetAllowedClaims(set) => set.run(db).await,
+		Command::SetSkipConsent(set) => set.run(db).await,
+		Command::IssueInitialAccessToken(issue) => issue.run(db).await,
+		Command::RevokeInitialAccessToken(revoke) => revoke.run(db).await,
 	}
 }
 
@@ -41,14 +63,66 @@ pub(super) struct Add {
 	#[arg(long, required = true)]
 	redirect_uri: Vec<Url>,
 
+	/// A URI the Client may send users to after they've logged out
+	///
+	/// As with redirect URIs, these must be declared in advance.  A client that asks for users to
+	/// be sent anywhere else after logout will be refused.
+	///
+	/// May be specified multiple times.
+	#[arg(long)]
+	post_logout_redirect_uri: Vec<Url>,
+
+	/// A URI to notify whenever a user who signed in to the Client logs out
+	///
+	/// Authul will POST a signed logout token to this URI, as per OpenID Connect Back-Channel
+	/// Logout 1.0, so the Client can end its own session for the user.  Deliveries which fail
+	/// are retried for up to a day.
+	#[arg(long)]
+	backchannel_logout_uri: Option<Url>,
+
+	/// How the Client will prove who it is when requesting tokens
+	///
+	/// One of `private_key_jwt` (the default), `client_secret_basic`, `client_secret_post`, or
+	/// `none`.  For the two `client_secret_*` methods, a client secret is generated and printed
+	/// out; it is not stored anywhere in a recoverable form, so it needs to be copied into the
+	/// Client's configuration straight away.
+	///
+	/// Use `none` for public clients, such as single-page web apps and native apps, which
+	/// cannot keep a secret.  These rely on PKCE alone, and so are restricted to redirect URIs
+	/// on the loopback interface, with a private-use URI scheme (such as
+	/// `com.example.app:/callback`), or on one of the Client's allowed origins.
+	#[arg(long, default_value_t = ClientAuthMethod::PrivateKeyJwt)]
+	token_endpoint_auth_method: ClientAuthMethod,
+
+	/// A web origin from which the Client may call the token endpoint
+	///
+	/// Browser-based apps need to be able to make cross-origin requests to get tokens; only the
+	/// origins given here will be allowed to do so.  Any path in the URL is ignored.
+	///
+	/// May be specified multiple times.
+	#[arg(long)]
+	allowed_origin: Vec<Url>,
+
+	/// A scope the Client may request access to on its own behalf
+	///
+	/// A Client with at least one allowed scope can use the client credentials grant to get access
+	/// tokens for calling other services, with no user involved.  Public clients can't use the
+	/// client credentials grant, whatever scopes they have.
+	///
+	/// May be specified multiple times.
+	#[arg(long, value_parser = parse_scope)]
+	allowed_scope: Vec<String>,
+
 	/// The URL from which the Client's signing JWK Set will be fetched
 	///
-	/// When requesting a token from Authul, the client must authenticate itself by providing a
-	/// single-use JWT signed with a key in this JWK Set.  This URL will be retrieved whenever a
-	/// token request is received, unless the JWK Set is "fresh" according to the caching
-	/// configuration of the last HTTP response provided by the client.
-	#[arg(long, required = true)]
-	jwks_uri: Url,
+	/// When requesting a token from Authul, a client using `private_key_jwt` must authenticate
+	/// itself by providing a single-use JWT signed with a key in this JWK Set.  This URL will be
+	/// retrieved whenever a token request is received, unless the JWK Set is "fresh" according to
+	/// the caching configuration of the last HTTP response provided by the client.
+	///
+	/// Required if the Client is using `private_key_jwt`.
+	#[arg(long)]
+	jwks_uri: Option<Url>,
 
 	/// The URL from which the Client's token forwarding JWK will be fetched
 	///
@@ -57,12 +131,106 @@ pub(super) struct Add {
 	/// useful for the client to be provided that access token for its own purposes.  Since an
 	/// access token is extremely sensitive, it will only be provided in the ID token if the client
 	/// provides a JWK containing an Ed25519 public key at the URL specified by this option.
+	///
+	/// The encrypted access token is handed over in the `attrs` claim, so the Client will also
+	/// need `--include-attrs-claim`.
 	#[arg(long)]
 	token_forward_jwk_uri: Option<Url>,
+
+	/// Require users to provide a second factor when signing in to this Client
+	///
+	/// Users who have not yet set up a TOTP authenticator app will be asked to do so the next
+	/// time they sign in.
+	#[arg(long)]
+	require_mfa: bool,
+	/// Require the Client to push its authorization requests to Authul before sending users here
+	///
+	/// The Client will have to POST its authorization request parameters to the pushed
+	/// authorization request endpoint (RFC9126), and send users to the authorization endpoint with
+	/// only the `request_uri` it gets back, so that the parameters never appear in a URL.
+	/// Authorization requests which include the parameters directly will be refused.
+	#[arg(long)]
+	require_pushed_authorization_requests: bool,
+
+	/// Include all of the user's identity attributes in an `attrs` claim
+	///
+	/// Clients get the standard OIDC claims (`email`, `name`, etc) for the scopes they request.
+	/// Clients written before those were available may instead rely on the `attrs` claim, which
+	/// lists everything we know about the user, including any access token forwarded via the
+	/// `--token-forward-jwk-uri`.
+	#[arg(long)]
+	include_attrs_claim: bool,
+
+	/// A claim, or identity attribute kind, the Client may be given about a user
+	///
+	/// Claims are named as in OpenID Connect (`email`, `name`, etc); identity attribute kinds are
+	/// named as they appear in the `attrs` claim (`Username`, `AccessToken`, etc).  Whatever the
+	/// Client requests, it will only ever get the ones listed here.  If none are given, the Client
+	/// may get anything it requests.
+	///
+	/// May be specified multiple times.
+	#[arg(long)]
+	allowed_claim: Vec<String>,
+
+	/// Identify users to the Client with pairwise subject identifiers, rather than their IDs
+	///
+	/// Each user gets a different `sub` in each sector, so that Clients in different sectors
+	/// can't work out which of their users are the same people.  The sector is the host that all
+	/// of the Client's redirect URIs are on, unless `--sector-identifier` says otherwise.
+	#[arg(long)]
+	pairwise_subject: bool,
+
+	/// The sector the Client belongs to, for pairwise subject identifiers
+	///
+	/// Clients with the same sector identifier are given the same `sub` for each user.  Implies
+	/// `--pairwise-subject`.
+	#[arg(long)]
+	sector_identifier: Option<String>,
+
+	/// Give the Client what it asks for about users without asking them first
+	///
+	/// Otherwise, the first time a user signs in to the Client, they are shown what it wants to
+	/// know about them, and asked whether it may.  This is meant for first-party apps, which
+	/// users have no reason to keep anything from.
+	#[arg(long)]
+	skip_consent: bool,
 }
 
 impl Add {
 	async fn run(self, db: authul_db::Pool) -> Result<(), Box<dyn std::error::Error>> {
+		let allowed_origins = self
+			.allowed_origin
+			.iter()
+			.map(|u| u.origin().ascii_serialization())
+			.collect::<Vec<_>>();
+
+		OidcClient::check_registration(
+			self.token_endpoint_auth_method,
+			&self.redirect_uri,
+			&allowed_origins,
+			self.jwks_uri.as_ref().map(Url::as_str),
+		)?;
+
+		let sector_identifier = match self.sector_identifier {
+			Some(sector_identifier) => Some(sector_identifier),
+			None if self.pairwise_subject => {
+				Some(OidcClient::sector_identifier_for(&self.redirect_uri)?)
+			}
+			None => None,
+		};
+
+		let client_secret = if self.token_endpoint_auth_method.uses_secret() {
+			let mut secret = [0u8; 32];
+			rand::thread_rng().fill_bytes(&mut secret);
+			Some(BASE64_URL_SAFE_NO_PAD.encode(secret))
+		} else {
+			None
+		};
+		let client_secret_hash = client_secret
+			.as_ref()
+			.map(|s| bcrypt::hash(s, bcrypt::DEFAULT_COST))
+			.transpose()?;
+
 		let mut conn = db.conn().await?;
 		let txn = conn.transaction().await?;
 
@@ -71,14 +239,244 @@ impl Add {
 			.new()
 			.with_name(self.name)
 			.with_redirect_uris(self.redirect_uri)
-			.with_jwks_uri(self.jwks_uri)
+			.with_post_logout_redirect_uris(self.post_logout_redirect_uri)
+			.with_token_endpoint_auth_method(self.token_endpoint_auth_method)
+			.with_client_secret_hash(client_secret_hash)
+			.with_allowed_origins(allowed_origins)
+			.with_allowed_scopes(self.allowed_scope)
+			.with_jwks_uri(self.jwks_uri.map(|u| u.to_string()))
 			.with_token_forward_jwk_uri(self.token_forward_jwk_uri.map(|u| u.to_string()))
+			.with_backchannel_logout_uri(self.backchannel_logout_uri.map(|u| u.to_string()))
+			.with_require_mfa(self.require_mfa)
+			.with_require_pushed_authorization_requests(self.require_pushed_authorization_requests)
+			.with_include_attrs_claim(self.include_attrs_claim)
+			.with_allowed_claims((!self.allowed_claim.is_empty()).then_some(self.allowed_claim))
+			.with_sector_identifier(sector_identifier)
+			.with_skip_consent(self.skip_consent)
 			.save()
 			.await?;
 
 		txn.commit().await?;
 
 		println!("Client ID: {}", client.id().to_base64());
+		if let Some(secret) = client_secret {
+			println!("Client secret: {secret}");
+			println!("The client secret cannot be displayed again, so keep it somewhere safe.");
+		}
+		Ok(())
+	}
+}
+
+#[derive(Clone, Debug, Args)]
+pub(super) struct SetScopes {
+	/// The ID of the Client whose scopes are to be changed
+	client_id: String,
+
+	/// A scope the Client may request access to on its own behalf
+	///
+	/// Replaces whatever scopes the Client was previously allowed.  Give no scopes at all to stop
+	/// the Client from using the client credentials grant.
+	///
+	/// May be specified multiple times.
+	#[arg(long, value_parser = parse_scope)]
+	scope: Vec<String>,
+}
+
+impl SetScopes {
+	async fn run(self, db: authul_db::Pool) -> Result<(), Box<dyn std::error::Error>> {
+		let clients = db.oidc_client().await?;
+		let mut client = clients.find(&Uuid::from_base64(&self.client_id)?).await?;
+
+		client.update_allowed_scopes(self.scope);
+		client.save(&clients).await?;
+
+		if client.allowed_scopes().is_empty() {
+			println!(
+				"Client {} may not use the client credentials grant",
+				self.client_id
+			);
+		} else {
+			println!(
+				"Client {} may request: {}",
+				self.client_id,
+				client.allowed_scopes().join(" ")
+			);
+		}
+		Ok(())
+	}
+}
+
+#[derive(Clone, Debug, Args)]
+pub(super) struct IssueInitialAccessToken {
+	/// What the token is for
+	///
+	/// This is only stored for reference, so that whoever comes across the token later can tell
+	/// what will stop working if it is revoked.
+	description: String,
+
+	/// A scope that Clients registered with the token may be allowed to request on their own behalf
+	///
+	/// Can be specified multiple times.  Clients registered with the token can ask for any of these
+	/// scopes, but nothing else; with no scopes at all, they can't use the client credentials grant.
+	#[arg(long, value_parser = parse_scope)]
+	allowed_scope: Vec<String>,
+}
+
+impl IssueInitialAccessToken {
+	async fn run(self, db: authul_db::Pool) -> Result<(), Box<dyn std::error::Error>> {
+		let mut secret = [0u8; 32];
+		rand::thread_rng().fill_bytes(&mut secret);
+		let secret = BASE64_URL_SAFE_NO_PAD.encode(secret);
+
+		let token = db
+			.initial_access_token()
+			.await?
+			.new()
+			.with_description(self.description)
+			.with_secret_hash(bcrypt::hash(&secret, bcrypt::DEFAULT_COST)?)
+			.with_allowed_scopes(self.allowed_scope)
+			.save()
+			.await?;
+
+		println!("Initial access token: {}.{secret}", token.id().to_base64());
+		println!("Anyone with this token can register clients, so keep it somewhere safe.");
+		println!("The token cannot be displayed again; the part before the `.` identifies it.");
+		Ok(())
+	}
+}
+
+#[derive(Clone, Debug, Args)]
+pub(super) struct RevokeInitialAccessToken {
+	/// The token to revoke, or just the part of it before the `.`
+	///
+	/// Clients which have already been registered with the token are not affected.
+	token: String,
+}
+
+impl RevokeInitialAccessToken {
+	async fn run(self, db: authul_db::Pool) -> Result<(), Box<dyn std::error::Error>> {
+		let token = db
+			.initial_access_token()
+			.await?
+			.find(&Uuid::from_base64(
+				self.token.split('.').next().unwrap_or_default(),
+			)?)
+			.await?;
+		let description = token.description().clone();
+
+		db.delete(token).await?;
+
+		println!("Revoked initial access token for {description}");
+		Ok(())
+	}
+}
+
+#[derive(Clone, Debug, Args)]
+pub(super) struct SetAttrsClaim {
+	/// The ID of the Client to change
+	client_id: String,
+
+	/// Whether the Client should get the `attrs` claim
+	#[arg(action = clap::ArgAction::Set)]
+	enabled: bool,
+}
+
+impl SetAttrsClaim {
+	async fn run(self, db: authul_db::Pool) -> Result<(), Box<dyn std::error::Error>> {
+		let clients = db.oidc_client().await?;
+		let mut client = clients.find(&Uuid::from_base64(&self.client_id)?).await?;
+
+		client.update_include_attrs_claim(self.enabled);
+		client.save(&clients).await?;
+
+		if self.enabled {
+			println!("Client {} will get the attrs claim", self.client_id);
+		} else {
+			println!("Client {} will not get the attrs claim", self.client_id);
+		}
+		Ok(())
+	}
+}
+
+#[derive(Clone, Debug, Args)]
+pub(super) struct SetAllowedClaims {
+	/// The ID of the Client to change
+	client_id: String,
+
+	/// A claim, or identity attribute kind, the Client may be given about a user
+	///
+	/// Replaces whatever the Client was previously allowed.
+	///
+	/// May be specified multiple times.
+	#[arg(long, required_unless_present = "unrestricted")]
+	claim: Vec<String>,
+
+	/// Let the Client have any claim it requests
+	#[arg(long, conflicts_with = "claim")]
+	unrestricted: bool,
+}
+
+impl SetAllowedClaims {
+	async fn run(self, db: authul_db::Pool) -> Result<(), Box<dyn std::error::Error>> {
+		let clients = db.oidc_client().await?;
+		let mut client = clients.find(&Uuid::from_base64(&self.client_id)?).await?;
+
+		client.update_allowed_claims((!self.unrestricted).then_some(self.claim));
+		client.save(&clients).await?;
+
+		match client.allowed_claims() {
+			None => println!("Client {} may get any claim it requests", self.client_id),
+			Some(claims) => println!(
+				"Client {} may only get: {}",
+				self.client_id,
+				claims.join(" ")
+			),
+		}
+		Ok(())
+	}
+}
+
+// RFC6749 s3.3
+fn parse_scope(s: &str) -> Result<String, String> {
+	if !s.is_empty()
+		&& s.chars()
+			.all(|c| c.is_ascii_graphic() && c != '"' && c != '\\')
+	{
+		Ok(s.to_string())
+	} else {
+		Err(format!("{s:?} is not a valid scope"))
+	}
+}
+
+#[derive(Clone, Debug, Args)]
+pub(super) struct SetSkipConsent {
+	/// The ID of the Client to change
+	client_id: String,
+
+	/// Whether the Client should be given users' details without asking them first
+	#[arg(action = clap::ArgAction::Set)]
+	enabled: bool,
+}
+
+impl SetSkipConsent {
+	async fn run(self, db: authul_db::Pool) -> Result<(), Box<dyn std::error::Error>> {
+		let clients = db.oidc_client().await?;
+		let mut client = clients.find(&Uuid::from_base64(&self.client_id)?).await?;
+
+		client.update_skip_consent(self.enabled);
+		client.save(&clients).await?;
+
+		if self.enabled {
+			println!(
+				"Users will not be asked to consent to client {}",
+				self.client_id
+			);
+		} else {
+			println!(
+				"Users will be asked to consent to client {}",
+				self.client_id
+			);
+		}
 		Ok(())
 	}
 }
@@ -1,4 +1,5 @@
 mod client;
+mod principal;
 
 use clap::Parser;
 use service_skeleton::ServiceConfig;
@@ -15,6 +16,8 @@ enum Cli {
 	Frontend,
 	/// Manage OIDC clients (the websites that use us to authenticate)
 	Client(client::Client),
+	/// Manage principals (the users who authenticate with us)
+	Principal(principal::Principal),
 }
 
 pub fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -41,5 +44,6 @@ async fn run_cli(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
 			panic!("CAN'T HAPPEN");
 		}
 		Cli::Client(cfg) => client::main(cfg, db).await,
+		Cli::Principal(cfg) => principal::main(cfg, db).await,
 	}
 }
@@ -0,0 +1,78 @@
+use clap::{Args, Subcommand};
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Subcommand)]
+pub(super) enum Command {
+	/// Invalidate all of a principal's recovery codes
+	RevokeRecoveryCodes(RevokeRecoveryCodes),
+	/// Log a principal out of all their current sessions
+	EndSessions(EndSessions),
+}
+
+#[derive(Clone, Debug, Args)]
+pub(super) struct Principal {
+	#[command(subcommand)]
+	subcommand: Command,
+}
+
+pub(super) async fn main(
+	cfg: Principal,
+	db: authul_db::Pool,
+) -> Result<(), Box<dyn std::error::Error>> {
+	match cfg.subcommand {
+		Command::RevokeRecoveryCodes(revoke) => revoke.run(db).await,
+		Command::EndSessions(end) => end.run(db).await,
+	}
+}
+
+#[derive(Clone, Debug, Args)]
+pub(super) struct RevokeRecoveryCodes {
+	/// The ID of the principal whose recovery codes are to be revoked
+	///
+	/// This is the `sub` claim in the ID tokens issued for the principal.
+	principal_id: Uuid,
+}
+
+impl RevokeRecoveryCodes {
+	async fn run(self, db: authul_db::Pool) -> Result<(), Box<dyn std::error::Error>> {
+		let principal = db.principal().await?.find(&self.principal_id).await?;
+
+		let count = db
+			.recovery_code()
+			.await?
+			.delete_all_by_principal_id(principal.id())
+			.await?;
+
+		println!("Revoked {count} recovery code(s)");
+		println!(
+			"A new set will be issued the next time the principal signs in with a second factor."
+		);
+		Ok(())
+	}
+}
+
+#[derive(Clone, Debug, Args)]
+pub(super) struct EndSessions {
+	/// The ID of the principal whose sessions are to be ended
+	///
+	/// This is the `sub` claim in the ID tokens issued for the principal.
+	principal_id: Uuid,
+}
+
+impl EndSessions {
+	async fn run(self, db: authul_db::Pool) -> Result<(), Box<dyn std::error::Error>> {
+		let principal = db.principal().await?.find(&self.principal_id).await?;
+
+		let count = db
+			.session()
+			.await?
+			.end_all_by_principal_id(principal.id())
+			.await?;
+
+		println!("Ended {count} session(s)");
+		println!(
+			"Clients with a back-channel logout URI will be notified by the running server shortly."
+		);
+		Ok(())
+	}
+}
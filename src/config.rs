@@ -1,3 +1,5 @@
+#[cfg(feature = "frontend-ssr")]
+use secrecy::ExposeSecret as _;
 use secrecy::Secret;
 use service_skeleton::ServiceConfig;
 #[cfg(feature = "frontend-ssr")]
@@ -35,6 +37,12 @@ pub(crate) struct Config {
 	#[cfg(feature = "frontend-ssr")]
 	#[config(encrypted, key_file_field = "secret_key")]
 	google_oauth_creds: Option<OAuthClientBuilder<provider::Google>>,
+
+	#[cfg(feature = "frontend-ssr")]
+	#[config(encrypted, key_file_field = "secret_key")]
+	mail_transport: Option<Secret<String>>,
+	#[cfg(feature = "frontend-ssr")]
+	mail_from: Option<String>,
 }
 
 impl Config {
@@ -80,6 +88,15 @@ impl Config {
 		if let Some(u) = self.frontend_css_url {
 			b.css_url(u);
 		}
+		if let Some(t) = self.mail_transport {
+			b = b.mail_transport(
+				authul_frontend::mail::transport_from_url(t.expose_secret())
+					.expect("invalid mail_transport"),
+			);
+		}
+		if let Some(f) = self.mail_from {
+			b = b.mail_from(f);
+		}
 
 		b.build().expect("invalid config")
 	}
@@ -18,6 +18,11 @@ async fn run(cfg: Config) {
 
 	let app_cfg: FrontendConfig = cfg.clone().into_frontend_config(db);
 
+	app_cfg
+		.re_encrypt_server_secrets()
+		.await
+		.expect("server secret re-encryption failed");
+
 	periodic_tasks::spawn(app_cfg.clone())
 		.await
 		.expect("background tasks did not spawn");
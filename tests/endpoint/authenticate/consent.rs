@@ -0,0 +1,256 @@
+use authul_frontend::AuthContext;
+use time::OffsetDateTime;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{css, util};
+
+async fn oidc_client(
+	srv: &util::ConfiguredTestServer,
+	skip_consent: bool,
+) -> authul_db::model::OidcClient {
+	util::oidc_client_with(&srv.db, "https://example.com/cb", |c| {
+		c.update_name("Nosy Parker");
+		c.update_skip_consent(skip_consent);
+	})
+	.await
+}
+
+async fn post(srv: &util::ConfiguredTestServer, path: &str, form: &[(&str, &str)]) -> Url {
+	let res = srv
+		.post(path)
+		.insert_header(("accept", "text/html"))
+		.send_form(form)
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap()
+}
+
+/// Sign in with a password, and return wherever that sends the user next
+async fn sign_in(
+	srv: &util::ConfiguredTestServer,
+	oidc_client: &authul_db::model::OidcClient,
+	user: &authul_db::model::User,
+	scope: &str,
+) -> Url {
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"xyzzy123",
+	)
+	.with_scope(scope)
+	.with_state("nevada")
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash())
+	.to_string();
+
+	post(
+		srv,
+		"/authenticate/submit_password",
+		&[("ctx", ctx.as_str()), ("password", "hunter2")],
+	)
+	.await
+}
+
+async fn decide(srv: &util::ConfiguredTestServer, consent_url: &Url, decision: &str) -> Url {
+	let ctx = util::param(consent_url, "ctx").expect("ctx");
+	post(
+		srv,
+		"/authenticate/submit_consent",
+		&[("ctx", ctx.as_str()), ("decision", decision)],
+	)
+	.await
+}
+
+#[actix_rt::test]
+async fn third_party_client_asks_for_consent_once() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+
+	let redirect_url = sign_in(&srv, &oidc_client, &user, "openid email").await;
+	assert_eq!("/authenticate/consent", redirect_url.path());
+
+	let mut res = srv
+		.get(&redirect_url[url::Position::BeforePath..])
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+	let doc = util::doc(&mut res).await;
+	assert!(
+		doc.html().contains("Nosy Parker"),
+		"client name not on consent page"
+	);
+	let items = doc
+		.select(css!("ul#consent-items li"))
+		.map(|i| i.text().collect::<String>())
+		.collect::<Vec<_>>();
+	assert_eq!(vec!["Your email address".to_string()], items);
+
+	let redirect_url = decide(&srv, &redirect_url, "allow").await;
+	assert_eq!("example.com", redirect_url.host_str().unwrap());
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+	assert_eq!(
+		Some("nevada"),
+		util::param(&redirect_url, "state").as_deref()
+	);
+
+	// Having agreed once, the user isn't bothered about it again
+	let redirect_url = sign_in(&srv, &oidc_client, &user, "openid email").await;
+	assert_eq!("example.com", redirect_url.host_str().unwrap());
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+}
+
+#[actix_rt::test]
+async fn asking_for_more_asks_again() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+
+	let redirect_url = sign_in(&srv, &oidc_client, &user, "openid email").await;
+	decide(&srv, &redirect_url, "allow").await;
+
+	let redirect_url = sign_in(&srv, &oidc_client, &user, "openid email profile").await;
+	assert_eq!("/authenticate/consent", redirect_url.path());
+}
+
+#[actix_rt::test]
+async fn consent_can_be_refused() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+
+	let redirect_url = sign_in(&srv, &oidc_client, &user, "openid profile").await;
+	assert_eq!("/authenticate/consent", redirect_url.path());
+
+	let redirect_url = decide(&srv, &redirect_url, "deny").await;
+	assert_eq!("example.com", redirect_url.host_str().unwrap());
+	assert_eq!(
+		Some("access_denied"),
+		util::param(&redirect_url, "error").as_deref()
+	);
+	assert_eq!(
+		Some("nevada"),
+		util::param(&redirect_url, "state").as_deref()
+	);
+	assert!(
+		util::param(&redirect_url, "code").is_none(),
+		"code issued anyway"
+	);
+
+	// Saying no isn't remembered; they get asked again next time
+	let redirect_url = sign_in(&srv, &oidc_client, &user, "openid profile").await;
+	assert_eq!("/authenticate/consent", redirect_url.path());
+}
+
+#[actix_rt::test]
+async fn first_party_client_skips_consent() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, true).await;
+	let user = util::create_user(&srv.db).await;
+
+	let redirect_url = sign_in(&srv, &oidc_client, &user, "openid email profile").await;
+	assert_eq!("example.com", redirect_url.host_str().unwrap());
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+}
+
+#[actix_rt::test]
+async fn nothing_to_consent_to_for_openid_alone() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+
+	let redirect_url = sign_in(&srv, &oidc_client, &user, "openid").await;
+	assert_eq!("example.com", redirect_url.host_str().unwrap());
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+}
+
+/// A context from part way through signing in (here, waiting on a second factor) mustn't be able to
+/// go around the rest of it by way of the consent page
+#[actix_rt::test]
+async fn consent_before_completing_authentication_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"xyzzy123",
+	)
+	.with_scope("openid email")
+	.with_principal(*user.principal().id())
+	.with_auth_time(OffsetDateTime::now_utc().unix_timestamp())
+	.to_string();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_consent",
+		&[("ctx", ctx.as_str()), ("decision", "allow")],
+	)
+	.await;
+	assert_eq!("/authenticate/consent", redirect_url.path());
+	assert_eq!(
+		Some("invalid_context"),
+		util::param(&redirect_url, "err").as_deref()
+	);
+}
+
+#[actix_rt::test]
+async fn consent_without_second_factor_is_rejected_for_mfa_client() {
+	let srv = util::setup(util::default).await;
+
+	let clients = srv.db.oidc_client().await.expect("oidc_client");
+	let mut oidc_client = oidc_client(&srv, false).await;
+	oidc_client.update_require_mfa(true);
+	oidc_client.save(&clients).await.expect("save");
+	let user = util::create_user(&srv.db).await;
+
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"xyzzy123",
+	)
+	.with_scope("openid email")
+	.with_principal(*user.principal().id())
+	.with_auth_time(OffsetDateTime::now_utc().unix_timestamp())
+	.with_session(Uuid::now_v7())
+	.to_string();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_consent",
+		&[("ctx", ctx.as_str()), ("decision", "allow")],
+	)
+	.await;
+	assert_eq!("/authenticate/consent", redirect_url.path());
+	assert_eq!(
+		Some("invalid_context"),
+		util::param(&redirect_url, "err").as_deref()
+	);
+}
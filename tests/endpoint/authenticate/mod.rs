@@ -4,8 +4,15 @@ use uuid::Uuid;
 use crate::{css, util};
 use authul_frontend::AuthContext;
 
+mod consent;
 mod oauth_callback;
 mod password_auth;
+mod recovery_code;
+mod register;
+mod reset_password;
+mod totp;
+mod verify_email;
+mod webauthn;
 
 #[actix_rt::test]
 async fn get_with_no_context_returns_helpful_page() {
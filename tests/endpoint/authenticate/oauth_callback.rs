@@ -42,10 +42,12 @@ async fn req_setup(cfg: &authul_frontend::Config) -> (String, String, String) {
 		.new()
 		.with_name("OAuth Proxy")
 		.with_redirect_uris(["https://example.com/oidc/callback"])
-		.with_jwks_uri("https://example.com/jwks.json")
+		.with_jwks_uri("https://example.com/jwks.json".to_string())
 		.with_token_forward_jwk_uri(Some(
 			"https://example.com/token_forward_jwk.json".to_string(),
 		))
+		// The forwarded access token is only handed over as an identity attribute
+		.with_include_attrs_claim(true)
 		.save()
 		.await
 		.expect("oidc_client save");
@@ -509,19 +509,28 @@ async fn post_with_correct_password_for_known_user() {
 		.new()
 		.with_name("Oh, I Dee Cee")
 		.with_redirect_uris(["https://example.com/cb"])
-		.with_jwks_uri("https://example.com/jwks.json")
+		.with_jwks_uri("https://example.com/jwks.json".to_string())
 		.save()
 		.await
 		.expect("OidcClient");
 
+	let user = srv
+		.db
+		.user()
+		.await
+		.expect("user")
+		.create("jaime@example.com", bcrypt::hash("hunter2", 5).unwrap())
+		.await
+		.expect("User");
+
 	let ctx = AuthContext::new(
 		srv.cfg.clone(),
 		oidc_client.id(),
 		"https://example.com/all_good",
 		"bobble",
 	)
-	.with_principal(Uuid::now_v7())
-	.with_pwhash(bcrypt::hash("hunter2", 5).unwrap());
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash());
 
 	let res = srv
 		.post("/authenticate/submit_password")
@@ -0,0 +1,440 @@
+use authul_frontend::AuthContext;
+use time::OffsetDateTime;
+use totp_rs::{Algorithm, TOTP};
+use url::Url;
+
+use crate::{css, util};
+
+const SECRET: &[u8] = b"twenty bytes, please";
+
+/// Give the user a TOTP credential, but (unlike a real enrolment) no recovery codes
+async fn enrol(srv: &util::ConfiguredTestServer, user: &authul_db::model::User) {
+	let principal = srv
+		.db
+		.principal()
+		.await
+		.expect("principal")
+		.find(user.principal().id())
+		.await
+		.expect("Principal");
+
+	srv.db
+		.totp_credential()
+		.await
+		.expect("totp_credential")
+		.new()
+		.with_principal(principal)
+		.with_secret(
+			srv.cfg
+				.totp_secret_strong_box()
+				.encrypt(SECRET, b"")
+				.expect("encrypt"),
+		)
+		.save()
+		.await
+		.expect("TotpCredential");
+}
+
+fn current_code() -> String {
+	TOTP::new(
+		Algorithm::SHA1,
+		6,
+		0,
+		30,
+		SECRET.to_vec(),
+		None,
+		"test".to_string(),
+	)
+	.expect("TOTP")
+	.generate_current()
+	.expect("code")
+}
+
+fn authenticated_ctx(
+	srv: &util::ConfiguredTestServer,
+	oidc_client: &authul_db::model::OidcClient,
+	user: &authul_db::model::User,
+) -> AuthContext {
+	AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.with_principal(*user.principal().id())
+	.with_email(user.email())
+	.with_auth_time(OffsetDateTime::now_utc().unix_timestamp())
+}
+
+async fn post(srv: &util::ConfiguredTestServer, path: &str, form: &[(&str, &str)]) -> Url {
+	let res = srv
+		.post(path)
+		.insert_header(("accept", "text/html"))
+		.send_form(form)
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap()
+}
+
+/// Load up the page that shows a new set of recovery codes, and return whatever codes it shows
+async fn codes_from_page(srv: &util::ConfiguredTestServer, url: &Url) -> Vec<String> {
+	let mut res = srv
+		.get(&url[url::Position::BeforePath..])
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+
+	let doc = util::doc(&mut res).await;
+	doc.select(css!("ol#recovery-codes code.recovery-code"))
+		.map(|c| c.text().collect::<String>())
+		.collect()
+}
+
+/// Ask for a new set of recovery codes, as someone who has just provided their second factor, and
+/// return the codes that are shown
+async fn regenerate(
+	srv: &util::ConfiguredTestServer,
+	oidc_client: &authul_db::model::OidcClient,
+	user: &authul_db::model::User,
+) -> Vec<String> {
+	let ctx = authenticated_ctx(srv, oidc_client, user)
+		.with_mfa_time(OffsetDateTime::now_utc().unix_timestamp())
+		.to_string();
+
+	let redirect_url = post(
+		srv,
+		"/authenticate/regenerate_recovery_codes",
+		&[("ctx", ctx.as_str())],
+	)
+	.await;
+	assert_eq!("/authenticate/recovery_codes", redirect_url.path());
+	assert_eq!(None, util::param(&redirect_url, "err"));
+
+	codes_from_page(srv, &redirect_url).await
+}
+
+async fn code_count(srv: &util::ConfiguredTestServer, user: &authul_db::model::User) -> i64 {
+	srv.db
+		.recovery_code()
+		.await
+		.expect("recovery_code")
+		.count_by_principal_id(user.principal().id())
+		.await
+		.expect("count_by_principal_id")
+}
+
+#[actix_rt::test]
+async fn user_without_codes_is_given_codes_after_second_factor() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user).to_string();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_totp",
+		&[("ctx", ctx.as_str()), ("code", &current_code())],
+	)
+	.await;
+	assert_eq!("/authenticate/recovery_codes", redirect_url.path());
+
+	let codes = codes_from_page(&srv, &redirect_url).await;
+	assert_eq!(10, codes.len());
+	assert_eq!(10, code_count(&srv, &user).await);
+
+	// Looking at the page again doesn't make a new set
+	assert_eq!(codes, codes_from_page(&srv, &redirect_url).await);
+
+	let ctx = util::param(&redirect_url, "ctx").expect("ctx");
+	let redirect_url = post(
+		&srv,
+		"/authenticate/acknowledge_recovery_codes",
+		&[("ctx", ctx.as_str())],
+	)
+	.await;
+	assert_eq!("example.com", redirect_url.host_str().unwrap());
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+}
+
+#[actix_rt::test]
+async fn recovery_code_can_be_used_instead_of_second_factor() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+
+	let codes = regenerate(&srv, &oidc_client, &user).await;
+	assert_eq!(10, codes.len());
+
+	// Case and punctuation shouldn't matter
+	let typed = codes[3].to_uppercase().replace('-', " ");
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user).to_string();
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_recovery_code",
+		&[("ctx", ctx.as_str()), ("code", &typed)],
+	)
+	.await;
+	assert_eq!("/authenticate/recovery_codes", redirect_url.path());
+	assert_eq!(None, util::param(&redirect_url, "err"));
+
+	// Having used a code, the user is offered a new set, rather than just being given one
+	let mut res = srv
+		.get(&redirect_url[url::Position::BeforePath..])
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+
+	let doc = util::doc(&mut res).await;
+	assert_eq!(0, doc.select(css!("code.recovery-code")).count());
+	assert_eq!(
+		1,
+		doc.select(css!("form#regenerate-recovery-codes-form"))
+			.count(),
+		"page should offer new codes"
+	);
+	assert_eq!(9, code_count(&srv, &user).await);
+
+	// ... and can carry on without them
+	let redirect_url = post(
+		&srv,
+		"/authenticate/acknowledge_recovery_codes",
+		&[(
+			"ctx",
+			util::param(&redirect_url, "ctx").expect("ctx").as_str(),
+		)],
+	)
+	.await;
+	assert_eq!("example.com", redirect_url.host_str().unwrap());
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+
+	// The other codes still work
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_recovery_code",
+		&[("ctx", ctx.as_str()), ("code", &codes[4])],
+	)
+	.await;
+	assert_eq!("/authenticate/recovery_codes", redirect_url.path());
+	assert_eq!(None, util::param(&redirect_url, "err"));
+}
+
+#[actix_rt::test]
+async fn repeated_wrong_recovery_codes_lock_out() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+	let codes = regenerate(&srv, &oidc_client, &user).await;
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user).to_string();
+
+	for _ in 0..5 {
+		let redirect_url = post(
+			&srv,
+			"/authenticate/submit_recovery_code",
+			&[("ctx", ctx.as_str()), ("code", "0000-0000-0000")],
+		)
+		.await;
+		assert_eq!(
+			Some("wrong_code".to_string()),
+			util::param(&redirect_url, "err")
+		);
+	}
+
+	// Even a right code is refused now
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_recovery_code",
+		&[("ctx", ctx.as_str()), ("code", &codes[0])],
+	)
+	.await;
+	assert_eq!("/authenticate/recovery_codes/use", redirect_url.path());
+	assert_eq!(
+		Some("locked_out".to_string()),
+		util::param(&redirect_url, "err")
+	);
+	assert_eq!(10, code_count(&srv, &user).await);
+}
+
+#[actix_rt::test]
+async fn recovery_code_cannot_be_reused() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+
+	let codes = regenerate(&srv, &oidc_client, &user).await;
+
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user).to_string();
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_recovery_code",
+		&[("ctx", ctx.as_str()), ("code", &codes[0])],
+	)
+	.await;
+	assert_eq!(None, util::param(&redirect_url, "err"));
+	assert_eq!(9, code_count(&srv, &user).await);
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_recovery_code",
+		&[("ctx", ctx.as_str()), ("code", &codes[0])],
+	)
+	.await;
+	assert_eq!(
+		Some("wrong_code".to_string()),
+		util::param(&redirect_url, "err")
+	);
+}
+
+#[actix_rt::test]
+async fn recovery_code_without_primary_authentication_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+
+	let codes = regenerate(&srv, &oidc_client, &user).await;
+
+	// What the context looks like before the password has been checked
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash())
+	.to_string();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_recovery_code",
+		&[("ctx", ctx.as_str()), ("code", &codes[0])],
+	)
+	.await;
+	assert_eq!(
+		Some("invalid_context".to_string()),
+		util::param(&redirect_url, "err")
+	);
+	assert_eq!(10, code_count(&srv, &user).await);
+}
+
+#[actix_rt::test]
+async fn codes_are_not_issued_without_second_factor() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+
+	// Password, but no second factor
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user).to_string();
+
+	let mut res = srv
+		.get(&format!("/authenticate/recovery_codes?ctx={ctx}"))
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+
+	let doc = util::doc(&mut res).await;
+	assert_eq!(0, doc.select(css!("code.recovery-code")).count());
+	assert_eq!(
+		1,
+		doc.select(css!("#recovery-codes-unavailable")).count(),
+		"page should explain why there are no codes"
+	);
+	assert_eq!(0, code_count(&srv, &user).await);
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/regenerate_recovery_codes",
+		&[("ctx", ctx.as_str())],
+	)
+	.await;
+	assert_eq!(
+		Some("invalid_context".to_string()),
+		util::param(&redirect_url, "err")
+	);
+	assert_eq!(0, code_count(&srv, &user).await);
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/acknowledge_recovery_codes",
+		&[("ctx", ctx.as_str())],
+	)
+	.await;
+	assert_eq!(
+		Some("invalid_context".to_string()),
+		util::param(&redirect_url, "err")
+	);
+	assert_eq!(None, util::param(&redirect_url, "code"));
+}
+
+#[actix_rt::test]
+async fn viewing_the_page_does_not_replace_codes() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+	let codes = regenerate(&srv, &oidc_client, &user).await;
+
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user)
+		.with_mfa_time(OffsetDateTime::now_utc().unix_timestamp())
+		.to_string();
+	let mut res = srv
+		.get(&format!("/authenticate/recovery_codes?ctx={ctx}"))
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+
+	// There's nothing new to show, just a way to ask for a new set
+	let doc = util::doc(&mut res).await;
+	assert_eq!(0, doc.select(css!("code.recovery-code")).count());
+	assert_eq!(
+		1,
+		doc.select(css!("form#regenerate-recovery-codes-form"))
+			.count(),
+		"page should offer new codes"
+	);
+
+	// ... and the codes the user already has still work
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_recovery_code",
+		&[
+			(
+				"ctx",
+				authenticated_ctx(&srv, &oidc_client, &user)
+					.to_string()
+					.as_str(),
+			),
+			("code", &codes[0]),
+		],
+	)
+	.await;
+	assert_eq!(None, util::param(&redirect_url, "err"));
+}
@@ -0,0 +1,278 @@
+use actix_web::HttpMessage as _;
+use authul_frontend::AuthContext;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{css, util};
+
+const GOOD_PASSWORD: &str = "Wombat-Velocity-Tangerine-42";
+
+fn param_from(res: &actix_test::ClientResponse, name: &str) -> Option<String> {
+	let redirect_url =
+		Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+	assert_eq!("/authenticate/register", redirect_url.path());
+
+	redirect_url
+		.query_pairs()
+		.find(|(k, _)| k == name)
+		.map(|(_, v)| v.to_string())
+}
+
+fn error_from(res: &actix_test::ClientResponse) -> Option<String> {
+	param_from(res, "err")
+}
+
+#[actix_rt::test]
+async fn get_with_valid_context_asks_for_details() {
+	let srv = util::setup(util::default).await;
+
+	let ctx = AuthContext::new(srv.cfg.clone(), Uuid::now_v7(), "", "").to_string();
+
+	let mut res = srv
+		.get(&format!("/authenticate/register?ctx={ctx}"))
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	assert_eq!(res.content_type(), "text/html");
+
+	let doc = util::doc(&mut res).await;
+
+	let mut forms = doc.select(css!("form"));
+	assert_eq!(1, forms.clone().count(), "page needs a registration form");
+	let form = forms.next().unwrap();
+
+	assert_eq!(
+		1,
+		form.select(css!("input[name='ctx']")).count(),
+		"form doesn't submit auth context"
+	);
+	assert_eq!(
+		1,
+		form.select(css!("input[name='email'][type='email']"))
+			.count(),
+		"form needs an email box"
+	);
+	assert_eq!(
+		1,
+		form.select(css!("input[name='password'][type='password']"))
+			.count(),
+		"form needs a password box"
+	);
+	assert_eq!(
+		1,
+		form.select(css!("input[name='password_confirmation'][type='password']"))
+			.count(),
+		"form needs a password confirmation box"
+	);
+}
+
+#[actix_rt::test]
+async fn password_page_links_to_registration() {
+	let srv = util::setup(util::default).await;
+
+	let ctx = AuthContext::new(srv.cfg.clone(), Uuid::now_v7(), "", "")
+		.with_principal(AuthContext::UNKNOWN_USER)
+		.with_pwhash(srv.cfg.dummy_pwhash())
+		.to_string();
+
+	let mut res = srv
+		.get(&format!("/authenticate/pw?ctx={ctx}"))
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+
+	let doc = util::doc(&mut res).await;
+	assert_eq!(
+		1,
+		doc.select(css!("a[href^='register?ctx=']")).count(),
+		"password page should offer registration"
+	);
+}
+
+#[actix_rt::test]
+async fn mismatched_passwords_are_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let ctx = AuthContext::new(srv.cfg.clone(), Uuid::now_v7(), "", "").to_string();
+
+	let res = srv
+		.post("/authenticate/submit_registration")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.as_str()),
+			("email", "jaime@example.com"),
+			("password", GOOD_PASSWORD),
+			("password_confirmation", "something else entirely"),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(302, res.status().as_u16());
+	assert_eq!(Some("password_mismatch".to_string()), error_from(&res));
+}
+
+#[actix_rt::test]
+async fn weak_passwords_are_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let ctx = AuthContext::new(srv.cfg.clone(), Uuid::now_v7(), "", "").to_string();
+
+	let res = srv
+		.post("/authenticate/submit_registration")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.as_str()),
+			("email", "jaime@example.com"),
+			("password", "hunter2"),
+			("password_confirmation", "hunter2"),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(302, res.status().as_u16());
+	assert_eq!(Some("weak_password".to_string()), error_from(&res));
+
+	assert!(
+		srv.db
+			.user()
+			.await
+			.expect("user")
+			.find_by_email("jaime@example.com")
+			.await
+			.is_err(),
+		"user should not have been created"
+	);
+}
+
+#[actix_rt::test]
+async fn invalid_email_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let ctx = AuthContext::new(srv.cfg.clone(), Uuid::now_v7(), "", "").to_string();
+
+	let res = srv
+		.post("/authenticate/submit_registration")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.as_str()),
+			("email", "Jaime Bloggs"),
+			("password", GOOD_PASSWORD),
+			("password_confirmation", GOOD_PASSWORD),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(302, res.status().as_u16());
+	assert_eq!(Some("invalid_email".to_string()), error_from(&res));
+}
+
+#[actix_rt::test]
+async fn existing_account_is_not_overwritten() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	let existing = util::create_user(&srv.db).await;
+
+	let ctx = AuthContext::new(srv.cfg.clone(), Uuid::now_v7(), "", "").to_string();
+
+	let res = srv
+		.post("/authenticate/submit_registration")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.as_str()),
+			("email", "jaime@example.com"),
+			("password", GOOD_PASSWORD),
+			("password_confirmation", GOOD_PASSWORD),
+		])
+		.await
+		.unwrap();
+
+	// The person registering can't tell that there was already an account...
+	assert_eq!(302, res.status().as_u16());
+	assert_eq!(None, error_from(&res));
+	assert_eq!(Some("check_email".to_string()), param_from(&res, "status"));
+
+	// ... but the account's owner gets told
+	let messages = mail.messages();
+	assert_eq!(1, messages.len(), "expected exactly one email");
+	assert!(
+		messages[0].contains("To: jaime@example.com"),
+		"email sent to wrong address"
+	);
+	assert!(
+		messages[0].contains("there is already an account"),
+		"wrong email sent"
+	);
+
+	let user = srv
+		.db
+		.user()
+		.await
+		.expect("user")
+		.find_by_email("jaime@example.com")
+		.await
+		.expect("user to still exist");
+	assert_eq!(existing.pwhash(), user.pwhash());
+}
+
+#[actix_rt::test]
+async fn successful_registration_creates_user_and_asks_to_check_email() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	let ctx = AuthContext::new(srv.cfg.clone(), Uuid::now_v7(), "", "").to_string();
+
+	let res = srv
+		.post("/authenticate/submit_registration")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.as_str()),
+			("email", "jaime@example.com"),
+			("password", GOOD_PASSWORD),
+			("password_confirmation", GOOD_PASSWORD),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(302, res.status().as_u16());
+	assert_eq!(None, error_from(&res));
+	assert_eq!(Some("check_email".to_string()), param_from(&res, "status"));
+
+	let user = srv
+		.db
+		.user()
+		.await
+		.expect("user")
+		.find_by_email("jaime@example.com")
+		.await
+		.expect("user was not saved in DB");
+	assert!(bcrypt::verify(GOOD_PASSWORD, user.pwhash()).expect("valid pwhash"));
+
+	let messages = mail.messages();
+	assert_eq!(1, messages.len(), "expected exactly one email");
+	assert!(
+		messages[0].contains("authenticate/verify_email"),
+		"verification email not sent"
+	);
+
+	let location = res.headers().get("location").unwrap().to_str().unwrap();
+	let mut res = srv
+		.get(&location[location.find("/authenticate").unwrap()..])
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+	let doc = util::doc(&mut res).await;
+	assert_eq!(
+		1,
+		doc.select(css!("p#check-email")).count(),
+		"page should ask the user to check their email"
+	);
+}
@@ -0,0 +1,335 @@
+use actix_web::HttpMessage as _;
+use authul_frontend::AuthContext;
+use std::time::Duration;
+use time::OffsetDateTime;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{css, util};
+use authul_util::Base64Uuid;
+
+const GOOD_PASSWORD: &str = "Wombat-Velocity-Tangerine-42";
+
+async fn reset_token(
+	srv: &util::ConfiguredTestServer,
+	user: authul_db::model::User,
+) -> authul_db::model::PasswordResetToken {
+	srv.db
+		.password_reset_token()
+		.await
+		.expect("password_reset_token")
+		.new()
+		.with_user(user)
+		.save()
+		.await
+		.expect("PasswordResetToken")
+}
+
+async fn find_user(srv: &util::ConfiguredTestServer) -> authul_db::model::User {
+	srv.db
+		.user()
+		.await
+		.expect("user")
+		.find_by_email("jaime@example.com")
+		.await
+		.expect("user")
+}
+
+async fn request_reset(srv: &util::ConfiguredTestServer, ctx: &str) -> Url {
+	let res = srv
+		.post("/authenticate/request_password_reset")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[("ctx", ctx)])
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap()
+}
+
+async fn submit_reset(
+	srv: &util::ConfiguredTestServer,
+	token: &str,
+	password: &str,
+	confirmation: &str,
+) -> Url {
+	let res = srv
+		.post("/authenticate/submit_password_reset")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("token", token),
+			("password", password),
+			("password_confirmation", confirmation),
+		])
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap()
+}
+
+#[actix_rt::test]
+async fn password_page_offers_reset_when_mail_is_configured() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	let ctx = AuthContext::new(srv.cfg.clone(), Uuid::now_v7(), "", "")
+		.with_principal(AuthContext::UNKNOWN_USER)
+		.with_pwhash(srv.cfg.dummy_pwhash())
+		.to_string();
+
+	let mut res = srv
+		.get(&format!("/authenticate/pw?ctx={ctx}"))
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+
+	let doc = util::doc(&mut res).await;
+	assert_eq!(
+		1,
+		doc.select(css!("form#forgot-password-form input[name='ctx']"))
+			.count(),
+		"password page should offer a password reset"
+	);
+}
+
+#[actix_rt::test]
+async fn password_page_does_not_offer_reset_without_mail() {
+	let srv = util::setup(util::default).await;
+
+	let ctx = AuthContext::new(srv.cfg.clone(), Uuid::now_v7(), "", "")
+		.with_principal(AuthContext::UNKNOWN_USER)
+		.with_pwhash(srv.cfg.dummy_pwhash())
+		.to_string();
+
+	let mut res = srv
+		.get(&format!("/authenticate/pw?ctx={ctx}"))
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+
+	let doc = util::doc(&mut res).await;
+	assert_eq!(
+		0,
+		doc.select(css!("form#forgot-password-form")).count(),
+		"password reset offered when it can't work"
+	);
+}
+
+#[actix_rt::test]
+async fn request_for_known_user_sends_reset_link() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	let user = util::create_user(&srv.db).await;
+	let ctx = AuthContext::new(srv.cfg.clone(), Uuid::now_v7(), "", "")
+		.with_principal(*user.principal().id())
+		.with_pwhash(user.pwhash())
+		.with_email(user.email())
+		.to_string();
+
+	let redirect_url = request_reset(&srv, &ctx).await;
+	assert_eq!("/authenticate/pw", redirect_url.path());
+	assert_eq!(
+		Some("reset_sent".to_string()),
+		util::param(&redirect_url, "status")
+	);
+
+	let messages = mail.wait_for_messages(1).await;
+	assert!(
+		messages[0].contains("To: jaime@example.com"),
+		"email sent to wrong address"
+	);
+	assert!(
+		messages[0].contains("/authenticate/reset_password?token="),
+		"email does not contain reset link"
+	);
+}
+
+#[actix_rt::test]
+async fn request_for_unknown_user_looks_the_same_but_sends_nothing() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	let ctx = AuthContext::new(srv.cfg.clone(), Uuid::now_v7(), "", "")
+		.with_principal(AuthContext::UNKNOWN_USER)
+		.with_pwhash(srv.cfg.dummy_pwhash())
+		.to_string();
+
+	let redirect_url = request_reset(&srv, &ctx).await;
+	assert_eq!("/authenticate/pw", redirect_url.path());
+	assert_eq!(
+		Some("reset_sent".to_string()),
+		util::param(&redirect_url, "status")
+	);
+	assert_eq!(None, util::param(&redirect_url, "err"));
+
+	actix_rt::time::sleep(Duration::from_millis(500)).await;
+	assert!(mail.messages().is_empty(), "email sent for unknown user");
+}
+
+#[actix_rt::test]
+async fn reset_page_asks_for_new_password() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	let user = util::create_user(&srv.db).await;
+	let token = reset_token(&srv, user).await;
+
+	let mut res = srv
+		.get(&format!(
+			"/authenticate/reset_password?token={}",
+			token.id().to_base64()
+		))
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	assert_eq!(res.content_type(), "text/html");
+
+	let doc = util::doc(&mut res).await;
+	let mut forms = doc.select(css!("form#reset-password-form"));
+	assert_eq!(1, forms.clone().count(), "page needs a reset form");
+	let form = forms.next().unwrap();
+
+	assert_eq!(
+		1,
+		form.select(css!("input[name='token']")).count(),
+		"form doesn't submit token"
+	);
+	assert_eq!(
+		1,
+		form.select(css!("input[name='password'][type='password']"))
+			.count(),
+		"form needs a password box"
+	);
+	assert_eq!(
+		1,
+		form.select(css!("input[name='password_confirmation'][type='password']"))
+			.count(),
+		"form needs a password confirmation box"
+	);
+}
+
+#[actix_rt::test]
+async fn valid_token_changes_password() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	let user = util::create_user(&srv.db).await;
+	let token = reset_token(&srv, user).await.id().to_base64();
+
+	let redirect_url = submit_reset(&srv, &token, GOOD_PASSWORD, GOOD_PASSWORD).await;
+	assert_eq!("/authenticate/reset_password", redirect_url.path());
+	assert_eq!(
+		Some("reset".to_string()),
+		util::param(&redirect_url, "status")
+	);
+
+	let user = find_user(&srv).await;
+	assert!(bcrypt::verify(GOOD_PASSWORD, user.pwhash()).expect("valid pwhash"));
+	assert!(
+		*user.email_verified(),
+		"completing a reset should verify the email address"
+	);
+}
+
+#[actix_rt::test]
+async fn token_can_only_be_used_once() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	let user = util::create_user(&srv.db).await;
+	let token = reset_token(&srv, user).await.id().to_base64();
+
+	submit_reset(&srv, &token, GOOD_PASSWORD, GOOD_PASSWORD).await;
+	let redirect_url = submit_reset(
+		&srv,
+		&token,
+		"Another-Perfectly-Fine-Password-99",
+		"Another-Perfectly-Fine-Password-99",
+	)
+	.await;
+	assert_eq!(
+		Some("invalid_token".to_string()),
+		util::param(&redirect_url, "err")
+	);
+
+	let user = find_user(&srv).await;
+	assert!(bcrypt::verify(GOOD_PASSWORD, user.pwhash()).expect("valid pwhash"));
+}
+
+#[actix_rt::test]
+async fn expired_token_is_rejected() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	let user = util::create_user(&srv.db).await;
+	let token = srv
+		.db
+		.password_reset_token()
+		.await
+		.expect("password_reset_token")
+		.new()
+		.with_user(user)
+		.with_valid_before(OffsetDateTime::now_utc() - Duration::from_secs(1))
+		.save()
+		.await
+		.expect("PasswordResetToken")
+		.id()
+		.to_base64();
+
+	let redirect_url = submit_reset(&srv, &token, GOOD_PASSWORD, GOOD_PASSWORD).await;
+	assert_eq!(
+		Some("invalid_token".to_string()),
+		util::param(&redirect_url, "err")
+	);
+
+	let user = find_user(&srv).await;
+	assert!(bcrypt::verify("hunter2", user.pwhash()).expect("valid pwhash"));
+}
+
+#[actix_rt::test]
+async fn weak_password_is_rejected_without_using_up_token() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	let user = util::create_user(&srv.db).await;
+	let token = reset_token(&srv, user).await.id().to_base64();
+
+	let redirect_url = submit_reset(&srv, &token, "hunter3", "hunter3").await;
+	assert_eq!(
+		Some("weak_password".to_string()),
+		util::param(&redirect_url, "err")
+	);
+	assert_eq!(Some(token.clone()), util::param(&redirect_url, "token"));
+
+	let redirect_url = submit_reset(&srv, &token, GOOD_PASSWORD, GOOD_PASSWORD).await;
+	assert_eq!(
+		Some("reset".to_string()),
+		util::param(&redirect_url, "status")
+	);
+}
+
+#[actix_rt::test]
+async fn mismatched_passwords_are_rejected() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	let user = util::create_user(&srv.db).await;
+	let token = reset_token(&srv, user).await.id().to_base64();
+
+	let redirect_url = submit_reset(&srv, &token, GOOD_PASSWORD, "something else entirely").await;
+	assert_eq!(
+		Some("password_mismatch".to_string()),
+		util::param(&redirect_url, "err")
+	);
+}
@@ -0,0 +1,562 @@
+use actix_web::HttpMessage as _;
+use authul_frontend::AuthContext;
+use time::OffsetDateTime;
+use totp_rs::{Algorithm, Secret, TOTP};
+use url::Url;
+
+use crate::{css, util};
+
+const SECRET: &[u8] = b"twenty bytes, please";
+
+async fn oidc_client(
+	srv: &util::ConfiguredTestServer,
+	require_mfa: bool,
+) -> authul_db::model::OidcClient {
+	util::oidc_client_with(&srv.db, "https://example.com/cb", |c| {
+		c.update_require_mfa(require_mfa);
+	})
+	.await
+}
+
+async fn enrol(
+	srv: &util::ConfiguredTestServer,
+	user: &authul_db::model::User,
+) -> authul_db::model::TotpCredential {
+	let principal = srv
+		.db
+		.principal()
+		.await
+		.expect("principal")
+		.find(user.principal().id())
+		.await
+		.expect("Principal");
+
+	// Users who've been enrolled for a while have recovery codes, and don't get asked to save a
+	// new set when they sign in
+	srv.db
+		.recovery_code()
+		.await
+		.expect("recovery_code")
+		.replace_all(&principal, [b"not a real hash".to_vec()])
+		.await
+		.expect("replace_all");
+
+	srv.db
+		.totp_credential()
+		.await
+		.expect("totp_credential")
+		.new()
+		.with_principal(principal)
+		.with_secret(
+			srv.cfg
+				.totp_secret_strong_box()
+				.encrypt(SECRET, b"")
+				.expect("encrypt"),
+		)
+		.save()
+		.await
+		.expect("TotpCredential")
+}
+
+fn current_code(secret: &[u8]) -> String {
+	TOTP::new(
+		Algorithm::SHA1,
+		6,
+		0,
+		30,
+		secret.to_vec(),
+		None,
+		"test".to_string(),
+	)
+	.expect("TOTP")
+	.generate_current()
+	.expect("code")
+}
+
+fn authenticated_ctx(
+	srv: &util::ConfiguredTestServer,
+	oidc_client: &authul_db::model::OidcClient,
+	user: &authul_db::model::User,
+) -> AuthContext {
+	AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.with_principal(*user.principal().id())
+	.with_email(user.email())
+	.with_auth_time(OffsetDateTime::now_utc().unix_timestamp())
+}
+
+async fn post(srv: &util::ConfiguredTestServer, path: &str, form: &[(&str, &str)]) -> Url {
+	let res = srv
+		.post(path)
+		.insert_header(("accept", "text/html"))
+		.send_form(form)
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap()
+}
+
+#[actix_rt::test]
+async fn enrolled_user_is_asked_for_code_after_password() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash())
+	.with_email(user.email())
+	.to_string();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_password",
+		&[("ctx", ctx.as_str()), ("password", "hunter2")],
+	)
+	.await;
+
+	assert_eq!("/authenticate/totp", redirect_url.path());
+	assert_eq!(None, util::param(&redirect_url, "code"));
+
+	let mut res = srv
+		.get(&redirect_url[url::Position::BeforePath..])
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	assert_eq!(res.content_type(), "text/html");
+
+	let doc = util::doc(&mut res).await;
+	assert_eq!(
+		1,
+		doc.select(css!("form#totp-form input[name='code']"))
+			.count(),
+		"page needs a code form"
+	);
+}
+
+#[actix_rt::test]
+async fn correct_code_issues_auth_code() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user).to_string();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_totp",
+		&[("ctx", ctx.as_str()), ("code", &current_code(SECRET))],
+	)
+	.await;
+
+	assert_eq!("example.com", redirect_url.host_str().unwrap());
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+}
+
+#[actix_rt::test]
+async fn wrong_code_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user).to_string();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_totp",
+		&[
+			("ctx", ctx.as_str()),
+			("code", &current_code(b"some other secret!!!")),
+		],
+	)
+	.await;
+
+	assert_eq!("/authenticate/totp", redirect_url.path());
+	assert_eq!(
+		Some("wrong_code".to_string()),
+		util::param(&redirect_url, "err")
+	);
+}
+
+#[actix_rt::test]
+async fn repeated_wrong_codes_lock_the_credential() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user).to_string();
+	let wrong_code = current_code(b"some other secret!!!");
+
+	for _ in 0..5 {
+		let redirect_url = post(
+			&srv,
+			"/authenticate/submit_totp",
+			&[("ctx", ctx.as_str()), ("code", &wrong_code)],
+		)
+		.await;
+		assert_eq!(
+			Some("wrong_code".to_string()),
+			util::param(&redirect_url, "err")
+		);
+	}
+
+	// Even the right code doesn't get anyone in while the credential is locked
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_totp",
+		&[("ctx", ctx.as_str()), ("code", &current_code(SECRET))],
+	)
+	.await;
+	assert_eq!("/authenticate/totp", redirect_url.path());
+	assert_eq!(
+		Some("locked_out".to_string()),
+		util::param(&redirect_url, "err")
+	);
+	assert!(
+		util::param(&redirect_url, "code").is_none(),
+		"code issued anyway"
+	);
+}
+
+#[actix_rt::test]
+async fn right_code_resets_the_wrong_code_count() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user).to_string();
+	let wrong_code = current_code(b"some other secret!!!");
+
+	for _ in 0..4 {
+		post(
+			&srv,
+			"/authenticate/submit_totp",
+			&[("ctx", ctx.as_str()), ("code", &wrong_code)],
+		)
+		.await;
+	}
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_totp",
+		&[("ctx", ctx.as_str()), ("code", &current_code(SECRET))],
+	)
+	.await;
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_totp",
+		&[("ctx", ctx.as_str()), ("code", &wrong_code)],
+	)
+	.await;
+	assert_eq!(
+		Some("wrong_code".to_string()),
+		util::param(&redirect_url, "err")
+	);
+}
+
+#[actix_rt::test]
+async fn code_cannot_be_replayed() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user).to_string();
+	let code = current_code(SECRET);
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_totp",
+		&[("ctx", ctx.as_str()), ("code", &code)],
+	)
+	.await;
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_totp",
+		&[("ctx", ctx.as_str()), ("code", &code)],
+	)
+	.await;
+	assert_eq!(
+		Some("wrong_code".to_string()),
+		util::param(&redirect_url, "err")
+	);
+}
+
+#[actix_rt::test]
+async fn code_without_primary_authentication_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	enrol(&srv, &user).await;
+	// This is what the context looks like after the email has been submitted, but before the
+	// password has been checked
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash())
+	.to_string();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_totp",
+		&[("ctx", ctx.as_str()), ("code", &current_code(SECRET))],
+	)
+	.await;
+
+	assert_eq!(
+		Some("invalid_context".to_string()),
+		util::param(&redirect_url, "err")
+	);
+	assert_eq!(None, util::param(&redirect_url, "code"));
+}
+
+#[actix_rt::test]
+async fn client_requiring_mfa_makes_user_enrol() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, true).await;
+	let user = util::create_user(&srv.db).await;
+
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash())
+	.with_email(user.email())
+	.to_string();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_password",
+		&[("ctx", ctx.as_str()), ("password", "hunter2")],
+	)
+	.await;
+	assert_eq!("/authenticate/totp/enrol", redirect_url.path());
+
+	let mut res = srv
+		.get(&redirect_url[url::Position::BeforePath..])
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+
+	let doc = util::doc(&mut res).await;
+	let secret = doc
+		.select(css!("code#totp-secret"))
+		.next()
+		.expect("page needs to show the secret")
+		.text()
+		.collect::<String>();
+	let secret = Secret::Encoded(secret).to_bytes().expect("base32 secret");
+	assert_eq!(
+		1,
+		doc.select(css!("form#totp-enrol-form input[name='code']"))
+			.count(),
+		"page needs a code form"
+	);
+
+	let ctx = util::param(&redirect_url, "ctx").expect("ctx");
+	let redirect_url = post(
+		&srv,
+		"/authenticate/confirm_totp_enrolment",
+		&[("ctx", ctx.as_str()), ("code", &current_code(&secret))],
+	)
+	.await;
+	assert_eq!("/authenticate/recovery_codes", redirect_url.path());
+	assert_eq!(None, util::param(&redirect_url, "code"));
+
+	let cred = srv
+		.db
+		.totp_credential()
+		.await
+		.expect("totp_credential")
+		.find_by_principal_id(user.principal().id())
+		.await
+		.expect("find_by_principal_id")
+		.expect("no credential was saved");
+	assert_ne!(&secret, cred.secret(), "secret stored in plaintext");
+	assert_eq!(
+		secret,
+		srv.cfg
+			.totp_secret_strong_box()
+			.decrypt(cred.secret(), b"")
+			.expect("decrypt")
+	);
+}
+
+#[actix_rt::test]
+async fn user_can_choose_to_enrol() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user).to_string();
+
+	let mut res = srv
+		.get(format!("/authenticate/totp/enrol?ctx={ctx}"))
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+	let doc = util::doc(&mut res).await;
+	assert_eq!(
+		1,
+		doc.select(css!("form#totp-start-form")).count(),
+		"page needs a form to start enrolment"
+	);
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/start_totp_enrolment",
+		&[("ctx", ctx.as_str())],
+	)
+	.await;
+	assert_eq!("/authenticate/totp/enrol", redirect_url.path());
+	assert_eq!(None, util::param(&redirect_url, "err"));
+
+	let secret = AuthContext::from_str(&util::param(&redirect_url, "ctx").expect("ctx"), &srv.cfg)
+		.expect("AuthContext")
+		.totp_secret()
+		.expect("no TOTP secret in context")
+		.clone();
+
+	let ctx = util::param(&redirect_url, "ctx").expect("ctx");
+	let redirect_url = post(
+		&srv,
+		"/authenticate/confirm_totp_enrolment",
+		&[("ctx", ctx.as_str()), ("code", &current_code(&secret))],
+	)
+	.await;
+	assert_eq!("/authenticate/recovery_codes", redirect_url.path());
+	assert!(srv
+		.db
+		.totp_credential()
+		.await
+		.expect("totp_credential")
+		.find_by_principal_id(user.principal().id())
+		.await
+		.expect("find_by_principal_id")
+		.is_some());
+}
+
+#[actix_rt::test]
+async fn choosing_to_enrol_needs_a_recent_sign_in() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user)
+		.with_auth_time(OffsetDateTime::now_utc().unix_timestamp() - 3600)
+		.to_string();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/start_totp_enrolment",
+		&[("ctx", ctx.as_str())],
+	)
+	.await;
+	assert_eq!(
+		Some("invalid_context".to_string()),
+		util::param(&redirect_url, "err")
+	);
+
+	// Nor can someone who already has a second factor swap theirs out this way
+	enrol(&srv, &user).await;
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user).to_string();
+	let redirect_url = post(
+		&srv,
+		"/authenticate/start_totp_enrolment",
+		&[("ctx", ctx.as_str())],
+	)
+	.await;
+	assert_eq!(
+		Some("invalid_context".to_string()),
+		util::param(&redirect_url, "err")
+	);
+}
+
+#[actix_rt::test]
+async fn enrolment_cannot_replace_existing_credential() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = oidc_client(&srv, true).await;
+	let user = util::create_user(&srv.db).await;
+	let cred = enrol(&srv, &user).await;
+
+	let other_secret = b"an attacker's secret".to_vec();
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user)
+		.with_totp_secret(other_secret.clone())
+		.to_string();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/confirm_totp_enrolment",
+		&[
+			("ctx", ctx.as_str()),
+			("code", &current_code(&other_secret)),
+		],
+	)
+	.await;
+	assert_eq!("/authenticate/totp", redirect_url.path());
+	assert_eq!(None, util::param(&redirect_url, "code"));
+
+	let saved = srv
+		.db
+		.totp_credential()
+		.await
+		.expect("totp_credential")
+		.find_by_principal_id(user.principal().id())
+		.await
+		.expect("find_by_principal_id")
+		.expect("credential went missing");
+	assert_eq!(cred.id(), saved.id());
+	assert_eq!(
+		SECRET,
+		srv.cfg
+			.totp_secret_strong_box()
+			.decrypt(saved.secret(), b"")
+			.expect("decrypt")
+	);
+}
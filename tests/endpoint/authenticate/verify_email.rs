@@ -0,0 +1,278 @@
+use actix_web::HttpMessage as _;
+use authul_frontend::AuthContext;
+use authul_util::Base64Uuid;
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use serde_json::Value;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{css, util};
+
+const GOOD_PASSWORD: &str = "Wombat-Velocity-Tangerine-42";
+
+async fn oidc_client(srv: &util::ConfiguredTestServer) -> authul_db::model::OidcClient {
+	util::oidc_client_with(&srv.db, "https://example.com/cb", |c| {
+		c.update_include_attrs_claim(true);
+	})
+	.await
+}
+
+async fn register(srv: &util::ConfiguredTestServer) -> actix_test::ClientResponse {
+	let oidc_client = oidc_client(srv).await;
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.to_string();
+
+	let res = srv
+		.post("/authenticate/submit_registration")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.as_str()),
+			("email", "jaime@example.com"),
+			("password", GOOD_PASSWORD),
+			("password_confirmation", GOOD_PASSWORD),
+		])
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	res
+}
+
+fn verification_link(msg: &str) -> Url {
+	Url::parse(
+		msg.lines()
+			.find(|l| l.starts_with("http"))
+			.expect("no link in verification email"),
+	)
+	.expect("invalid link in verification email")
+}
+
+fn token_from(link: &Url) -> String {
+	link.query_pairs()
+		.find(|(k, _)| k == "token")
+		.map(|(_, v)| v.to_string())
+		.expect("no token in verification link")
+}
+
+async fn confirm(srv: &util::ConfiguredTestServer, token: &str) -> Url {
+	let res = srv
+		.post("/authenticate/confirm_email")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[("token", token)])
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap()
+}
+
+fn id_token_attrs(token: &str) -> Vec<Value> {
+	serde_json::from_slice::<Value>(
+		&BASE64_URL_SAFE_NO_PAD
+			.decode(token.split('.').nth(1).expect("payload part"))
+			.expect("base64 payload"),
+	)
+	.expect("json payload")
+	.get("attrs")
+	.expect("attrs")
+	.as_array()
+	.expect("attrs array")
+	.clone()
+}
+
+/// Sign in with the password the user registered with, and return the attributes that the client
+/// gets told about
+async fn sign_in(srv: &util::ConfiguredTestServer) -> Vec<Value> {
+	let oidc_client = oidc_client(srv).await;
+	let user = srv
+		.db
+		.user()
+		.await
+		.expect("user")
+		.find_by_email("jaime@example.com")
+		.await
+		.expect("user");
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash())
+	.with_email(user.email())
+	.to_string();
+
+	let res = srv
+		.post("/authenticate/submit_password")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[("ctx", ctx.as_str()), ("password", GOOD_PASSWORD)])
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	let redirect_url =
+		Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+	let code = redirect_url
+		.query_pairs()
+		.find(|(k, _)| k == "code")
+		.map(|(_, v)| v.to_string())
+		.expect("code");
+
+	let token = srv
+		.db
+		.oidc_token()
+		.await
+		.expect("oidc_token")
+		.find(&Uuid::from_base64(&code).expect("uuid"))
+		.await
+		.expect("token not found");
+
+	id_token_attrs(token.token())
+}
+
+fn has_attr(attrs: &[Value], kind: &str, value: &str) -> bool {
+	attrs.iter().any(|a| {
+		a.get("kind") == Some(&Value::from(kind)) && a.get("value") == Some(&Value::from(value))
+	})
+}
+
+#[actix_rt::test]
+async fn registration_sends_verification_email() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	register(&srv).await;
+
+	let messages = mail.messages();
+	assert_eq!(1, messages.len(), "expected exactly one email");
+	assert!(
+		messages[0].contains("To: jaime@example.com"),
+		"email sent to wrong address"
+	);
+
+	let link = verification_link(&messages[0]);
+	assert_eq!(
+		srv.base_url()
+			.join("authenticate/verify_email")
+			.unwrap()
+			.as_str(),
+		&link[..url::Position::AfterPath]
+	);
+}
+
+#[actix_rt::test]
+async fn registered_email_starts_unverified() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	register(&srv).await;
+
+	let attrs = sign_in(&srv).await;
+	assert!(has_attr(&attrs, "Email", "jaime@example.com"));
+	assert!(!has_attr(&attrs, "VerifiedEmail", "jaime@example.com"));
+}
+
+#[actix_rt::test]
+async fn verification_page_asks_for_confirmation() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	register(&srv).await;
+	let link = verification_link(&mail.messages()[0]);
+
+	let mut res = srv
+		.get(&link[url::Position::BeforePath..])
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	assert_eq!(res.content_type(), "text/html");
+
+	let doc = util::doc(&mut res).await;
+	assert_eq!(
+		1,
+		doc.select(css!("form#verify-email-form input[name='token']"))
+			.count(),
+		"page needs a confirmation form"
+	);
+
+	let user = srv
+		.db
+		.user()
+		.await
+		.expect("user")
+		.find_by_email("jaime@example.com")
+		.await
+		.expect("user");
+	assert!(
+		!*user.email_verified(),
+		"merely visiting the link should not verify the address"
+	);
+}
+
+#[actix_rt::test]
+async fn confirming_verifies_email() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	register(&srv).await;
+	let token = token_from(&verification_link(&mail.messages()[0]));
+
+	let redirect_url = confirm(&srv, &token).await;
+	assert_eq!("/authenticate/verify_email", redirect_url.path());
+	assert_eq!(
+		Some("verified".to_string()),
+		redirect_url
+			.query_pairs()
+			.find(|(k, _)| k == "status")
+			.map(|(_, v)| v.to_string())
+	);
+
+	let user = srv
+		.db
+		.user()
+		.await
+		.expect("user")
+		.find_by_email("jaime@example.com")
+		.await
+		.expect("user");
+	assert!(*user.email_verified(), "email was not marked as verified");
+}
+
+#[actix_rt::test]
+async fn bogus_token_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let redirect_url = confirm(&srv, "bm90IGEgdG9rZW4").await;
+	assert_eq!(
+		Some("invalid_token".to_string()),
+		redirect_url
+			.query_pairs()
+			.find(|(k, _)| k == "err")
+			.map(|(_, v)| v.to_string())
+	);
+}
+
+#[actix_rt::test]
+async fn verified_user_gets_verified_email_attribute() {
+	let mail = util::MailDrop::new();
+	let srv = util::setup(mail.cfg()).await;
+
+	register(&srv).await;
+	let token = token_from(&verification_link(&mail.messages()[0]));
+	confirm(&srv, &token).await;
+
+	let attrs = sign_in(&srv).await;
+	assert!(
+		has_attr(&attrs, "VerifiedEmail", "jaime@example.com"),
+		"attributes missing VerifiedEmail"
+	);
+}
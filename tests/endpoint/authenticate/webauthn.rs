@@ -0,0 +1,686 @@
+use authul_frontend::AuthContext;
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD as BASE64};
+use ciborium::value::Value;
+use p256::ecdsa::{signature::Signer as _, Signature, SigningKey};
+use sha2::{Digest as _, Sha256};
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::{css, util};
+
+/// Just enough of an authenticator to go through the ceremonies with, so we don't need a real
+/// security key plugged in to run the tests
+struct SoftAuthenticator {
+	key: SigningKey,
+	credential_id: Vec<u8>,
+	sign_count: u32,
+}
+
+impl SoftAuthenticator {
+	fn new() -> Self {
+		Self {
+			key: SigningKey::random(&mut rand::rngs::OsRng),
+			credential_id: b"soft authenticator credential".to_vec(),
+			sign_count: 0,
+		}
+	}
+
+	fn cose_key(&self) -> Vec<u8> {
+		let point = self.key.verifying_key().to_encoded_point(false);
+		let key = Value::Map(vec![
+			(Value::from(1_i64), Value::from(2_i64)),
+			(Value::from(3_i64), Value::from(-7_i64)),
+			(Value::from(-1_i64), Value::from(1_i64)),
+			(
+				Value::from(-2_i64),
+				Value::Bytes(point.x().unwrap().to_vec()),
+			),
+			(
+				Value::from(-3_i64),
+				Value::Bytes(point.y().unwrap().to_vec()),
+			),
+		]);
+
+		let mut out = vec![];
+		ciborium::into_writer(&key, &mut out).unwrap();
+		out
+	}
+
+	fn authenticator_data(
+		&self,
+		srv: &util::ConfiguredTestServer,
+		flags: u8,
+		attested: bool,
+	) -> Vec<u8> {
+		let mut data = Sha256::digest(srv.cfg.base_url().host_str().unwrap()).to_vec();
+		data.push(if attested { flags | 0x40 } else { flags });
+		data.extend_from_slice(&self.sign_count.to_be_bytes());
+
+		if attested {
+			data.extend_from_slice(&[0u8; 16]);
+			data.extend_from_slice(
+				&u16::try_from(self.credential_id.len())
+					.unwrap()
+					.to_be_bytes(),
+			);
+			data.extend_from_slice(&self.credential_id);
+			data.extend_from_slice(&self.cose_key());
+		}
+
+		data
+	}
+
+	/// What `navigator.credentials.create()` would hand back
+	fn create(
+		&self,
+		srv: &util::ConfiguredTestServer,
+		challenge: &[u8],
+	) -> Vec<(&'static str, String)> {
+		let client_data_json = client_data_json("webauthn.create", challenge, &origin(srv));
+
+		let attestation_object = Value::Map(vec![
+			(Value::from("fmt"), Value::from("none")),
+			(Value::from("attStmt"), Value::Map(vec![])),
+			(
+				Value::from("authData"),
+				Value::Bytes(self.authenticator_data(srv, 0x05, true)),
+			),
+		]);
+		let mut att = vec![];
+		ciborium::into_writer(&attestation_object, &mut att).unwrap();
+
+		vec![
+			("client_data_json", BASE64.encode(client_data_json)),
+			("attestation_object", BASE64.encode(att)),
+		]
+	}
+
+	/// What `navigator.credentials.get()` would hand back
+	fn get(
+		&mut self,
+		srv: &util::ConfiguredTestServer,
+		challenge: &[u8],
+		origin: &str,
+		flags: u8,
+	) -> Vec<(&'static str, String)> {
+		self.sign_count += 1;
+
+		let client_data_json = client_data_json("webauthn.get", challenge, origin);
+		let authenticator_data = self.authenticator_data(srv, flags, false);
+
+		let mut signed = authenticator_data.clone();
+		signed.extend_from_slice(&Sha256::digest(&client_data_json));
+		let signature: Signature = self.key.sign(&signed);
+
+		vec![
+			("credential_id", BASE64.encode(&self.credential_id)),
+			("client_data_json", BASE64.encode(client_data_json)),
+			("authenticator_data", BASE64.encode(authenticator_data)),
+			("signature", BASE64.encode(signature.to_der().as_bytes())),
+		]
+	}
+}
+
+fn origin(srv: &util::ConfiguredTestServer) -> String {
+	srv.cfg.base_url().origin().ascii_serialization()
+}
+
+fn client_data_json(kind: &str, challenge: &[u8], origin: &str) -> Vec<u8> {
+	serde_json::json!({
+		"type": kind,
+		"challenge": BASE64.encode(challenge),
+		"origin": origin,
+		"crossOrigin": false,
+	})
+	.to_string()
+	.into_bytes()
+}
+
+async fn register(
+	srv: &util::ConfiguredTestServer,
+	user: &authul_db::model::User,
+	authenticator: &SoftAuthenticator,
+) -> authul_db::model::WebauthnCredential {
+	let principal = srv
+		.db
+		.principal()
+		.await
+		.expect("principal")
+		.find(user.principal().id())
+		.await
+		.expect("Principal");
+
+	srv.db
+		.recovery_code()
+		.await
+		.expect("recovery_code")
+		.replace_all(&principal, [b"not a real hash".to_vec()])
+		.await
+		.expect("replace_all");
+
+	srv.db
+		.webauthn_credential()
+		.await
+		.expect("webauthn_credential")
+		.new()
+		.with_principal(principal)
+		.with_credential_id(authenticator.credential_id.clone())
+		.with_public_key(authenticator.cose_key())
+		.save()
+		.await
+		.expect("WebauthnCredential")
+}
+
+/// Record a challenge, the way the server does when it renders a ceremony
+async fn challenge(srv: &util::ConfiguredTestServer) -> Vec<u8> {
+	let challenge = rand::random::<[u8; 32]>().to_vec();
+
+	srv.db
+		.webauthn_challenge()
+		.await
+		.expect("webauthn_challenge")
+		.new()
+		.with_challenge(challenge.clone())
+		.save()
+		.await
+		.expect("WebauthnChallenge");
+
+	challenge
+}
+
+fn authenticated_ctx(
+	srv: &util::ConfiguredTestServer,
+	oidc_client: &authul_db::model::OidcClient,
+	user: &authul_db::model::User,
+) -> AuthContext {
+	AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.with_principal(*user.principal().id())
+	.with_email(user.email())
+	.with_auth_time(OffsetDateTime::now_utc().unix_timestamp())
+}
+
+async fn post(srv: &util::ConfiguredTestServer, path: &str, form: &[(&str, String)]) -> Url {
+	let res = srv
+		.post(path)
+		.insert_header(("accept", "text/html"))
+		.send_form(form)
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap()
+}
+
+fn with_ctx(
+	ctx: &AuthContext,
+	mut form: Vec<(&'static str, String)>,
+) -> Vec<(&'static str, String)> {
+	form.push(("ctx", ctx.to_string()));
+	form
+}
+
+/// Load up the registration page, and return the context (with the ceremony's challenge in it) and
+/// the ceremony options, if there's a ceremony on offer
+async fn registration_ceremony(
+	srv: &util::ConfiguredTestServer,
+	ctx: &AuthContext,
+) -> Option<(AuthContext, serde_json::Value)> {
+	let mut res = srv
+		.get(&format!("/authenticate/webauthn/register?ctx={ctx}"))
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+
+	let doc = util::doc(&mut res).await;
+	let button = doc
+		.select(css!(
+			"button#webauthn-register-button[data-webauthn='create']"
+		))
+		.next()?;
+
+	Some((
+		AuthContext::from_str(
+			button.value().attr("data-webauthn-ctx").expect("no ctx"),
+			&srv.cfg,
+		)
+		.expect("cannot decrypt ctx"),
+		serde_json::from_str(
+			button
+				.value()
+				.attr("data-webauthn-options")
+				.expect("no ceremony options"),
+		)
+		.expect("ceremony options are not JSON"),
+	))
+}
+
+#[actix_rt::test]
+async fn authenticate_page_offers_passkey_sign_in() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.to_string();
+
+	let mut res = srv
+		.get(&format!("/authenticate?ctx={ctx}"))
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+
+	let doc = util::doc(&mut res).await;
+	let button = doc
+		.select(css!("button#passkey-button[data-webauthn='get']"))
+		.next()
+		.expect("page needs a passkey button");
+
+	let options: serde_json::Value = serde_json::from_str(
+		button
+			.value()
+			.attr("data-webauthn-options")
+			.expect("no ceremony options"),
+	)
+	.expect("ceremony options are not JSON");
+	let challenge = BASE64
+		.decode(
+			options["publicKey"]["challenge"]
+				.as_str()
+				.expect("no challenge"),
+		)
+		.expect("challenge is not base64url");
+	assert_eq!(32, challenge.len());
+	assert_eq!("required", options["publicKey"]["userVerification"]);
+
+	let ceremony_ctx = AuthContext::from_str(
+		button.value().attr("data-webauthn-ctx").expect("no ctx"),
+		&srv.cfg,
+	)
+	.expect("cannot decrypt ctx");
+	assert_eq!(Some(&challenge), ceremony_ctx.webauthn_challenge());
+}
+
+#[actix_rt::test]
+async fn registration_stores_credential() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	let authenticator = SoftAuthenticator::new();
+
+	let (ctx, _) = registration_ceremony(&srv, &authenticated_ctx(&srv, &oidc_client, &user))
+		.await
+		.expect("page needs a registration button");
+	let challenge = ctx.webauthn_challenge().expect("no challenge").clone();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_webauthn_registration",
+		&with_ctx(&ctx, authenticator.create(&srv, &challenge)),
+	)
+	.await;
+	assert_eq!("/authenticate/recovery_codes", redirect_url.path());
+
+	let creds = srv
+		.db
+		.webauthn_credential()
+		.await
+		.expect("webauthn_credential")
+		.find_all_by_principal_id(user.principal().id())
+		.await
+		.expect("find_all_by_principal_id");
+	assert_eq!(1, creds.len());
+	assert_eq!(&authenticator.credential_id, creds[0].credential_id());
+}
+
+#[actix_rt::test]
+async fn second_factor_cannot_be_added_without_providing_the_first() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	register(&srv, &user, &SoftAuthenticator::new()).await;
+
+	// Password, but no second factor
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user);
+	assert!(registration_ceremony(&srv, &ctx).await.is_none());
+
+	let challenge = challenge(&srv).await;
+	let imposter = SoftAuthenticator {
+		credential_id: b"imposter credential".to_vec(),
+		..SoftAuthenticator::new()
+	};
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_webauthn_registration",
+		&with_ctx(
+			&ctx.with_webauthn_challenge(challenge.clone()),
+			imposter.create(&srv, &challenge),
+		),
+	)
+	.await;
+	assert_eq!(
+		Some("invalid_context".to_string()),
+		util::param(&redirect_url, "err")
+	);
+	assert_eq!(
+		1,
+		srv.db
+			.webauthn_credential()
+			.await
+			.expect("webauthn_credential")
+			.find_all_by_principal_id(user.principal().id())
+			.await
+			.expect("find_all_by_principal_id")
+			.len()
+	);
+}
+
+#[actix_rt::test]
+async fn another_credential_can_be_added_after_providing_second_factor() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	let first = SoftAuthenticator::new();
+	register(&srv, &user, &first).await;
+
+	let ctx = authenticated_ctx(&srv, &oidc_client, &user)
+		.with_mfa_time(OffsetDateTime::now_utc().unix_timestamp());
+	let (ctx, options) = registration_ceremony(&srv, &ctx)
+		.await
+		.expect("page needs a registration button");
+	assert_eq!(
+		BASE64.encode(&first.credential_id),
+		options["publicKey"]["excludeCredentials"][0]["id"]
+	);
+
+	let second = SoftAuthenticator {
+		credential_id: b"second soft authenticator credential".to_vec(),
+		..SoftAuthenticator::new()
+	};
+	let challenge = ctx.webauthn_challenge().expect("no challenge").clone();
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_webauthn_registration",
+		&with_ctx(&ctx, second.create(&srv, &challenge)),
+	)
+	.await;
+	assert_eq!(None, util::param(&redirect_url, "err"));
+	assert_eq!("example.com", redirect_url.host_str().unwrap());
+
+	let creds = srv
+		.db
+		.webauthn_credential()
+		.await
+		.expect("webauthn_credential")
+		.find_all_by_principal_id(user.principal().id())
+		.await
+		.expect("find_all_by_principal_id");
+	assert_eq!(2, creds.len());
+	assert!(creds
+		.iter()
+		.any(|c| c.credential_id() == &second.credential_id));
+}
+
+#[actix_rt::test]
+async fn registration_with_wrong_origin_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	let authenticator = SoftAuthenticator::new();
+	let challenge = challenge(&srv).await;
+	let ctx =
+		authenticated_ctx(&srv, &oidc_client, &user).with_webauthn_challenge(challenge.clone());
+
+	let mut form = authenticator.create(&srv, &challenge);
+	form[0].1 = BASE64.encode(client_data_json(
+		"webauthn.create",
+		&challenge,
+		"https://evil.example.com",
+	));
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_webauthn_registration",
+		&with_ctx(&ctx, form),
+	)
+	.await;
+	assert_eq!("/authenticate/webauthn/register", redirect_url.path());
+	assert_eq!(
+		Some("webauthn_failed".to_string()),
+		util::param(&redirect_url, "err")
+	);
+	assert!(srv
+		.db
+		.webauthn_credential()
+		.await
+		.expect("webauthn_credential")
+		.find_all_by_principal_id(user.principal().id())
+		.await
+		.expect("find_all_by_principal_id")
+		.is_empty());
+}
+
+#[actix_rt::test]
+async fn registered_user_is_asked_for_security_key_after_password() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	register(&srv, &user, &SoftAuthenticator::new()).await;
+
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash())
+	.with_email(user.email())
+	.to_string();
+
+	let redirect_url = post(
+		&srv,
+		"/authenticate/submit_password",
+		&[("ctx", ctx), ("password", "hunter2".to_string())],
+	)
+	.await;
+	assert_eq!("/authenticate/webauthn", redirect_url.path());
+	assert_eq!(None, util::param(&redirect_url, "code"));
+
+	let mut res = srv
+		.get(&redirect_url[url::Position::BeforePath..])
+		.insert_header(("accept", "text/html"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+
+	let doc = util::doc(&mut res).await;
+	let button = doc
+		.select(css!("button#webauthn-button[data-webauthn='get']"))
+		.next()
+		.expect("page needs a security key button");
+	let options: serde_json::Value = serde_json::from_str(
+		button
+			.value()
+			.attr("data-webauthn-options")
+			.expect("no ceremony options"),
+	)
+	.expect("ceremony options are not JSON");
+	assert_eq!(
+		BASE64.encode(SoftAuthenticator::new().credential_id),
+		options["publicKey"]["allowCredentials"][0]["id"]
+	);
+}
+
+#[actix_rt::test]
+async fn second_factor_assertion_issues_auth_code() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	let mut authenticator = SoftAuthenticator::new();
+	register(&srv, &user, &authenticator).await;
+	let challenge = challenge(&srv).await;
+	let ctx =
+		authenticated_ctx(&srv, &oidc_client, &user).with_webauthn_challenge(challenge.clone());
+
+	let form = authenticator.get(&srv, &challenge, &origin(&srv), 0x01);
+	let redirect_url = post(&srv, "/authenticate/submit_webauthn", &with_ctx(&ctx, form)).await;
+
+	assert_eq!("example.com", redirect_url.host_str().unwrap());
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+}
+
+#[actix_rt::test]
+async fn challenge_cannot_be_replayed() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	let mut authenticator = SoftAuthenticator::new();
+	register(&srv, &user, &authenticator).await;
+	let challenge = challenge(&srv).await;
+	let ctx =
+		authenticated_ctx(&srv, &oidc_client, &user).with_webauthn_challenge(challenge.clone());
+
+	let form = authenticator.get(&srv, &challenge, &origin(&srv), 0x01);
+	let redirect_url = post(&srv, "/authenticate/submit_webauthn", &with_ctx(&ctx, form)).await;
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+
+	// A fresh signature, so the counter goes up, but over a challenge that has been used already
+	let form = authenticator.get(&srv, &challenge, &origin(&srv), 0x01);
+	let redirect_url = post(&srv, "/authenticate/submit_webauthn", &with_ctx(&ctx, form)).await;
+	assert_eq!("/authenticate/webauthn", redirect_url.path());
+	assert_eq!(
+		Some("webauthn_failed".to_string()),
+		util::param(&redirect_url, "err")
+	);
+}
+
+#[actix_rt::test]
+async fn assertion_with_bad_signature_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	register(&srv, &user, &SoftAuthenticator::new()).await;
+	let challenge = challenge(&srv).await;
+	let ctx =
+		authenticated_ctx(&srv, &oidc_client, &user).with_webauthn_challenge(challenge.clone());
+
+	// Same credential ID, different key
+	let mut imposter = SoftAuthenticator::new();
+	let form = imposter.get(&srv, &challenge, &origin(&srv), 0x01);
+	let redirect_url = post(&srv, "/authenticate/submit_webauthn", &with_ctx(&ctx, form)).await;
+
+	assert_eq!("/authenticate/webauthn", redirect_url.path());
+	assert_eq!(
+		Some("webauthn_failed".to_string()),
+		util::param(&redirect_url, "err")
+	);
+}
+
+#[actix_rt::test]
+async fn assertion_from_wrong_origin_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	let mut authenticator = SoftAuthenticator::new();
+	register(&srv, &user, &authenticator).await;
+	let challenge = challenge(&srv).await;
+	let ctx =
+		authenticated_ctx(&srv, &oidc_client, &user).with_webauthn_challenge(challenge.clone());
+
+	let form = authenticator.get(&srv, &challenge, "https://evil.example.com", 0x01);
+	let redirect_url = post(&srv, "/authenticate/submit_webauthn", &with_ctx(&ctx, form)).await;
+
+	assert_eq!(
+		Some("webauthn_failed".to_string()),
+		util::param(&redirect_url, "err")
+	);
+	assert_eq!(None, util::param(&redirect_url, "code"));
+}
+
+#[actix_rt::test]
+async fn passkey_signs_in_without_password() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	let mut authenticator = SoftAuthenticator::new();
+	register(&srv, &user, &authenticator).await;
+	let challenge = challenge(&srv).await;
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.with_webauthn_challenge(challenge.clone());
+
+	let mut form = authenticator.get(&srv, &challenge, &origin(&srv), 0x05);
+	form.push((
+		"user_handle",
+		BASE64.encode(user.principal().id().as_bytes()),
+	));
+	let redirect_url = post(&srv, "/authenticate/submit_passkey", &with_ctx(&ctx, form)).await;
+
+	assert_eq!("example.com", redirect_url.host_str().unwrap());
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+}
+
+#[actix_rt::test]
+async fn passkey_requires_user_verification() {
+	let srv = util::setup(util::default).await;
+
+	let oidc_client = util::oidc_client(&srv.db, "https://example.com/cb").await;
+	let user = util::create_user(&srv.db).await;
+	let mut authenticator = SoftAuthenticator::new();
+	register(&srv, &user, &authenticator).await;
+	let challenge = challenge(&srv).await;
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		oidc_client.id(),
+		"https://example.com/cb",
+		"",
+	)
+	.with_webauthn_challenge(challenge.clone());
+
+	// User present, but not verified
+	let form = authenticator.get(&srv, &challenge, &origin(&srv), 0x01);
+	let redirect_url = post(&srv, "/authenticate/submit_passkey", &with_ctx(&ctx, form)).await;
+
+	assert_eq!("/authenticate", redirect_url.path());
+	assert_eq!(
+		Some("passkey_failed".to_string()),
+		util::param(&redirect_url, "err")
+	);
+}
@@ -1,4 +1,21 @@
 mod authenticate;
 mod oidc_authorize;
+mod oidc_backchannel_logout;
+mod oidc_claims;
+mod oidc_client_credentials;
+mod oidc_client_secret;
+mod oidc_device_authorization;
+mod oidc_dpop;
+mod oidc_end_session;
+mod oidc_introspect;
+mod oidc_pairwise_subject;
 mod oidc_provider_metadata;
+mod oidc_public_client;
+mod oidc_pushed_authorization;
+mod oidc_refresh_token;
+mod oidc_registration;
+mod oidc_request_object;
+mod oidc_revoke;
 mod oidc_token;
+mod oidc_userinfo;
+mod session;
@@ -19,7 +19,7 @@ async fn create_test_records(db: &authul_db::Pool) -> (OidcClient, OidcClient) {
 			.new()
 			.with_name("Caves")
 			.with_redirect_uris(["https://example.com/oidc/callback"])
-			.with_jwks_uri("https://example.com/jwks.json")
+			.with_jwks_uri("https://example.com/jwks.json".to_string())
 			.save()
 			.await
 			.expect("OidcClient create"),
@@ -29,7 +29,7 @@ async fn create_test_records(db: &authul_db::Pool) -> (OidcClient, OidcClient) {
 			.new()
 			.with_name("Kilroy")
 			.with_redirect_uris(["https://example.com/hackers/rule"])
-			.with_jwks_uri("https://example.com/jwks.json")
+			.with_jwks_uri("https://example.com/jwks.json".to_string())
 			.save()
 			.await
 			.expect("OidcClient create"),
@@ -348,7 +348,7 @@ async fn accept_not_understood_scope() {
 
 	let (client, _) = create_test_records(&srv.db).await;
 
-	let res = srv.get("/oidc/authorize?".to_string() + encode_params!(redirect_uri: "https://example.com/oidc/callback", client_id: &client.id().to_base64(), scope: "openid token", response_type: "code", code_challenge_method: "S256", code_challenge: "xyzzy123")).with_csrf_cookie().send().await.unwrap();
+	let res = srv.get("/oidc/authorize?".to_string() + encode_params!(redirect_uri: "https://example.com/oidc/callback", client_id: &client.id().to_base64(), scope: "openid token email", response_type: "code", code_challenge_method: "S256", code_challenge: "xyzzy123")).with_csrf_cookie().send().await.unwrap();
 
 	assert_eq!(303, res.status().as_u16());
 	assert_eq!(res.content_type(), "");
@@ -376,6 +376,11 @@ async fn accept_not_understood_scope() {
 			.into_owned()
 			.collect();
 	assert!(redirect_params.contains_key("ctx"));
+
+	// The scopes we don't understand are left out of what gets granted
+	let ctx = AuthContext::from_str(redirect_params.get("ctx").unwrap(), &srv.cfg)
+		.expect("AuthContext decrypt/decode failed");
+	assert_eq!(Some("openid email"), ctx.scope().map(String::as_str));
 }
 
 #[actix_rt::test]
@@ -729,3 +734,118 @@ async fn unsupported_scope_reports_error() {
 		redirect_params.get("error").map(|x| x.as_str())
 	);
 }
+
+#[actix_rt::test]
+async fn login_hint_prefills_email() {
+	let srv = util::setup(util::default).await;
+
+	let (client, _) = create_test_records(&srv.db).await;
+
+	let res = srv.get("/oidc/authorize?".to_string() + encode_params!(redirect_uri: "https://example.com/oidc/callback", client_id: &client.id().to_base64(), scope: "openid", response_type: "code", code_challenge_method: "S256", code_challenge: "xyzzy123", login_hint: "jaime@example.com")).with_csrf_cookie().send().await.unwrap();
+
+	assert_eq!(303, res.status().as_u16());
+	let redirect_url = Url::parse(
+		res.headers()
+			.get("location")
+			.expect("a location header")
+			.to_str()
+			.expect("an ASCII location header"),
+	)
+	.expect("a valid redirect header");
+	assert_eq!("/authenticate", redirect_url.path());
+	let redirect_params: HashMap<String, String> =
+		url::form_urlencoded::parse(redirect_url.query().unwrap().as_bytes())
+			.into_owned()
+			.collect();
+	assert_eq!(
+		Some("jaime@example.com"),
+		redirect_params.get("email").map(|s| s.as_str())
+	);
+}
+
+#[actix_rt::test]
+async fn non_email_login_hint_is_ignored() {
+	let srv = util::setup(util::default).await;
+
+	let (client, _) = create_test_records(&srv.db).await;
+
+	let res = srv.get("/oidc/authorize?".to_string() + encode_params!(redirect_uri: "https://example.com/oidc/callback", client_id: &client.id().to_base64(), scope: "openid", response_type: "code", code_challenge_method: "S256", code_challenge: "xyzzy123", login_hint: "+61 400 000 000")).with_csrf_cookie().send().await.unwrap();
+
+	assert_eq!(303, res.status().as_u16());
+	let redirect_url = Url::parse(
+		res.headers()
+			.get("location")
+			.expect("a location header")
+			.to_str()
+			.expect("an ASCII location header"),
+	)
+	.expect("a valid redirect header");
+	assert_eq!("/authenticate", redirect_url.path());
+	let redirect_params: HashMap<String, String> =
+		url::form_urlencoded::parse(redirect_url.query().unwrap().as_bytes())
+			.into_owned()
+			.collect();
+	assert_eq!(None, redirect_params.get("email"));
+}
+
+#[actix_rt::test]
+async fn unsupported_prompt_reports_error() {
+	let srv = util::setup(util::default).await;
+
+	let (client, _) = create_test_records(&srv.db).await;
+
+	for prompt in ["bogus", "none login"] {
+		let res = srv.get("/oidc/authorize?".to_string() + encode_params!(redirect_uri: "https://example.com/oidc/callback", client_id: &client.id().to_base64(), scope: "openid", response_type: "code", code_challenge_method: "S256", code_challenge: "xyzzy123", prompt: prompt)).with_csrf_cookie().send().await.unwrap();
+
+		assert_eq!(302, res.status().as_u16(), "prompt={prompt}");
+		let redirect_url = Url::parse(
+			res.headers()
+				.get("location")
+				.expect("a location header")
+				.to_str()
+				.expect("an ASCII location header"),
+		)
+		.expect("a valid redirect header");
+		assert_eq!("/oidc/callback", redirect_url.path());
+		let redirect_params: HashMap<String, String> =
+			url::form_urlencoded::parse(redirect_url.query().unwrap().as_bytes())
+				.into_owned()
+				.collect();
+		assert_eq!(
+			Some("invalid_request"),
+			redirect_params.get("error").map(|x| x.as_str()),
+			"prompt={prompt}"
+		);
+	}
+}
+
+#[actix_rt::test]
+async fn invalid_max_age_reports_error() {
+	let srv = util::setup(util::default).await;
+
+	let (client, _) = create_test_records(&srv.db).await;
+
+	for max_age in ["-1", "a while"] {
+		let res = srv.get("/oidc/authorize?".to_string() + encode_params!(redirect_uri: "https://example.com/oidc/callback", client_id: &client.id().to_base64(), scope: "openid", response_type: "code", code_challenge_method: "S256", code_challenge: "xyzzy123", max_age: max_age)).with_csrf_cookie().send().await.unwrap();
+
+		assert_eq!(302, res.status().as_u16(), "max_age={max_age}");
+		let redirect_url = Url::parse(
+			res.headers()
+				.get("location")
+				.expect("a location header")
+				.to_str()
+				.expect("an ASCII location header"),
+		)
+		.expect("a valid redirect header");
+		assert_eq!("/oidc/callback", redirect_url.path());
+		let redirect_params: HashMap<String, String> =
+			url::form_urlencoded::parse(redirect_url.query().unwrap().as_bytes())
+				.into_owned()
+				.collect();
+		assert_eq!(
+			Some("invalid_request"),
+			redirect_params.get("error").map(|x| x.as_str()),
+			"max_age={max_age}"
+		);
+	}
+}
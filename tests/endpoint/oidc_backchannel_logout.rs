@@ -0,0 +1,278 @@
+use actix_web::{cookie::Cookie, http::StatusCode, web, App, HttpResponse};
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+use time::OffsetDateTime;
+
+use crate::util;
+use authul_crypto::Jwt;
+use authul_db::model::{OidcClient, Session, User};
+use authul_frontend::{periodic_tasks::deliver_backchannel_logouts, AuthContext};
+use authul_util::Base64Uuid;
+
+/// Something to play the part of a client's back-channel logout endpoint, which records every
+/// logout token it's sent and answers with the given status
+struct StandInRp {
+	srv: actix_test::TestServer,
+	received: Arc<Mutex<Vec<String>>>,
+}
+
+impl StandInRp {
+	fn start(status: StatusCode) -> Self {
+		let received = Arc::new(Mutex::new(vec![]));
+		let r = received.clone();
+
+		let srv = actix_test::start(move || {
+			App::new()
+				.app_data(web::Data::from(r.clone()))
+				.app_data(web::Data::new(status))
+				.route("/backchannel_logout", web::post().to(receive_logout))
+		});
+
+		Self { srv, received }
+	}
+
+	fn uri(&self) -> String {
+		self.srv.url("/backchannel_logout")
+	}
+
+	fn received(&self) -> Vec<String> {
+		self.received.lock().unwrap().clone()
+	}
+
+	/// Give the server a little while to get around to telling us about a logout
+	async fn wait_for(&self, count: usize) -> Vec<String> {
+		for _ in 0..50 {
+			if self.received.lock().unwrap().len() >= count {
+				break;
+			}
+			actix_rt::time::sleep(Duration::from_millis(100)).await;
+		}
+		self.received()
+	}
+}
+
+async fn receive_logout(
+	received: web::Data<Mutex<Vec<String>>>,
+	status: web::Data<StatusCode>,
+	form: web::Form<HashMap<String, String>>,
+) -> HttpResponse {
+	if let Some(token) = form.get("logout_token") {
+		received.lock().unwrap().push(token.clone());
+	}
+	HttpResponse::build(**status).finish()
+}
+
+async fn oidc_client(srv: &util::ConfiguredTestServer, logout_uri: Option<String>) -> OidcClient {
+	util::oidc_client_with(&srv.db, "https://example.com/oidc/callback", |c| {
+		c.update_backchannel_logout_uri(logout_uri);
+	})
+	.await
+}
+
+async fn create_session(
+	srv: &util::ConfiguredTestServer,
+	user: &User,
+	client: &OidcClient,
+) -> Session {
+	let principal = srv
+		.db
+		.principal()
+		.await
+		.expect("principal")
+		.find(user.principal().id())
+		.await
+		.expect("Principal");
+
+	let mut attrs = vec![];
+	ciborium::into_writer(&authul_db::types::IdentityAttributes::new(), &mut attrs).expect("CBOR");
+
+	let session = srv
+		.db
+		.session()
+		.await
+		.expect("session")
+		.new()
+		.with_principal(principal)
+		.with_attrs(
+			srv.cfg
+				.session_attrs_strong_box()
+				.encrypt(attrs, b"")
+				.expect("encrypt"),
+		)
+		.with_auth_time(OffsetDateTime::now_utc())
+		.save()
+		.await
+		.expect("Session");
+
+	srv.db
+		.session()
+		.await
+		.expect("session")
+		.add_client(session.id(), client.id())
+		.await
+		.expect("add_client");
+
+	session
+}
+
+async fn notification_attempts(srv: &util::ConfiguredTestServer) -> Vec<i32> {
+	srv.db
+		.conn()
+		.await
+		.expect("db conn")
+		.query("SELECT attempts FROM backchannel_logout_notifications", &[])
+		.await
+		.expect("query")
+		.iter()
+		.map(|row| row.get(0))
+		.collect()
+}
+
+#[actix_rt::test]
+async fn logout_notifies_client_user_signed_in_to() {
+	let srv = util::setup(util::default).await;
+	let rp = StandInRp::start(StatusCode::OK);
+
+	let client = oidc_client(&srv, Some(rp.uri())).await;
+	let user = util::create_user(&srv.db).await;
+
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		client.id(),
+		"https://example.com/oidc/callback",
+		"xyzzy123",
+	)
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash());
+
+	let res = srv
+		.post("/authenticate/submit_password")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.to_string()),
+			("password", "hunter2".to_string()),
+		])
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+	let cookie = res.cookie("authul_session").expect("no session cookie");
+
+	let res = srv
+		.get("/oidc/end_session")
+		.cookie(Cookie::new("authul_session", cookie.value().to_string()))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+
+	let received = rp.wait_for(1).await;
+	assert_eq!(1, received.len(), "no logout token was delivered");
+
+	let logout_token: Jwt = received[0].parse().expect("logout token is a JWT");
+	assert!(
+		srv.cfg
+			.oidc_jwks()
+			.await
+			.expect("oidc_jwks")
+			.iter()
+			.any(|k| logout_token.verify(k)),
+		"logout token not signed with an OIDC key"
+	);
+	assert_eq!(Some(srv.cfg.base_url().as_str()), logout_token.peek_iss());
+	assert_eq!(
+		Some(client.id().to_base64().as_str()),
+		logout_token.peek_aud()
+	);
+	assert_eq!(
+		Some(user.principal().id().to_string().as_str()),
+		logout_token.peek_sub()
+	);
+	assert!(logout_token.peek_jti().is_some(), "logout token has no jti");
+	assert_eq!(
+		Some(&serde_json::json!({ "http://schemas.openid.net/event/backchannel-logout": {} })),
+		logout_token.peek_events()
+	);
+
+	assert_eq!(Vec::<i32>::new(), notification_attempts(&srv).await);
+}
+
+#[actix_rt::test]
+async fn client_without_logout_uri_is_not_notified() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, None).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user, &client).await;
+
+	srv.db
+		.session()
+		.await
+		.expect("session")
+		.end(&session)
+		.await
+		.expect("end");
+
+	assert_eq!(Vec::<i32>::new(), notification_attempts(&srv).await);
+}
+
+#[actix_rt::test]
+async fn failed_delivery_is_retried_later() {
+	let srv = util::setup(util::default).await;
+	let rp = StandInRp::start(StatusCode::INTERNAL_SERVER_ERROR);
+
+	let client = oidc_client(&srv, Some(rp.uri())).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user, &client).await;
+
+	srv.db
+		.session()
+		.await
+		.expect("session")
+		.end(&session)
+		.await
+		.expect("end");
+	assert_eq!(vec![0], notification_attempts(&srv).await);
+
+	deliver_backchannel_logouts(&srv.cfg)
+		.await
+		.expect("delivery run");
+	assert_eq!(1, rp.received().len());
+	assert_eq!(vec![1], notification_attempts(&srv).await);
+
+	// The next attempt has been put off for a while, so an immediate re-run does nothing
+	deliver_backchannel_logouts(&srv.cfg)
+		.await
+		.expect("delivery run");
+	assert_eq!(1, rp.received().len());
+	assert_eq!(vec![1], notification_attempts(&srv).await);
+}
+
+#[actix_rt::test]
+async fn ending_all_sessions_notifies_clients() {
+	let srv = util::setup(util::default).await;
+	let rp = StandInRp::start(StatusCode::OK);
+
+	let client = oidc_client(&srv, Some(rp.uri())).await;
+	let user = util::create_user(&srv.db).await;
+	create_session(&srv, &user, &client).await;
+	create_session(&srv, &user, &client).await;
+
+	let count = srv
+		.db
+		.session()
+		.await
+		.expect("session")
+		.end_all_by_principal_id(user.principal().id())
+		.await
+		.expect("end_all_by_principal_id");
+	assert_eq!(2, count);
+
+	deliver_backchannel_logouts(&srv.cfg)
+		.await
+		.expect("delivery run");
+	assert_eq!(2, rp.received().len());
+	assert_eq!(Vec::<i32>::new(), notification_attempts(&srv).await);
+}
@@ -0,0 +1,257 @@
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+	encode_params,
+	util::{self, WithCsrfCookie as _},
+};
+use authul_db::model::{OidcClient, OidcToken, User};
+use authul_frontend::AuthContext;
+use authul_util::Base64Uuid;
+
+async fn oidc_client(srv: &util::ConfiguredTestServer, include_attrs_claim: bool) -> OidcClient {
+	util::oidc_client_with(&srv.db, "https://example.com/callback", |c| {
+		c.update_include_attrs_claim(include_attrs_claim);
+		c.update_skip_consent(true);
+	})
+	.await
+}
+
+async fn sign_in(
+	srv: &util::ConfiguredTestServer,
+	client: &OidcClient,
+	user: &User,
+	scope: &str,
+) -> OidcToken {
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		client.id(),
+		"https://example.com/callback",
+		"xyzzy123",
+	)
+	.with_scope(scope);
+
+	complete_sign_in(srv, user, ctx).await
+}
+
+/// The context the authorization endpoint sends the user off to authenticate with
+async fn authorize(
+	srv: &util::ConfiguredTestServer,
+	client: &OidcClient,
+	scope: &str,
+	claims: Value,
+) -> AuthContext {
+	let res = srv.get("/oidc/authorize?".to_string() + encode_params!(redirect_uri: "https://example.com/callback", client_id: &client.id().to_base64(), scope: scope, claims: &claims.to_string(), response_type: "code", code_challenge_method: "S256", code_challenge: "xyzzy123")).with_csrf_cookie().send().await.unwrap();
+	assert_eq!(303, res.status().as_u16());
+
+	let redirect_url =
+		Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+	assert_eq!("/authenticate", redirect_url.path());
+	let ctx = redirect_url
+		.query_pairs()
+		.find(|(k, _)| k == "ctx")
+		.map(|(_, v)| v.to_string())
+		.expect("no ctx");
+
+	AuthContext::from_str(&ctx, &srv.cfg).expect("AuthContext decrypt/decode failed")
+}
+
+async fn complete_sign_in(
+	srv: &util::ConfiguredTestServer,
+	user: &User,
+	ctx: AuthContext,
+) -> OidcToken {
+	let ctx = ctx
+		.with_principal(*user.principal().id())
+		.with_pwhash(user.pwhash());
+
+	let res = srv
+		.post("/authenticate/submit_password")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.to_string()),
+			("password", "hunter2".to_string()),
+		])
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	let redirect_url =
+		Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+	let code = redirect_url
+		.query_pairs()
+		.find(|(k, _)| k == "code")
+		.map(|(_, v)| v.to_string())
+		.expect("no code");
+
+	srv.db
+		.oidc_token()
+		.await
+		.expect("oidc_token")
+		.find(&Uuid::from_base64(&code).expect("valid UUID"))
+		.await
+		.expect("token was not saved in DB")
+}
+
+fn payload(token: &str) -> Value {
+	serde_json::from_slice(
+		&BASE64_URL_SAFE_NO_PAD
+			.decode(token.split('.').nth(1).expect("payload part"))
+			.expect("base64 payload"),
+	)
+	.expect("json payload")
+}
+
+#[actix_rt::test]
+async fn email_scope_gets_email_claims() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+
+	let token = sign_in(&srv, &client, &user, "openid email").await;
+	let id_token = payload(token.token());
+
+	assert_eq!(Some("jaime@example.com"), id_token["email"].as_str());
+	assert_eq!(Some(false), id_token["email_verified"].as_bool());
+	assert!(id_token.get("attrs").is_none(), "attrs claim present");
+}
+
+#[actix_rt::test]
+async fn openid_scope_alone_gets_no_claims() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+
+	let token = sign_in(&srv, &client, &user, "openid").await;
+	let id_token = payload(token.token());
+
+	assert!(id_token.get("email").is_none(), "email claim present");
+	assert!(id_token.get("attrs").is_none(), "attrs claim present");
+}
+
+#[actix_rt::test]
+async fn attrs_claim_is_opt_in() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv, true).await;
+	let user = util::create_user(&srv.db).await;
+
+	let token = sign_in(&srv, &client, &user, "openid").await;
+	let id_token = payload(token.token());
+
+	assert_eq!(
+		Some("jaime@example.com"),
+		id_token["attrs"][0]["value"].as_str()
+	);
+}
+
+#[actix_rt::test]
+async fn userinfo_returns_standard_claims() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+
+	let token = sign_in(&srv, &client, &user, "openid email profile").await;
+
+	let mut res = srv
+		.get("/oidc/userinfo")
+		.bearer_auth(token.access_token())
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	let doc: Value = res.json().await.expect("invalid JSON response body");
+	assert_eq!(Some("jaime@example.com"), doc["email"].as_str());
+	assert_eq!(Some(false), doc["email_verified"].as_bool());
+	assert!(doc.get("attrs").is_none(), "attrs present");
+	assert_eq!(
+		Some("openid email profile"),
+		payload(token.access_token())["scope"].as_str()
+	);
+}
+
+#[actix_rt::test]
+async fn requested_claims_are_released() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+
+	let ctx = authorize(
+		&srv,
+		&client,
+		"openid",
+		json!({
+			"id_token": {"email": {"essential": true}},
+			"userinfo": {"email_verified": null},
+		}),
+	)
+	.await;
+	let token = complete_sign_in(&srv, &user, ctx).await;
+
+	let id_token = payload(token.token());
+	assert_eq!(Some("jaime@example.com"), id_token["email"].as_str());
+	assert!(
+		id_token.get("email_verified").is_none(),
+		"email_verified in ID token"
+	);
+
+	let access_token = payload(token.access_token());
+	assert!(access_token.get("email").is_none(), "email in access token");
+	assert_eq!(Some(false), access_token["email_verified"].as_bool());
+}
+
+#[actix_rt::test]
+async fn malformed_claims_param_reports_error() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv, false).await;
+
+	let res = srv.get("/oidc/authorize?".to_string() + encode_params!(redirect_uri: "https://example.com/callback", client_id: &client.id().to_base64(), scope: "openid", claims: "[\"email\"]", response_type: "code", code_challenge_method: "S256", code_challenge: "xyzzy123")).with_csrf_cookie().send().await.unwrap();
+
+	let redirect_url =
+		Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+	let redirect_params: HashMap<String, String> =
+		redirect_url.query_pairs().into_owned().collect();
+	assert_eq!(
+		Some("invalid_request"),
+		redirect_params.get("error").map(String::as_str)
+	);
+}
+
+#[actix_rt::test]
+async fn allowed_claims_limit_what_is_released() {
+	let srv = util::setup(util::default).await;
+	let client = srv
+		.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Caves")
+		.with_redirect_uris(["https://example.com/callback"])
+		.with_jwks_uri("https://example.com/jwks.json".to_string())
+		.with_include_attrs_claim(true)
+		.with_allowed_claims(vec!["email_verified".to_string()])
+		.with_skip_consent(true)
+		.save()
+		.await
+		.expect("OidcClient");
+	let user = util::create_user(&srv.db).await;
+
+	let ctx = authorize(
+		&srv,
+		&client,
+		"openid email",
+		// Asking for it by name doesn't help, either
+		json!({"id_token": {"email": null}}),
+	)
+	.await;
+	let token = complete_sign_in(&srv, &user, ctx).await;
+	let id_token = payload(token.token());
+
+	assert_eq!(Some(false), id_token["email_verified"].as_bool());
+	assert!(id_token.get("email").is_none(), "email claim present");
+	assert_eq!(Some(&vec![]), id_token["attrs"].as_array());
+}
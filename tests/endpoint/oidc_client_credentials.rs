@@ -0,0 +1,201 @@
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::util;
+use authul_crypto::Jwt;
+use authul_db::{model::OidcClient, types::ClientAuthMethod};
+use authul_frontend::Config as FrontendConfig;
+use authul_util::Base64Uuid;
+
+async fn oidc_client(cfg: &FrontendConfig, allowed_scopes: &[&str]) -> OidcClient {
+	util::oidc_client_with(&cfg.db(), "https://example.com/callback", |c| {
+		c.update_allowed_scopes(allowed_scopes.iter().copied());
+	})
+	.await
+}
+
+async fn request_token(
+	srv: &util::ConfiguredTestServer,
+	client_assertion: &str,
+	scope: Option<&str>,
+) -> (u16, Value) {
+	let mut params = vec![
+		("grant_type", "client_credentials"),
+		(
+			"client_assertion_type",
+			"urn:ietf:params:oauth:client-assertion-type:jwt-bearer",
+		),
+		("client_assertion", client_assertion),
+	];
+	if let Some(scope) = scope {
+		params.push(("scope", scope));
+	}
+
+	let mut res = srv.post("/oidc/token").send_form(&params).await.unwrap();
+
+	(
+		res.status().as_u16(),
+		res.json().await.expect("invalid JSON response body"),
+	)
+}
+
+#[actix_rt::test]
+async fn client_gets_access_token_for_itself() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = oidc_client(&srv.cfg, &["invoices:read", "invoices:write"]).await;
+
+	let jti = Uuid::new_v4().to_base64();
+	let (status, doc) = request_token(&srv, &util::client_jwt(&srv.cfg, &client, &jti), None).await;
+
+	assert_eq!(200, status);
+	assert_eq!(Some("Bearer"), doc["token_type"].as_str());
+	assert_eq!(Some(3600), doc["expires_in"].as_u64());
+	assert_eq!(Some("invoices:read invoices:write"), doc["scope"].as_str());
+	assert_eq!(
+		None,
+		doc.get("id_token"),
+		"nobody for an ID token to identify"
+	);
+	assert_eq!(None, doc.get("refresh_token"));
+
+	let access_token: Jwt = doc["access_token"]
+		.as_str()
+		.expect("no access_token")
+		.parse()
+		.expect("access token is a JWT");
+	assert_eq!(
+		Some(client.id().to_base64().as_str()),
+		access_token.peek_sub()
+	);
+	assert_eq!(
+		Some("invoices:read invoices:write"),
+		access_token.peek_scope()
+	);
+}
+
+#[actix_rt::test]
+async fn client_can_ask_for_fewer_scopes() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = oidc_client(&srv.cfg, &["invoices:read", "invoices:write"]).await;
+
+	let jti = Uuid::new_v4().to_base64();
+	let (status, doc) = request_token(
+		&srv,
+		&util::client_jwt(&srv.cfg, &client, &jti),
+		Some("invoices:read"),
+	)
+	.await;
+
+	assert_eq!(200, status);
+	assert_eq!(Some("invoices:read"), doc["scope"].as_str());
+}
+
+#[actix_rt::test]
+async fn client_cannot_ask_for_scopes_it_is_not_allowed() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = oidc_client(&srv.cfg, &["invoices:read"]).await;
+
+	let jti = Uuid::new_v4().to_base64();
+	let (status, doc) = request_token(
+		&srv,
+		&util::client_jwt(&srv.cfg, &client, &jti),
+		Some("invoices:read invoices:write"),
+	)
+	.await;
+
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_scope"}), doc);
+}
+
+#[actix_rt::test]
+async fn client_without_scopes_cannot_use_grant() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = oidc_client(&srv.cfg, &[]).await;
+
+	let jti = Uuid::new_v4().to_base64();
+	let (status, doc) = request_token(&srv, &util::client_jwt(&srv.cfg, &client, &jti), None).await;
+
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "unauthorized_client"}), doc);
+}
+
+#[actix_rt::test]
+async fn client_assertion_cannot_be_replayed() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = oidc_client(&srv.cfg, &["invoices:read"]).await;
+
+	let assertion = util::client_jwt(&srv.cfg, &client, &Uuid::new_v4().to_base64());
+
+	let (status, _) = request_token(&srv, &assertion, None).await;
+	assert_eq!(200, status);
+
+	let (status, doc) = request_token(&srv, &assertion, None).await;
+	assert_eq!(401, status);
+	assert_eq!(json!({"error": "invalid_client"}), doc);
+}
+
+#[actix_rt::test]
+async fn public_client_cannot_use_grant() {
+	let srv = util::setup(util::default).await;
+	let client = srv
+		.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Caves")
+		.with_redirect_uris(["http://127.0.0.1/callback"])
+		.with_token_endpoint_auth_method(ClientAuthMethod::None)
+		.with_allowed_scopes(["invoices:read"])
+		.save()
+		.await
+		.expect("OidcClient");
+
+	let mut res = srv
+		.post("/oidc/token")
+		.send_form(&[
+			("grant_type", "client_credentials"),
+			("client_id", &client.id().to_base64()),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "unauthorized_client"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn client_credentials_access_token_can_be_introspected() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = oidc_client(&srv.cfg, &["invoices:read"]).await;
+
+	let jti = Uuid::new_v4().to_base64();
+	let (status, doc) = request_token(&srv, &util::client_jwt(&srv.cfg, &client, &jti), None).await;
+	assert_eq!(200, status);
+	let access_token = doc["access_token"].as_str().expect("no access_token");
+
+	let mut res = srv
+		.post("/oidc/introspect")
+		.send_form(&[
+			("token", access_token),
+			(
+				"client_assertion_type",
+				"urn:ietf:params:oauth:client-assertion-type:jwt-bearer",
+			),
+			(
+				"client_assertion",
+				&util::client_jwt(&srv.cfg, &client, access_token),
+			),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	let doc: Value = res.json().await.expect("invalid JSON response body");
+	assert_eq!(Some(true), doc["active"].as_bool());
+	assert_eq!(Some(client.id().to_base64().as_str()), doc["sub"].as_str());
+	assert_eq!(Some("invoices:read"), doc["scope"].as_str());
+}
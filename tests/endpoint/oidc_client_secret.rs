@@ -0,0 +1,205 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::util;
+use authul_db::{
+	model::{OidcClient, OidcToken},
+	types::ClientAuthMethod,
+};
+use authul_util::Base64Uuid;
+
+const SECRET: &str = "correct-horse-battery-staple";
+
+async fn client_and_code(
+	srv: &util::ConfiguredTestServer,
+	method: ClientAuthMethod,
+) -> (OidcClient, OidcToken) {
+	let client = srv
+		.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Caves")
+		.with_redirect_uris(["https://example.com/callback"])
+		.with_token_endpoint_auth_method(method)
+		.with_client_secret_hash(bcrypt::hash(SECRET, 4).unwrap())
+		.save()
+		.await
+		.expect("client save failed");
+
+	let token = srv
+		.db
+		.oidc_token()
+		.await
+		.expect("oidc_token")
+		.new()
+		.with_oidc_client(client.clone())
+		.with_token("thisisnotarealtoken")
+		.with_access_token("thisisnotarealaccesstoken")
+		.with_redirect_uri("https://example.com/callback")
+		.with_code_challenge("xkvndgXSG7Ic99LmZ0g07LfnQiie4uAQwxXzaMADYoo")
+		.save()
+		.await
+		.expect("token saved");
+
+	(client, token)
+}
+
+fn code_params(token: &OidcToken) -> Vec<(&'static str, String)> {
+	vec![
+		("grant_type", "authorization_code".to_string()),
+		("code", token.id().to_base64()),
+		("redirect_uri", "https://example.com/callback".to_string()),
+		("code_verifier", "uniques3kr1t".to_string()),
+	]
+}
+
+#[actix_rt::test]
+async fn client_secret_basic_works() {
+	let srv = util::setup(util::default).await;
+	let (client, token) = client_and_code(&srv, ClientAuthMethod::ClientSecretBasic).await;
+
+	let mut res = srv
+		.post("/oidc/token")
+		.basic_auth(client.id().to_base64(), SECRET)
+		.send_form(&code_params(&token))
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	let doc: HashMap<String, Value> = res.json().await.expect("invalid JSON response body");
+	assert_eq!(Some(&json!("thisisnotarealtoken")), doc.get("id_token"));
+}
+
+#[actix_rt::test]
+async fn client_secret_post_works() {
+	let srv = util::setup(util::default).await;
+	let (client, token) = client_and_code(&srv, ClientAuthMethod::ClientSecretPost).await;
+
+	let mut params = code_params(&token);
+	params.push(("client_id", client.id().to_base64()));
+	params.push(("client_secret", SECRET.to_string()));
+
+	let mut res = srv.post("/oidc/token").send_form(&params).await.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	let doc: HashMap<String, Value> = res.json().await.expect("invalid JSON response body");
+	assert_eq!(Some(&json!("thisisnotarealtoken")), doc.get("id_token"));
+}
+
+#[actix_rt::test]
+async fn wrong_secret_is_rejected() {
+	let srv = util::setup(util::default).await;
+	let (client, token) = client_and_code(&srv, ClientAuthMethod::ClientSecretBasic).await;
+
+	let mut res = srv
+		.post("/oidc/token")
+		.basic_auth(client.id().to_base64(), "incorrect-horse")
+		.send_form(&code_params(&token))
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		Some("Basic realm=\"authul\""),
+		res.headers()
+			.get("www-authenticate")
+			.map(|v| v.to_str().unwrap())
+	);
+	assert_eq!(
+		json!({"error": "invalid_client"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn client_must_use_its_registered_method() {
+	let srv = util::setup(util::default).await;
+	let (client, token) = client_and_code(&srv, ClientAuthMethod::ClientSecretBasic).await;
+
+	let mut params = code_params(&token);
+	params.push(("client_id", client.id().to_base64()));
+	params.push(("client_secret", SECRET.to_string()));
+
+	let mut res = srv.post("/oidc/token").send_form(&params).await.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_client"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn private_key_jwt_client_cannot_use_a_secret() {
+	let srv = util::setup(util::default).await;
+
+	let client = srv
+		.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Caves")
+		.with_redirect_uris(["https://example.com/callback"])
+		.with_jwks_uri("https://example.com/jwks.json".to_string())
+		.save()
+		.await
+		.expect("client save failed");
+	let (_, token) = client_and_code(&srv, ClientAuthMethod::ClientSecretBasic).await;
+
+	let mut res = srv
+		.post("/oidc/token")
+		.basic_auth(client.id().to_base64(), SECRET)
+		.send_form(&code_params(&token))
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_client"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn multiple_auth_methods_are_rejected() {
+	let srv = util::setup(util::default).await;
+	let (client, token) = client_and_code(&srv, ClientAuthMethod::ClientSecretBasic).await;
+
+	let mut params = code_params(&token);
+	params.push(("client_secret", SECRET.to_string()));
+
+	let mut res = srv
+		.post("/oidc/token")
+		.basic_auth(client.id().to_base64(), SECRET)
+		.send_form(&params)
+		.await
+		.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_request"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn secret_client_can_introspect() {
+	let srv = util::setup(util::default).await;
+	let (client, _) = client_and_code(&srv, ClientAuthMethod::ClientSecretPost).await;
+
+	let mut res = srv
+		.post("/oidc/introspect")
+		.send_form(&[
+			("token", "not-a-token".to_string()),
+			("client_id", client.id().to_base64()),
+			("client_secret", SECRET.to_string()),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	assert_eq!(json!({"active": false}), res.json::<Value>().await.unwrap());
+}
@@ -0,0 +1,270 @@
+use serde_json::{json, Value};
+use std::time::Duration;
+use time::OffsetDateTime;
+use url::Url;
+use uuid::Uuid;
+
+use crate::util;
+use authul_db::{
+	model::{DeviceCode, OidcClient},
+	types::ClientAuthMethod,
+};
+use authul_frontend::AuthContext;
+use authul_util::Base64Uuid;
+
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+async fn oidc_client(srv: &util::ConfiguredTestServer) -> OidcClient {
+	srv.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Caves CLI")
+		.with_redirect_uris(["http://127.0.0.1/callback"])
+		.with_token_endpoint_auth_method(ClientAuthMethod::None)
+		.save()
+		.await
+		.expect("OidcClient")
+}
+
+async fn device_code(
+	srv: &util::ConfiguredTestServer,
+	client: &OidcClient,
+	valid_before: OffsetDateTime,
+) -> DeviceCode {
+	srv.db
+		.device_code()
+		.await
+		.expect("device_code")
+		.new()
+		.with_oidc_client(client.clone())
+		.with_user_code("WDJB-MJHT")
+		// Long enough ago that the device can poll straight away
+		.with_last_polled_at(OffsetDateTime::now_utc() - Duration::from_secs(60))
+		.with_valid_before(valid_before)
+		.save()
+		.await
+		.expect("DeviceCode")
+}
+
+fn in_ten_minutes() -> OffsetDateTime {
+	OffsetDateTime::now_utc() + Duration::from_secs(600)
+}
+
+async fn poll(
+	srv: &util::ConfiguredTestServer,
+	client: &OidcClient,
+	device_code: &DeviceCode,
+) -> (u16, Value) {
+	let mut res = srv
+		.post("/oidc/token")
+		.send_form(&[
+			("grant_type", DEVICE_CODE_GRANT_TYPE),
+			("device_code", &device_code.id().to_base64()),
+			("client_id", &client.id().to_base64()),
+		])
+		.await
+		.unwrap();
+
+	(
+		res.status().as_u16(),
+		res.json().await.expect("invalid JSON response body"),
+	)
+}
+
+#[actix_rt::test]
+async fn device_authorization_hands_out_codes() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv).await;
+
+	let mut res = srv
+		.post("/oidc/device_authorization")
+		.send_form(&[
+			("client_id", client.id().to_base64().as_str()),
+			("scope", "openid"),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	let doc: Value = res.json().await.expect("invalid JSON response body");
+
+	let user_code = doc["user_code"].as_str().expect("no user_code");
+	assert_eq!(9, user_code.len());
+	assert_eq!(Some('-'), user_code.chars().nth(4));
+	assert!(doc["device_code"].as_str().is_some(), "no device_code");
+	assert_eq!(
+		Some(srv.url("/device").as_str()),
+		doc["verification_uri"].as_str()
+	);
+	assert_eq!(
+		Some(format!("{}?user_code={user_code}", srv.url("/device")).as_str()),
+		doc["verification_uri_complete"].as_str()
+	);
+	assert_eq!(Some(5), doc["interval"].as_u64());
+	assert!(doc["expires_in"]
+		.as_i64()
+		.is_some_and(|e| e > 0 && e <= 600));
+}
+
+#[actix_rt::test]
+async fn device_authorization_requires_openid_scope() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv).await;
+
+	let mut res = srv
+		.post("/oidc/device_authorization")
+		.send_form(&[
+			("client_id", client.id().to_base64().as_str()),
+			("scope", "profile"),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_scope"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn polling_before_sign_in_is_pending() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv).await;
+	let dc = device_code(&srv, &client, in_ten_minutes()).await;
+
+	let (status, doc) = poll(&srv, &client, &dc).await;
+
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "authorization_pending"}), doc);
+}
+
+#[actix_rt::test]
+async fn polling_too_often_is_told_to_slow_down() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv).await;
+	let dc = device_code(&srv, &client, in_ten_minutes()).await;
+
+	let (status, _) = poll(&srv, &client, &dc).await;
+	assert_eq!(400, status);
+
+	let (status, doc) = poll(&srv, &client, &dc).await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "slow_down"}), doc);
+}
+
+#[actix_rt::test]
+async fn expired_device_code_is_rejected() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv).await;
+	let dc = device_code(
+		&srv,
+		&client,
+		OffsetDateTime::now_utc() - Duration::from_secs(1),
+	)
+	.await;
+
+	let (status, doc) = poll(&srv, &client, &dc).await;
+
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "expired_token"}), doc);
+}
+
+#[actix_rt::test]
+async fn unknown_user_code_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let res = srv
+		.post("/device/submit_user_code")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[("user_code", "BCDF-GHJK")])
+		.await
+		.unwrap();
+
+	assert_eq!(302, res.status().as_u16());
+	let redirect_url = Url::parse(res.headers().get("location").unwrap().to_str().unwrap())
+		.expect("a valid redirect header");
+	assert_eq!("/device", redirect_url.path());
+	assert!(redirect_url
+		.query_pairs()
+		.any(|(k, v)| k == "err" && v == "invalid_code"));
+}
+
+#[actix_rt::test]
+async fn signing_in_hands_tokens_to_device() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv).await;
+	let dc = device_code(&srv, &client, in_ten_minutes()).await;
+	let user = util::create_user(&srv.db).await;
+
+	// Users are not always careful about how they type in codes
+	let res = srv
+		.post("/device/submit_user_code")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[("user_code", "wdjb mjht")])
+		.await
+		.unwrap();
+
+	assert_eq!(302, res.status().as_u16());
+	let redirect_url = Url::parse(res.headers().get("location").unwrap().to_str().unwrap())
+		.expect("a valid redirect header");
+	assert_eq!("/authenticate", redirect_url.path());
+	let (_, ctx) = redirect_url
+		.query_pairs()
+		.find(|(k, _)| k == "ctx")
+		.expect("no ctx in redirect");
+
+	let ctx = AuthContext::from_str(&ctx, &srv.cfg)
+		.expect("valid ctx")
+		.with_principal(*user.principal().id())
+		.with_pwhash(user.pwhash());
+
+	let res = srv
+		.post("/authenticate/submit_password")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.to_string()),
+			("password", "hunter2".to_string()),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(302, res.status().as_u16());
+	let redirect_url = Url::parse(res.headers().get("location").unwrap().to_str().unwrap())
+		.expect("a valid redirect header");
+	assert_eq!("/device", redirect_url.path());
+	assert!(redirect_url
+		.query_pairs()
+		.any(|(k, v)| k == "status" && v == "approved"));
+
+	let (status, doc) = poll(&srv, &client, &dc).await;
+	assert_eq!(200, status);
+	assert_eq!(Some("Bearer"), doc["token_type"].as_str());
+	assert!(doc["id_token"].as_str().is_some(), "no id_token");
+	assert!(doc["access_token"].as_str().is_some(), "no access_token");
+	let refresh_token = srv
+		.db
+		.refresh_token()
+		.await
+		.expect("refresh_token")
+		.find(
+			&Uuid::from_base64(doc["refresh_token"].as_str().expect("no refresh_token"))
+				.expect("refresh_token is a UUID"),
+		)
+		.await
+		.expect("refresh token was not saved in DB");
+	// Whatever the upstream provider told us about the user is nobody else's business
+	let attrs = srv
+		.cfg
+		.refresh_token_attrs_strong_box()
+		.decrypt(refresh_token.attrs(), b"")
+		.expect("refresh token attrs are not encrypted");
+	ciborium::from_reader::<authul_db::types::IdentityAttributes, _>(&attrs[..]).expect("CBOR");
+
+	// The tokens can only be collected once
+	let (status, doc) = poll(&srv, &client, &dc).await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_grant"}), doc);
+}
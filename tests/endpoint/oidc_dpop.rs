@@ -0,0 +1,289 @@
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::util;
+use authul_crypto::{DpopProof, Jwk, Jwt};
+use authul_db::{
+	model::{OidcClient, OidcToken, User},
+	types::ClientAuthMethod,
+};
+use authul_frontend::Config as FrontendConfig;
+use authul_util::Base64Uuid;
+
+async fn access_token(cfg: &FrontendConfig, sub: impl Into<String>, jkt: Option<&str>) -> String {
+	let mut jwt = Jwt::new()
+		.with_iss(cfg.base_url().as_str())
+		.with_sub(sub)
+		.with_jti(Uuid::new_v4().to_base64())
+		.with_validity_period(FrontendConfig::ACCESS_TOKEN_VALIDITY_PERIOD);
+	if let Some(jkt) = jkt {
+		jwt = jwt.with_cnf_jkt(jkt);
+	}
+
+	jwt.sign(
+		&cfg.current_access_token_signing_jwk()
+			.await
+			.expect("access token signing key"),
+	)
+	.expect("signing failed")
+}
+
+async fn client_and_code(srv: &util::ConfiguredTestServer, user: &User) -> (OidcClient, OidcToken) {
+	let client = srv
+		.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Caves")
+		.with_redirect_uris(["http://127.0.0.1/callback"])
+		.with_token_endpoint_auth_method(ClientAuthMethod::None)
+		.save()
+		.await
+		.expect("OidcClient");
+
+	let token = srv
+		.db
+		.oidc_token()
+		.await
+		.expect("oidc_token")
+		.new()
+		.with_oidc_client(client.clone())
+		.with_token("thisisnotarealtoken")
+		.with_access_token(access_token(&srv.cfg, user.principal().id().to_string(), None).await)
+		.with_redirect_uri("http://127.0.0.1/callback")
+		.with_code_challenge("xkvndgXSG7Ic99LmZ0g07LfnQiie4uAQwxXzaMADYoo")
+		.save()
+		.await
+		.expect("OidcToken");
+
+	(client, token)
+}
+
+fn code_params(client: &OidcClient, token: &OidcToken) -> Vec<(&'static str, String)> {
+	vec![
+		("grant_type", "authorization_code".to_string()),
+		("code", token.id().to_base64()),
+		("redirect_uri", "http://127.0.0.1/callback".to_string()),
+		("code_verifier", "uniques3kr1t".to_string()),
+		("client_id", client.id().to_base64()),
+	]
+}
+
+fn www_authenticate(res: &impl actix_web::HttpMessage) -> Option<String> {
+	res.headers()
+		.get("www-authenticate")
+		.map(|v| v.to_str().unwrap().to_string())
+}
+
+#[actix_rt::test]
+async fn token_request_with_proof_gets_bound_token() {
+	let srv = util::setup(util::default).await;
+	let user = util::create_user(&srv.db).await;
+	let (client, token) = client_and_code(&srv, &user).await;
+	let key = Jwk::new_ed25519();
+
+	let proof = DpopProof::new("POST", srv.url("/oidc/token"))
+		.sign(&key)
+		.expect("signing failed");
+
+	let mut res = srv
+		.post("/oidc/token")
+		.insert_header(("DPoP", proof))
+		.send_form(&code_params(&client, &token))
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	let doc: Value = res.json().await.expect("invalid JSON response body");
+	assert_eq!(Some("DPoP"), doc["token_type"].as_str());
+
+	let access_token: Jwt = doc["access_token"]
+		.as_str()
+		.expect("no access_token")
+		.parse()
+		.expect("access token is a JWT");
+	assert_eq!(
+		Some(key.to_public_jwk().thumbprint().unwrap().as_str()),
+		access_token.peek_cnf_jkt()
+	);
+}
+
+#[actix_rt::test]
+async fn token_request_without_proof_gets_bearer_token() {
+	let srv = util::setup(util::default).await;
+	let user = util::create_user(&srv.db).await;
+	let (client, token) = client_and_code(&srv, &user).await;
+
+	let mut res = srv
+		.post("/oidc/token")
+		.send_form(&code_params(&client, &token))
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	let doc: Value = res.json().await.expect("invalid JSON response body");
+	assert_eq!(Some("Bearer"), doc["token_type"].as_str());
+}
+
+#[actix_rt::test]
+async fn stale_proof_is_rejected_at_token_endpoint() {
+	let srv = util::setup(util::default).await;
+	let user = util::create_user(&srv.db).await;
+	let (client, token) = client_and_code(&srv, &user).await;
+
+	let proof = DpopProof::new("POST", srv.url("/oidc/token"))
+		.with_broken_iat()
+		.sign(&Jwk::new_ed25519())
+		.expect("signing failed");
+
+	let mut res = srv
+		.post("/oidc/token")
+		.insert_header(("DPoP", proof))
+		.send_form(&code_params(&client, &token))
+		.await
+		.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_dpop_proof"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn proof_for_another_endpoint_is_rejected() {
+	let srv = util::setup(util::default).await;
+	let user = util::create_user(&srv.db).await;
+	let (client, token) = client_and_code(&srv, &user).await;
+
+	let proof = DpopProof::new("POST", srv.url("/oidc/revoke"))
+		.sign(&Jwk::new_ed25519())
+		.expect("signing failed");
+
+	let mut res = srv
+		.post("/oidc/token")
+		.insert_header(("DPoP", proof))
+		.send_form(&code_params(&client, &token))
+		.await
+		.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_dpop_proof"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn bound_token_works_at_userinfo_with_proof() {
+	let srv = util::setup(util::default).await;
+	let user = util::create_user(&srv.db).await;
+	let key = Jwk::new_ed25519();
+	let jkt = key.to_public_jwk().thumbprint().unwrap();
+	let token = access_token(&srv.cfg, user.principal().id().to_string(), Some(&jkt)).await;
+
+	let proof = DpopProof::new("GET", srv.url("/oidc/userinfo"))
+		.with_access_token(&token)
+		.sign(&key)
+		.expect("signing failed");
+
+	let mut res = srv
+		.get("/oidc/userinfo")
+		.insert_header(("authorization", format!("DPoP {token}")))
+		.insert_header(("DPoP", proof))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	let doc: Value = res.json().await.expect("invalid JSON response body");
+	assert_eq!(
+		Some(user.principal().id().to_string().as_str()),
+		doc["sub"].as_str()
+	);
+}
+
+#[actix_rt::test]
+async fn bound_token_cannot_be_used_as_bearer_token() {
+	let srv = util::setup(util::default).await;
+	let user = util::create_user(&srv.db).await;
+	let jkt = Jwk::new_ed25519().to_public_jwk().thumbprint().unwrap();
+	let token = access_token(&srv.cfg, user.principal().id().to_string(), Some(&jkt)).await;
+
+	let res = srv
+		.get("/oidc/userinfo")
+		.bearer_auth(&token)
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		Some("Bearer error=\"invalid_token\""),
+		www_authenticate(&res).as_deref()
+	);
+}
+
+#[actix_rt::test]
+async fn bound_token_needs_proof_from_bound_key() {
+	let srv = util::setup(util::default).await;
+	let user = util::create_user(&srv.db).await;
+	let jkt = Jwk::new_ed25519().to_public_jwk().thumbprint().unwrap();
+	let token = access_token(&srv.cfg, user.principal().id().to_string(), Some(&jkt)).await;
+
+	let proof = DpopProof::new("GET", srv.url("/oidc/userinfo"))
+		.with_access_token(&token)
+		.sign(&Jwk::new_ed25519())
+		.expect("signing failed");
+
+	let res = srv
+		.get("/oidc/userinfo")
+		.insert_header(("authorization", format!("DPoP {token}")))
+		.insert_header(("DPoP", proof))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		Some("DPoP error=\"invalid_dpop_proof\""),
+		www_authenticate(&res).as_deref()
+	);
+}
+
+#[actix_rt::test]
+async fn proof_cannot_be_replayed() {
+	let srv = util::setup(util::default).await;
+	let user = util::create_user(&srv.db).await;
+	let key = Jwk::new_ed25519();
+	let jkt = key.to_public_jwk().thumbprint().unwrap();
+	let token = access_token(&srv.cfg, user.principal().id().to_string(), Some(&jkt)).await;
+
+	let proof = DpopProof::new("GET", srv.url("/oidc/userinfo"))
+		.with_access_token(&token)
+		.sign(&key)
+		.expect("signing failed");
+
+	let res = srv
+		.get("/oidc/userinfo")
+		.insert_header(("authorization", format!("DPoP {token}")))
+		.insert_header(("DPoP", proof.clone()))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+
+	let res = srv
+		.get("/oidc/userinfo")
+		.insert_header(("authorization", format!("DPoP {token}")))
+		.insert_header(("DPoP", proof))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		Some("DPoP error=\"invalid_dpop_proof\""),
+		www_authenticate(&res).as_deref()
+	);
+}
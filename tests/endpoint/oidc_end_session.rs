@@ -0,0 +1,279 @@
+use actix_web::cookie::Cookie;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::util;
+use authul_crypto::{Jwk, Jwt};
+use authul_db::model::{OidcClient, Session, User};
+use authul_util::Base64Uuid;
+
+async fn oidc_client(srv: &util::ConfiguredTestServer) -> OidcClient {
+	util::oidc_client_with(&srv.db, "https://example.com/oidc/callback", |c| {
+		c.update_post_logout_redirect_uris(["https://example.com/goodbye"]);
+	})
+	.await
+}
+
+async fn create_session(srv: &util::ConfiguredTestServer, user: &User) -> Session {
+	let principal = srv
+		.db
+		.principal()
+		.await
+		.expect("principal")
+		.find(user.principal().id())
+		.await
+		.expect("Principal");
+
+	let mut attrs = vec![];
+	ciborium::into_writer(&authul_db::types::IdentityAttributes::new(), &mut attrs).expect("CBOR");
+
+	srv.db
+		.session()
+		.await
+		.expect("session")
+		.new()
+		.with_principal(principal)
+		.with_attrs(
+			srv.cfg
+				.session_attrs_strong_box()
+				.encrypt(attrs, b"")
+				.expect("encrypt"),
+		)
+		.with_auth_time(OffsetDateTime::now_utc())
+		.save()
+		.await
+		.expect("Session")
+}
+
+/// An ID token of the sort we'd have issued to the client some time ago
+async fn id_token(srv: &util::ConfiguredTestServer, client: &OidcClient, user: &User) -> String {
+	Jwt::new()
+		.with_iss(srv.cfg.base_url().as_str())
+		.with_sub(user.principal().id().to_string())
+		.with_aud(client.id().to_base64())
+		.with_broken_exp()
+		.sign(
+			&srv.cfg
+				.current_oidc_signing_jwk()
+				.await
+				.expect("OIDC signing key"),
+		)
+		.expect("signing failed")
+}
+
+async fn session_exists(srv: &util::ConfiguredTestServer, session: &Session) -> bool {
+	srv.db
+		.session()
+		.await
+		.expect("session")
+		.find_valid(session.id())
+		.await
+		.expect("find_valid")
+		.is_some()
+}
+
+async fn end_session(
+	srv: &util::ConfiguredTestServer,
+	session: &Session,
+	params: &[(&str, &str)],
+) -> actix_test::ClientResponse {
+	let query = url::form_urlencoded::Serializer::new(String::new())
+		.extend_pairs(params)
+		.finish();
+
+	srv.get(format!("/oidc/end_session?{query}"))
+		.cookie(Cookie::new("authul_session", session.id().to_string()))
+		.send()
+		.await
+		.unwrap()
+}
+
+#[actix_rt::test]
+async fn logout_ends_session() {
+	let srv = util::setup(util::default).await;
+
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user).await;
+
+	let res = end_session(&srv, &session, &[]).await;
+
+	assert_eq!(200, res.status().as_u16());
+	let cookie = res
+		.cookie("authul_session")
+		.expect("session cookie not cleared");
+	assert_eq!("", cookie.value());
+	assert_eq!(Some(time::Duration::ZERO), cookie.max_age());
+	assert!(!session_exists(&srv, &session).await);
+}
+
+#[actix_rt::test]
+async fn logout_redirects_to_registered_uri() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user).await;
+	let hint = id_token(&srv, &client, &user).await;
+
+	let res = end_session(
+		&srv,
+		&session,
+		&[
+			("id_token_hint", &hint),
+			("post_logout_redirect_uri", "https://example.com/goodbye"),
+			("state", "inebriation"),
+		],
+	)
+	.await;
+
+	assert_eq!(303, res.status().as_u16());
+	let location = Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+	assert_eq!("example.com", location.host_str().unwrap());
+	assert_eq!("/goodbye", location.path());
+	assert_eq!(
+		Some("inebriation".to_string()),
+		location
+			.query_pairs()
+			.find(|(k, _)| k == "state")
+			.map(|(_, v)| v.to_string())
+	);
+	assert!(!session_exists(&srv, &session).await);
+}
+
+#[actix_rt::test]
+async fn client_id_can_identify_client() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user).await;
+
+	let res = end_session(
+		&srv,
+		&session,
+		&[
+			("client_id", &client.id().to_base64()),
+			("post_logout_redirect_uri", "https://example.com/goodbye"),
+		],
+	)
+	.await;
+
+	assert_eq!(303, res.status().as_u16());
+	assert!(!session_exists(&srv, &session).await);
+}
+
+#[actix_rt::test]
+async fn unregistered_redirect_uri_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user).await;
+	let hint = id_token(&srv, &client, &user).await;
+
+	let res = end_session(
+		&srv,
+		&session,
+		&[
+			("id_token_hint", &hint),
+			(
+				"post_logout_redirect_uri",
+				"https://evil.example.com/goodbye",
+			),
+		],
+	)
+	.await;
+
+	assert_eq!(400, res.status().as_u16());
+	assert!(res.headers().get("location").is_none());
+	assert!(
+		session_exists(&srv, &session).await,
+		"session should survive a rejected logout"
+	);
+}
+
+#[actix_rt::test]
+async fn redirect_uri_without_client_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user).await;
+
+	let res = end_session(
+		&srv,
+		&session,
+		&[("post_logout_redirect_uri", "https://example.com/goodbye")],
+	)
+	.await;
+
+	assert_eq!(400, res.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn mismatched_client_id_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv).await;
+	let other_client = oidc_client(&srv).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user).await;
+	let hint = id_token(&srv, &client, &user).await;
+
+	let res = end_session(
+		&srv,
+		&session,
+		&[
+			("id_token_hint", &hint),
+			("client_id", &other_client.id().to_base64()),
+			("post_logout_redirect_uri", "https://example.com/goodbye"),
+		],
+	)
+	.await;
+
+	assert_eq!(400, res.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn forged_id_token_hint_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user).await;
+	let key: Jwk = serde_json::from_str(r#"{"Ed25519":[0, 168, 50, 245, 78, 42, 57, 251, 163, 95, 74, 205, 191, 22, 96, 105, 10, 96, 109, 226, 1, 66, 246, 13, 86, 47, 113, 29, 41, 225, 78, 136]}"#).expect("JWK decode failed");
+	let hint = Jwt::new()
+		.with_sub(user.principal().id().to_string())
+		.with_aud(client.id().to_base64())
+		.sign(&key)
+		.expect("signing failed");
+
+	let res = end_session(
+		&srv,
+		&session,
+		&[
+			("id_token_hint", &hint),
+			("post_logout_redirect_uri", "https://example.com/goodbye"),
+		],
+	)
+	.await;
+
+	assert_eq!(400, res.status().as_u16());
+	assert!(session_exists(&srv, &session).await);
+}
+
+#[actix_rt::test]
+async fn provider_metadata_advertises_end_session_endpoint() {
+	let srv = util::setup(util::default).await;
+
+	let mut res = srv
+		.get("/.well-known/openid-configuration")
+		.send()
+		.await
+		.unwrap();
+	let doc: serde_json::Value = res.json().await.expect("invalid JSON response body");
+
+	assert_eq!(
+		Some(srv.url("/oidc/end_session").as_str()),
+		doc["end_session_endpoint"].as_str()
+	);
+}
@@ -0,0 +1,271 @@
+use actix_web::HttpMessage as _;
+use serde_json::{json, Value};
+use std::time::Duration;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::util;
+use authul_crypto::Jwt;
+use authul_db::model::{OidcClient, RefreshToken, User};
+use authul_frontend::Config as FrontendConfig;
+use authul_util::Base64Uuid;
+
+async fn refresh_token(cfg: &FrontendConfig, client: &OidcClient, user: &User) -> RefreshToken {
+	let principal = cfg
+		.db()
+		.principal()
+		.await
+		.expect("principal")
+		.find(user.principal().id())
+		.await
+		.expect("Principal");
+
+	let mut attrs = vec![];
+	ciborium::into_writer(&authul_db::types::IdentityAttributes::new(), &mut attrs).expect("CBOR");
+
+	cfg.db()
+		.refresh_token()
+		.await
+		.expect("refresh_token")
+		.new()
+		.with_oidc_client(client.clone())
+		.with_principal(principal)
+		.with_attrs(
+			cfg.refresh_token_attrs_strong_box()
+				.encrypt(attrs, b"")
+				.expect("encrypt"),
+		)
+		.with_auth_time(OffsetDateTime::now_utc())
+		.save()
+		.await
+		.expect("RefreshToken")
+}
+
+async fn access_token(cfg: &FrontendConfig, client: &OidcClient, user: &User) -> String {
+	Jwt::new()
+		.with_iss(cfg.base_url().as_str())
+		.with_sub(user.principal().id().to_string())
+		.with_aud(client.id().to_base64())
+		.with_jti(Uuid::new_v4().to_base64())
+		.with_validity_period(FrontendConfig::ACCESS_TOKEN_VALIDITY_PERIOD)
+		.sign(
+			&cfg.current_access_token_signing_jwk()
+				.await
+				.expect("access token signing key"),
+		)
+		.expect("signing failed")
+}
+
+async fn introspect(srv: &util::ConfiguredTestServer, client: &OidcClient, token: &str) -> Value {
+	let mut res = srv
+		.post("/oidc/introspect")
+		.send_form(&[
+			("token", token),
+			(
+				"client_assertion_type",
+				"urn:ietf:params:oauth:client-assertion-type:jwt-bearer",
+			),
+			(
+				"client_assertion",
+				&util::client_jwt(&srv.cfg, client, token),
+			),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	assert_eq!("application/json", res.content_type());
+	res.json().await.expect("invalid JSON response body")
+}
+
+#[actix_rt::test]
+async fn get_does_not_work() {
+	let srv = util::setup(util::default).await;
+
+	let res = srv.get("/oidc/introspect").send().await.unwrap();
+
+	assert_eq!(405, res.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn active_access_token() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let user = util::create_user(&srv.db).await;
+	let token = access_token(&srv.cfg, &client, &user).await;
+
+	// Access tokens are meant for APIs, so anyone can ask about them
+	let other_client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let doc = introspect(&srv, &other_client, &token).await;
+
+	assert_eq!(Some(true), doc["active"].as_bool());
+	assert_eq!(
+		Some(user.principal().id().to_string().as_str()),
+		doc["sub"].as_str()
+	);
+	assert_eq!(
+		Some(client.id().to_base64().as_str()),
+		doc["client_id"].as_str()
+	);
+	assert_eq!(Some("Bearer"), doc["token_type"].as_str());
+	assert!(
+		doc["exp"].as_i64().expect("no exp") > OffsetDateTime::now_utc().unix_timestamp(),
+		"exp is in the past"
+	);
+}
+
+#[actix_rt::test]
+async fn revoked_access_token_is_inactive() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let user = util::create_user(&srv.db).await;
+	let token = access_token(&srv.cfg, &client, &user).await;
+	let jti = token
+		.parse::<Jwt>()
+		.expect("JWT")
+		.peek_jti()
+		.map(str::to_string);
+
+	srv.db
+		.revoked_access_token()
+		.await
+		.expect("revoked_access_token")
+		.revoke(
+			&Uuid::from_base64(&jti.expect("no jti")).expect("jti is a UUID"),
+			&(OffsetDateTime::now_utc() + Duration::from_secs(3600)),
+		)
+		.await
+		.expect("revoke");
+
+	assert_eq!(
+		json!({"active": false}),
+		introspect(&srv, &client, &token).await
+	);
+}
+
+#[actix_rt::test]
+async fn expired_access_token_is_inactive() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let user = util::create_user(&srv.db).await;
+	let token = Jwt::new()
+		.with_iss(srv.cfg.base_url().as_str())
+		.with_sub(user.principal().id().to_string())
+		.with_aud(client.id().to_base64())
+		.with_jti(Uuid::new_v4().to_base64())
+		.with_broken_exp()
+		.sign(
+			&srv.cfg
+				.current_access_token_signing_jwk()
+				.await
+				.expect("access token signing key"),
+		)
+		.expect("signing failed");
+
+	assert_eq!(
+		json!({"active": false}),
+		introspect(&srv, &client, &token).await
+	);
+}
+
+#[actix_rt::test]
+async fn active_refresh_token() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let user = util::create_user(&srv.db).await;
+	let rt = refresh_token(&srv.cfg, &client, &user).await;
+
+	let doc = introspect(&srv, &client, &rt.id().to_base64()).await;
+
+	assert_eq!(Some(true), doc["active"].as_bool());
+	assert_eq!(
+		Some(user.principal().id().to_string().as_str()),
+		doc["sub"].as_str()
+	);
+	assert_eq!(
+		Some(client.id().to_base64().as_str()),
+		doc["client_id"].as_str()
+	);
+	assert_eq!(
+		Some(rt.valid_before().unix_timestamp()),
+		doc["exp"].as_i64()
+	);
+}
+
+#[actix_rt::test]
+async fn someone_elses_refresh_token_is_inactive() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let other_client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let user = util::create_user(&srv.db).await;
+	let rt = refresh_token(&srv.cfg, &client, &user).await;
+
+	assert_eq!(
+		json!({"active": false}),
+		introspect(&srv, &other_client, &rt.id().to_base64()).await
+	);
+}
+
+#[actix_rt::test]
+async fn used_refresh_token_is_inactive() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let user = util::create_user(&srv.db).await;
+	let rt = refresh_token(&srv.cfg, &client, &user).await;
+
+	assert!(srv
+		.db
+		.refresh_token()
+		.await
+		.expect("refresh_token")
+		.mark_used(rt.id())
+		.await
+		.expect("mark_used"));
+
+	assert_eq!(
+		json!({"active": false}),
+		introspect(&srv, &client, &rt.id().to_base64()).await
+	);
+}
+
+#[actix_rt::test]
+async fn garbage_token_is_inactive() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+
+	assert_eq!(
+		json!({"active": false}),
+		introspect(&srv, &client, "lolnope").await
+	);
+	assert_eq!(
+		json!({"active": false}),
+		introspect(&srv, &client, &Uuid::new_v4().to_base64()).await
+	);
+}
+
+#[actix_rt::test]
+async fn unauthenticated_introspection_is_rejected() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let user = util::create_user(&srv.db).await;
+	let token = access_token(&srv.cfg, &client, &user).await;
+
+	let mut res = srv
+		.post("/oidc/introspect")
+		.send_form(&[
+			("token", token.as_str()),
+			(
+				"client_assertion_type",
+				"urn:ietf:params:oauth:client-assertion-type:jwt-bearer",
+			),
+			("client_assertion", "lolnope"),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_client"}),
+		res.json::<Value>().await.expect("json doc")
+	);
+}
@@ -0,0 +1,163 @@
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use secrecy::Secret;
+use serde_json::Value;
+use url::Url;
+use uuid::Uuid;
+
+use crate::util;
+use authul_db::model::{OidcClient, OidcToken, User};
+use authul_frontend::AuthContext;
+use authul_util::Base64Uuid;
+
+async fn oidc_client(
+	srv: &util::ConfiguredTestServer,
+	sector_identifier: Option<&str>,
+) -> OidcClient {
+	util::oidc_client_with(&srv.db, "https://example.com/callback", |c| {
+		c.update_sector_identifier(sector_identifier.map(str::to_string));
+	})
+	.await
+}
+
+async fn sign_in(srv: &util::ConfiguredTestServer, client: &OidcClient, user: &User) -> OidcToken {
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		client.id(),
+		"https://example.com/callback",
+		"xyzzy123",
+	)
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash());
+
+	let res = srv
+		.post("/authenticate/submit_password")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.to_string()),
+			("password", "hunter2".to_string()),
+		])
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	let redirect_url =
+		Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+	let code = redirect_url
+		.query_pairs()
+		.find(|(k, _)| k == "code")
+		.map(|(_, v)| v.to_string())
+		.expect("no code");
+
+	srv.db
+		.oidc_token()
+		.await
+		.expect("oidc_token")
+		.find(&Uuid::from_base64(&code).expect("valid UUID"))
+		.await
+		.expect("token was not saved in DB")
+}
+
+fn sub(token: &str) -> String {
+	let payload: Value = serde_json::from_slice(
+		&BASE64_URL_SAFE_NO_PAD
+			.decode(token.split('.').nth(1).expect("payload part"))
+			.expect("base64 payload"),
+	)
+	.expect("json payload");
+
+	payload["sub"].as_str().expect("sub").to_string()
+}
+
+#[actix_rt::test]
+async fn pairwise_sub_is_shared_within_a_sector() {
+	let srv = util::setup(util::default).await;
+	let user = util::create_user(&srv.db).await;
+	let public = oidc_client(&srv, None).await;
+	let caves = oidc_client(&srv, Some("caves.example.com")).await;
+	let more_caves = oidc_client(&srv, Some("caves.example.com")).await;
+	let mines = oidc_client(&srv, Some("mines.example.com")).await;
+
+	let public_token = sign_in(&srv, &public, &user).await;
+	assert_eq!(user.principal().id().to_string(), sub(public_token.token()));
+
+	let caves_token = sign_in(&srv, &caves, &user).await;
+	let caves_sub = sub(caves_token.token());
+	assert_ne!(user.principal().id().to_string(), caves_sub);
+	assert_eq!(caves_sub, sub(caves_token.access_token()));
+
+	let more_caves_token = sign_in(&srv, &more_caves, &user).await;
+	assert_eq!(caves_sub, sub(more_caves_token.token()));
+
+	let mines_token = sign_in(&srv, &mines, &user).await;
+	assert_ne!(caves_sub, sub(mines_token.token()));
+}
+
+#[actix_rt::test]
+async fn userinfo_works_with_pairwise_sub() {
+	let srv = util::setup(util::default).await;
+	let user = util::create_user(&srv.db).await;
+	let client = oidc_client(&srv, Some("caves.example.com")).await;
+
+	let token = sign_in(&srv, &client, &user).await;
+
+	let mut res = srv
+		.get("/oidc/userinfo")
+		.bearer_auth(token.access_token())
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	let doc: Value = res.json().await.expect("invalid JSON response body");
+	assert_eq!(Some(sub(token.token()).as_str()), doc["sub"].as_str());
+}
+
+/// Relying parties link accounts by `sub`, so rotating the root keys mustn't change it
+#[actix_rt::test]
+async fn pairwise_sub_survives_root_key_rotation() {
+	let srv = util::setup(util::default).await;
+	let user = util::create_user(&srv.db).await;
+	let client = oidc_client(&srv, Some("caves.example.com")).await;
+
+	let token = sign_in(&srv, &client, &user).await;
+	let original_sub = sub(token.token());
+
+	let rotated = authul_frontend::ConfigBuilder::default()
+		.base_url(srv.cfg.base_url().as_str())
+		.unwrap()
+		.root_encryption_key(&Secret::new("rotated".to_string()))
+		.unwrap()
+		.root_decryption_keys([&Secret::new("test".to_string())])
+		.unwrap()
+		.database_handle(srv.db.clone())
+		.build()
+		.expect("invalid config");
+	rotated
+		.re_encrypt_server_secrets()
+		.await
+		.expect("re_encrypt_server_secrets");
+	assert_eq!(
+		original_sub,
+		rotated
+			.subject_identifier(&client, user.principal().id())
+			.await
+			.expect("subject_identifier")
+	);
+
+	// Once everything has been re-encrypted, the old root key can go away entirely
+	let retired = authul_frontend::ConfigBuilder::default()
+		.base_url(srv.cfg.base_url().as_str())
+		.unwrap()
+		.root_encryption_key(&Secret::new("rotated".to_string()))
+		.unwrap()
+		.database_handle(srv.db.clone())
+		.build()
+		.expect("invalid config");
+	assert_eq!(
+		original_sub,
+		retired
+			.subject_identifier(&client, user.principal().id())
+			.await
+			.expect("subject_identifier")
+	);
+}
@@ -1,5 +1,5 @@
 use actix_web::HttpMessage as _;
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::{collections::HashMap, time::Duration};
 use time::OffsetDateTime;
 
@@ -24,6 +24,82 @@ async fn get_oidc_provider_metadata() {
 		Some(srv.url("").as_str()),
 		doc.get("issuer").map(|v| v.as_str().unwrap())
 	);
+	assert_eq!(
+		Some(&serde_json::json!([
+			"authorization_code",
+			"refresh_token",
+			"client_credentials",
+			"urn:ietf:params:oauth:grant-type:device_code"
+		])),
+		doc.get("grant_types_supported")
+	);
+	assert_eq!(
+		Some(srv.url("/oidc/device_authorization").as_str()),
+		doc.get("device_authorization_endpoint")
+			.map(|v| v.as_str().unwrap())
+	);
+	assert_eq!(
+		Some(srv.url("/oidc/par").as_str()),
+		doc.get("pushed_authorization_request_endpoint")
+			.map(|v| v.as_str().unwrap())
+	);
+	assert_eq!(
+		Some(&json!(["openid", "email", "profile"])),
+		doc.get("scopes_supported")
+	);
+	assert!(doc
+		.get("claims_supported")
+		.and_then(|v| v.as_array())
+		.expect("claims_supported")
+		.contains(&json!("email_verified")));
+	assert_eq!(Some(&json!(true)), doc.get("claims_parameter_supported"));
+	assert_eq!(
+		Some(&json!(["public", "pairwise"])),
+		doc.get("subject_types_supported")
+	);
+	assert_eq!(
+		Some(srv.url("/oidc/register").as_str()),
+		doc.get("registration_endpoint")
+			.map(|v| v.as_str().unwrap())
+	);
+	assert_eq!(
+		Some(&Value::Bool(false)),
+		doc.get("request_uri_parameter_supported")
+	);
+	assert_eq!(
+		Some(&Value::Bool(true)),
+		doc.get("request_parameter_supported")
+	);
+	assert_eq!(
+		Some(&serde_json::json!(["EdDSA"])),
+		doc.get("dpop_signing_alg_values_supported")
+	);
+	assert_eq!(
+		Some(&serde_json::json!([
+			"private_key_jwt",
+			"client_secret_basic",
+			"client_secret_post",
+			"none"
+		])),
+		doc.get("token_endpoint_auth_methods_supported")
+	);
+	assert_eq!(
+		Some(srv.url("/oidc/revoke").as_str()),
+		doc.get("revocation_endpoint").map(|v| v.as_str().unwrap())
+	);
+	assert_eq!(
+		Some(srv.url("/oidc/introspect").as_str()),
+		doc.get("introspection_endpoint")
+			.map(|v| v.as_str().unwrap())
+	);
+	assert_eq!(
+		Some(&Value::Bool(true)),
+		doc.get("backchannel_logout_supported")
+	);
+	assert_eq!(
+		Some(&Value::Bool(false)),
+		doc.get("backchannel_logout_session_supported")
+	);
 }
 
 #[actix_rt::test]
@@ -0,0 +1,297 @@
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::{
+	encode_params,
+	util::{self, WithCsrfCookie as _},
+};
+use authul_db::{
+	model::{OidcClient, OidcToken},
+	types::ClientAuthMethod,
+};
+use authul_util::Base64Uuid;
+
+const ORIGIN: &str = "https://app.example.com";
+
+async fn public_client(srv: &util::ConfiguredTestServer) -> OidcClient {
+	srv.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Caves")
+		.with_redirect_uris([
+			"https://app.example.com/callback",
+			"http://127.0.0.1/callback",
+			"com.example.app:/callback",
+		])
+		.with_token_endpoint_auth_method(ClientAuthMethod::None)
+		.with_allowed_origins([ORIGIN])
+		.save()
+		.await
+		.expect("client save failed")
+}
+
+async fn code_for(srv: &util::ConfiguredTestServer, client: &OidcClient) -> OidcToken {
+	srv.db
+		.oidc_token()
+		.await
+		.expect("oidc_token")
+		.new()
+		.with_oidc_client(client.clone())
+		.with_token("thisisnotarealtoken")
+		.with_access_token("thisisnotarealaccesstoken")
+		.with_redirect_uri("https://app.example.com/callback")
+		.with_code_challenge("xkvndgXSG7Ic99LmZ0g07LfnQiie4uAQwxXzaMADYoo")
+		.save()
+		.await
+		.expect("token saved")
+}
+
+fn code_params(token: &OidcToken, code_verifier: &str) -> Vec<(&'static str, String)> {
+	vec![
+		("grant_type", "authorization_code".to_string()),
+		("code", token.id().to_base64()),
+		(
+			"redirect_uri",
+			"https://app.example.com/callback".to_string(),
+		),
+		("code_verifier", code_verifier.to_string()),
+	]
+}
+
+#[actix_rt::test]
+async fn pkce_alone_gets_a_token() {
+	let srv = util::setup(util::default).await;
+	let client = public_client(&srv).await;
+	let token = code_for(&srv, &client).await;
+
+	let mut params = code_params(&token, "uniques3kr1t");
+	params.push(("client_id", client.id().to_base64()));
+
+	let mut res = srv
+		.post("/oidc/token")
+		.insert_header(("origin", ORIGIN))
+		.send_form(&params)
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	assert_eq!(
+		Some(ORIGIN),
+		res.headers()
+			.get("access-control-allow-origin")
+			.map(|v| v.to_str().unwrap())
+	);
+	let doc: Value = res.json().await.expect("invalid JSON response body");
+	assert_eq!(json!("thisisnotarealtoken"), doc["id_token"]);
+}
+
+#[actix_rt::test]
+async fn wrong_code_verifier_is_rejected() {
+	let srv = util::setup(util::default).await;
+	let client = public_client(&srv).await;
+	let token = code_for(&srv, &client).await;
+
+	let mut params = code_params(&token, "guessing");
+	params.push(("client_id", client.id().to_base64()));
+
+	let mut res = srv.post("/oidc/token").send_form(&params).await.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_grant"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn confidential_client_cannot_skip_authentication() {
+	let srv = util::setup(util::default).await;
+	let client = srv
+		.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Caves")
+		.with_redirect_uris(["https://app.example.com/callback"])
+		.with_jwks_uri("https://example.com/jwks.json".to_string())
+		.save()
+		.await
+		.expect("client save failed");
+	let token = code_for(&srv, &client).await;
+
+	let mut params = code_params(&token, "uniques3kr1t");
+	params.push(("client_id", client.id().to_base64()));
+
+	let mut res = srv.post("/oidc/token").send_form(&params).await.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_client"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn unregistered_origin_is_rejected() {
+	let srv = util::setup(util::default).await;
+	let client = public_client(&srv).await;
+	let token = code_for(&srv, &client).await;
+
+	let mut params = code_params(&token, "uniques3kr1t");
+	params.push(("client_id", client.id().to_base64()));
+
+	let mut res = srv
+		.post("/oidc/token")
+		.insert_header(("origin", "https://evil.example.com"))
+		.send_form(&params)
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(None, res.headers().get("access-control-allow-origin"));
+	assert_eq!(
+		json!({"error": "invalid_client"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn another_clients_origin_is_rejected() {
+	let srv = util::setup(util::default).await;
+	let client = public_client(&srv).await;
+	srv.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Elsewhere")
+		.with_redirect_uris(["https://other.example.com/callback"])
+		.with_token_endpoint_auth_method(ClientAuthMethod::None)
+		.with_allowed_origins(["https://other.example.com"])
+		.save()
+		.await
+		.expect("client save failed");
+	let token = code_for(&srv, &client).await;
+
+	let mut params = code_params(&token, "uniques3kr1t");
+	params.push(("client_id", client.id().to_base64()));
+
+	let mut res = srv
+		.post("/oidc/token")
+		.insert_header(("origin", "https://other.example.com"))
+		.send_form(&params)
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(None, res.headers().get("access-control-allow-origin"));
+	assert_eq!(
+		json!({"error": "invalid_client"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn cors_preflight_only_allows_registered_origins() {
+	let srv = util::setup(util::default).await;
+	public_client(&srv).await;
+
+	let res = srv
+		.options("/oidc/token")
+		.insert_header(("origin", ORIGIN))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(204, res.status().as_u16());
+	let headers = res.headers();
+	for (hdr, val) in [
+		("access-control-allow-origin", ORIGIN),
+		("access-control-allow-methods", "POST, OPTIONS"),
+		("access-control-max-age", "604800"),
+	] {
+		assert_eq!(
+			Some(val),
+			headers.get(hdr).map(|v| v.to_str().unwrap()),
+			"bad {hdr} header"
+		);
+	}
+	assert!(
+		headers.get_all("vary").any(|v| v == "origin"),
+		"response doesn't vary by origin"
+	);
+
+	let res = srv
+		.options("/oidc/token")
+		.insert_header(("origin", "https://evil.example.com"))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(204, res.status().as_u16());
+	assert_eq!(None, res.headers().get("access-control-allow-origin"));
+}
+
+#[actix_rt::test]
+async fn public_client_cannot_introspect() {
+	let srv = util::setup(util::default).await;
+	let client = public_client(&srv).await;
+
+	let mut res = srv
+		.post("/oidc/introspect")
+		.send_form(&[
+			("token", "not-a-token".to_string()),
+			("client_id", client.id().to_base64()),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_client"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn loopback_redirect_uri_can_use_any_port() {
+	let srv = util::setup(util::default).await;
+	let client = public_client(&srv).await;
+
+	let res = srv.get("/oidc/authorize?".to_string() + encode_params!(redirect_uri: "http://127.0.0.1:54321/callback", client_id: &client.id().to_base64(), scope: "openid", response_type: "code", code_challenge_method: "S256", code_challenge: "xyzzy123")).with_csrf_cookie().send().await.unwrap();
+
+	assert_eq!(303, res.status().as_u16());
+	let redirect_url = Url::parse(res.headers().get("location").unwrap().to_str().unwrap())
+		.expect("a valid redirect header");
+	assert_eq!("/authenticate", redirect_url.path());
+
+	// ... but nothing else about it can be different
+	let res = srv.get("/oidc/authorize?".to_string() + encode_params!(redirect_uri: "http://127.0.0.1:54321/elsewhere", client_id: &client.id().to_base64(), scope: "openid", response_type: "code", code_challenge_method: "S256", code_challenge: "xyzzy123")).with_csrf_cookie().send().await.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+}
+
+#[test]
+fn public_redirect_uris_are_restricted() {
+	let origins = [ORIGIN];
+
+	for (uri, ok) in [
+		("http://127.0.0.1:8080/callback", true),
+		("http://[::1]/callback", true),
+		("com.example.app:/callback", true),
+		("https://app.example.com/callback", true),
+		("https://elsewhere.example.com/callback", false),
+		("http://app.example.com/callback", false),
+		("http://localhost/callback", false),
+		("myapp:/callback", false),
+	] {
+		assert_eq!(
+			ok,
+			OidcClient::is_acceptable_public_redirect_uri(uri, &origins),
+			"{uri}"
+		);
+	}
+}
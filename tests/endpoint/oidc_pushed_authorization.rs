@@ -0,0 +1,216 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use url::Url;
+
+use crate::{
+	encode_params,
+	util::{self, WithCsrfCookie as _},
+};
+use authul_db::{model::OidcClient, types::ClientAuthMethod};
+use authul_frontend::AuthContext;
+use authul_util::Base64Uuid;
+
+const SECRET: &str = "correct-horse-battery-staple";
+
+async fn oidc_client(srv: &util::ConfiguredTestServer, require_par: bool) -> OidcClient {
+	srv.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Caves")
+		.with_redirect_uris(["https://example.com/oidc/callback"])
+		.with_token_endpoint_auth_method(ClientAuthMethod::ClientSecretBasic)
+		.with_client_secret_hash(bcrypt::hash(SECRET, 4).unwrap())
+		.with_require_pushed_authorization_requests(require_par)
+		.save()
+		.await
+		.expect("OidcClient")
+}
+
+async fn push(
+	srv: &util::ConfiguredTestServer,
+	client: &OidcClient,
+	redirect_uri: &str,
+) -> (u16, Value) {
+	let mut res = srv
+		.post("/oidc/par")
+		.basic_auth(client.id().to_base64(), SECRET)
+		.send_form(&[
+			("redirect_uri", redirect_uri),
+			("scope", "openid"),
+			("response_type", "code"),
+			("code_challenge_method", "S256"),
+			("code_challenge", "xyzzy123"),
+			("state", "keep-it-secret"),
+		])
+		.await
+		.unwrap();
+
+	(
+		res.status().as_u16(),
+		res.json().await.expect("invalid JSON response body"),
+	)
+}
+
+#[actix_rt::test]
+async fn pushed_request_is_used_by_authorize() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv, false).await;
+
+	let (status, doc) = push(&srv, &client, "https://example.com/oidc/callback").await;
+	assert_eq!(201, status);
+	let request_uri = doc["request_uri"].as_str().expect("no request_uri");
+	assert!(request_uri.starts_with("urn:ietf:params:oauth:request_uri:"));
+	assert!(doc["expires_in"].as_i64().is_some_and(|e| e > 0 && e <= 90));
+
+	let res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &client.id().to_base64(), request_uri: request_uri),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(303, res.status().as_u16());
+	let redirect_url = Url::parse(res.headers().get("location").unwrap().to_str().unwrap())
+		.expect("a valid redirect header");
+	assert_eq!("/authenticate", redirect_url.path());
+	let redirect_params: HashMap<String, String> =
+		url::form_urlencoded::parse(redirect_url.query().unwrap().as_bytes())
+			.into_owned()
+			.collect();
+
+	let ctx = AuthContext::from_str(
+		redirect_params
+			.get("ctx")
+			.expect("redirect_params doesn't have ctx"),
+		&srv.cfg,
+	)
+	.expect("AuthContext decrypt/decode failed");
+	assert_eq!(client.id(), ctx.oidc_client_id());
+	assert_eq!("https://example.com/oidc/callback", ctx.redirect_uri());
+	assert_eq!("xyzzy123", ctx.code_challenge());
+	assert_eq!(Some("keep-it-secret"), ctx.state().map(String::as_str));
+}
+
+#[actix_rt::test]
+async fn pushed_request_can_only_be_used_once() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv, false).await;
+
+	let (_, doc) = push(&srv, &client, "https://example.com/oidc/callback").await;
+	let request_uri = doc["request_uri"].as_str().expect("no request_uri");
+
+	let res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &client.id().to_base64(), request_uri: request_uri),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(303, res.status().as_u16());
+
+	let mut res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &client.id().to_base64(), request_uri: request_uri),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_request_uri"}),
+		res.json::<Value>().await.expect("json response")
+	);
+}
+
+#[actix_rt::test]
+async fn pushed_request_belongs_to_pushing_client() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv, false).await;
+	let other_client = oidc_client(&srv, false).await;
+
+	let (_, doc) = push(&srv, &client, "https://example.com/oidc/callback").await;
+	let request_uri = doc["request_uri"].as_str().expect("no request_uri");
+
+	let mut res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &other_client.id().to_base64(), request_uri: request_uri),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_request_uri"}),
+		res.json::<Value>().await.expect("json response")
+	);
+}
+
+#[actix_rt::test]
+async fn push_requires_client_authentication() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv, false).await;
+
+	let mut res = srv
+		.post("/oidc/par")
+		.basic_auth(client.id().to_base64(), "incorrect-horse")
+		.send_form(&[("redirect_uri", "https://example.com/oidc/callback")])
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_client"}),
+		res.json::<Value>().await.unwrap()
+	);
+}
+
+#[actix_rt::test]
+async fn push_with_unregistered_redirect_uri_is_rejected() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv, false).await;
+
+	let (status, doc) = push(&srv, &client, "https://example.com/hackers/rule").await;
+
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_request"}), doc);
+}
+
+#[actix_rt::test]
+async fn client_requiring_par_cannot_send_params_inline() {
+	let srv = util::setup(util::default).await;
+	let client = oidc_client(&srv, true).await;
+
+	let mut res = srv.get("/oidc/authorize?".to_string() + encode_params!(redirect_uri: "https://example.com/oidc/callback", client_id: &client.id().to_base64(), scope: "openid", response_type: "code", code_challenge_method: "S256", code_challenge: "xyzzy123")).with_csrf_cookie().send().await.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_request"}),
+		res.json::<Value>().await.expect("json response")
+	);
+
+	// Pushing the same request is fine, though
+	let (_, doc) = push(&srv, &client, "https://example.com/oidc/callback").await;
+	let request_uri = doc["request_uri"].as_str().expect("no request_uri");
+
+	let res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &client.id().to_base64(), request_uri: request_uri),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(303, res.status().as_u16());
+}
@@ -0,0 +1,318 @@
+use actix_web::HttpMessage as _;
+use serde_json::{json, Value};
+use std::time::Duration;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::util;
+use authul_crypto::{DpopProof, Jwk};
+use authul_db::model::{OidcClient, RefreshToken};
+use authul_frontend::Config as FrontendConfig;
+use authul_util::Base64Uuid;
+
+async fn refresh_token(
+	cfg: &FrontendConfig,
+	client: &OidcClient,
+	valid_before: OffsetDateTime,
+) -> RefreshToken {
+	let user = util::create_user(&cfg.db()).await;
+	let principal = cfg
+		.db()
+		.principal()
+		.await
+		.expect("principal")
+		.find(user.principal().id())
+		.await
+		.expect("Principal");
+
+	let mut attrs = vec![];
+	ciborium::into_writer(&authul_db::types::IdentityAttributes::new(), &mut attrs).expect("CBOR");
+
+	cfg.db()
+		.refresh_token()
+		.await
+		.expect("refresh_token")
+		.new()
+		.with_oidc_client(client.clone())
+		.with_principal(principal)
+		.with_attrs(
+			cfg.refresh_token_attrs_strong_box()
+				.encrypt(attrs, b"")
+				.expect("encrypt"),
+		)
+		.with_auth_time(OffsetDateTime::now_utc() - Duration::from_secs(60))
+		.with_valid_before(valid_before)
+		.save()
+		.await
+		.expect("RefreshToken")
+}
+
+async fn refresh(
+	srv: &util::ConfiguredTestServer,
+	client: &OidcClient,
+	token: &str,
+) -> (u16, Value) {
+	refresh_with_proof(srv, client, token, None).await
+}
+
+async fn refresh_with_proof(
+	srv: &util::ConfiguredTestServer,
+	client: &OidcClient,
+	token: &str,
+	dpop_key: Option<&Jwk>,
+) -> (u16, Value) {
+	let mut req = srv.post("/oidc/token");
+	if let Some(key) = dpop_key {
+		req = req.insert_header((
+			"DPoP",
+			DpopProof::new("POST", srv.url("/oidc/token"))
+				.sign(key)
+				.expect("signing failed"),
+		));
+	}
+
+	let mut res = req
+		.send_form(&[
+			("grant_type", "refresh_token"),
+			("refresh_token", token),
+			(
+				"client_assertion_type",
+				"urn:ietf:params:oauth:client-assertion-type:jwt-bearer",
+			),
+			(
+				"client_assertion",
+				&util::client_jwt(&srv.cfg, client, token),
+			),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!("application/json", res.content_type());
+	(
+		res.status().as_u16(),
+		res.json().await.expect("invalid JSON response body"),
+	)
+}
+
+fn in_a_day() -> OffsetDateTime {
+	OffsetDateTime::now_utc() + Duration::from_secs(86400)
+}
+
+#[actix_rt::test]
+async fn code_exchange_hands_out_refresh_token() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let rt = refresh_token(&srv.cfg, &client, in_a_day()).await;
+
+	let token = srv
+		.db
+		.oidc_token()
+		.await
+		.expect("oidc_token")
+		.new()
+		.with_oidc_client(client.clone())
+		.with_token("thisisnotarealtoken")
+		.with_access_token("thisisnotarealaccesstoken")
+		.with_refresh_token_id(Some(*rt.id()))
+		.with_redirect_uri("https://example.com/callback")
+		.with_code_challenge("xkvndgXSG7Ic99LmZ0g07LfnQiie4uAQwxXzaMADYoo")
+		.save()
+		.await
+		.expect("token saved");
+
+	let mut res = srv
+		.post("/oidc/token")
+		.send_form(&[
+			("grant_type", "authorization_code"),
+			("code", &token.id().to_base64()),
+			("redirect_uri", "https://example.com/callback"),
+			(
+				"client_assertion_type",
+				"urn:ietf:params:oauth:client-assertion-type:jwt-bearer",
+			),
+			(
+				"client_assertion",
+				&util::client_jwt(&srv.cfg, &client, &token.id().to_base64()),
+			),
+			("code_verifier", "uniques3kr1t"),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	let doc: Value = res.json().await.expect("invalid JSON response body");
+	assert_eq!(
+		Some(rt.id().to_base64().as_str()),
+		doc["refresh_token"].as_str()
+	);
+}
+
+#[actix_rt::test]
+async fn refresh_token_gets_new_tokens() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let rt = refresh_token(&srv.cfg, &client, in_a_day()).await;
+
+	let (status, doc) = refresh(&srv, &client, &rt.id().to_base64()).await;
+
+	assert_eq!(200, status);
+	assert_eq!(Some("Bearer"), doc["token_type"].as_str());
+	assert_eq!(Some(3600), doc["expires_in"].as_u64());
+	assert!(doc["id_token"].as_str().is_some(), "no id_token");
+	assert!(doc["access_token"].as_str().is_some(), "no access_token");
+
+	let new_id = Uuid::from_base64(doc["refresh_token"].as_str().expect("no refresh_token"))
+		.expect("refresh_token is a UUID");
+	assert_ne!(rt.id(), &new_id, "refresh token was not rotated");
+
+	let new_rt = srv
+		.db
+		.refresh_token()
+		.await
+		.expect("refresh_token")
+		.find(&new_id)
+		.await
+		.expect("new refresh token was not saved in DB");
+	assert_eq!(rt.family_id(), new_rt.family_id());
+	assert_eq!(rt.principal().id(), new_rt.principal().id());
+	assert_eq!(
+		rt.auth_time().unix_timestamp(),
+		new_rt.auth_time().unix_timestamp()
+	);
+	assert!(!*new_rt.used());
+}
+
+#[actix_rt::test]
+async fn reused_refresh_token_revokes_family() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let rt = refresh_token(&srv.cfg, &client, in_a_day()).await;
+
+	let (status, doc) = refresh(&srv, &client, &rt.id().to_base64()).await;
+	assert_eq!(200, status);
+	let new_token = doc["refresh_token"]
+		.as_str()
+		.expect("no refresh_token")
+		.to_string();
+
+	let (status, doc) = refresh(&srv, &client, &rt.id().to_base64()).await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_grant"}), doc);
+
+	// The token handed out by the first refresh was in the same family, so it's gone too
+	let (status, doc) = refresh(&srv, &client, &new_token).await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_grant"}), doc);
+}
+
+#[actix_rt::test]
+async fn expired_refresh_token_is_rejected() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let rt = refresh_token(
+		&srv.cfg,
+		&client,
+		OffsetDateTime::now_utc() - Duration::from_secs(1),
+	)
+	.await;
+
+	let (status, doc) = refresh(&srv, &client, &rt.id().to_base64()).await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_grant"}), doc);
+}
+
+#[actix_rt::test]
+async fn unknown_refresh_token_is_rejected() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+
+	let (status, doc) = refresh(&srv, &client, &Uuid::new_v4().to_base64()).await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_grant"}), doc);
+}
+
+#[actix_rt::test]
+async fn cannot_use_someone_elses_refresh_token() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let other_client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let rt = refresh_token(&srv.cfg, &client, in_a_day()).await;
+
+	let (status, doc) = refresh(&srv, &other_client, &rt.id().to_base64()).await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_grant"}), doc);
+
+	// A failed attempt by the wrong client doesn't use up the token
+	let (status, _) = refresh(&srv, &client, &rt.id().to_base64()).await;
+	assert_eq!(200, status);
+}
+
+#[actix_rt::test]
+async fn missing_refresh_token_is_rejected() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+
+	let mut res = srv
+		.post("/oidc/token")
+		.send_form(&[
+			("grant_type", "refresh_token"),
+			(
+				"client_assertion_type",
+				"urn:ietf:params:oauth:client-assertion-type:jwt-bearer",
+			),
+			("client_assertion", &util::client_jwt(&srv.cfg, &client, "")),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_request"}),
+		res.json::<Value>().await.expect("json doc")
+	);
+}
+
+#[actix_rt::test]
+async fn dpop_bound_refresh_token_needs_proof_from_bound_key() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let rt = refresh_token(&srv.cfg, &client, in_a_day()).await;
+	let key = Jwk::new_ed25519();
+
+	let (status, doc) = refresh_with_proof(&srv, &client, &rt.id().to_base64(), Some(&key)).await;
+	assert_eq!(200, status);
+	assert_eq!(Some("DPoP"), doc["token_type"].as_str());
+	let bound_token = doc["refresh_token"]
+		.as_str()
+		.expect("no refresh_token")
+		.to_string();
+
+	let (status, doc) = refresh(&srv, &client, &bound_token).await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_dpop_proof"}), doc);
+
+	let (status, doc) =
+		refresh_with_proof(&srv, &client, &bound_token, Some(&Jwk::new_ed25519())).await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_dpop_proof"}), doc);
+
+	// Neither of those used the token up, so the client holding the key can still refresh with it
+	let (status, doc) = refresh_with_proof(&srv, &client, &bound_token, Some(&key)).await;
+	assert_eq!(200, status);
+	assert_eq!(Some("DPoP"), doc["token_type"].as_str());
+
+	let new_id = Uuid::from_base64(doc["refresh_token"].as_str().expect("no refresh_token"))
+		.expect("refresh_token is a UUID");
+	let new_rt = srv
+		.db
+		.refresh_token()
+		.await
+		.expect("refresh_token")
+		.find(&new_id)
+		.await
+		.expect("new refresh token was not saved in DB");
+	assert_eq!(
+		Some(key.to_public_jwk().thumbprint().unwrap()),
+		new_rt.dpop_jkt().clone()
+	);
+}
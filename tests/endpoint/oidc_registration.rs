@@ -0,0 +1,483 @@
+use serde_json::{json, Value};
+use url::Url;
+use uuid::Uuid;
+
+use crate::util;
+use authul_db::types::ClientAuthMethod;
+use authul_util::Base64Uuid;
+
+async fn initial_access_token(srv: &util::ConfiguredTestServer) -> String {
+	let id = srv
+		.db
+		.initial_access_token()
+		.await
+		.expect("initial_access_token")
+		.new()
+		.with_description("preview environments")
+		.with_secret_hash(bcrypt::hash("s3kr1t", 4).unwrap())
+		.with_allowed_scopes(vec!["caves:read".to_string()])
+		.save()
+		.await
+		.expect("InitialAccessToken")
+		.id()
+		.to_base64();
+
+	format!("{id}.s3kr1t")
+}
+
+async fn register(srv: &util::ConfiguredTestServer, token: &str, metadata: Value) -> (u16, Value) {
+	let mut res = srv
+		.post("/oidc/register")
+		.bearer_auth(token)
+		.send_json(&metadata)
+		.await
+		.unwrap();
+
+	let status = res.status().as_u16();
+	(status, res.json().await.unwrap_or(Value::Null))
+}
+
+fn registration_path(doc: &Value) -> String {
+	Url::parse(
+		doc["registration_client_uri"]
+			.as_str()
+			.expect("no registration_client_uri"),
+	)
+	.expect("valid registration_client_uri")
+	.path()
+	.to_string()
+}
+
+#[actix_rt::test]
+async fn registration_requires_initial_access_token() {
+	let srv = util::setup(util::default).await;
+
+	let res = srv
+		.post("/oidc/register")
+		.send_json(&json!({
+			"client_name": "Preview 42",
+			"redirect_uris": ["https://pr-42.example.com/callback"],
+		}))
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		Some("Bearer error=\"invalid_token\""),
+		res.headers()
+			.get("www-authenticate")
+			.map(|v| v.to_str().unwrap())
+	);
+
+	let (status, _) = register(
+		&srv,
+		&format!("{}.s3kr1t", Uuid::new_v4().to_base64()),
+		json!({
+			"client_name": "Preview 42",
+			"redirect_uris": ["https://pr-42.example.com/callback"],
+		}),
+	)
+	.await;
+
+	assert_eq!(401, status);
+
+	// Knowing which token is which isn't enough without its secret
+	let token = initial_access_token(&srv).await;
+	let (token_id, _) = token.split_once('.').unwrap();
+	for bad_token in [token_id.to_string(), format!("{token_id}.guessing")] {
+		let (status, _) = register(
+			&srv,
+			&bad_token,
+			json!({
+				"client_name": "Preview 42",
+				"redirect_uris": ["https://pr-42.example.com/callback"],
+			}),
+		)
+		.await;
+
+		assert_eq!(401, status, "{bad_token} was accepted");
+	}
+}
+
+#[actix_rt::test]
+async fn registration_creates_client() {
+	let srv = util::setup(util::default).await;
+	let token = initial_access_token(&srv).await;
+
+	let (status, doc) = register(
+		&srv,
+		&token,
+		json!({
+			"client_name": "Preview 42",
+			"redirect_uris": ["https://pr-42.example.com/callback"],
+			"scope": "caves:read",
+		}),
+	)
+	.await;
+
+	assert_eq!(201, status);
+	// Clients that don't say otherwise get a secret, as per RFC7591 s2
+	assert_eq!(
+		Some("client_secret_basic"),
+		doc["token_endpoint_auth_method"].as_str()
+	);
+	assert_eq!(Some(0), doc["client_secret_expires_at"].as_u64());
+	assert!(doc["registration_access_token"].as_str().is_some());
+
+	let client_id = doc["client_id"].as_str().expect("no client_id");
+	assert_eq!(
+		format!("/oidc/register/{client_id}"),
+		registration_path(&doc)
+	);
+
+	let client = srv
+		.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.find(&Uuid::from_base64(client_id).unwrap())
+		.await
+		.expect("registered client");
+	assert_eq!("Preview 42", client.name());
+	assert!(client.has_redirect_uri("https://pr-42.example.com/callback"));
+	assert!(client.has_allowed_scope("caves:read"));
+	assert_eq!(
+		&ClientAuthMethod::ClientSecretBasic,
+		client.token_endpoint_auth_method()
+	);
+	assert!(bcrypt::verify(
+		doc["client_secret"].as_str().expect("no client_secret"),
+		client.client_secret_hash().as_ref().unwrap()
+	)
+	.unwrap());
+}
+
+#[actix_rt::test]
+async fn registration_can_ask_for_pairwise_subject() {
+	let srv = util::setup(util::default).await;
+	let token = initial_access_token(&srv).await;
+
+	let (status, doc) = register(
+		&srv,
+		&token,
+		json!({
+			"client_name": "Preview 42",
+			"redirect_uris": ["https://pr-42.example.com/callback"],
+			"subject_type": "pairwise",
+		}),
+	)
+	.await;
+
+	assert_eq!(201, status);
+	assert_eq!(Some("pairwise"), doc["subject_type"].as_str());
+	let client = srv
+		.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.find(&Uuid::from_base64(doc["client_id"].as_str().unwrap()).unwrap())
+		.await
+		.expect("registered client");
+	assert_eq!(
+		Some("pr-42.example.com"),
+		client.sector_identifier().as_deref()
+	);
+
+	// There's no telling which sector a client belongs to if its redirect URIs are all over the
+	// place
+	let (status, doc) = register(
+		&srv,
+		&token,
+		json!({
+			"client_name": "Preview 42",
+			"redirect_uris": [
+				"https://pr-42.example.com/callback",
+				"https://pr-43.example.com/callback",
+			],
+			"subject_type": "pairwise",
+		}),
+	)
+	.await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_client_metadata"}), doc);
+}
+
+#[actix_rt::test]
+async fn registration_rejects_what_the_cli_would() {
+	let srv = util::setup(util::default).await;
+	let token = initial_access_token(&srv).await;
+
+	let (status, doc) = register(
+		&srv,
+		&token,
+		json!({
+			"client_name": "Preview 42",
+			"redirect_uris": ["https://pr-42.example.com/callback"],
+			"token_endpoint_auth_method": "private_key_jwt",
+		}),
+	)
+	.await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_client_metadata"}), doc);
+
+	// A public client can only use HTTPS redirect URIs on one of its allowed origins
+	let (status, doc) = register(
+		&srv,
+		&token,
+		json!({
+			"client_name": "Preview 42",
+			"redirect_uris": ["https://pr-42.example.com/callback"],
+			"token_endpoint_auth_method": "none",
+			"allowed_origins": ["https://pr-43.example.com"],
+		}),
+	)
+	.await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_redirect_uri"}), doc);
+
+	let (status, doc) = register(
+		&srv,
+		&token,
+		json!({
+			"client_name": "Preview 42",
+			"redirect_uris": [],
+		}),
+	)
+	.await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_redirect_uri"}), doc);
+}
+
+#[actix_rt::test]
+async fn registration_only_allows_scopes_the_token_permits() {
+	let srv = util::setup(util::default).await;
+	let token = initial_access_token(&srv).await;
+
+	let (status, doc) = register(
+		&srv,
+		&token,
+		json!({
+			"client_name": "Preview 42",
+			"redirect_uris": ["https://pr-42.example.com/callback"],
+			"scope": "caves:read caves:write",
+		}),
+	)
+	.await;
+	assert_eq!(400, status);
+	assert_eq!(json!({"error": "invalid_client_metadata"}), doc);
+
+	let (status, doc) = register(
+		&srv,
+		&token,
+		json!({
+			"client_name": "Preview 42",
+			"redirect_uris": ["https://pr-42.example.com/callback"],
+			"scope": "caves:read",
+		}),
+	)
+	.await;
+	assert_eq!(201, status);
+	let client_id = doc["client_id"].as_str().unwrap().to_string();
+	let path = registration_path(&doc);
+	let registration_access_token = doc["registration_access_token"].as_str().unwrap();
+
+	// Nor can the client give itself more once it's registered
+	let res = srv
+		.put(&path)
+		.bearer_auth(registration_access_token)
+		.send_json(&json!({
+			"client_id": client_id,
+			"client_name": "Preview 42",
+			"redirect_uris": ["https://pr-42.example.com/callback"],
+			"scope": "caves:read caves:write",
+		}))
+		.await
+		.unwrap();
+	assert_eq!(400, res.status().as_u16());
+
+	let client = srv
+		.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.find(&Uuid::from_base64(&client_id).unwrap())
+		.await
+		.expect("OidcClient");
+	assert!(!client.has_allowed_scope("caves:write"));
+}
+
+#[actix_rt::test]
+async fn registration_rejects_urls_we_would_fetch_from_our_own_network() {
+	let srv = util::setup(util::default).await;
+	let token = initial_access_token(&srv).await;
+
+	for url in [
+		"http://caves.example.com/jwks.json",
+		"https://localhost/jwks.json",
+		"https://127.0.0.1/jwks.json",
+		"https://10.1.2.3/jwks.json",
+		"https://169.254.169.254/latest/meta-data",
+		"https://[::1]/jwks.json",
+		"https://[fd00::1]/jwks.json",
+		"https://[::ffff:192.168.0.1]/jwks.json",
+	] {
+		for field in ["jwks_uri", "backchannel_logout_uri"] {
+			let mut metadata = json!({
+				"client_name": "Preview 42",
+				"redirect_uris": ["https://pr-42.example.com/callback"],
+			});
+			metadata[field] = json!(url);
+
+			let (status, doc) = register(&srv, &token, metadata).await;
+			assert_eq!(400, status, "{field} {url} was accepted");
+			assert_eq!(json!({"error": "invalid_client_metadata"}), doc);
+		}
+	}
+
+	let (status, _) = register(
+		&srv,
+		&token,
+		json!({
+			"client_name": "Preview 42",
+			"redirect_uris": ["https://pr-42.example.com/callback"],
+			"token_endpoint_auth_method": "private_key_jwt",
+			"jwks_uri": "https://pr-42.example.com/jwks.json",
+			"backchannel_logout_uri": "https://pr-42.example.com/logout",
+		}),
+	)
+	.await;
+	assert_eq!(201, status);
+}
+
+#[actix_rt::test]
+async fn client_can_manage_its_registration() {
+	let srv = util::setup(util::default).await;
+	let token = initial_access_token(&srv).await;
+
+	let (_, doc) = register(
+		&srv,
+		&token,
+		json!({
+			"client_name": "Preview 42",
+			"redirect_uris": ["https://pr-42.example.com/callback"],
+		}),
+	)
+	.await;
+	let client_id = doc["client_id"].as_str().unwrap().to_string();
+	let path = registration_path(&doc);
+	let first_token = doc["registration_access_token"]
+		.as_str()
+		.unwrap()
+		.to_string();
+
+	let mut res = srv
+		.get(&path)
+		.bearer_auth(&first_token)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+	let doc: Value = res.json().await.unwrap();
+	assert_eq!(Some("Preview 42"), doc["client_name"].as_str());
+	// The secret is only handed out once
+	assert!(doc.get("client_secret").is_none());
+	// Just looking doesn't change the registration access token
+	assert_eq!(
+		Some(first_token.as_str()),
+		doc["registration_access_token"].as_str()
+	);
+	let res = srv
+		.get(&path)
+		.bearer_auth(&first_token)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+
+	// The client's ID has to match the one it's updating
+	let res = srv
+		.put(&path)
+		.bearer_auth(&first_token)
+		.send_json(&json!({
+			"client_id": Uuid::new_v4().to_base64(),
+			"client_name": "Preview 42 (renamed)",
+			"redirect_uris": ["https://pr-42.example.com/callback"],
+		}))
+		.await
+		.unwrap();
+	assert_eq!(400, res.status().as_u16());
+
+	let mut res = srv
+		.put(&path)
+		.bearer_auth(&first_token)
+		.send_json(&json!({
+			"client_id": client_id,
+			"client_name": "Preview 42 (renamed)",
+			"redirect_uris": ["https://pr-42.example.com/auth/callback"],
+		}))
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+	let doc: Value = res.json().await.unwrap();
+	assert_eq!(Some("Preview 42 (renamed)"), doc["client_name"].as_str());
+	assert_eq!(
+		json!(["https://pr-42.example.com/auth/callback"]),
+		doc["redirect_uris"]
+	);
+	let second_token = doc["registration_access_token"]
+		.as_str()
+		.unwrap()
+		.to_string();
+
+	// An update comes with a new registration access token, which replaces the old one
+	let res = srv
+		.get(&path)
+		.bearer_auth(&first_token)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(401, res.status().as_u16());
+
+	let res = srv
+		.delete(&path)
+		.bearer_auth(&second_token)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(204, res.status().as_u16());
+
+	assert!(srv
+		.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.find(&Uuid::from_base64(&client_id).unwrap())
+		.await
+		.is_err());
+}
+
+#[actix_rt::test]
+async fn cli_clients_cannot_be_managed() {
+	let srv = util::setup(util::default).await;
+	let client = srv
+		.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Caves")
+		.with_redirect_uris(["https://caves.example.com/callback"])
+		.with_jwks_uri("https://caves.example.com/jwks.json".to_string())
+		.save()
+		.await
+		.expect("OidcClient");
+
+	let res = srv
+		.get(format!("/oidc/register/{}", client.id().to_base64()))
+		.bearer_auth("whatever")
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+}
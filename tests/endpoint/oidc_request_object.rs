@@ -0,0 +1,315 @@
+use serde_json::{json, Value};
+use std::{collections::HashMap, time::Duration};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+	encode_params,
+	util::{self, WithCsrfCookie as _},
+};
+use authul_crypto::{Jwk, Jwt, REQUEST_OBJECT_TYP};
+use authul_db::model::OidcClient;
+use authul_frontend::{AuthContext, Config as FrontendConfig};
+use authul_util::Base64Uuid;
+
+fn someone_elses_key() -> Jwk {
+	serde_json::from_str(r#"{"Ed25519":[42, 17, 99, 3, 200, 151, 64, 12, 88, 231, 9, 176, 45, 120, 33, 250, 7, 61, 142, 19, 211, 86, 4, 163, 72, 29, 195, 240, 58, 101, 14, 127]}"#).expect("JWK decode failed")
+}
+
+fn request_object(cfg: &FrontendConfig, client: &OidcClient) -> Jwt {
+	Jwt::new()
+		.with_iss(client.id().to_base64())
+		.with_aud(cfg.base_url().as_str())
+		.with_jti(Uuid::new_v4().to_base64())
+		.with_scope("openid")
+		.with_claim("client_id", client.id().to_base64())
+		.with_claim("redirect_uri", "https://example.com/oidc/callback")
+		.with_claim("response_type", "code")
+		.with_claim("code_challenge_method", "S256")
+		.with_claim("code_challenge", "xyzzy123")
+		.with_claim("state", "from-the-request-object")
+}
+
+fn auth_context(srv: &util::ConfiguredTestServer, location: &str) -> AuthContext {
+	let redirect_url = Url::parse(location).expect("a valid redirect header");
+	assert_eq!("/authenticate", redirect_url.path());
+	let redirect_params: HashMap<String, String> =
+		url::form_urlencoded::parse(redirect_url.query().unwrap().as_bytes())
+			.into_owned()
+			.collect();
+
+	AuthContext::from_str(
+		redirect_params
+			.get("ctx")
+			.expect("redirect_params doesn't have ctx"),
+		&srv.cfg,
+	)
+	.expect("AuthContext decrypt/decode failed")
+}
+
+#[actix_rt::test]
+async fn request_object_params_are_used() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/oidc/callback").await;
+
+	let request = request_object(&srv.cfg, &client)
+		.sign(&util::jwt_signing_key())
+		.expect("signing failed");
+
+	let res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &client.id().to_base64(), request: &request),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(303, res.status().as_u16());
+	let ctx = auth_context(
+		&srv,
+		res.headers().get("location").unwrap().to_str().unwrap(),
+	);
+	assert_eq!(client.id(), ctx.oidc_client_id());
+	assert_eq!("https://example.com/oidc/callback", ctx.redirect_uri());
+	assert_eq!("xyzzy123", ctx.code_challenge());
+	assert_eq!(
+		Some("from-the-request-object"),
+		ctx.state().map(String::as_str)
+	);
+}
+
+#[actix_rt::test]
+async fn request_object_wins_over_query_params() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/oidc/callback").await;
+
+	let request = request_object(&srv.cfg, &client)
+		.sign(&util::jwt_signing_key())
+		.expect("signing failed");
+
+	let res = srv.get("/oidc/authorize?".to_string() + encode_params!(client_id: &client.id().to_base64(), request: &request, state: "from-the-query", code_challenge: "plugh456")).with_csrf_cookie().send().await.unwrap();
+
+	assert_eq!(303, res.status().as_u16());
+	let ctx = auth_context(
+		&srv,
+		res.headers().get("location").unwrap().to_str().unwrap(),
+	);
+	assert_eq!("xyzzy123", ctx.code_challenge());
+	assert_eq!(
+		Some("from-the-request-object"),
+		ctx.state().map(String::as_str)
+	);
+}
+
+#[actix_rt::test]
+async fn request_object_signed_by_someone_else_is_rejected() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/oidc/callback").await;
+
+	let request = request_object(&srv.cfg, &client)
+		.sign(&someone_elses_key())
+		.expect("signing failed");
+
+	let mut res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &client.id().to_base64(), request: &request),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_request_object"}),
+		res.json::<Value>().await.expect("json response")
+	);
+}
+
+#[actix_rt::test]
+async fn request_object_for_another_server_is_rejected() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/oidc/callback").await;
+
+	let request = request_object(&srv.cfg, &client)
+		.with_aud("https://idp.example.net/")
+		.sign(&util::jwt_signing_key())
+		.expect("signing failed");
+
+	let mut res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &client.id().to_base64(), request: &request),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_request_object"}),
+		res.json::<Value>().await.expect("json response")
+	);
+}
+
+#[actix_rt::test]
+async fn request_object_for_another_client_is_rejected() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/oidc/callback").await;
+	let other_client = util::oidc_client(&srv.db, "https://example.com/oidc/callback").await;
+
+	let request = request_object(&srv.cfg, &client)
+		.sign(&util::jwt_signing_key())
+		.expect("signing failed");
+
+	let mut res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &other_client.id().to_base64(), request: &request),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_request_object"}),
+		res.json::<Value>().await.expect("json response")
+	);
+}
+
+#[actix_rt::test]
+async fn explicitly_typed_request_object_is_used() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/oidc/callback").await;
+
+	let request = request_object(&srv.cfg, &client)
+		.sign_with_typ(&util::jwt_signing_key(), REQUEST_OBJECT_TYP)
+		.expect("signing failed");
+
+	let res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &client.id().to_base64(), request: &request),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(303, res.status().as_u16());
+	let ctx = auth_context(
+		&srv,
+		res.headers().get("location").unwrap().to_str().unwrap(),
+	);
+	assert_eq!("xyzzy123", ctx.code_challenge());
+}
+
+#[actix_rt::test]
+async fn request_object_aud_without_trailing_slash_is_us() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/oidc/callback").await;
+
+	let request = request_object(&srv.cfg, &client)
+		.with_aud(srv.cfg.base_url().as_str().trim_end_matches('/'))
+		.sign(&util::jwt_signing_key())
+		.expect("signing failed");
+
+	let res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &client.id().to_base64(), request: &request),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(303, res.status().as_u16());
+	let ctx = auth_context(
+		&srv,
+		res.headers().get("location").unwrap().to_str().unwrap(),
+	);
+	assert_eq!("xyzzy123", ctx.code_challenge());
+}
+
+#[actix_rt::test]
+async fn request_object_claiming_a_subject_is_rejected() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/oidc/callback").await;
+
+	let request = request_object(&srv.cfg, &client)
+		.with_sub("someone-else")
+		.sign(&util::jwt_signing_key())
+		.expect("signing failed");
+
+	let mut res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &client.id().to_base64(), request: &request),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_request_object"}),
+		res.json::<Value>().await.expect("json response")
+	);
+}
+
+#[actix_rt::test]
+async fn request_object_cannot_be_replayed() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/oidc/callback").await;
+
+	let request = request_object(&srv.cfg, &client)
+		.sign(&util::jwt_signing_key())
+		.expect("signing failed");
+	let url = "/oidc/authorize?".to_string()
+		+ encode_params!(client_id: &client.id().to_base64(), request: &request);
+
+	let res = srv.get(&url).with_csrf_cookie().send().await.unwrap();
+	assert_eq!(303, res.status().as_u16());
+
+	let mut res = srv.get(&url).with_csrf_cookie().send().await.unwrap();
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_request_object"}),
+		res.json::<Value>().await.expect("json response")
+	);
+}
+
+#[actix_rt::test]
+async fn long_lived_request_object_is_rejected() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/oidc/callback").await;
+
+	let request = request_object(&srv.cfg, &client)
+		.with_validity_period(Duration::from_secs(86400))
+		.sign(&util::jwt_signing_key())
+		.expect("signing failed");
+
+	let mut res = srv
+		.get(
+			"/oidc/authorize?".to_string()
+				+ encode_params!(client_id: &client.id().to_base64(), request: &request),
+		)
+		.with_csrf_cookie()
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!(
+		json!({"error": "invalid_request_object"}),
+		res.json::<Value>().await.expect("json response")
+	);
+}
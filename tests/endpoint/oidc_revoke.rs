@@ -0,0 +1,231 @@
+use actix_web::HttpMessage as _;
+use serde_json::{json, Value};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::util;
+use authul_crypto::Jwt;
+use authul_db::model::{OidcClient, RefreshToken, User};
+use authul_frontend::Config as FrontendConfig;
+use authul_util::Base64Uuid;
+
+async fn refresh_token(cfg: &FrontendConfig, client: &OidcClient, user: &User) -> RefreshToken {
+	let principal = cfg
+		.db()
+		.principal()
+		.await
+		.expect("principal")
+		.find(user.principal().id())
+		.await
+		.expect("Principal");
+
+	let mut attrs = vec![];
+	ciborium::into_writer(&authul_db::types::IdentityAttributes::new(), &mut attrs).expect("CBOR");
+
+	cfg.db()
+		.refresh_token()
+		.await
+		.expect("refresh_token")
+		.new()
+		.with_oidc_client(client.clone())
+		.with_principal(principal)
+		.with_attrs(
+			cfg.refresh_token_attrs_strong_box()
+				.encrypt(attrs, b"")
+				.expect("encrypt"),
+		)
+		.with_auth_time(OffsetDateTime::now_utc())
+		.save()
+		.await
+		.expect("RefreshToken")
+}
+
+async fn access_token(cfg: &FrontendConfig, client: &OidcClient, user: &User) -> String {
+	Jwt::new()
+		.with_iss(cfg.base_url().as_str())
+		.with_sub(user.principal().id().to_string())
+		.with_aud(client.id().to_base64())
+		.with_jti(Uuid::new_v4().to_base64())
+		.with_validity_period(FrontendConfig::ACCESS_TOKEN_VALIDITY_PERIOD)
+		.sign(
+			&cfg.current_access_token_signing_jwk()
+				.await
+				.expect("access token signing key"),
+		)
+		.expect("signing failed")
+}
+
+async fn revoke(srv: &util::ConfiguredTestServer, client: &OidcClient, token: &str) -> u16 {
+	let res = srv
+		.post("/oidc/revoke")
+		.send_form(&[
+			("token", token),
+			(
+				"client_assertion_type",
+				"urn:ietf:params:oauth:client-assertion-type:jwt-bearer",
+			),
+			(
+				"client_assertion",
+				&util::client_jwt(&srv.cfg, client, token),
+			),
+		])
+		.await
+		.unwrap();
+
+	res.status().as_u16()
+}
+
+async fn userinfo_status(srv: &util::ConfiguredTestServer, token: &str) -> u16 {
+	srv.get("/oidc/userinfo")
+		.bearer_auth(token)
+		.send()
+		.await
+		.unwrap()
+		.status()
+		.as_u16()
+}
+
+#[actix_rt::test]
+async fn get_does_not_work() {
+	let srv = util::setup(util::default).await;
+
+	let res = srv.get("/oidc/revoke").send().await.unwrap();
+
+	assert_eq!(405, res.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn revoking_refresh_token_revokes_family() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let user = util::create_user(&srv.db).await;
+	let rt = refresh_token(&srv.cfg, &client, &user).await;
+	let sibling = srv
+		.db
+		.refresh_token()
+		.await
+		.expect("refresh_token")
+		.new()
+		.with_family_id(*rt.family_id())
+		.with_oidc_client(client.clone())
+		.with_principal(rt.principal().clone())
+		.with_attrs(rt.attrs().clone())
+		.with_auth_time(*rt.auth_time())
+		.save()
+		.await
+		.expect("RefreshToken");
+
+	assert_eq!(200, revoke(&srv, &client, &rt.id().to_base64()).await);
+
+	for id in [rt.id(), sibling.id()] {
+		assert!(
+			srv.db
+				.refresh_token()
+				.await
+				.expect("refresh_token")
+				.find(id)
+				.await
+				.is_err(),
+			"refresh token {id} still exists"
+		);
+	}
+}
+
+#[actix_rt::test]
+async fn revoking_access_token_stops_it_working() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let user = util::create_user(&srv.db).await;
+	let token = access_token(&srv.cfg, &client, &user).await;
+
+	assert_eq!(200, userinfo_status(&srv, &token).await);
+	assert_eq!(200, revoke(&srv, &client, &token).await);
+	assert_eq!(401, userinfo_status(&srv, &token).await);
+
+	// Doing it again is harmless
+	assert_eq!(200, revoke(&srv, &client, &token).await);
+}
+
+#[actix_rt::test]
+async fn cannot_revoke_someone_elses_tokens() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let other_client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let user = util::create_user(&srv.db).await;
+	let rt = refresh_token(&srv.cfg, &client, &user).await;
+	let token = access_token(&srv.cfg, &client, &user).await;
+
+	// As far as the other client can tell, it worked...
+	assert_eq!(200, revoke(&srv, &other_client, &rt.id().to_base64()).await);
+	assert_eq!(200, revoke(&srv, &other_client, &token).await);
+
+	// ... but it didn't
+	srv.db
+		.refresh_token()
+		.await
+		.expect("refresh_token")
+		.find(rt.id())
+		.await
+		.expect("refresh token was revoked");
+	assert_eq!(200, userinfo_status(&srv, &token).await);
+}
+
+#[actix_rt::test]
+async fn revoking_unknown_token_succeeds() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+
+	assert_eq!(
+		200,
+		revoke(&srv, &client, &Uuid::new_v4().to_base64()).await
+	);
+	assert_eq!(200, revoke(&srv, &client, "lolnope").await);
+}
+
+#[actix_rt::test]
+async fn missing_token_is_rejected() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+
+	let mut res = srv
+		.post("/oidc/revoke")
+		.send_form(&[
+			(
+				"client_assertion_type",
+				"urn:ietf:params:oauth:client-assertion-type:jwt-bearer",
+			),
+			("client_assertion", &util::client_jwt(&srv.cfg, &client, "")),
+		])
+		.await
+		.unwrap();
+
+	assert_eq!(400, res.status().as_u16());
+	assert_eq!("application/json", res.content_type());
+	assert_eq!(
+		json!({"error": "invalid_request"}),
+		res.json::<Value>().await.expect("json doc")
+	);
+}
+
+#[actix_rt::test]
+async fn unauthenticated_revocation_is_rejected() {
+	let srv = util::setup(util::vcr("tests/cassettes/example_jwks.json")).await;
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let user = util::create_user(&srv.db).await;
+	let rt = refresh_token(&srv.cfg, &client, &user).await;
+
+	let res = srv
+		.post("/oidc/revoke")
+		.send_form(&[("token", rt.id().to_base64().as_str())])
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	srv.db
+		.refresh_token()
+		.await
+		.expect("refresh_token")
+		.find(rt.id())
+		.await
+		.expect("refresh token was revoked");
+}
@@ -5,7 +5,7 @@ use time::OffsetDateTime;
 use uuid::Uuid;
 
 use crate::util;
-use authul_crypto::{Jwk, Jwt};
+use authul_crypto::Jwt;
 use authul_db::model::{OidcClient, OidcToken};
 use authul_frontend::Config as FrontendConfig;
 use authul_util::Base64Uuid;
@@ -19,10 +19,6 @@ async fn get_does_not_work() {
 	assert_eq!(405, res.status().as_u16());
 }
 
-fn jwt_signing_key() -> Jwk {
-	serde_json::from_str(r#"{"Ed25519":[0, 168, 50, 245, 78, 42, 57, 251, 163, 95, 74, 205, 191, 22, 96, 105, 10, 96, 109, 226, 1, 66, 246, 13, 86, 47, 113, 29, 41, 225, 78, 136]}"#).expect("JWK decode failed")
-}
-
 async fn creds_and_client(cfg: &FrontendConfig) -> (String, OidcClient, OidcToken) {
 	let client = cfg
 		.db()
@@ -32,7 +28,7 @@ async fn creds_and_client(cfg: &FrontendConfig) -> (String, OidcClient, OidcToke
 		.new()
 		.with_name("Caves")
 		.with_redirect_uris(["https://example.com/callback"])
-		.with_jwks_uri("https://example.com/jwks.json")
+		.with_jwks_uri("https://example.com/jwks.json".to_string())
 		.save()
 		.await
 		.expect("client save failed");
@@ -45,6 +41,7 @@ async fn creds_and_client(cfg: &FrontendConfig) -> (String, OidcClient, OidcToke
 		.new()
 		.with_oidc_client(client.clone())
 		.with_token("thisisnotarealtoken")
+		.with_access_token("thisisnotarealaccesstoken")
 		.with_redirect_uri("https://example.com/callback")
 		.with_code_challenge("xkvndgXSG7Ic99LmZ0g07LfnQiie4uAQwxXzaMADYoo")
 		.save()
@@ -56,7 +53,7 @@ async fn creds_and_client(cfg: &FrontendConfig) -> (String, OidcClient, OidcToke
 		.with_sub(client.id().to_base64())
 		.with_aud(cfg.base_url().as_str())
 		.with_jti(token.id().to_base64())
-		.sign(&jwt_signing_key())
+		.sign(&util::jwt_signing_key())
 		.expect("signing failed");
 
 	(client_jwt, client, token)
@@ -99,14 +96,19 @@ async fn can_retrieve_valid_token() {
 			.map(|v| v.as_str().expect("id_token should be string"))
 	);
 	assert_eq!(
-		None,
-		doc.get("access_token"),
-		"we don't hand out access tokens"
+		Some("thisisnotarealaccesstoken"),
+		doc.get("access_token")
+			.map(|v| v.as_str().expect("access_token should be string"))
+	);
+	assert_eq!(
+		Some(3600),
+		doc.get("expires_in")
+			.map(|v| v.as_u64().expect("expires_in should be a number"))
 	);
 	assert_eq!(
 		None,
 		doc.get("refresh_token"),
-		"we don't hand out refresh tokens"
+		"no refresh token was issued along with this code"
 	);
 }
 
@@ -178,7 +180,7 @@ async fn post_without_client_assertion_fails() {
 		.await
 		.unwrap();
 
-	assert_eq!(400, res.status().as_u16());
+	assert_eq!(401, res.status().as_u16());
 	assert_eq!("application/json", res.content_type());
 	assert_eq!(
 		json!({"error": "invalid_client"}),
@@ -232,7 +234,7 @@ async fn post_with_invalid_creds_fails() {
 		.await
 		.unwrap();
 
-	assert_eq!(400, res.status().as_u16());
+	assert_eq!(401, res.status().as_u16());
 	assert_eq!("application/json", res.content_type());
 	assert_eq!(
 		json!({"error": "invalid_client"}),
@@ -251,7 +253,7 @@ async fn post_with_future_jwt_fails() {
 		.with_aud(srv.cfg.base_url().as_str())
 		.with_jti(token.id().to_base64())
 		.with_broken_iat()
-		.sign(&jwt_signing_key())
+		.sign(&util::jwt_signing_key())
 		.expect("signing failed");
 
 	let mut res = srv
@@ -270,7 +272,7 @@ async fn post_with_future_jwt_fails() {
 		.await
 		.unwrap();
 
-	assert_eq!(400, res.status().as_u16());
+	assert_eq!(401, res.status().as_u16());
 	assert_eq!("application/json", res.content_type());
 	assert_eq!(
 		json!({"error": "invalid_client"}),
@@ -289,7 +291,7 @@ async fn post_with_expired_jwt_fails() {
 		.with_aud(srv.cfg.base_url().as_str())
 		.with_jti(token.id().to_base64())
 		.with_broken_exp()
-		.sign(&jwt_signing_key())
+		.sign(&util::jwt_signing_key())
 		.expect("signing failed");
 
 	let mut res = srv
@@ -308,7 +310,7 @@ async fn post_with_expired_jwt_fails() {
 		.await
 		.unwrap();
 
-	assert_eq!(400, res.status().as_u16());
+	assert_eq!(401, res.status().as_u16());
 	assert_eq!("application/json", res.content_type());
 	assert_eq!(
 		json!({"error": "invalid_client"}),
@@ -325,7 +327,7 @@ async fn post_with_no_jti_in_jwt_fails() {
 		.with_iss(client.id().to_base64())
 		.with_sub(client.id().to_base64())
 		.with_aud(srv.cfg.base_url().as_str())
-		.sign(&jwt_signing_key())
+		.sign(&util::jwt_signing_key())
 		.expect("signing failed");
 
 	let mut res = srv
@@ -344,7 +346,7 @@ async fn post_with_no_jti_in_jwt_fails() {
 		.await
 		.unwrap();
 
-	assert_eq!(400, res.status().as_u16());
+	assert_eq!(401, res.status().as_u16());
 	assert_eq!("application/json", res.content_type());
 	assert_eq!(
 		json!({"error": "invalid_client"}),
@@ -362,7 +364,7 @@ async fn post_with_wrong_jti_fails() {
 		.with_sub(client.id().to_base64())
 		.with_aud(srv.cfg.base_url().as_str())
 		.with_jti("bob")
-		.sign(&jwt_signing_key())
+		.sign(&util::jwt_signing_key())
 		.expect("signing failed");
 
 	let mut res = srv
@@ -601,6 +603,7 @@ async fn cannot_retrieve_expired_token() {
 		.new()
 		.with_oidc_client(oidc_client)
 		.with_token("thisisnotarealtoken")
+		.with_access_token("thisisnotarealaccesstoken")
 		.with_redirect_uri("https://example.com/callback")
 		.with_code_challenge("xyzzy123")
 		.with_valid_before(OffsetDateTime::now_utc() - Duration::from_secs(60))
@@ -692,25 +695,26 @@ async fn cannot_get_someone_elses_token() {
 }
 
 #[actix_rt::test]
-async fn cors_preflight() {
+async fn cors_preflight_requires_registered_origin() {
 	let srv = util::setup(util::default).await;
-	let res = srv.options("/oidc/token").send().await.unwrap();
+	let res = srv
+		.options("/oidc/token")
+		.insert_header(("origin", "https://example.com"))
+		.send()
+		.await
+		.unwrap();
 
 	assert_eq!(204, res.status().as_u16());
 	assert_eq!("", res.content_type());
 
+	// Only public clients with registered origins get to call us from a browser (see
+	// oidc_public_client for that)
 	let headers = res.headers();
-
-	for (hdr, val) in [
-		("access-control-allow-origin", "*"),
-		("access-control-allow-methods", "POST, OPTIONS"),
-		("access-control-max-age", "604800"),
+	for hdr in [
+		"access-control-allow-origin",
+		"access-control-allow-methods",
+		"access-control-max-age",
 	] {
-		assert_eq!(1, headers.get_all(hdr).count(), "no {hdr} header");
-		let actual = headers.get(hdr).unwrap().to_str().unwrap();
-		assert_eq!(
-			val, actual,
-			"bad {hdr} header value (expected {val}, got {actual}"
-		);
+		assert_eq!(0, headers.get_all(hdr).count(), "unexpected {hdr} header");
 	}
 }
@@ -0,0 +1,392 @@
+use actix_web::HttpMessage as _;
+use serde_json::{json, Value};
+use std::time::Duration;
+use time::OffsetDateTime;
+use url::Url;
+use uuid::Uuid;
+
+use crate::util;
+use authul_crypto::Jwt;
+use authul_db::{model::OidcClient, types::ClientAuthMethod};
+use authul_frontend::{AuthContext, Config as FrontendConfig};
+use authul_util::Base64Uuid;
+
+async fn access_token(cfg: &FrontendConfig, sub: impl Into<String>) -> String {
+	Jwt::new()
+		.with_iss(cfg.base_url().as_str())
+		.with_sub(sub)
+		.with_jti(Uuid::new_v4().to_base64())
+		.with_attrs(json!([{"kind": "email", "value": "jaime@example.com"}]))
+		.with_validity_period(FrontendConfig::ACCESS_TOKEN_VALIDITY_PERIOD)
+		.sign(
+			&cfg.current_access_token_signing_jwk()
+				.await
+				.expect("access token signing key"),
+		)
+		.expect("signing failed")
+}
+
+fn www_authenticate(res: &impl actix_web::HttpMessage) -> Option<String> {
+	res.headers()
+		.get("www-authenticate")
+		.map(|v| v.to_str().unwrap().to_string())
+}
+
+#[actix_rt::test]
+async fn valid_access_token_gets_claims() {
+	let srv = util::setup(util::default).await;
+
+	let user = util::create_user(&srv.db).await;
+	let token = access_token(&srv.cfg, user.principal().id().to_string()).await;
+
+	let mut res = srv
+		.get("/oidc/userinfo")
+		.bearer_auth(&token)
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	assert_eq!("application/json", res.content_type());
+
+	let doc: Value = res.json().await.expect("invalid JSON response body");
+	assert_eq!(
+		Some(user.principal().id().to_string().as_str()),
+		doc["sub"].as_str()
+	);
+	assert_eq!(
+		json!([{"kind": "email", "value": "jaime@example.com"}]),
+		doc["attrs"]
+	);
+}
+
+#[actix_rt::test]
+async fn userinfo_can_be_posted_to() {
+	let srv = util::setup(util::default).await;
+
+	let user = util::create_user(&srv.db).await;
+	let token = access_token(&srv.cfg, user.principal().id().to_string()).await;
+
+	let res = srv
+		.post("/oidc/userinfo")
+		.bearer_auth(&token)
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn access_token_from_authentication_works() {
+	let srv = util::setup(util::default).await;
+
+	let client = util::oidc_client(&srv.db, "https://example.com/callback").await;
+	let user = util::create_user(&srv.db).await;
+
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		client.id(),
+		"https://example.com/callback",
+		"xyzzy123",
+	)
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash());
+
+	let res = srv
+		.post("/authenticate/submit_password")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.to_string()),
+			("password", "hunter2".to_string()),
+		])
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	let redirect_url =
+		Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+	let code = redirect_url
+		.query_pairs()
+		.find(|(k, _)| k == "code")
+		.map(|(_, v)| v.to_string())
+		.expect("no code");
+	let token = srv
+		.db
+		.oidc_token()
+		.await
+		.expect("oidc_token")
+		.find(&Uuid::from_base64(&code).expect("valid UUID"))
+		.await
+		.expect("token was not saved in DB");
+
+	let mut res = srv
+		.get("/oidc/userinfo")
+		.bearer_auth(token.access_token())
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(200, res.status().as_u16());
+	let doc: Value = res.json().await.expect("invalid JSON response body");
+	assert_eq!(
+		Some(user.principal().id().to_string().as_str()),
+		doc["sub"].as_str()
+	);
+}
+
+#[actix_rt::test]
+async fn missing_access_token_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let res = srv.get("/oidc/userinfo").send().await.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert!(www_authenticate(&res)
+		.expect("no www-authenticate header")
+		.starts_with("Bearer"));
+}
+
+#[actix_rt::test]
+async fn garbage_access_token_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let res = srv
+		.get("/oidc/userinfo")
+		.bearer_auth("lolnope")
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		Some(r#"Bearer error="invalid_token""#.to_string()),
+		www_authenticate(&res)
+	);
+}
+
+#[actix_rt::test]
+async fn id_token_is_not_an_access_token() {
+	let srv = util::setup(util::default).await;
+
+	let user = util::create_user(&srv.db).await;
+	let id_token = Jwt::new()
+		.with_iss(srv.cfg.base_url().as_str())
+		.with_sub(user.principal().id().to_string())
+		.sign(
+			&srv.cfg
+				.current_oidc_signing_jwk()
+				.await
+				.expect("OIDC signing key"),
+		)
+		.expect("signing failed");
+
+	let res = srv
+		.get("/oidc/userinfo")
+		.bearer_auth(&id_token)
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		Some(r#"Bearer error="invalid_token""#.to_string()),
+		www_authenticate(&res)
+	);
+}
+
+#[actix_rt::test]
+async fn expired_access_token_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let user = util::create_user(&srv.db).await;
+	let token = Jwt::new()
+		.with_iss(srv.cfg.base_url().as_str())
+		.with_sub(user.principal().id().to_string())
+		.with_broken_exp()
+		.sign(
+			&srv.cfg
+				.current_access_token_signing_jwk()
+				.await
+				.expect("access token signing key"),
+		)
+		.expect("signing failed");
+
+	let res = srv
+		.get("/oidc/userinfo")
+		.bearer_auth(&token)
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn access_token_for_unknown_principal_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let token = access_token(&srv.cfg, Uuid::now_v7().to_string()).await;
+
+	let res = srv
+		.get("/oidc/userinfo")
+		.bearer_auth(&token)
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		Some(r#"Bearer error="invalid_token""#.to_string()),
+		www_authenticate(&res)
+	);
+}
+
+#[actix_rt::test]
+async fn revoked_access_token_is_rejected() {
+	let srv = util::setup(util::default).await;
+
+	let user = util::create_user(&srv.db).await;
+	let token = access_token(&srv.cfg, user.principal().id().to_string()).await;
+	let jti = token
+		.parse::<Jwt>()
+		.expect("JWT")
+		.peek_jti()
+		.map(str::to_string);
+
+	srv.db
+		.revoked_access_token()
+		.await
+		.expect("revoked_access_token")
+		.revoke(
+			&Uuid::from_base64(&jti.expect("no jti")).expect("jti is a UUID"),
+			&(OffsetDateTime::now_utc() + Duration::from_secs(3600)),
+		)
+		.await
+		.expect("revoke");
+
+	let res = srv
+		.get("/oidc/userinfo")
+		.bearer_auth(&token)
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(401, res.status().as_u16());
+	assert_eq!(
+		Some(r#"Bearer error="invalid_token""#.to_string()),
+		www_authenticate(&res)
+	);
+}
+
+#[actix_rt::test]
+async fn provider_metadata_advertises_userinfo_endpoint() {
+	let srv = util::setup(util::default).await;
+
+	let mut res = srv
+		.get("/.well-known/openid-configuration")
+		.send()
+		.await
+		.unwrap();
+	let doc: Value = res.json().await.expect("invalid JSON response body");
+
+	assert_eq!(
+		Some(srv.url("/oidc/userinfo").as_str()),
+		doc["userinfo_endpoint"].as_str()
+	);
+}
+
+#[actix_rt::test]
+async fn browser_client_can_call_userinfo_from_its_own_origin() {
+	let srv = util::setup(util::default).await;
+
+	let client = srv
+		.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Caves")
+		.with_redirect_uris(["https://app.example.com/callback"])
+		.with_token_endpoint_auth_method(ClientAuthMethod::None)
+		.with_allowed_origins(["https://app.example.com"])
+		.save()
+		.await
+		.expect("OidcClient");
+	srv.db
+		.oidc_client()
+		.await
+		.expect("oidc_client")
+		.new()
+		.with_name("Elsewhere")
+		.with_redirect_uris(["https://other.example.com/callback"])
+		.with_token_endpoint_auth_method(ClientAuthMethod::None)
+		.with_allowed_origins(["https://other.example.com"])
+		.save()
+		.await
+		.expect("OidcClient");
+	let user = util::create_user(&srv.db).await;
+
+	let token = Jwt::new()
+		.with_iss(srv.cfg.base_url().as_str())
+		.with_sub(user.principal().id().to_string())
+		.with_aud(client.id().to_base64())
+		.with_jti(Uuid::new_v4().to_base64())
+		.with_validity_period(FrontendConfig::ACCESS_TOKEN_VALIDITY_PERIOD)
+		.sign(
+			&srv.cfg
+				.current_access_token_signing_jwk()
+				.await
+				.expect("access token signing key"),
+		)
+		.expect("signing failed");
+
+	let res = srv
+		.options("/oidc/userinfo")
+		.insert_header(("origin", "https://app.example.com"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(204, res.status().as_u16());
+	for (hdr, val) in [
+		("access-control-allow-origin", "https://app.example.com"),
+		("access-control-allow-methods", "GET, POST, OPTIONS"),
+		("access-control-allow-headers", "authorization, dpop"),
+	] {
+		assert_eq!(
+			Some(val),
+			res.headers().get(hdr).map(|v| v.to_str().unwrap()),
+			"bad {hdr} header"
+		);
+	}
+
+	let res = srv
+		.get("/oidc/userinfo")
+		.insert_header(("origin", "https://app.example.com"))
+		.bearer_auth(&token)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+	assert_eq!(
+		Some("https://app.example.com"),
+		res.headers()
+			.get("access-control-allow-origin")
+			.map(|v| v.to_str().unwrap())
+	);
+	assert!(
+		res.headers().get_all("vary").any(|v| v == "origin"),
+		"response doesn't vary by origin"
+	);
+
+	// Some other client's origin doesn't get to read what this client's token says
+	let res = srv
+		.get("/oidc/userinfo")
+		.insert_header(("origin", "https://other.example.com"))
+		.bearer_auth(&token)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(200, res.status().as_u16());
+	assert_eq!(None, res.headers().get("access-control-allow-origin"));
+}
@@ -0,0 +1,416 @@
+use actix_web::{cookie::Cookie, HttpMessage as _};
+use std::time::Duration;
+use time::OffsetDateTime;
+use url::Url;
+use uuid::Uuid;
+
+use crate::util::{self, WithCsrfCookie as _};
+use authul_db::model::{OidcClient, Session, User};
+use authul_frontend::AuthContext;
+use authul_util::Base64Uuid;
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use serde_json::Value;
+
+async fn oidc_client(srv: &util::ConfiguredTestServer, require_mfa: bool) -> OidcClient {
+	util::oidc_client_with(&srv.db, "https://example.com/oidc/callback", |c| {
+		c.update_require_mfa(require_mfa);
+	})
+	.await
+}
+
+async fn create_session(
+	srv: &util::ConfiguredTestServer,
+	user: &User,
+	mfa_time: Option<OffsetDateTime>,
+	valid_before: OffsetDateTime,
+) -> Session {
+	let principal = srv
+		.db
+		.principal()
+		.await
+		.expect("principal")
+		.find(user.principal().id())
+		.await
+		.expect("Principal");
+
+	let mut attrs = vec![];
+	ciborium::into_writer(&authul_db::types::IdentityAttributes::new(), &mut attrs).expect("CBOR");
+
+	srv.db
+		.session()
+		.await
+		.expect("session")
+		.new()
+		.with_principal(principal)
+		.with_attrs(
+			srv.cfg
+				.session_attrs_strong_box()
+				.encrypt(attrs, b"")
+				.expect("encrypt"),
+		)
+		.with_auth_time(OffsetDateTime::now_utc() - Duration::from_secs(60))
+		.with_mfa_time(mfa_time)
+		.with_valid_before(valid_before)
+		.save()
+		.await
+		.expect("Session")
+}
+
+async fn authorize(
+	srv: &util::ConfiguredTestServer,
+	client: &OidcClient,
+	session_cookie: Option<String>,
+	extra_params: &[(&str, &str)],
+) -> Url {
+	let query = url::form_urlencoded::Serializer::new(String::new())
+		.append_pair("redirect_uri", "https://example.com/oidc/callback")
+		.append_pair("client_id", &client.id().to_base64())
+		.append_pair("scope", "openid")
+		.append_pair("response_type", "code")
+		.append_pair("code_challenge_method", "S256")
+		.append_pair("code_challenge", "xyzzy123")
+		.append_pair("state", "inebriation")
+		.extend_pairs(extra_params)
+		.finish();
+
+	let mut req = srv
+		.get(format!("/oidc/authorize?{query}"))
+		.with_csrf_cookie();
+	if let Some(value) = session_cookie {
+		req = req.cookie(Cookie::new("authul_session", value));
+	}
+	let res = req.send().await.unwrap();
+
+	assert!(res.status().is_redirection());
+	Url::parse(res.headers().get("location").unwrap().to_str().unwrap()).unwrap()
+}
+
+/// Pull the claims out of the ID token that was issued along with the given authorization code
+async fn id_token_claims(srv: &util::ConfiguredTestServer, redirect_url: &Url) -> Value {
+	let token = srv
+		.db
+		.oidc_token()
+		.await
+		.expect("oidc_token")
+		.find(
+			&Uuid::from_base64(&util::param(redirect_url, "code").expect("no code"))
+				.expect("valid UUID"),
+		)
+		.await
+		.expect("token was not saved in DB");
+
+	let payload = token.token().split('.').nth(1).expect("JWT payload");
+	serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(payload).expect("base64")).expect("JSON")
+}
+
+fn in_an_hour() -> OffsetDateTime {
+	OffsetDateTime::now_utc() + Duration::from_secs(3600)
+}
+
+#[actix_rt::test]
+async fn successful_login_starts_a_session() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		client.id(),
+		"https://example.com/oidc/callback",
+		"xyzzy123",
+	)
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash());
+
+	let res = srv
+		.post("/authenticate/submit_password")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.to_string()),
+			("password", "hunter2".to_string()),
+		])
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+
+	let cookie = res.cookie("authul_session").expect("no session cookie");
+	assert_eq!(Some(true), cookie.secure());
+	assert_eq!(Some(true), cookie.http_only());
+
+	let session = srv
+		.db
+		.session()
+		.await
+		.expect("session")
+		.find_valid(&Uuid::parse_str(cookie.value()).expect("session cookie is a UUID"))
+		.await
+		.expect("find_valid")
+		.expect("session was not saved in DB");
+	assert_eq!(user.principal().id(), session.principal().id());
+	assert_eq!(&None, session.mfa_time());
+	// Whatever the upstream provider told us about the user is nobody else's business
+	let attrs = srv
+		.cfg
+		.session_attrs_strong_box()
+		.decrypt(session.attrs(), b"")
+		.expect("session attrs are not encrypted");
+	ciborium::from_reader::<authul_db::types::IdentityAttributes, _>(&attrs[..]).expect("CBOR");
+}
+
+#[actix_rt::test]
+async fn failed_login_does_not_start_a_session() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+
+	let ctx = AuthContext::new(
+		srv.cfg.clone(),
+		client.id(),
+		"https://example.com/oidc/callback",
+		"xyzzy123",
+	)
+	.with_principal(*user.principal().id())
+	.with_pwhash(user.pwhash());
+
+	let res = srv
+		.post("/authenticate/submit_password")
+		.insert_header(("accept", "text/html"))
+		.send_form(&[
+			("ctx", ctx.to_string()),
+			("password", "hunter3".to_string()),
+		])
+		.await
+		.unwrap();
+	assert_eq!(302, res.status().as_u16());
+	assert!(res.cookie("authul_session").is_none());
+}
+
+#[actix_rt::test]
+async fn valid_session_skips_authentication() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user, None, in_an_hour()).await;
+
+	let redirect_url = authorize(&srv, &client, Some(session.id().to_string()), &[]).await;
+
+	assert_eq!("example.com", redirect_url.host_str().unwrap());
+	assert_eq!("/oidc/callback", redirect_url.path());
+	assert_eq!(
+		Some("inebriation".to_string()),
+		util::param(&redirect_url, "state")
+	);
+
+	let token = srv
+		.db
+		.oidc_token()
+		.await
+		.expect("oidc_token")
+		.find(
+			&Uuid::from_base64(&util::param(&redirect_url, "code").expect("no code"))
+				.expect("valid UUID"),
+		)
+		.await
+		.expect("token was not saved in DB");
+	assert_eq!("xyzzy123", token.code_challenge());
+}
+
+#[actix_rt::test]
+async fn unknown_session_is_ignored() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, false).await;
+
+	let redirect_url = authorize(&srv, &client, Some(Uuid::new_v4().to_string()), &[]).await;
+	assert_eq!("/authenticate", redirect_url.path());
+
+	let redirect_url = authorize(&srv, &client, Some("gibberish".to_string()), &[]).await;
+	assert_eq!("/authenticate", redirect_url.path());
+}
+
+#[actix_rt::test]
+async fn expired_session_is_ignored() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(
+		&srv,
+		&user,
+		None,
+		OffsetDateTime::now_utc() - Duration::from_secs(1),
+	)
+	.await;
+
+	let redirect_url = authorize(&srv, &client, Some(session.id().to_string()), &[]).await;
+	assert_eq!("/authenticate", redirect_url.path());
+}
+
+#[actix_rt::test]
+async fn session_without_mfa_does_not_satisfy_mfa_client() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, true).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user, None, in_an_hour()).await;
+
+	let redirect_url = authorize(&srv, &client, Some(session.id().to_string()), &[]).await;
+	assert_eq!("/authenticate", redirect_url.path());
+
+	let session = create_session(
+		&srv,
+		&user,
+		Some(OffsetDateTime::now_utc() - Duration::from_secs(30)),
+		in_an_hour(),
+	)
+	.await;
+
+	let redirect_url = authorize(&srv, &client, Some(session.id().to_string()), &[]).await;
+	assert_eq!("/oidc/callback", redirect_url.path());
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+}
+
+#[actix_rt::test]
+async fn id_token_from_session_has_original_auth_time() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user, None, in_an_hour()).await;
+
+	let redirect_url = authorize(&srv, &client, Some(session.id().to_string()), &[]).await;
+
+	let claims = id_token_claims(&srv, &redirect_url).await;
+	assert_eq!(
+		Some(session.auth_time().unix_timestamp()),
+		claims["auth_time"].as_i64()
+	);
+}
+
+#[actix_rt::test]
+async fn prompt_login_ignores_session() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user, None, in_an_hour()).await;
+
+	let redirect_url = authorize(
+		&srv,
+		&client,
+		Some(session.id().to_string()),
+		&[("prompt", "login")],
+	)
+	.await;
+	assert_eq!("/authenticate", redirect_url.path());
+
+	let redirect_url = authorize(
+		&srv,
+		&client,
+		Some(session.id().to_string()),
+		&[("prompt", "select_account")],
+	)
+	.await;
+	assert_eq!("/authenticate", redirect_url.path());
+}
+
+#[actix_rt::test]
+async fn prompt_none_uses_session() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user, None, in_an_hour()).await;
+
+	let redirect_url = authorize(
+		&srv,
+		&client,
+		Some(session.id().to_string()),
+		&[("prompt", "none")],
+	)
+	.await;
+	assert_eq!("/oidc/callback", redirect_url.path());
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+}
+
+#[actix_rt::test]
+async fn prompt_none_without_session_requires_login() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, false).await;
+
+	let redirect_url = authorize(&srv, &client, None, &[("prompt", "none")]).await;
+	assert_eq!("/oidc/callback", redirect_url.path());
+	assert_eq!(
+		Some("login_required".to_string()),
+		util::param(&redirect_url, "error")
+	);
+	assert_eq!(None, util::param(&redirect_url, "code"));
+}
+
+#[actix_rt::test]
+async fn prompt_none_with_consent_outstanding_requires_consent() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	let session = create_session(&srv, &user, None, in_an_hour()).await;
+
+	let redirect_url = authorize(
+		&srv,
+		&client,
+		Some(session.id().to_string()),
+		&[
+			("prompt", "none"),
+			("claims", r#"{"id_token":{"email":null}}"#),
+		],
+	)
+	.await;
+	assert_eq!("/oidc/callback", redirect_url.path());
+	assert_eq!(
+		Some("consent_required".to_string()),
+		util::param(&redirect_url, "error")
+	);
+	assert_eq!(None, util::param(&redirect_url, "code"));
+}
+
+#[actix_rt::test]
+async fn max_age_ignores_older_session() {
+	let srv = util::setup(util::default).await;
+
+	let client = oidc_client(&srv, false).await;
+	let user = util::create_user(&srv.db).await;
+	// Sessions from create_session were authenticated a minute ago
+	let session = create_session(&srv, &user, None, in_an_hour()).await;
+
+	let redirect_url = authorize(
+		&srv,
+		&client,
+		Some(session.id().to_string()),
+		&[("max_age", "30")],
+	)
+	.await;
+	assert_eq!("/authenticate", redirect_url.path());
+
+	let redirect_url = authorize(
+		&srv,
+		&client,
+		Some(session.id().to_string()),
+		&[("max_age", "3600")],
+	)
+	.await;
+	assert_eq!("/oidc/callback", redirect_url.path());
+	assert!(
+		util::param(&redirect_url, "code").is_some(),
+		"no code issued"
+	);
+}
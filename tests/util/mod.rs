@@ -96,6 +96,10 @@ async fn srv(
 	.build()
 	.expect("invalid config");
 
+	app_cfg
+		.re_encrypt_server_secrets()
+		.await
+		.expect("server secrets could not be re-encrypted");
 	authul_frontend::periodic_tasks::spawn(app_cfg.clone())
 		.await
 		.expect("background tasks shat themselves");
@@ -203,3 +207,148 @@ pub(crate) async fn doc(res: &mut actix_test::ClientResponse) -> scraper::Html {
 	);
 	doc
 }
+
+/// The user that most tests sign in as, whose password is `hunter2`
+pub(crate) async fn create_user(db: &authul_db::Pool) -> authul_db::model::User {
+	db.user()
+		.await
+		.expect("user")
+		.create("jaime@example.com", bcrypt::hash("hunter2", 4).unwrap())
+		.await
+		.expect("User")
+}
+
+/// A client that authenticates with assertions signed by [`jwt_signing_key`], whose public half is
+/// in the `example_jwks.json` cassette
+pub(crate) async fn oidc_client(
+	db: &authul_db::Pool,
+	redirect_uri: &str,
+) -> authul_db::model::OidcClient {
+	oidc_client_with(db, redirect_uri, |_| ()).await
+}
+
+/// The same client as [`oidc_client`], with whatever else the test needs changed about it
+pub(crate) async fn oidc_client_with(
+	db: &authul_db::Pool,
+	redirect_uri: &str,
+	adjust: impl FnOnce(&mut authul_db::model::OidcClient),
+) -> authul_db::model::OidcClient {
+	let clients = db.oidc_client().await.expect("oidc_client");
+	let mut client = clients
+		.new()
+		.with_name("Caves")
+		.with_redirect_uris([redirect_uri])
+		.with_jwks_uri("https://example.com/jwks.json".to_string())
+		.save()
+		.await
+		.expect("OidcClient");
+
+	adjust(&mut client);
+	client.save(&clients).await.expect("OidcClient update");
+	client
+}
+
+pub(crate) fn jwt_signing_key() -> authul_crypto::Jwk {
+	serde_json::from_str(r#"{"Ed25519":[0, 168, 50, 245, 78, 42, 57, 251, 163, 95, 74, 205, 191, 22, 96, 105, 10, 96, 109, 226, 1, 66, 246, 13, 86, 47, 113, 29, 41, 225, 78, 136]}"#).expect("JWK decode failed")
+}
+
+/// A `private_key_jwt` client assertion for the given client
+pub(crate) fn client_jwt(
+	cfg: &authul_frontend::Config,
+	client: &authul_db::model::OidcClient,
+	jti: &str,
+) -> String {
+	use authul_util::Base64Uuid as _;
+
+	authul_crypto::Jwt::new()
+		.with_iss(client.id().to_base64())
+		.with_sub(client.id().to_base64())
+		.with_aud(cfg.base_url().as_str())
+		.with_jti(jti)
+		.sign(&jwt_signing_key())
+		.expect("signing failed")
+}
+
+pub(crate) fn param(url: &Url, name: &str) -> Option<String> {
+	url.query_pairs()
+		.find(|(k, _)| k == name)
+		.map(|(_, v)| v.to_string())
+}
+
+/// A directory that the server under test drops its outgoing mail into, rather than sending it
+#[derive(Debug)]
+pub(crate) struct MailDrop(PathBuf);
+
+impl MailDrop {
+	pub(crate) fn new() -> Self {
+		let mut dir = env::temp_dir();
+		dir.push(format!("authul-mail-{}", uuid::Uuid::now_v7()));
+		std::fs::create_dir_all(&dir).expect("failed to create mail drop directory");
+
+		Self(dir)
+	}
+
+	pub(crate) fn cfg(
+		&self,
+	) -> impl Fn(authul_frontend::ConfigBuilder) -> authul_frontend::ConfigBuilder {
+		let dir = self.0.clone();
+		move |cfg| cfg.mail_transport(Arc::new(authul_frontend::mail::FileDrop::new(&dir)))
+	}
+
+	/// The full text of every message sent so far, with any quoted-printable encoding undone
+	pub(crate) fn messages(&self) -> Vec<String> {
+		std::fs::read_dir(&self.0)
+			.expect("failed to read mail drop directory")
+			.map(|e| e.expect("failed to read mail drop entry").path())
+			.filter(|p| p.extension().is_some_and(|ext| ext == "eml"))
+			.map(|p| {
+				let msg = std::fs::read_to_string(p).expect("failed to read message");
+				if msg.contains("Content-Transfer-Encoding: quoted-printable") {
+					decode_quoted_printable(&msg)
+				} else {
+					msg
+				}
+			})
+			.collect()
+	}
+
+	/// Wait (for a little while) for at least `n` messages to have been sent, for when mail is
+	/// sent in the background
+	pub(crate) async fn wait_for_messages(&self, n: usize) -> Vec<String> {
+		for _ in 0..50 {
+			let messages = self.messages();
+			if messages.len() >= n {
+				return messages;
+			}
+			actix_rt::time::sleep(std::time::Duration::from_millis(100)).await;
+		}
+
+		panic!("gave up waiting for {n} message(s) to be sent");
+	}
+}
+
+impl Drop for MailDrop {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}
+
+fn decode_quoted_printable(s: &str) -> String {
+	let s = s.replace("=\r\n", "");
+	let mut out = Vec::with_capacity(s.len());
+	let mut bytes = s.bytes();
+
+	while let Some(b) = bytes.next() {
+		if b == b'=' {
+			let hex = [bytes.next().unwrap(), bytes.next().unwrap()];
+			out.push(
+				u8::from_str_radix(std::str::from_utf8(&hex).unwrap(), 16)
+					.expect("invalid quoted-printable escape"),
+			);
+		} else {
+			out.push(b);
+		}
+	}
+
+	String::from_utf8(out).expect("message is not UTF-8")
+}